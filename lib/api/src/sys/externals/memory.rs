@@ -9,7 +9,10 @@ use std::mem;
 use std::mem::MaybeUninit;
 use std::slice;
 use wasmer_types::Pages;
-use wasmer_vm::{InternalStoreHandle, MemoryError, StoreHandle, StoreObjects, VMExtern, VMMemory};
+use wasmer_vm::{
+    InternalStoreHandle, MemoryError, MemoryInitialization, StoreHandle, StoreObjects, VMExtern,
+    VMMemory,
+};
 
 /// A WebAssembly `memory` instance.
 ///
@@ -48,7 +51,10 @@ impl Memory {
         let mut store = store.as_store_mut();
         let tunables = store.tunables();
         let style = tunables.memory_style(&ty);
-        let memory = tunables.create_host_memory(&ty, &style)?;
+        let mut memory = tunables.create_host_memory(&ty, &style)?;
+        if tunables.memory_initialization() == MemoryInitialization::Eager {
+            memory.zero_fill();
+        }
 
         Ok(Self {
             handle: StoreHandle::new(store.objects_mut(), memory),
@@ -72,10 +72,13 @@ impl Table {
         let item = value_to_table_element(&mut store, init)?;
         let mut store = store.as_store_mut();
         let tunables = store.tunables();
+        let ty = tunables
+            .limit_table_type(&ty)
+            .map_err(|e| RuntimeError::new(e.to_string()))?;
         let style = tunables.table_style(&ty);
         let mut table = tunables
             .create_host_table(&ty, &style)
-            .map_err(RuntimeError::new)?;
+            .map_err(|e| RuntimeError::new(e.to_string()))?;
 
         let num_elements = table.size();
         for i in 0..num_elements {
@@ -29,7 +29,7 @@ pub use crate::sys::store::{AsStoreMut, AsStoreRef, StoreMut, StoreRef};
 
 pub use crate::sys::ptr::{Memory32, Memory64, MemorySize, WasmPtr, WasmPtr64};
 pub use crate::sys::store::Store;
-pub use crate::sys::tunables::BaseTunables;
+pub use crate::sys::tunables::{AccountingTunables, BaseTunables};
 pub use crate::sys::value::Value;
 pub use target_lexicon::{Architecture, CallingConvention, OperatingSystem, Triple, HOST};
 #[cfg(feature = "compiler")]
@@ -48,18 +48,18 @@ pub use wasmer_types::{
 
 pub use wasmer_types::{
     Bytes, CompileError, DeserializeError, ExportIndex, GlobalInit, LocalFunctionIndex,
-    MiddlewareError, Pages, ParseCpuFeatureError, SerializeError, ValueType, WasmError, WasmResult,
-    WASM_MAX_PAGES, WASM_MIN_PAGES, WASM_PAGE_SIZE,
+    MemoryIndex, MiddlewareError, Pages, ParseCpuFeatureError, SerializeError, ValueType,
+    WasmError, WasmResult, WASM_MAX_PAGES, WASM_MIN_PAGES, WASM_PAGE_SIZE,
 };
 
 // TODO: should those be moved into wasmer::vm as well?
-pub use wasmer_vm::{raise_user_trap, MemoryError};
+pub use wasmer_vm::{raise_user_trap, MemoryError, MemoryInitialization, TableError};
 pub mod vm {
     //! The `vm` module re-exports wasmer-vm types.
 
     pub use wasmer_vm::{
-        MemoryError, MemoryStyle, TableStyle, VMExtern, VMMemory, VMMemoryDefinition, VMTable,
-        VMTableDefinition,
+        MemoryError, MemoryInitialization, MemoryStyle, TableError, TableStyle, VMExtern, VMMemory,
+        VMMemoryDefinition, VMTable, VMTableDefinition,
     };
 }
 
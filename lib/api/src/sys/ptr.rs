@@ -141,6 +141,11 @@ impl<T, M: MemorySize> WasmPtr<T, M> {
 impl<T: ValueType, M: MemorySize> WasmPtr<T, M> {
     /// Creates a `WasmRef` from this `WasmPtr` which allows reading and
     /// mutating of the value being pointed to.
+    ///
+    /// The returned `WasmRef` uses the memory as it was when it was created.
+    /// It must not be held across calls that can grow the memory (another
+    /// thread or a call back into the instance); dereference the pointer
+    /// again afterwards instead.
     #[inline]
     pub fn deref<'a>(self, store: &'a impl AsStoreRef, memory: &'a Memory) -> WasmRef<'a, T> {
         WasmRef::new(store, memory, self.offset.into())
@@ -168,6 +173,9 @@ impl<T: ValueType, M: MemorySize> WasmPtr<T, M> {
     ///
     /// Returns a `MemoryAccessError` if the slice length overflows a 64-bit
     /// address.
+    ///
+    /// Like [`WasmPtr::deref`], the `WasmSlice` must not be held across calls
+    /// that can grow the memory.
     #[inline]
     pub fn slice<'a>(
         self,
@@ -16,7 +16,7 @@ use wasmer_vm::StoreObjects;
 pub(crate) struct StoreInner {
     pub(crate) objects: StoreObjects,
     pub(crate) engine: Arc<dyn Engine + Send + Sync>,
-    pub(crate) tunables: Box<dyn Tunables + Send + Sync>,
+    pub(crate) tunables: Arc<dyn Tunables + Send + Sync>,
     pub(crate) trap_handler: Option<Box<TrapHandlerFn<'static>>>,
 }
 
@@ -64,11 +64,18 @@ impl Store {
         // This is required for handling traps.
         init_traps();
 
+        let tunables: Arc<dyn Tunables + Send + Sync> = Arc::new(tunables);
+        let mut objects = StoreObjects::default();
+        let observer = tunables.clone();
+        objects.set_memory_grow_hook(Some(Arc::new(move |index, prev, new| {
+            observer.on_memory_grow(index, prev, new)
+        })));
+
         Self {
             inner: Box::new(StoreInner {
-                objects: Default::default(),
+                objects,
                 engine: engine.cloned(),
-                tunables: Box::new(tunables),
+                tunables,
                 trap_handler: None,
             }),
         }
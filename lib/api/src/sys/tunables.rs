@@ -1,10 +1,17 @@
-use crate::sys::{MemoryType, Pages, TableType};
+use crate::sys::{GlobalType, MemoryIndex, MemoryType, Pages, TableType};
+use std::mem::size_of;
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use target_lexicon::PointerWidth;
-use wasmer_compiler::{Target, Tunables};
-use wasmer_vm::MemoryError;
+use wasmer_compiler::{LinkError, Target, Tunables};
+use wasmer_types::entity::PrimaryMap;
+use wasmer_types::{LocalGlobalIndex, LocalMemoryIndex, LocalTableIndex, ModuleInfo, TableIndex};
+use wasmer_vm::{InternalStoreHandle, MemoryInitialization, StoreObjects, VMFuncRef};
+use wasmer_vm::{MemoryError, TableError};
 use wasmer_vm::{
-    MemoryStyle, TableStyle, VMMemory, VMMemoryDefinition, VMTable, VMTableDefinition,
+    MemoryStyle, TableStyle, VMGlobal, VMGlobalDefinition, VMMemory, VMMemoryDefinition, VMTable,
+    VMTableDefinition,
 };
 
 /// Tunable parameters for WebAssembly compilation.
@@ -45,6 +52,16 @@ impl BaseTunables {
                 PointerWidth::U64 => (0x1_0000.into(), 0x8000_0000),
             };
 
+        Self::with_bounds(static_memory_bound, static_memory_offset_guard_size)
+    }
+
+    /// Get `BaseTunables` that make a memory static when its declared
+    /// maximum fits in `static_memory_bound`, and dynamic otherwise.
+    ///
+    /// Static memories reserve `static_memory_bound` plus
+    /// `static_memory_offset_guard_size` bytes of address space up front, in
+    /// exchange for cheaper bounds checks.
+    pub fn with_bounds(static_memory_bound: Pages, static_memory_offset_guard_size: u64) -> Self {
         // Allocate a small guard to optimize common cases but without
         // wasting too much memory.
         // The Windows memory manager seems more laxed than the other ones
@@ -66,6 +83,16 @@ impl BaseTunables {
 impl Tunables for BaseTunables {
     /// Get a `MemoryStyle` for the provided `MemoryType`
     fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        // Other threads may be accessing a shared memory while it grows, so
+        // its base must never move: its whole maximum is reserved up front.
+        if memory.shared {
+            let maximum = memory.maximum.unwrap_or_else(Pages::max_value);
+            return MemoryStyle::Static {
+                bound: std::cmp::max(maximum, self.static_memory_bound),
+                offset_guard_size: self.static_memory_offset_guard_size,
+            };
+        }
+
         // A heap with a maximum that doesn't exceed the static memory bound specified by the
         // tunables make it static.
         //
@@ -113,7 +140,7 @@ impl Tunables for BaseTunables {
     }
 
     /// Create a table owned by the host given a [`TableType`] and a [`TableStyle`].
-    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, TableError> {
         VMTable::new(ty, style)
     }
 
@@ -127,14 +154,160 @@ impl Tunables for BaseTunables {
         ty: &TableType,
         style: &TableStyle,
         vm_definition_location: NonNull<VMTableDefinition>,
-    ) -> Result<VMTable, String> {
+    ) -> Result<VMTable, TableError> {
         VMTable::from_definition(ty, style, vm_definition_location)
     }
 }
 
+/// Wraps a [`Tunables`] and counts the bytes of the memories, tables and
+/// globals it creates for instances, e.g. to hold each tenant to a memory
+/// budget.
+///
+/// The memories count for their size once created and whenever an
+/// instance grows them with `memory.grow`, the tables for their elements,
+/// which are references, and the globals for their definition. Memories and
+/// tables created by the host, and memories grown by the host, are not
+/// counted. Clones share the count: keep one to read
+/// [`AccountingTunables::allocated_bytes`] once the tunables were handed to
+/// a [`Store`](crate::Store).
+#[derive(Clone)]
+pub struct AccountingTunables<T> {
+    inner: T,
+    allocated_bytes: Arc<AtomicU64>,
+}
+
+impl<T: Tunables> AccountingTunables<T> {
+    /// Wraps `inner`, with the count starting at zero
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            allocated_bytes: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the number of bytes allocated for the instances so far
+    pub fn allocated_bytes(&self) -> u64 {
+        self.allocated_bytes.load(Ordering::SeqCst)
+    }
+
+    fn add_allocated_bytes(&self, bytes: usize) {
+        self.allocated_bytes
+            .fetch_add(bytes as u64, Ordering::SeqCst);
+    }
+}
+
+impl<T: Tunables> Tunables for AccountingTunables<T> {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.inner.memory_style(memory)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.inner.table_style(table)
+    }
+
+    fn memory_initialization(&self) -> MemoryInitialization {
+        self.inner.memory_initialization()
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        self.inner.create_host_memory(ty, style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        self.inner
+            .create_vm_memory(ty, style, vm_definition_location)
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, TableError> {
+        self.inner.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, TableError> {
+        self.inner
+            .create_vm_table(ty, style, vm_definition_location)
+    }
+
+    fn max_table_elements(&self, ty: &TableType) -> Option<u32> {
+        self.inner.max_table_elements(ty)
+    }
+
+    fn limit_table_type(&self, ty: &TableType) -> Result<TableType, TableError> {
+        self.inner.limit_table_type(ty)
+    }
+
+    fn on_memory_grow(&self, index: MemoryIndex, prev: Pages, new: Pages) {
+        self.add_allocated_bytes(new.bytes().0 - prev.bytes().0);
+        self.inner.on_memory_grow(index, prev, new)
+    }
+
+    fn create_global(&self, ty: GlobalType) -> Result<VMGlobal, String> {
+        self.inner.create_global(ty)
+    }
+
+    unsafe fn create_memories(
+        &self,
+        context: &mut StoreObjects,
+        module: &ModuleInfo,
+        memory_styles: &PrimaryMap<MemoryIndex, MemoryStyle>,
+        memory_definition_locations: &[NonNull<VMMemoryDefinition>],
+    ) -> Result<PrimaryMap<LocalMemoryIndex, InternalStoreHandle<VMMemory>>, LinkError> {
+        let memories = self.inner.create_memories(
+            context,
+            module,
+            memory_styles,
+            memory_definition_locations,
+        )?;
+        for memory in memories.values() {
+            self.add_allocated_bytes(memory.get(context).size().bytes().0);
+        }
+        Ok(memories)
+    }
+
+    unsafe fn create_tables(
+        &self,
+        context: &mut StoreObjects,
+        module: &ModuleInfo,
+        table_styles: &PrimaryMap<TableIndex, TableStyle>,
+        table_definition_locations: &[NonNull<VMTableDefinition>],
+    ) -> Result<PrimaryMap<LocalTableIndex, InternalStoreHandle<VMTable>>, LinkError> {
+        let tables =
+            self.inner
+                .create_tables(context, module, table_styles, table_definition_locations)?;
+        for table in tables.values() {
+            self.add_allocated_bytes(table.get(context).size() as usize * size_of::<VMFuncRef>());
+        }
+        Ok(tables)
+    }
+
+    fn create_globals(
+        &self,
+        context: &mut StoreObjects,
+        module: &ModuleInfo,
+    ) -> Result<PrimaryMap<LocalGlobalIndex, InternalStoreHandle<VMGlobal>>, LinkError> {
+        let globals = self.inner.create_globals(context, module)?;
+        self.add_allocated_bytes(globals.len() * size_of::<VMGlobalDefinition>());
+        Ok(globals)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sys::Type;
 
     #[test]
     fn memory_style() {
@@ -145,7 +318,7 @@ mod tests {
         };
 
         // No maximum
-        let requested = MemoryType::new(3, None, true);
+        let requested = MemoryType::new(3, None, false);
         let style = tunables.memory_style(&requested);
         match style {
             MemoryStyle::Dynamic { offset_guard_size } => assert_eq!(offset_guard_size, 256),
@@ -153,7 +326,7 @@ mod tests {
         }
 
         // Large maximum
-        let requested = MemoryType::new(3, Some(5_000_000), true);
+        let requested = MemoryType::new(3, Some(5_000_000), false);
         let style = tunables.memory_style(&requested);
         match style {
             MemoryStyle::Dynamic { offset_guard_size } => assert_eq!(offset_guard_size, 256),
@@ -161,7 +334,7 @@ mod tests {
         }
 
         // Small maximum
-        let requested = MemoryType::new(3, Some(16), true);
+        let requested = MemoryType::new(3, Some(16), false);
         let style = tunables.memory_style(&requested);
         match style {
             MemoryStyle::Static {
@@ -174,4 +347,91 @@ mod tests {
             s => panic!("Unexpected memory style: {:?}", s),
         }
     }
+
+    #[test]
+    fn shared_memory_style() {
+        let tunables = BaseTunables::with_bounds(Pages(100), 0x1000);
+
+        // Shared memories are static, even past the static memory bound
+        for (maximum, expected_bound) in [(16, 100), (5_000, 5_000)] {
+            let requested = MemoryType::new(1, Some(maximum), true);
+            let style = tunables.memory_style(&requested);
+            match style {
+                MemoryStyle::Static {
+                    bound,
+                    offset_guard_size,
+                } => {
+                    assert_eq!(bound, Pages(expected_bound));
+                    assert_eq!(offset_guard_size, 0x1000);
+                }
+                s => panic!("Unexpected memory style: {:?}", s),
+            }
+        }
+
+        let requested = MemoryType::new(1, Some(4), true);
+        let style = tunables.memory_style(&requested);
+        let mut memory = tunables.create_host_memory(&requested, &style).unwrap();
+        let base = unsafe { memory.vmmemory().as_ref().base };
+        memory.grow(Pages(3)).unwrap();
+        assert_eq!(unsafe { memory.vmmemory().as_ref().base }, base);
+        assert!(memory.grow(Pages(1)).is_err());
+
+        // Shared memories can't move, nor be unbounded
+        let dynamic = MemoryStyle::Dynamic {
+            offset_guard_size: 0x1000,
+        };
+        assert!(matches!(
+            tunables.create_host_memory(&requested, &dynamic),
+            Err(MemoryError::InvalidMemory { .. })
+        ));
+        let requested = MemoryType::new(1, None, true);
+        let style = tunables.memory_style(&requested);
+        assert!(matches!(
+            tunables.create_host_memory(&requested, &style),
+            Err(MemoryError::InvalidMemory { .. })
+        ));
+    }
+
+    #[test]
+    fn with_bounds() {
+        let tunables = BaseTunables::with_bounds(Pages(100), 0x1000);
+
+        let requested = MemoryType::new(1, Some(100), false);
+        match tunables.memory_style(&requested) {
+            MemoryStyle::Static {
+                bound,
+                offset_guard_size,
+            } => {
+                assert_eq!(bound, Pages(100));
+                assert_eq!(offset_guard_size, 0x1000);
+            }
+            s => panic!("Unexpected memory style: {:?}", s),
+        }
+
+        let requested = MemoryType::new(1, Some(101), false);
+        match tunables.memory_style(&requested) {
+            MemoryStyle::Dynamic { offset_guard_size } => {
+                assert_eq!(offset_guard_size, tunables.dynamic_memory_offset_guard_size)
+            }
+            s => panic!("Unexpected memory style: {:?}", s),
+        }
+    }
+
+    #[test]
+    fn table_errors() {
+        let tunables = BaseTunables::with_bounds(Pages(100), 0x1000);
+
+        let requested = TableType::new(Type::FuncRef, 10, Some(5));
+        let style = tunables.table_style(&requested);
+        assert!(matches!(
+            tunables.create_host_table(&requested, &style),
+            Err(TableError::MinimumExceedsMaximum { ty }) if ty == requested
+        ));
+
+        let requested = TableType::new(Type::I32, 1, None);
+        assert!(matches!(
+            tunables.create_host_table(&requested, &style),
+            Err(TableError::UnsupportedElementType { ty }) if ty == requested
+        ));
+    }
 }
@@ -1,6 +1,8 @@
 #[cfg(feature = "sys")]
 mod sys {
     use anyhow::Result;
+    use std::ptr::NonNull;
+    use std::sync::{Arc, Mutex};
     use wasmer::FunctionEnv;
     use wasmer::*;
 
@@ -73,4 +75,257 @@ mod sys {
 
         Ok(())
     }
+
+    /// Delegates to `base`, except for the hooks a test overrides
+    struct TestTunables<T: Tunables> {
+        base: T,
+        max_table_elements: Option<u32>,
+        memory_initialization: Option<MemoryInitialization>,
+        /// Records every `memory.grow`
+        grows: Option<Arc<Mutex<Vec<(MemoryIndex, Pages, Pages)>>>>,
+    }
+
+    impl<T: Tunables> TestTunables<T> {
+        fn new(base: T) -> Self {
+            Self {
+                base,
+                max_table_elements: None,
+                memory_initialization: None,
+                grows: None,
+            }
+        }
+    }
+
+    impl<T: Tunables> Tunables for TestTunables<T> {
+        fn memory_style(&self, memory: &MemoryType) -> vm::MemoryStyle {
+            self.base.memory_style(memory)
+        }
+
+        fn table_style(&self, table: &TableType) -> vm::TableStyle {
+            self.base.table_style(table)
+        }
+
+        fn memory_initialization(&self) -> MemoryInitialization {
+            self.memory_initialization.unwrap_or_else(|| self.base.memory_initialization())
+        }
+
+        fn create_host_memory(
+            &self,
+            ty: &MemoryType,
+            style: &vm::MemoryStyle,
+        ) -> Result<vm::VMMemory, MemoryError> {
+            self.base.create_host_memory(ty, style)
+        }
+
+        unsafe fn create_vm_memory(
+            &self,
+            ty: &MemoryType,
+            style: &vm::MemoryStyle,
+            vm_definition_location: NonNull<vm::VMMemoryDefinition>,
+        ) -> Result<vm::VMMemory, MemoryError> {
+            self.base
+                .create_vm_memory(ty, style, vm_definition_location)
+        }
+
+        fn create_host_table(
+            &self,
+            ty: &TableType,
+            style: &vm::TableStyle,
+        ) -> Result<vm::VMTable, vm::TableError> {
+            self.base.create_host_table(ty, style)
+        }
+
+        unsafe fn create_vm_table(
+            &self,
+            ty: &TableType,
+            style: &vm::TableStyle,
+            vm_definition_location: NonNull<vm::VMTableDefinition>,
+        ) -> Result<vm::VMTable, vm::TableError> {
+            self.base.create_vm_table(ty, style, vm_definition_location)
+        }
+
+        fn max_table_elements(&self, ty: &TableType) -> Option<u32> {
+            self.max_table_elements.or_else(|| self.base.max_table_elements(ty))
+        }
+
+        fn on_memory_grow(&self, index: MemoryIndex, prev: Pages, new: Pages) {
+            if let Some(grows) = &self.grows {
+                grows.lock().unwrap().push((index, prev, new));
+            }
+            self.base.on_memory_grow(index, prev, new);
+        }
+    }
+
+    #[test]
+    fn max_table_elements() -> Result<()> {
+        let engine = Store::default().as_store_ref().engine().clone();
+        // Caps every table to 1024 elements
+        let tunables = TestTunables {
+            max_table_elements: Some(1024),
+            ..TestTunables::new(BaseTunables::for_target(engine.target()))
+        };
+        let ty = TableType::new(Type::FuncRef, 10000, None);
+        assert_eq!(
+            tunables.limit_table_type(&ty),
+            Err(vm::TableError::MinimumTooLarge {
+                ty,
+                max_allowed: 1024
+            })
+        );
+        let mut store = Store::new_with_tunables(&*engine, tunables);
+
+        let module = Module::new(&store, "(module (table 10000 funcref))")?;
+        let result = Instance::new(&mut store, &module, &imports! {});
+        assert!(matches!(result, Err(InstantiationError::Link(_))));
+
+        // Tables under the cap can't grow past it
+        let module = Module::new(
+            &store,
+            r#"
+    (module
+      (table $table (export "table") 1000 funcref)
+      (func (export "grow") (param $delta i32) (result i32)
+        (table.grow $table (ref.null func) (local.get $delta))))
+"#,
+        )?;
+        let instance = Instance::new(&mut store, &module, &imports! {})?;
+        let grow: TypedFunction<i32, i32> =
+            instance.exports.get_typed_function(&mut store, "grow")?;
+        assert_eq!(grow.call(&mut store, 100)?, -1);
+        assert_eq!(grow.call(&mut store, 24)?, 1000);
+        assert_eq!(instance.exports.get_table("table")?.size(&mut store), 1024);
+
+        let ty = TableType::new(Type::FuncRef, 2000, None);
+        assert!(Table::new(&mut store, ty, Value::FuncRef(None)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn on_memory_grow() -> Result<()> {
+        let engine = Store::default().as_store_ref().engine().clone();
+        let grows = Arc::new(Mutex::new(Vec::new()));
+        let tunables = TestTunables {
+            grows: Some(grows.clone()),
+            ..TestTunables::new(BaseTunables::for_target(engine.target()))
+        };
+        let mut store = Store::new_with_tunables(&*engine, tunables);
+
+        let module = Module::new(
+            &store,
+            r#"
+    (module
+      (memory $memory (export "memory") 1 10)
+      (func (export "grow") (param $delta i32) (result i32)
+        (memory.grow (local.get $delta))))
+"#,
+        )?;
+        let instance = Instance::new(&mut store, &module, &imports! {})?;
+        let grow: TypedFunction<i32, i32> =
+            instance.exports.get_typed_function(&mut store, "grow")?;
+        assert_eq!(grow.call(&mut store, 2)?, 1);
+        // Failed grows are not reported
+        assert_eq!(grow.call(&mut store, 100)?, -1);
+        assert_eq!(grow.call(&mut store, 1)?, 3);
+
+        // Neither are the grows made by the host
+        let memory = instance.exports.get_memory("memory")?;
+        memory.grow(&mut store, 1)?;
+
+        assert_eq!(
+            *grows.lock().unwrap(),
+            [
+                (MemoryIndex::from_u32(0), Pages(1), Pages(3)),
+                (MemoryIndex::from_u32(0), Pages(3), Pages(4)),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lazy_memory_initialization() -> Result<()> {
+        assert_eq!(
+            BaseTunables::for_target(&Target::default()).memory_initialization(),
+            MemoryInitialization::Eager
+        );
+
+        let engine = Store::default().as_store_ref().engine().clone();
+        // Leaves the pages of the memories to be zero-filled by the OS
+        let tunables = TestTunables {
+            memory_initialization: Some(MemoryInitialization::Lazy),
+            ..TestTunables::new(BaseTunables::for_target(engine.target()))
+        };
+        let mut store = Store::new_with_tunables(&*engine, tunables);
+
+        let module = Module::new(
+            &store,
+            r#"
+    (module
+      (memory $memory (export "memory") 2)
+      (data (i32.const 65536) "data"))
+"#,
+        )?;
+        let instance = Instance::new(&mut store, &module, &imports! {})?;
+        let memory = instance.exports.get_memory("memory")?;
+
+        // The data segments are still written, the rest of the memory is zero
+        let mut contents = vec![1; 2 * 65536];
+        memory.read(&store, 0, &mut contents)?;
+        assert_eq!(&contents[65536..65540], b"data");
+        assert!(contents[..65536].iter().all(|&byte| byte == 0));
+        assert!(contents[65540..].iter().all(|&byte| byte == 0));
+
+        // So are the memories created by the host
+        let memory = Memory::new(&mut store, MemoryType::new(1, None, false))?;
+        let mut contents = vec![1; 65536];
+        memory.read(&store, 0, &mut contents)?;
+        assert!(contents.iter().all(|&byte| byte == 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn accounting_tunables() -> Result<()> {
+        let engine = Store::default().as_store_ref().engine().clone();
+        let tunables = AccountingTunables::new(BaseTunables::for_target(engine.target()));
+        let mut store = Store::new_with_tunables(&*engine, tunables.clone());
+
+        let module = Module::new(
+            &store,
+            r#"
+    (module
+      (memory $memory (export "memory") 2 10)
+      (table $table 3 funcref)
+      (global $counter (mut i32) (i32.const 0))
+      (global $limit i64 (i64.const 10))
+      (func (export "grow") (param $pages i32) (result i32)
+        (memory.grow (local.get $pages))))
+"#,
+        )?;
+        assert_eq!(tunables.allocated_bytes(), 0);
+
+        // 2 pages, 3 references and 2 global definitions of 16 bytes
+        let per_instance = 2 * 65536 + 3 * std::mem::size_of::<usize>() as u64 + 2 * 16;
+        let instance = Instance::new(&mut store, &module, &imports! {})?;
+        assert_eq!(tunables.allocated_bytes(), per_instance);
+        Instance::new(&mut store, &module, &imports! {})?;
+        assert_eq!(tunables.allocated_bytes(), 2 * per_instance);
+
+        // Memories created or grown by the host are not counted
+        let memory = instance.exports.get_memory("memory")?;
+        memory.grow(&mut store, 1)?;
+        Memory::new(&mut store, MemoryType::new(1, None, false))?;
+        assert_eq!(tunables.allocated_bytes(), 2 * per_instance);
+
+        // Memories grown by the instances are
+        let grow: TypedFunction<i32, i32> =
+            instance.exports.get_typed_function(&mut store, "grow")?;
+        assert_eq!(grow.call(&mut store, 2)?, 3);
+        assert_eq!(tunables.allocated_bytes(), 2 * per_instance + 2 * 65536);
+        assert_eq!(grow.call(&mut store, 100)?, -1);
+        assert_eq!(tunables.allocated_bytes(), 2 * per_instance + 2 * 65536);
+
+        Ok(())
+    }
 }
@@ -254,6 +254,8 @@ impl<'data> ModuleEnvironment<'data> {
     }
 
     pub(crate) fn declare_memory(&mut self, memory: MemoryType) -> WasmResult<()> {
+        // the host can create shared memories, but the atomic instructions
+        // guests need to use their own ones are not implemented yet
         if memory.shared {
             return Err(WasmError::Unsupported(
                 "shared memories are not supported yet".to_owned(),
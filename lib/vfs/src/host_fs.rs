@@ -92,27 +92,90 @@ impl crate::FileSystem for FileSystem {
         fs::create_dir(path).map_err(Into::into)
     }
 
+    #[cfg(unix)]
+    fn create_dir_with_mode(&self, path: &Path, mode: u32) -> Result<()> {
+        use std::os::unix::fs::DirBuilderExt;
+
+        fs::DirBuilder::new()
+            .mode(mode)
+            .create(path)
+            .map_err(Into::into)
+    }
+
     fn remove_dir(&self, path: &Path) -> Result<()> {
-        fs::remove_dir(path).map_err(Into::into)
+        fs::remove_dir(path).map_err(|err| {
+            // POSIX allows `rmdir` to fail with `EEXIST` for a non-empty directory
+            #[cfg(unix)]
+            match err.raw_os_error() {
+                Some(libc::ENOTEMPTY) | Some(libc::EEXIST) => return FsError::DirectoryNotEmpty,
+                Some(libc::ENOTDIR) => return FsError::BaseNotDirectory,
+                _ => (),
+            }
+            err.into()
+        })
     }
 
     fn rename(&self, from: &Path, to: &Path) -> Result<()> {
-        fs::rename(from, to).map_err(Into::into)
+        fs::rename(from, to).map_err(|err| {
+            #[cfg(unix)]
+            if err.raw_os_error() == Some(libc::EXDEV) {
+                return FsError::CrossDevice;
+            }
+            err.into()
+        })
     }
 
     fn remove_file(&self, path: &Path) -> Result<()> {
         fs::remove_file(path).map_err(Into::into)
     }
 
+    #[cfg(unix)]
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<u64>,
+        modified: Option<u64>,
+        follow_symlinks: bool,
+    ) -> Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| FsError::InvalidInput)?;
+        let times = host_timespecs(accessed, modified);
+        let flags = if follow_symlinks {
+            0
+        } else {
+            libc::AT_SYMLINK_NOFOLLOW
+        };
+        let result =
+            unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), flags) };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error().into())
+        }
+    }
+
     fn new_open_options(&self) -> OpenOptions {
         OpenOptions::new(Box::new(FileOpener))
     }
 
+    fn try_clone(&self) -> Option<Box<dyn crate::FileSystem>> {
+        Some(Box::new(self.clone()))
+    }
+
     fn metadata(&self, path: &Path) -> Result<Metadata> {
         fs::metadata(path)
             .and_then(TryInto::try_into)
             .map_err(Into::into)
     }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        fs::symlink_metadata(path)
+            .and_then(TryInto::try_into)
+            .map_err(Into::into)
+    }
 }
 
 impl TryInto<Metadata> for fs::Metadata {
@@ -169,6 +232,17 @@ impl TryInto<Metadata> for fs::Metadata {
                 })
                 .map_or(0, |time| time.as_nanos() as u64),
             len: self.len(),
+            mode: {
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    self.permissions().mode() & 0o7777
+                }
+                #[cfg(not(unix))]
+                {
+                    0
+                }
+            },
         })
     }
 }
@@ -187,6 +261,13 @@ impl crate::FileOpener for FileOpener {
         let write = conf.write();
         let append = conf.append();
         let mut oo = fs::OpenOptions::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            if let Some(mode) = conf.mode() {
+                oo.mode(mode);
+            }
+        }
         oo.read(conf.read())
             .write(conf.write())
             .create_new(conf.create_new())
@@ -380,8 +461,9 @@ impl Write for File {
 #[cfg_attr(feature = "enable-serde", typetag::serde)]
 impl VirtualFile for File {
     fn last_accessed(&self) -> u64 {
-        self.metadata()
-            .accessed()
+        self.inner
+            .metadata()
+            .and_then(|m| m.accessed())
             .ok()
             .and_then(|ct| ct.duration_since(SystemTime::UNIX_EPOCH).ok())
             .map(|ct| ct.as_nanos() as u64)
@@ -389,8 +471,9 @@ impl VirtualFile for File {
     }
 
     fn last_modified(&self) -> u64 {
-        self.metadata()
-            .modified()
+        self.inner
+            .metadata()
+            .and_then(|m| m.modified())
             .ok()
             .and_then(|ct| ct.duration_since(SystemTime::UNIX_EPOCH).ok())
             .map(|ct| ct.as_nanos() as u64)
@@ -398,16 +481,33 @@ impl VirtualFile for File {
     }
 
     fn created_time(&self) -> u64 {
-        self.metadata()
-            .created()
+        self.inner
+            .metadata()
+            .and_then(|m| m.created())
             .ok()
             .and_then(|ct| ct.duration_since(SystemTime::UNIX_EPOCH).ok())
             .map(|ct| ct.as_nanos() as u64)
             .unwrap_or(0)
     }
 
+    #[cfg(unix)]
+    fn set_last_accessed(&mut self, last_accessed: u64) -> Result<()> {
+        host_file_set_times(&self.inner, Some(last_accessed), None)
+    }
+
+    #[cfg(unix)]
+    fn set_last_modified(&mut self, last_modified: u64) -> Result<()> {
+        host_file_set_times(&self.inner, None, Some(last_modified))
+    }
+
     fn size(&self) -> u64 {
-        self.metadata().len()
+        // The metadata can't be read anymore on some platforms once the
+        // file was removed from the host
+        self.inner.metadata().map(|m| m.len()).unwrap_or(0)
+    }
+
+    fn current_len(&self) -> Result<u64> {
+        Ok(self.inner.metadata()?.len())
     }
 
     fn set_len(&mut self, new_size: u64) -> Result<()> {
@@ -421,9 +521,35 @@ impl VirtualFile for File {
         self.inner.sync_all().map_err(Into::into)
     }
 
+    fn sync_data_to_disk(&self) -> Result<()> {
+        self.inner.sync_data().map_err(Into::into)
+    }
+
     fn bytes_available(&self) -> Result<usize> {
         host_file_bytes_available(self.inner.try_into_filedescriptor()?)
     }
+
+    fn get_fd(&self) -> Option<FileDescriptor> {
+        self.inner.try_into_filedescriptor().ok()
+    }
+
+    #[cfg(unix)]
+    fn io_block_size(&self) -> Option<u32> {
+        use std::os::unix::fs::MetadataExt;
+
+        let blksize = self.inner.metadata().ok()?.blksize();
+        blksize.try_into().ok()
+    }
+
+    /// The new handle shares the OS file, including its cursor
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        Some(Box::new(File {
+            inner: self.inner.try_clone().ok()?,
+            host_path: self.host_path.clone(),
+            #[cfg(feature = "enable-serde")]
+            flags: self.flags,
+        }))
+    }
 }
 
 #[cfg(unix)]
@@ -441,6 +567,41 @@ fn host_file_bytes_available(host_fd: FileDescriptor) -> Result<usize> {
     }
 }
 
+/// The `[accessed, modified]` times in the form `futimens` and `utimensat`
+/// take them, the times that are `None` are left unchanged
+#[cfg(unix)]
+fn host_timespecs(accessed: Option<u64>, modified: Option<u64>) -> [libc::timespec; 2] {
+    let to_timespec = |time: Option<u64>| match time {
+        Some(nanos) => libc::timespec {
+            tv_sec: (nanos / 1_000_000_000) as _,
+            tv_nsec: (nanos % 1_000_000_000) as _,
+        },
+        None => libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+    };
+    [to_timespec(accessed), to_timespec(modified)]
+}
+
+/// Sets the access and/or modification times of a host file, the times
+/// that are `None` are left unchanged
+#[cfg(unix)]
+fn host_file_set_times(
+    file: &fs::File,
+    accessed: Option<u64>,
+    modified: Option<u64>,
+) -> Result<()> {
+    let times = host_timespecs(accessed, modified);
+    let result = unsafe { libc::futimens(file.as_raw_fd(), times.as_ptr()) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error().into())
+    }
+}
+
 #[cfg(not(unix))]
 fn host_file_bytes_available(_host_fd: FileDescriptor) -> Result<usize> {
     unimplemented!("host_file_bytes_available not yet implemented for non-Unix-like targets.  This probably means the program tried to use wasi::poll_oneoff")
@@ -540,6 +701,14 @@ impl VirtualFile for Stdout {
     fn get_fd(&self) -> Option<FileDescriptor> {
         io::stdout().try_into_filedescriptor().ok()
     }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        Some(Box::new(Stdout))
+    }
 }
 
 /// A wrapper type around Stderr that implements `VirtualFile` and
@@ -636,6 +805,14 @@ impl VirtualFile for Stderr {
     fn get_fd(&self) -> Option<FileDescriptor> {
         io::stderr().try_into_filedescriptor().ok()
     }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        Some(Box::new(Stderr))
+    }
 }
 
 /// A wrapper type around Stdin that implements `VirtualFile` and
@@ -728,7 +905,35 @@ impl VirtualFile for Stdin {
         host_file_bytes_available(io::stdin().try_into_filedescriptor()?)
     }
 
+    /// Returns `Some(0)` when no input is ready, and `None` when stdin is
+    /// ready but the number of bytes is unknown (e.g. at the end of the
+    /// input)
+    #[cfg(unix)]
+    fn bytes_available_read(&self) -> Result<Option<usize>> {
+        let mut fds = [libc::pollfd {
+            fd: io::stdin().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        match unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) } {
+            result if result < 0 => Err(io::Error::last_os_error().into()),
+            0 => Ok(Some(0)),
+            _ => match host_file_bytes_available(io::stdin().try_into_filedescriptor()?)? {
+                0 => Ok(None),
+                bytes => Ok(Some(bytes)),
+            },
+        }
+    }
+
     fn get_fd(&self) -> Option<FileDescriptor> {
         io::stdin().try_into_filedescriptor().ok()
     }
+
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        Some(Box::new(Stdin))
+    }
 }
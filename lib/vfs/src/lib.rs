@@ -37,18 +37,45 @@ impl From<FileDescriptor> for u32 {
 pub trait FileSystem: fmt::Debug + Send + Sync + 'static + Upcastable {
     fn read_dir(&self, path: &Path) -> Result<ReadDir>;
     fn create_dir(&self, path: &Path) -> Result<()>;
+    /// Creates a directory with the Unix permission bits `mode`, file
+    /// systems that don't track permissions ignore them.
+    /// Defaults to [`FileSystem::create_dir`].
+    fn create_dir_with_mode(&self, path: &Path, _mode: u32) -> Result<()> {
+        self.create_dir(path)
+    }
     fn remove_dir(&self, path: &Path) -> Result<()>;
     fn rename(&self, from: &Path, to: &Path) -> Result<()>;
     fn metadata(&self, path: &Path) -> Result<Metadata>;
     /// This method gets metadata without following symlinks in the path.
-    /// Currently identical to `metadata` because symlinks aren't implemented
-    /// yet.
+    /// Defaults to [`FileSystem::metadata`], for file systems without
+    /// symlinks.
     fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
         self.metadata(path)
     }
     fn remove_file(&self, path: &Path) -> Result<()>;
 
+    /// Sets the access and/or modification times of the entry at `path`, in
+    /// nanoseconds as UNIX timestamps, the times that are `None` are left
+    /// unchanged. When `follow_symlinks` is `false` and `path` is a symlink,
+    /// the times of the symlink itself are set.
+    /// Defaults to [`FsError::Unsupported`].
+    fn set_times(
+        &self,
+        _path: &Path,
+        _accessed: Option<u64>,
+        _modified: Option<u64>,
+        _follow_symlinks: bool,
+    ) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
     fn new_open_options(&self) -> OpenOptions;
+
+    /// Returns a new handle to the same file system, which shares its content.
+    /// Defaults to `None` which means the file system can't be shared
+    fn try_clone(&self) -> Option<Box<dyn FileSystem>> {
+        None
+    }
 }
 
 impl dyn FileSystem + 'static {
@@ -78,6 +105,7 @@ pub struct OpenOptionsConfig {
     create: bool,
     append: bool,
     truncate: bool,
+    mode: Option<u32>,
 }
 
 impl OpenOptionsConfig {
@@ -104,6 +132,10 @@ impl OpenOptionsConfig {
     pub const fn truncate(&self) -> bool {
         self.truncate
     }
+
+    pub const fn mode(&self) -> Option<u32> {
+        self.mode
+    }
 }
 
 // TODO: manually implement debug
@@ -124,6 +156,7 @@ impl OpenOptions {
                 create: false,
                 append: false,
                 truncate: false,
+                mode: None,
             },
         }
     }
@@ -162,6 +195,13 @@ impl OpenOptions {
         self
     }
 
+    /// Sets the Unix permission bits of the file if it is created, file
+    /// systems that don't track permissions ignore them.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.conf.mode = Some(mode);
+        self
+    }
+
     pub fn open<P: AsRef<Path>>(
         &mut self,
         path: P,
@@ -182,9 +222,30 @@ pub trait VirtualFile: fmt::Debug + Write + Read + Seek + Upcastable {
     /// the time at which the file was created in nanoseconds as a UNIX timestamp
     fn created_time(&self) -> u64;
 
+    /// Set the last time the file was accessed, in nanoseconds as a UNIX timestamp
+    /// Default implementation returns `Ok(())`.  You should implement this method if
+    /// the file keeps track of its access time
+    fn set_last_accessed(&mut self, _last_accessed: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Set the last time the file was modified, in nanoseconds as a UNIX timestamp
+    /// Default implementation returns `Ok(())`.  You should implement this method if
+    /// the file keeps track of its modification time
+    fn set_last_modified(&mut self, _last_modified: u64) -> Result<()> {
+        Ok(())
+    }
+
     /// the size of the file in bytes
     fn size(&self) -> u64;
 
+    /// The authoritative size of the file in bytes, read without moving the
+    /// cursor. Defaults to [`VirtualFile::size`], files whose content can
+    /// change concurrently should query their storage on every call
+    fn current_len(&self) -> Result<u64> {
+        Ok(self.size())
+    }
+
     /// Change the size of the file, if the `new_size` is greater than the current size
     /// the extra bytes will be allocated and zeroed
     fn set_len(&mut self, new_size: u64) -> Result<()>;
@@ -199,6 +260,13 @@ pub trait VirtualFile: fmt::Debug + Write + Read + Seek + Upcastable {
         Ok(())
     }
 
+    /// Store file contents to disk, along with only the metadata needed to
+    /// read them back, which is cheaper than [`VirtualFile::sync_to_disk`]
+    /// Default implementation calls [`VirtualFile::sync_to_disk`].
+    fn sync_data_to_disk(&self) -> Result<()> {
+        self.sync_to_disk()
+    }
+
     /// Returns the number of bytes available.  This function must not block
     fn bytes_available(&self) -> Result<usize> {
         Ok(self.bytes_available_read()?.unwrap_or(0usize)
@@ -228,6 +296,37 @@ pub trait VirtualFile: fmt::Debug + Write + Read + Seek + Upcastable {
     fn get_fd(&self) -> Option<FileDescriptor> {
         None
     }
+
+    /// Returns the SHA-256 digest of the whole content of the file, without
+    /// moving the cursor.
+    /// Defaults to `None` which means the file can't cheaply hash its
+    /// content, e.g. because it isn't held in memory
+    fn content_hash(&self) -> Option<[u8; 32]> {
+        None
+    }
+
+    /// Returns the block size preferred for I/O on the file, in bytes, like
+    /// `st_blksize` on Unix.
+    /// Defaults to `None` which means the file has no preference, e.g.
+    /// because it isn't backed by a host file
+    fn io_block_size(&self) -> Option<u32> {
+        None
+    }
+
+    /// Indicates if the file has a cursor that can be moved, so that it can
+    /// be read from and written to at a given offset.
+    /// Defaults to `true`, streams like pipes and the standard I/O streams
+    /// return `false`
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    /// Returns a new handle to the same underlying file, writes through one
+    /// handle are visible through the other.
+    /// Defaults to `None` which means the file can't be duplicated
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        None
+    }
 }
 
 // Implementation of `Upcastable` taken from https://users.rust-lang.org/t/why-does-downcasting-not-work-for-subtraits/33286/7 .
@@ -342,9 +441,37 @@ pub enum FsError {
     /// Directory not Empty
     #[error("directory not empty")]
     DirectoryNotEmpty,
+    /// The operation would move an entry to a different file system
+    #[error("cross-device link")]
+    CrossDevice,
+    /// The operation is not implemented by the file system or on this platform
+    #[error("operation not supported")]
+    Unsupported,
+    /// The file system is read-only
+    #[error("read-only file system")]
+    ReadOnly,
     /// Some other unhandled error. If you see this, it's probably a bug.
     #[error("unknown error found")]
     UnknownError,
+    /// An error of the host OS that none of the other variants describe,
+    /// with its raw error code, see [`FsError::os_error`]
+    #[error("os error {0}")]
+    OsError(i32),
+}
+
+impl FsError {
+    /// Returns the raw error code of the host OS when the error is an
+    /// [`FsError::OsError`].
+    ///
+    /// Only the OS errors that don't map to one of the other variants keep
+    /// their code when converted from an [`io::Error`], e.g. a missing file
+    /// always becomes [`FsError::EntityNotFound`].
+    pub fn os_error(&self) -> Option<i32> {
+        match self {
+            FsError::OsError(code) => Some(*code),
+            _ => None,
+        }
+    }
 }
 
 impl From<io::Error> for FsError {
@@ -367,9 +494,12 @@ impl From<io::Error> for FsError {
             io::ErrorKind::UnexpectedEof => FsError::UnexpectedEof,
             io::ErrorKind::WouldBlock => FsError::WouldBlock,
             io::ErrorKind::WriteZero => FsError::WriteZero,
-            io::ErrorKind::Other => FsError::IOError,
-            // if the following triggers, a new error type was added to this non-exhaustive enum
-            _ => FsError::UnknownError,
+            _ => match io_error.raw_os_error() {
+                Some(code) => FsError::OsError(code),
+                None if io_error.kind() == io::ErrorKind::Other => FsError::IOError,
+                // if the following triggers, a new error type was added to this non-exhaustive enum
+                None => FsError::UnknownError,
+            },
         }
     }
 }
@@ -425,6 +555,7 @@ pub struct Metadata {
     pub created: u64,
     pub modified: u64,
     pub len: u64,
+    mode: u32,
 }
 
 impl Metadata {
@@ -448,6 +579,11 @@ impl Metadata {
         self.modified
     }
 
+    /// The Unix permission bits, `0` when the file system doesn't track them
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
     pub fn file_type(&self) -> FileType {
         self.ft.clone()
     }
@@ -506,3 +642,25 @@ impl Iterator for ReadDir {
         None
     }
 }
+
+#[cfg(test)]
+mod test_fs_error {
+    use super::FsError;
+    use std::io;
+
+    #[test]
+    fn test_os_error() {
+        // an error code that no OS defines, so it has no `io::ErrorKind` of its own
+        let error = FsError::from(io::Error::from_raw_os_error(123_456));
+        assert_eq!(error, FsError::OsError(123_456));
+        assert_eq!(error.os_error(), Some(123_456));
+
+        let error = FsError::from(io::Error::new(io::ErrorKind::Other, "other"));
+        assert_eq!(error, FsError::IOError);
+        assert_eq!(error.os_error(), None);
+
+        let error = FsError::from(io::Error::new(io::ErrorKind::NotFound, "not found"));
+        assert_eq!(error, FsError::EntityNotFound);
+        assert_eq!(error.os_error(), None);
+    }
+}
@@ -4,6 +4,7 @@
 
 use super::*;
 use crate::{FileDescriptor, FsError, Result, VirtualFile};
+use sha2::{Digest, Sha256};
 use std::cmp;
 use std::convert::TryInto;
 use std::fmt;
@@ -42,6 +43,14 @@ impl FileHandle {
             append_mode,
         }
     }
+
+    /// A handle of the same inode in `filesystem`, with the same flags
+    pub(super) fn reopen(&self, filesystem: FileSystem) -> Self {
+        Self {
+            filesystem,
+            ..self.clone()
+        }
+    }
 }
 
 impl VirtualFile for FileHandle {
@@ -87,6 +96,38 @@ impl VirtualFile for FileHandle {
         node.metadata().created
     }
 
+    fn set_last_accessed(&mut self, last_accessed: u64) -> Result<()> {
+        let mut fs = self
+            .filesystem
+            .inner
+            .try_write()
+            .map_err(|_| FsError::Lock)?;
+
+        let node = fs
+            .storage
+            .get_mut(self.inode)
+            .ok_or(FsError::UnknownError)?;
+        node.metadata_mut().accessed = last_accessed;
+
+        Ok(())
+    }
+
+    fn set_last_modified(&mut self, last_modified: u64) -> Result<()> {
+        let mut fs = self
+            .filesystem
+            .inner
+            .try_write()
+            .map_err(|_| FsError::Lock)?;
+
+        let node = fs
+            .storage
+            .get_mut(self.inode)
+            .ok_or(FsError::UnknownError)?;
+        node.metadata_mut().modified = last_modified;
+
+        Ok(())
+    }
+
     fn size(&self) -> u64 {
         let fs = match self.filesystem.inner.try_read() {
             Ok(fs) => fs,
@@ -99,6 +140,16 @@ impl VirtualFile for FileHandle {
         }
     }
 
+    fn current_len(&self) -> Result<u64> {
+        // Unlike `size`, wait for concurrent writers instead of reporting 0
+        let fs = self.filesystem.inner.read().map_err(|_| FsError::Lock)?;
+
+        match fs.storage.get(self.inode) {
+            Some(Node::File { file, .. }) => Ok(file.len().try_into().unwrap_or(u64::MAX)),
+            _ => Err(FsError::NotAFile),
+        }
+    }
+
     fn set_len(&mut self, new_size: u64) -> Result<()> {
         let mut fs = self
             .filesystem
@@ -106,11 +157,13 @@ impl VirtualFile for FileHandle {
             .try_write()
             .map_err(|_| FsError::Lock)?;
 
+        let time = fs.time();
         match fs.storage.get_mut(self.inode) {
             Some(Node::File { file, metadata, .. }) => {
                 file.buffer
                     .resize(new_size.try_into().map_err(|_| FsError::UnknownError)?, 0);
                 metadata.len = new_size;
+                metadata.modified = time;
             }
             _ => return Err(FsError::NotAFile),
         }
@@ -187,11 +240,26 @@ impl VirtualFile for FileHandle {
     fn get_fd(&self) -> Option<FileDescriptor> {
         Some(FileDescriptor(self.inode))
     }
+
+    /// The digest is computed over the current content on every call
+    fn content_hash(&self) -> Option<[u8; 32]> {
+        let fs = self.filesystem.inner.try_read().ok()?;
+
+        match fs.storage.get(self.inode) {
+            Some(Node::File { file, .. }) => Some(Sha256::digest(&file.buffer).into()),
+            _ => None,
+        }
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        Some(Box::new(self.clone()))
+    }
 }
 
 #[cfg(test)]
 mod test_virtual_file {
     use crate::{mem_fs::*, FileDescriptor, FileSystem as FS};
+    use std::io::{self, Read, Seek, Write};
     use std::thread::sleep;
     use std::time::Duration;
 
@@ -244,6 +312,53 @@ mod test_virtual_file {
         assert!(file.last_modified() > 0, "last modified time is not zero");
     }
 
+    #[test]
+    fn test_io_updates_times() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        let fs = FileSystem::default();
+        let now = Arc::new(AtomicU64::new(10));
+        fs.set_clock({
+            let now = now.clone();
+            move || now.load(Ordering::SeqCst)
+        });
+
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+        assert_eq!(file.created_time(), 10, "created at the time of the clock");
+
+        now.store(20, Ordering::SeqCst);
+        let mut buf = [0; 4];
+        assert!(matches!(file.read(&mut buf), Ok(0)));
+        assert_eq!(file.last_accessed(), 20, "reading updates the access time");
+        assert_eq!(file.last_modified(), 10, "reading doesn't modify the file");
+
+        now.store(30, Ordering::SeqCst);
+        assert!(matches!(file.write(b"foo"), Ok(3)));
+        assert_eq!(
+            file.last_modified(),
+            30,
+            "writing updates the modified time"
+        );
+        assert_eq!(file.last_accessed(), 20, "writing doesn't access the file");
+
+        now.store(40, Ordering::SeqCst);
+        file.set_len(16).unwrap();
+        assert_eq!(
+            file.last_modified(),
+            40,
+            "resizing updates the modified time"
+        );
+
+        assert_eq!(file.created_time(), 10, "created time stays constant");
+    }
+
     #[test]
     fn test_created_time() {
         let fs = FileSystem::default();
@@ -397,6 +512,68 @@ mod test_virtual_file {
             "reading the file descriptor",
         );
     }
+
+    #[test]
+    fn test_content_hash() {
+        let fs = FileSystem::default();
+
+        let mut file = fs
+            .new_open_options()
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+
+        let hex = |digest: [u8; 32]| {
+            digest
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        };
+
+        assert_eq!(
+            file.content_hash().map(hex).as_deref(),
+            Some("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            "hashing an empty file",
+        );
+
+        file.write_all(b"hello world").unwrap();
+        assert_eq!(
+            file.content_hash().map(hex).as_deref(),
+            Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"),
+            "hashing the written content",
+        );
+
+        file.set_len(0).unwrap();
+        file.seek(io::SeekFrom::Start(0)).unwrap();
+        file.write_all(&[b'a'; 1000]).unwrap();
+        assert_eq!(
+            file.content_hash().map(hex).as_deref(),
+            Some("41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"),
+            "hashing content spanning several blocks",
+        );
+    }
+
+    #[test]
+    fn test_try_clone() {
+        let fs = FileSystem::default();
+
+        let mut file = fs
+            .new_open_options()
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .expect("failed to create a new file");
+        let clone = file.try_clone().expect("failed to clone the file");
+
+        file.write_all(b"hello").unwrap();
+        assert_eq!(clone.size(), 5, "the clone sees the writes of the original");
+        assert_eq!(
+            clone.content_hash(),
+            file.content_hash(),
+            "both handles share the content",
+        );
+    }
 }
 
 impl Read for FileHandle {
@@ -416,8 +593,9 @@ impl Read for FileHandle {
                 io::Error::new(io::ErrorKind::Other, "failed to acquire a write lock")
             })?;
 
-        let file = match fs.storage.get_mut(self.inode) {
-            Some(Node::File { file, .. }) => file,
+        let time = fs.time();
+        let (file, metadata) = match fs.storage.get_mut(self.inode) {
+            Some(Node::File { file, metadata, .. }) => (file, metadata),
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -426,7 +604,10 @@ impl Read for FileHandle {
             }
         };
 
-        file.read(buf)
+        let bytes_read = file.read(buf)?;
+        metadata.accessed = time;
+
+        Ok(bytes_read)
     }
 
     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
@@ -445,8 +626,9 @@ impl Read for FileHandle {
                 io::Error::new(io::ErrorKind::Other, "failed to acquire a write lock")
             })?;
 
-        let file = match fs.storage.get_mut(self.inode) {
-            Some(Node::File { file, .. }) => file,
+        let time = fs.time();
+        let (file, metadata) = match fs.storage.get_mut(self.inode) {
+            Some(Node::File { file, metadata, .. }) => (file, metadata),
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -455,7 +637,10 @@ impl Read for FileHandle {
             }
         };
 
-        file.read_to_end(buf)
+        let bytes_read = file.read_to_end(buf)?;
+        metadata.accessed = time;
+
+        Ok(bytes_read)
     }
 
     fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
@@ -493,8 +678,9 @@ impl Read for FileHandle {
                 io::Error::new(io::ErrorKind::Other, "failed to acquire a write lock")
             })?;
 
-        let file = match fs.storage.get_mut(self.inode) {
-            Some(Node::File { file, .. }) => file,
+        let time = fs.time();
+        let (file, metadata) = match fs.storage.get_mut(self.inode) {
+            Some(Node::File { file, metadata, .. }) => (file, metadata),
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -503,7 +689,10 @@ impl Read for FileHandle {
             }
         };
 
-        file.read_exact(buf)
+        file.read_exact(buf)?;
+        metadata.accessed = time;
+
+        Ok(())
     }
 }
 
@@ -563,6 +752,7 @@ impl Write for FileHandle {
                 io::Error::new(io::ErrorKind::Other, "failed to acquire a write lock")
             })?;
 
+        let time = fs.time();
         let (file, metadata) = match fs.storage.get_mut(self.inode) {
             Some(Node::File { file, metadata, .. }) => (file, metadata),
             _ => {
@@ -576,6 +766,7 @@ impl Write for FileHandle {
         let bytes_written = file.write(buf)?;
 
         metadata.len = file.len().try_into().unwrap();
+        metadata.modified = time;
 
         Ok(bytes_written)
     }
@@ -843,7 +1034,7 @@ impl fmt::Debug for FileHandle {
 
 /// The real file! It is simply a buffer of bytes with a cursor that
 /// represents a read/write position in the buffer.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(super) struct File {
     buffer: Vec<u8>,
     cursor: usize,
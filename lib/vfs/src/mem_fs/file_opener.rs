@@ -81,10 +81,11 @@ impl crate::FileOpener for FileOpener {
                     .try_write()
                     .map_err(|_| FsError::Lock)?;
 
+                let time = fs.time();
                 match fs.storage.get_mut(inode_of_file) {
                     Some(Node::File { metadata, file, .. }) => {
                         // Update the accessed time.
-                        metadata.accessed = time();
+                        metadata.accessed = time;
 
                         // Truncate if needed.
                         if truncate {
@@ -119,39 +120,50 @@ impl crate::FileOpener for FileOpener {
                     .try_write()
                     .map_err(|_| FsError::Lock)?;
 
-                let file = File::new();
-
-                // Creating the file in the storage.
-                let inode_of_file = fs.storage.vacant_entry().key();
-                let real_inode_of_file = fs.storage.insert(Node::File {
-                    inode: inode_of_file,
-                    name: name_of_file,
-                    file,
-                    metadata: {
-                        let time = time();
-
-                        Metadata {
-                            ft: FileType {
-                                file: true,
-                                ..Default::default()
+                // The file may have been created since the lookup, which
+                // was done under the read lock
+                let created_since = fs
+                    .from_parent_get_position_and_inode_of_file(inode_of_parent, &name_of_file)?
+                    .map(|(_nth, inode)| inode);
+                match created_since {
+                    Some(_inode_of_file) if create_new => return Err(FsError::AlreadyExists),
+                    Some(inode_of_file) => inode_of_file,
+                    None => {
+                        let file = File::new();
+
+                        // Creating the file in the storage.
+                        let time = fs.time();
+                        let inode_of_file = fs.storage.vacant_entry().key();
+                        let real_inode_of_file = fs.storage.insert(Node::File {
+                            inode: inode_of_file,
+                            name: name_of_file,
+                            file,
+                            metadata: {
+                                Metadata {
+                                    ft: FileType {
+                                        file: true,
+                                        ..Default::default()
+                                    },
+                                    accessed: time,
+                                    created: time,
+                                    modified: time,
+                                    len: 0,
+                                    mode: conf.mode().unwrap_or(0),
+                                }
                             },
-                            accessed: time,
-                            created: time,
-                            modified: time,
-                            len: 0,
-                        }
-                    },
-                });
+                        });
 
-                assert_eq!(
-                    inode_of_file, real_inode_of_file,
-                    "new file inode should have been correctly calculated",
-                );
+                        assert_eq!(
+                            inode_of_file, real_inode_of_file,
+                            "new file inode should have been correctly calculated",
+                        );
 
-                // Adding the new directory to its parent.
-                fs.add_child_to_node(inode_of_parent, inode_of_file)?;
+                        // Adding the new directory to its parent.
+                        fs.add_child_to_node(inode_of_parent, inode_of_file)?;
 
-                inode_of_file
+                        inode_of_file
+                    }
+                }
             }
 
             None => return Err(FsError::PermissionDenied),
@@ -258,6 +270,38 @@ mod test_file_opener {
         );
     }
 
+    #[test]
+    fn test_create_file_with_mode() {
+        let fs = FileSystem::default();
+
+        assert!(
+            matches!(
+                fs.new_open_options()
+                    .write(true)
+                    .create(true)
+                    .mode(0o600)
+                    .open(path!("/foo.txt")),
+                Ok(_),
+            ),
+            "creating a file with a mode",
+        );
+        assert_eq!(fs.metadata(path!("/foo.txt")).map(|m| m.mode()), Ok(0o600));
+
+        // The mode only applies when the file is created
+        assert!(
+            matches!(
+                fs.new_open_options()
+                    .write(true)
+                    .create(true)
+                    .mode(0o644)
+                    .open(path!("/foo.txt")),
+                Ok(_),
+            ),
+            "opening the file with another mode",
+        );
+        assert_eq!(fs.metadata(path!("/foo.txt")).map(|m| m.mode()), Ok(0o600));
+    }
+
     #[test]
     fn test_truncate_a_read_only_file() {
         let fs = FileSystem::default();
@@ -1,7 +1,7 @@
 //! This module contains the [`FileSystem`] type itself.
 
 use super::*;
-use crate::{DirEntry, FileType, FsError, Metadata, OpenOptions, ReadDir, Result};
+use crate::{DirEntry, FileType, FsError, Metadata, OpenOptions, ReadDir, Result, VirtualFile};
 use slab::Slab;
 use std::convert::identity;
 use std::ffi::OsString;
@@ -19,6 +19,45 @@ pub struct FileSystem {
     pub(super) inner: Arc<RwLock<FileSystemInner>>,
 }
 
+impl FileSystem {
+    /// Sets the clock the timestamps of the files and directories are read
+    /// from, in place of the system time. `clock` returns the current time
+    /// in nanoseconds, e.g. since the Unix epoch.
+    ///
+    /// The timestamps already set are kept, only the following updates and
+    /// the new entries read the clock.
+    pub fn set_clock(&self, clock: impl Fn() -> u64 + Send + Sync + 'static) {
+        self.inner.write().unwrap().clock = Some(Arc::new(clock));
+    }
+
+    /// Copies the files and directories into a new file system. Unlike a
+    /// clone, which shares them, the copy and `self` can then be modified
+    /// independently. The clock is shared.
+    pub fn deep_copy(&self) -> Self {
+        let fs = self.inner.read().unwrap();
+
+        Self {
+            inner: Arc::new(RwLock::new(FileSystemInner {
+                storage: fs.storage.clone(),
+                clock: fs.clock.clone(),
+            })),
+        }
+    }
+
+    /// Opens in this file system the file `file` is a handle of, with the
+    /// same flags, when `file` is a handle of another in-memory file system,
+    /// e.g. the one this one is a [`deep_copy`](Self::deep_copy) of. Returns
+    /// `None` for other files.
+    pub fn reopen(
+        &self,
+        file: &(dyn VirtualFile + Send + Sync + 'static),
+    ) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let handle = file.upcast_any_ref().downcast_ref::<FileHandle>()?;
+
+        Some(Box::new(handle.reopen(self.clone())))
+    }
+}
+
 impl crate::FileSystem for FileSystem {
     fn read_dir(&self, path: &Path) -> Result<ReadDir> {
         // Read lock.
@@ -78,14 +117,13 @@ impl crate::FileSystem for FileSystem {
             let mut fs = self.inner.try_write().map_err(|_| FsError::Lock)?;
 
             // Creating the directory in the storage.
+            let time = fs.time();
             let inode_of_directory = fs.storage.vacant_entry().key();
             let real_inode_of_directory = fs.storage.insert(Node::Directory {
                 inode: inode_of_directory,
                 name: name_of_directory,
                 children: Vec::new(),
                 metadata: {
-                    let time = time();
-
                     Metadata {
                         ft: FileType {
                             dir: true,
@@ -95,6 +133,7 @@ impl crate::FileSystem for FileSystem {
                         created: time,
                         modified: time,
                         len: 0,
+                        mode: 0,
                     }
                 },
             });
@@ -157,8 +196,13 @@ impl crate::FileSystem for FileSystem {
         Ok(())
     }
 
+    /// An existing entry at `to` is replaced, as long as it is a file
+    /// replaced by a file or an empty directory replaced by a directory
     fn rename(&self, from: &Path, to: &Path) -> Result<()> {
-        let ((position_of_from, inode, inode_of_from_parent), (inode_of_to_parent, name_of_to)) = {
+        let (
+            (position_of_from, inode, inode_of_from_parent),
+            (inode_of_to_parent, name_of_to, existing_to),
+        ) = {
             // Read lock.
             let fs = self.inner.try_read().map_err(|_| FsError::Lock)?;
 
@@ -186,9 +230,30 @@ impl crate::FileSystem for FileSystem {
                 .from_parent_get_position_and_inode(inode_of_from_parent, &name_of_from)?
                 .ok_or(FsError::NotAFile)?;
 
+            // Find the entry to replace, if any.
+            let existing_to =
+                fs.from_parent_get_position_and_inode(inode_of_to_parent, &name_of_to)?;
+            if let Some((_, inode_of_to)) = existing_to {
+                if inode_of_to == inode {
+                    return Ok(());
+                }
+                match (fs.storage.get(inode), fs.storage.get(inode_of_to)) {
+                    (Some(Node::Directory { .. }), Some(Node::File { .. })) => {
+                        return Err(FsError::BaseNotDirectory)
+                    }
+                    (Some(Node::File { .. }), Some(Node::Directory { .. })) => {
+                        return Err(FsError::NotAFile)
+                    }
+                    (_, Some(Node::Directory { children, .. })) if !children.is_empty() => {
+                        return Err(FsError::DirectoryNotEmpty)
+                    }
+                    _ => (),
+                }
+            }
+
             (
                 (position_of_from, inode, inode_of_from_parent),
-                (inode_of_to_parent, name_of_to),
+                (inode_of_to_parent, name_of_to, existing_to),
             )
         };
 
@@ -196,6 +261,14 @@ impl crate::FileSystem for FileSystem {
             // Write lock.
             let mut fs = self.inner.try_write().map_err(|_| FsError::Lock)?;
 
+            // Remove the replaced entry, from the storage and its parent.
+            // The position of `from` is only used below when the parents
+            // differ, so it isn't shifted by this.
+            if let Some((position_of_to, inode_of_to)) = existing_to {
+                fs.storage.remove(inode_of_to);
+                fs.remove_child_from_node(inode_of_to_parent, position_of_to)?;
+            }
+
             // Update the file name, and update the modified time.
             fs.update_node_name(inode, name_of_to)?;
 
@@ -211,11 +284,12 @@ impl crate::FileSystem for FileSystem {
             }
             // Otherwise, we need to at least update the modified time of the parent.
             else {
+                let time = fs.time();
                 match fs.storage.get_mut(inode_of_from_parent) {
                     Some(Node::Directory {
                         metadata: Metadata { modified, .. },
                         ..
-                    }) => *modified = time(),
+                    }) => *modified = time,
                     _ => return Err(FsError::UnknownError),
                 }
             }
@@ -236,6 +310,49 @@ impl crate::FileSystem for FileSystem {
             .clone())
     }
 
+    // There are no symlinks in the in-memory file system
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<u64>,
+        modified: Option<u64>,
+        _follow_symlinks: bool,
+    ) -> Result<()> {
+        // Write lock.
+        let mut fs = self.inner.try_write().map_err(|_| FsError::Lock)?;
+
+        let inode = fs.inode_of(path)?;
+        let metadata = fs
+            .storage
+            .get_mut(inode)
+            .ok_or(FsError::UnknownError)?
+            .metadata_mut();
+        if let Some(accessed) = accessed {
+            metadata.accessed = accessed;
+        }
+        if let Some(modified) = modified {
+            metadata.modified = modified;
+        }
+
+        Ok(())
+    }
+
+    fn create_dir_with_mode(&self, path: &Path, mode: u32) -> Result<()> {
+        self.create_dir(path)?;
+
+        // Write lock.
+        let mut fs = self.inner.try_write().map_err(|_| FsError::Lock)?;
+
+        let inode = fs.inode_of(path)?;
+        fs.storage
+            .get_mut(inode)
+            .ok_or(FsError::UnknownError)?
+            .metadata_mut()
+            .mode = mode;
+
+        Ok(())
+    }
+
     fn remove_file(&self, path: &Path) -> Result<()> {
         let (inode_of_parent, position, inode_of_file) = {
             // Read lock.
@@ -285,6 +402,10 @@ impl crate::FileSystem for FileSystem {
             filesystem: self.clone(),
         }))
     }
+
+    fn try_clone(&self) -> Option<Box<dyn crate::FileSystem>> {
+        Some(Box::new(self.clone()))
+    }
 }
 
 impl fmt::Debug for FileSystem {
@@ -299,9 +420,20 @@ impl fmt::Debug for FileSystem {
 /// indexed by their respective `Inode` in a slab.
 pub(super) struct FileSystemInner {
     pub(super) storage: Slab<Node>,
+    /// The clock set with [`FileSystem::set_clock`]
+    pub(super) clock: Option<Arc<dyn Fn() -> u64 + Send + Sync>>,
 }
 
 impl FileSystemInner {
+    /// The current time, in nanoseconds, from the clock of the file system
+    /// if one is set, from the system otherwise.
+    pub(super) fn time(&self) -> u64 {
+        match self.clock.as_ref() {
+            Some(clock) => clock(),
+            None => system_time(),
+        }
+    }
+
     /// Get the inode associated to a path if it exists.
     pub(super) fn inode_of(&self, path: &Path) -> Result<Inode> {
         // SAFETY: The root node always exists, so it's safe to unwrap here.
@@ -372,6 +504,10 @@ impl FileSystemInner {
                         }
                     }
 
+                    node if node.name() == name_of_directory => {
+                        Some(Err(FsError::BaseNotDirectory))
+                    }
+
                     _ => None,
                 })
                 .ok_or(FsError::InvalidInput)
@@ -437,10 +573,11 @@ impl FileSystemInner {
 
     /// Set a new name for the node represented by `inode`.
     pub(super) fn update_node_name(&mut self, inode: Inode, new_name: OsString) -> Result<()> {
+        let time = self.time();
         let node = self.storage.get_mut(inode).ok_or(FsError::UnknownError)?;
 
         node.set_name(new_name);
-        node.metadata_mut().modified = time();
+        node.metadata_mut().modified = time;
 
         Ok(())
     }
@@ -453,6 +590,7 @@ impl FileSystemInner {
     ///
     /// `inode` must represents an existing directory.
     pub(super) fn add_child_to_node(&mut self, inode: Inode, new_child: Inode) -> Result<()> {
+        let time = self.time();
         match self.storage.get_mut(inode) {
             Some(Node::Directory {
                 children,
@@ -460,7 +598,7 @@ impl FileSystemInner {
                 ..
             }) => {
                 children.push(new_child);
-                *modified = time();
+                *modified = time;
 
                 Ok(())
             }
@@ -477,6 +615,7 @@ impl FileSystemInner {
     ///
     /// `inode` must represents an existing directory.
     pub(super) fn remove_child_from_node(&mut self, inode: Inode, position: usize) -> Result<()> {
+        let time = self.time();
         match self.storage.get_mut(inode) {
             Some(Node::Directory {
                 children,
@@ -484,7 +623,7 @@ impl FileSystemInner {
                 ..
             }) => {
                 children.remove(position);
-                *modified = time();
+                *modified = time;
 
                 Ok(())
             }
@@ -607,7 +746,7 @@ impl fmt::Debug for FileSystemInner {
 
 impl Default for FileSystemInner {
     fn default() -> Self {
-        let time = time();
+        let time = system_time();
 
         let mut slab = Slab::new();
         slab.insert(Node::Directory {
@@ -623,10 +762,14 @@ impl Default for FileSystemInner {
                 created: time,
                 modified: time,
                 len: 0,
+                mode: 0,
             },
         });
 
-        Self { storage: slab }
+        Self {
+            storage: slab,
+            clock: None,
+        }
     }
 }
 
@@ -761,6 +904,18 @@ mod test_filesystem {
         }
     }
 
+    #[test]
+    fn test_create_dir_with_mode() {
+        let fs = FileSystem::default();
+
+        assert_eq!(fs.create_dir_with_mode(path!("/foo"), 0o700), Ok(()));
+        assert_eq!(fs.metadata(path!("/foo")).map(|m| m.mode()), Ok(0o700));
+
+        // Directories created without a mode don't track one
+        assert_eq!(fs.create_dir(path!("/bar")), Ok(()));
+        assert_eq!(fs.metadata(path!("/bar")).map(|m| m.mode()), Ok(0));
+    }
+
     #[test]
     fn test_remove_dir() {
         let fs = FileSystem::default();
@@ -808,6 +963,29 @@ mod test_filesystem {
 
         assert_eq!(fs.remove_dir(path!("/foo")), Ok(()), "removing a directory",);
 
+        assert!(
+            matches!(
+                fs.new_open_options()
+                    .write(true)
+                    .create_new(true)
+                    .open(path!("/baz.txt")),
+                Ok(_),
+            ),
+            "creating a file",
+        );
+
+        assert_eq!(
+            fs.remove_dir(path!("/baz.txt")),
+            Err(FsError::BaseNotDirectory),
+            "removing a file",
+        );
+
+        assert_eq!(
+            fs.remove_file(path!("/baz.txt")),
+            Ok(()),
+            "removing the file",
+        );
+
         {
             let fs_inner = fs.inner.read().unwrap();
             assert_eq!(
@@ -1042,6 +1220,65 @@ mod test_filesystem {
         }
     }
 
+    #[test]
+    fn test_rename_replaces_the_target() {
+        use std::io::{Read, Write};
+
+        let fs = FileSystem::default();
+        let create = |path: &std::path::Path| {
+            fs.new_open_options()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .unwrap()
+        };
+
+        create(path!("/a.txt")).write_all(b"a").unwrap();
+        create(path!("/b.txt")).write_all(b"b").unwrap();
+        assert_eq!(fs.create_dir(path!("/dir")), Ok(()));
+        assert_eq!(fs.create_dir(path!("/empty")), Ok(()));
+        assert_eq!(fs.create_dir(path!("/full")), Ok(()));
+        create(path!("/full/c.txt"));
+
+        assert_eq!(
+            fs.rename(path!("/a.txt"), path!("/dir")),
+            Err(FsError::NotAFile),
+            "a file can't replace a directory",
+        );
+        assert_eq!(
+            fs.rename(path!("/dir"), path!("/a.txt")),
+            Err(FsError::BaseNotDirectory),
+            "a directory can't replace a file",
+        );
+        assert_eq!(
+            fs.rename(path!("/dir"), path!("/full")),
+            Err(FsError::DirectoryNotEmpty),
+            "a directory can't replace a directory with entries",
+        );
+
+        assert_eq!(fs.rename(path!("/a.txt"), path!("/b.txt")), Ok(()));
+        let mut content = String::new();
+        fs.new_open_options()
+            .read(true)
+            .open(path!("/b.txt"))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "a");
+        assert!(fs.metadata(path!("/a.txt")).is_err());
+
+        assert_eq!(fs.rename(path!("/dir"), path!("/empty")), Ok(()));
+        assert!(fs.metadata(path!("/empty")).unwrap().is_dir());
+        assert!(fs.metadata(path!("/dir")).is_err());
+
+        let fs_inner = fs.inner.read().unwrap();
+        assert_eq!(fs_inner.storage.len(), 5, "the replaced entries are gone");
+        assert!(matches!(
+            fs_inner.storage.get(ROOT_INODE),
+            Some(Node::Directory { children, .. }) if children.len() == 3
+        ));
+    }
+
     #[test]
     fn test_metadata() {
         use std::thread::sleep;
@@ -1049,16 +1286,19 @@ mod test_filesystem {
 
         let fs = FileSystem::default();
         let root_metadata = fs.metadata(path!("/"));
+        assert!(root_metadata.is_ok());
+        let root_metadata = root_metadata.unwrap();
 
         assert!(matches!(
             root_metadata,
-            Ok(Metadata {
+            Metadata {
                 ft: FileType { dir: true, .. },
                 accessed,
                 created,
                 modified,
-                len: 0
-            }) if accessed == created && created == modified && modified > 0
+                len: 0,
+                mode: 0,
+            } if accessed == created && created == modified && modified > 0
         ));
 
         assert_eq!(fs.create_dir(path!("/foo")), Ok(()));
@@ -1074,7 +1314,8 @@ mod test_filesystem {
                 accessed,
                 created,
                 modified,
-                len: 0
+                len: 0,
+                mode: 0,
             } if accessed == created && created == modified && modified > 0
         ));
 
@@ -1090,7 +1331,8 @@ mod test_filesystem {
                     accessed,
                     created,
                     modified,
-                    len: 0
+                    len: 0,
+                    mode: 0,
                 }) if
                     accessed == foo_metadata.accessed &&
                     created == foo_metadata.created &&
@@ -1106,10 +1348,11 @@ mod test_filesystem {
                     accessed,
                     created,
                     modified,
-                    len: 0
+                    len: 0,
+                    mode: 0,
                 }) if
-                    accessed == foo_metadata.accessed &&
-                    created == foo_metadata.created &&
+                    accessed == root_metadata.accessed &&
+                    created == root_metadata.created &&
                     modified > foo_metadata.modified
             ),
             "the modified time of the parent is updated when file is renamed",
@@ -1284,6 +1527,43 @@ mod test_filesystem {
         assert!(matches!(readdir.next(), None), "no more entries");
     }
 
+    #[test]
+    fn test_deep_copy() {
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        let fs = FileSystem::default();
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path!("/foo.txt"))
+            .unwrap();
+        file.write_all(b"foo").unwrap();
+
+        let copy = fs.deep_copy();
+        let mut reopened = copy.reopen(file.as_ref()).unwrap();
+        reopened.write_all(b"bar").unwrap();
+        assert_eq!(copy.create_dir(path!("/baz")), Ok(()), "creating `baz`");
+
+        // The writes to the copy aren't visible in the original
+        assert_eq!(file.size(), 3, "the original file is unchanged");
+        assert!(
+            fs.metadata(path!("/baz")).is_err(),
+            "the directory is only created in the copy",
+        );
+
+        let mut content = String::new();
+        reopened.seek(SeekFrom::Start(0)).unwrap();
+        reopened.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "foobar", "the copy is written to");
+
+        assert!(
+            copy.reopen(&crate::mem_fs::Stdout::default()).is_none(),
+            "only the handles of an in-memory file system can be reopened",
+        );
+    }
+
     #[test]
     fn test_canonicalize() {
         let fs = FileSystem::default();
@@ -14,7 +14,7 @@ use std::ffi::{OsStr, OsString};
 type Inode = usize;
 const ROOT_INODE: Inode = 0;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 enum Node {
     File {
         inode: Inode,
@@ -67,7 +67,9 @@ impl Node {
     }
 }
 
-fn time() -> u64 {
+/// The system time in nanoseconds, read when no clock is set with
+/// [`FileSystem::set_clock`]
+fn system_time() -> u64 {
     #[cfg(not(feature = "no-time"))]
     {
         // SAFETY: It's very unlikely that the system returns a time that
@@ -75,7 +77,7 @@ fn time() -> u64 {
         std::time::SystemTime::now()
             .duration_since(std::time::SystemTime::UNIX_EPOCH)
             .unwrap()
-            .as_secs()
+            .as_nanos() as u64
     }
 
     #[cfg(feature = "no-time")]
@@ -55,6 +55,17 @@ macro_rules! impl_virtualfile_on_std_streams {
             fn get_fd(&self) -> Option<FileDescriptor> {
                 None
             }
+
+            fn is_seekable(&self) -> bool {
+                false
+            }
+
+            /// The new stream starts with a copy of the buffer
+            fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+                Some(Box::new(Self {
+                    buf: self.buf.clone(),
+                }))
+            }
         }
 
         impl_virtualfile_on_std_streams!(impl Seek for $name);
@@ -367,7 +367,17 @@ impl Instance {
             .memories
             .get(memory_index)
             .unwrap_or_else(|| panic!("no memory for index {}", memory_index.index()));
-        mem.get_mut(self.context_mut()).grow(delta.into())
+        let prev = mem.get_mut(self.context_mut()).grow(delta.into())?;
+        self.memory_grown(self.module.memory_index(memory_index), mem, prev);
+        Ok(prev)
+    }
+
+    /// Calls the memory grow hook of the context, if any, after `mem` grew
+    /// from `prev` pages
+    fn memory_grown(&self, index: MemoryIndex, mem: InternalStoreHandle<VMMemory>, prev: Pages) {
+        if let Some(hook) = self.context().memory_grow_hook() {
+            hook(index, prev, mem.get(self.context()).size());
+        }
     }
 
     /// Grow imported memory by the specified amount of pages.
@@ -388,7 +398,9 @@ impl Instance {
     {
         let import = self.imported_memory(memory_index);
         let mem = import.handle;
-        mem.get_mut(self.context_mut()).grow(delta.into())
+        let prev = mem.get_mut(self.context_mut()).grow(delta.into())?;
+        self.memory_grown(memory_index, mem, prev);
+        Ok(prev)
     }
 
     /// Returns the number of allocated wasm pages.
@@ -45,14 +45,14 @@ pub use crate::function_env::VMFunctionEnvironment;
 pub use crate::global::*;
 pub use crate::imports::Imports;
 pub use crate::instance::{InstanceAllocator, InstanceHandle};
-pub use crate::memory::{MemoryError, VMMemory};
+pub use crate::memory::{MemoryError, MemoryInitialization, VMMemory};
 pub use crate::mmap::Mmap;
 pub use crate::probestack::PROBESTACK;
 pub use crate::sig_registry::SignatureRegistry;
 pub use crate::store::{
-    InternalStoreHandle, MaybeInstanceOwned, StoreHandle, StoreId, StoreObjects,
+    InternalStoreHandle, MaybeInstanceOwned, MemoryGrowHook, StoreHandle, StoreId, StoreObjects,
 };
-pub use crate::table::{TableElement, VMTable};
+pub use crate::table::{TableElement, TableError, VMTable};
 pub use crate::trap::*;
 pub use crate::vmcontext::{
     VMCallerCheckedAnyfunc, VMContext, VMDynamicFunctionContext, VMFunctionContext,
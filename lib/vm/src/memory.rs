@@ -56,6 +56,25 @@ pub enum MemoryError {
     Generic(String),
 }
 
+/// How the pages of a newly created linear memory are initialized, see
+/// `Tunables::memory_initialization`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryInitialization {
+    /// The accessible pages are explicitly zeroed when the memory is
+    /// created, so that they are all committed before the instance runs.
+    Eager,
+    /// The pages are left untouched until the guest accesses them, they
+    /// read as zeros because the memory is backed by fresh anonymous
+    /// mappings which the OS zero-fills on demand.
+    Lazy,
+}
+
+impl Default for MemoryInitialization {
+    fn default() -> Self {
+        Self::Eager
+    }
+}
+
 /// A linear memory instance.
 pub struct VMMemory {
     // The underlying allocation.
@@ -139,6 +158,24 @@ impl VMMemory {
                 });
             }
         }
+        if memory.shared {
+            // The memory may be in use by other threads while it grows, it
+            // must be allocated once with room for its maximum
+            match (memory.maximum, style) {
+                (None, _) => {
+                    return Err(MemoryError::InvalidMemory {
+                        reason: "shared memories must have a maximum".to_string(),
+                    });
+                }
+                (Some(max), MemoryStyle::Static { bound, .. }) if max <= *bound => (),
+                _ => {
+                    return Err(MemoryError::InvalidMemory {
+                        reason: "shared memories must have a static style bounding their maximum"
+                            .to_string(),
+                    });
+                }
+            }
+        }
 
         let offset_guard_bytes = style.offset_guard_size() as usize;
 
@@ -204,6 +241,13 @@ impl VMMemory {
         &self.style
     }
 
+    /// Explicitly zeroes the accessible pages of the memory, which commits
+    /// them.
+    pub fn zero_fill(&mut self) {
+        let accessible_bytes = self.mmap.size.bytes().0;
+        self.mmap.alloc.as_mut_slice()[..accessible_bytes].fill(0);
+    }
+
     /// Returns the number of allocated wasm pages.
     pub fn size(&self) -> Pages {
         // TODO: investigate this function for race conditions
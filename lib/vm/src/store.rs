@@ -4,9 +4,14 @@ use std::{
     marker::PhantomData,
     num::{NonZeroU64, NonZeroUsize},
     ptr::NonNull,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
+use wasmer_types::{MemoryIndex, Pages};
+
 use crate::VMExternObj;
 
 use crate::{InstanceHandle, VMFunction, VMFunctionEnvironment, VMGlobal, VMMemory, VMTable};
@@ -59,10 +64,15 @@ impl_context_object! {
     function_environments => VMFunctionEnvironment,
 }
 
+/// Called after an instance successfully executed `memory.grow`, with the
+/// index of the memory in the instance and its previous and new sizes.
+pub type MemoryGrowHook = Arc<dyn Fn(MemoryIndex, Pages, Pages) + Send + Sync>;
+
 /// Set of objects managed by a context.
 #[derive(Default)]
 pub struct StoreObjects {
     id: StoreId,
+    memory_grow_hook: Option<MemoryGrowHook>,
     memories: Vec<VMMemory>,
     tables: Vec<VMTable>,
     globals: Vec<VMGlobal>,
@@ -78,6 +88,17 @@ impl StoreObjects {
         self.id
     }
 
+    /// Sets the hook called after every successful `memory.grow` executed
+    /// by the instances of this context.
+    pub fn set_memory_grow_hook(&mut self, hook: Option<MemoryGrowHook>) {
+        self.memory_grow_hook = hook;
+    }
+
+    /// Returns the hook set with [`StoreObjects::set_memory_grow_hook`].
+    pub fn memory_grow_hook(&self) -> Option<&MemoryGrowHook> {
+        self.memory_grow_hook.as_ref()
+    }
+
     /// Returns a pair of mutable references from two handles.
     ///
     /// Panics if both handles point to the same object.
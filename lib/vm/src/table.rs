@@ -14,9 +14,44 @@ use std::cell::UnsafeCell;
 use std::convert::TryFrom;
 use std::fmt;
 use std::ptr::NonNull;
+use thiserror::Error;
 use wasmer_types::TableStyle;
 use wasmer_types::{TableType, TrapCode, Type as ValType};
 
+/// Error type describing things that can go wrong when creating a table.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TableError {
+    /// Tables can only hold references.
+    #[error("tables of types other than funcref or externref ({})", ty.ty)]
+    UnsupportedElementType {
+        /// The type of the table that could not be created.
+        ty: TableType,
+    },
+    /// The table declares a minimum greater than its maximum.
+    #[error("Table minimum ({}) is larger than maximum ({})!", ty.minimum, ty.maximum.unwrap_or_default())]
+    MinimumExceedsMaximum {
+        /// The type of the table that could not be created.
+        ty: TableType,
+    },
+    /// The table declares a minimum greater than the tunables allow.
+    #[error("Table minimum ({} elements) exceeds the allowed maximum ({} elements)", ty.minimum, max_allowed)]
+    MinimumTooLarge {
+        /// The type of the table that could not be created.
+        ty: TableType,
+        /// The maximum number of elements the tunables allow.
+        max_allowed: u32,
+    },
+    /// The elements of the table could not be allocated.
+    #[error("Table minimum ({}) is bigger than usize", ty.minimum)]
+    AllocationFailed {
+        /// The type of the table that could not be created.
+        ty: TableType,
+    },
+    /// A user defined error value, used for error cases not listed above.
+    #[error("A user-defined error occurred: {0}")]
+    Generic(String),
+}
+
 /// A reference stored in a table. Can be either an externref or a funcref.
 #[derive(Debug, Clone)]
 pub enum TableElement {
@@ -84,7 +119,7 @@ impl VMTable {
     ///
     /// This creates a `Table` with metadata owned by a VM, pointed to by
     /// `vm_table_location`: this can be used to create a local table.
-    pub fn new(table: &TableType, style: &TableStyle) -> Result<Self, String> {
+    pub fn new(table: &TableType, style: &TableStyle) -> Result<Self, TableError> {
         unsafe { Self::new_inner(table, style, None) }
     }
 
@@ -99,7 +134,7 @@ impl VMTable {
         table: &TableType,
         style: &TableStyle,
         vm_table_location: NonNull<VMTableDefinition>,
-    ) -> Result<Self, String> {
+    ) -> Result<Self, TableError> {
         Self::new_inner(table, style, Some(vm_table_location))
     }
 
@@ -108,26 +143,18 @@ impl VMTable {
         table: &TableType,
         style: &TableStyle,
         vm_table_location: Option<NonNull<VMTableDefinition>>,
-    ) -> Result<Self, String> {
+    ) -> Result<Self, TableError> {
         match table.ty {
             ValType::FuncRef | ValType::ExternRef => (),
-            ty => {
-                return Err(format!(
-                    "tables of types other than funcref or externref ({})",
-                    ty
-                ))
-            }
+            _ => return Err(TableError::UnsupportedElementType { ty: *table }),
         };
         if let Some(max) = table.maximum {
             if max < table.minimum {
-                return Err(format!(
-                    "Table minimum ({}) is larger than maximum ({})!",
-                    table.minimum, max
-                ));
+                return Err(TableError::MinimumExceedsMaximum { ty: *table });
             }
         }
         let table_minimum = usize::try_from(table.minimum)
-            .map_err(|_| "Table minimum is bigger than usize".to_string())?;
+            .map_err(|_| TableError::AllocationFailed { ty: *table })?;
         let mut vec = vec![RawTableElement::default(); table_minimum];
         let base = vec.as_mut_ptr();
         match style {
@@ -231,6 +231,12 @@ pub trait VirtualSocket: fmt::Debug + Send + Sync + 'static {
 
     /// Returns the status/state of the socket
     fn status(&self) -> Result<SocketStatus>;
+
+    /// Sets whether receiving and sending return [`NetworkError::WouldBlock`]
+    /// instead of blocking. Defaults to [`NetworkError::Unsupported`]
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -365,7 +365,7 @@ impl VirtualConnectedSocket for LocalTcpStream {
 
     fn recv(&mut self) -> Result<SocketReceive> {
         let buf_size = 8192;
-        let mut buf = BytesMut::with_capacity(buf_size);
+        let mut buf = BytesMut::zeroed(buf_size);
         let read = self
             .stream
             .read(&mut buf[..])
@@ -379,7 +379,7 @@ impl VirtualConnectedSocket for LocalTcpStream {
 
     fn peek(&mut self) -> Result<SocketReceive> {
         let buf_size = 8192;
-        let mut buf = BytesMut::with_capacity(buf_size);
+        let mut buf = BytesMut::zeroed(buf_size);
         let read = self
             .stream
             .peek(&mut buf[..])
@@ -408,6 +408,12 @@ impl VirtualSocket for LocalTcpStream {
     fn status(&self) -> Result<SocketStatus> {
         Ok(SocketStatus::Opened)
     }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.stream
+            .set_nonblocking(nonblocking)
+            .map_err(io_err_into_net_error)
+    }
 }
 
 #[derive(Debug)]
@@ -506,7 +512,7 @@ impl VirtualConnectedSocket for LocalUdpSocket {
 
     fn recv(&mut self) -> Result<SocketReceive> {
         let buf_size = 8192;
-        let mut buf = BytesMut::with_capacity(buf_size);
+        let mut buf = BytesMut::zeroed(buf_size);
         let read = self.0.recv(&mut buf[..]).map_err(io_err_into_net_error)?;
         let buf = Bytes::from(buf).slice(..read);
         Ok(SocketReceive {
@@ -517,7 +523,7 @@ impl VirtualConnectedSocket for LocalUdpSocket {
 
     fn peek(&mut self) -> Result<SocketReceive> {
         let buf_size = 8192;
-        let mut buf = BytesMut::with_capacity(buf_size);
+        let mut buf = BytesMut::zeroed(buf_size);
         let read = self.0.peek(&mut buf[..]).map_err(io_err_into_net_error)?;
         let buf = Bytes::from(buf).slice(..read);
         Ok(SocketReceive {
@@ -536,7 +542,7 @@ impl VirtualConnectionlessSocket for LocalUdpSocket {
 
     fn recv_from(&mut self) -> Result<SocketReceiveFrom> {
         let buf_size = 8192;
-        let mut buf = BytesMut::with_capacity(buf_size);
+        let mut buf = BytesMut::zeroed(buf_size);
         let (read, peer) = self
             .0
             .recv_from(&mut buf[..])
@@ -551,7 +557,7 @@ impl VirtualConnectionlessSocket for LocalUdpSocket {
 
     fn peek_from(&mut self) -> Result<SocketReceiveFrom> {
         let buf_size = 8192;
-        let mut buf = BytesMut::with_capacity(buf_size);
+        let mut buf = BytesMut::zeroed(buf_size);
         let (read, peer) = self
             .0
             .peek_from(&mut buf[..])
@@ -581,4 +587,10 @@ impl VirtualSocket for LocalUdpSocket {
     fn status(&self) -> Result<SocketStatus> {
         Ok(SocketStatus::Opened)
     }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.0
+            .set_nonblocking(nonblocking)
+            .map_err(io_err_into_net_error)
+    }
 }
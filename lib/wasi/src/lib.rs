@@ -43,12 +43,20 @@ mod utils;
 use crate::syscalls::*;
 
 pub use crate::state::{
-    Fd, Pipe, Stderr, Stdin, Stdout, WasiFs, WasiInodes, WasiState, WasiStateBuilder,
-    WasiStateCreationError, ALL_RIGHTS, VIRTUAL_ROOT_FD,
+    errno_from_io_error_kind, iterate_poll_events, iterate_poll_events_indexed, ByteCounters,
+    BytesAvailable, CallbackFile, ContentValidator, CountingFile, DirEntry, Fd, FdInfo, FdKind,
+    FdOptions, GenericFile, HostClock, ManualClock, MountedFileSystem, NullFile, Pipe, PollEvent,
+    PollEventIter, PollEventSet, QuotaFile, ReadOnlyFileSystem, StaticInput, Stderr, Stdin, Stdout,
+    SyscallErrorStats, SyscallEvent, Tee, ValidatingFile, WasiClock, WasiFs, WasiInodes, WasiState,
+    WasiStateBuilder, WasiStateCreationError, ZeroFile, ALL_RIGHTS, DEFAULT_IO_BLOCK_SIZE,
+    DEFAULT_MAX_PATH_LEN, SYSCALL_INSTRUCTIONS, VIRTUAL_ROOT_FD,
 };
+#[cfg(feature = "mem-fs-mounts")]
+pub use crate::state::{FsSnapshot, OverlayFileSystem, TarFileSystem};
 pub use crate::syscalls::types;
 pub use crate::utils::{
-    get_wasi_version, get_wasi_versions, is_wasi_module, is_wasix_module, WasiVersion,
+    get_wasi_version, get_wasi_version_strict, get_wasi_versions, is_wasi_module, is_wasix_module,
+    WasiVersion,
 };
 pub use wasmer_vbus::{UnsupportedVirtualBus, VirtualBus};
 #[deprecated(since = "2.1.0", note = "Please use `wasmer_vfs::FsError`")]
@@ -60,11 +68,14 @@ pub use wasmer_vnet::{UnsupportedVirtualNetworking, VirtualNetworking};
 use wasmer_wasi_types::__WASI_CLOCK_MONOTONIC;
 
 use derivative::*;
+use std::collections::VecDeque;
+use std::convert::TryInto;
 use std::ops::Deref;
 use thiserror::Error;
 use wasmer::{
-    imports, namespace, AsStoreMut, Exports, Function, FunctionEnv, Imports, Memory, Memory32,
-    MemoryAccessError, MemorySize, Module, TypedFunction,
+    imports, namespace, AsStoreMut, AsStoreRef, ExportError, Exports, Function, FunctionEnv,
+    FunctionEnvMut, Imports, Instance, Memory, Memory32, MemoryAccessError, MemoryError,
+    MemorySize, Module, Pages, RuntimeError, TypedFunction, WasmPtr,
 };
 
 pub use runtime::{
@@ -76,17 +87,62 @@ use std::time::Duration;
 /// This is returned in `RuntimeError`.
 /// Use `downcast` or `downcast_ref` to retrieve the `ExitCode`.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum WasiError {
     #[error("WASI exited with code: {0}")]
     Exit(syscalls::types::__wasi_exitcode_t),
     #[error("The WASI version could not be determined")]
     UnknownWasiVersion,
+    /// The instruction budget set with [`WasiStateBuilder::max_instructions`]
+    /// is exhausted.
+    #[error("WASI ran out of its instruction budget")]
+    OutOfFuel,
+    /// The instance has no `_start` function to run.
+    #[error("The instance has no `_start` function: {0}")]
+    NoStart(ExportError),
+    /// The guest trapped for another reason than exiting.
+    #[error(transparent)]
+    Trap(RuntimeError),
+}
+
+impl WasiError {
+    /// Returns the code the guest exited with, `None` if it didn't exit
+    pub fn exit_code(&self) -> Option<syscalls::types::__wasi_exitcode_t> {
+        match self {
+            Self::Exit(code) => Some(*code),
+            _ => None,
+        }
+    }
 }
 
 /// Represents the ID of a WASI thread
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct WasiThreadId(u32);
 
+/// Runs the `_start` function of a WASI command and returns the code the
+/// guest exited with: the code given to `proc_exit`, or 0 if `_start`
+/// returned.
+///
+/// Traps other than [`WasiError::Exit`] are returned as errors, traps that
+/// aren't raised by WASI as [`WasiError::Trap`].
+pub fn run_wasi_start(
+    store: &mut impl AsStoreMut,
+    instance: &Instance,
+) -> Result<syscalls::types::__wasi_exitcode_t, WasiError> {
+    let start = instance
+        .exports
+        .get_function("_start")
+        .map_err(WasiError::NoStart)?;
+    match start.call(store, &[]) {
+        Ok(_) => Ok(0),
+        Err(err) => match err.downcast::<WasiError>() {
+            Ok(WasiError::Exit(code)) => Ok(code),
+            Ok(err) => Err(err),
+            Err(err) => Err(WasiError::Trap(err)),
+        },
+    }
+}
+
 impl From<u32> for WasiThreadId {
     fn from(id: u32) -> Self {
         Self(id)
@@ -226,6 +282,21 @@ pub struct WasiEnv {
     pub state: Arc<WasiState>,
     /// Implementation of the WASI runtime.
     pub(crate) runtime: Arc<dyn WasiRuntimeImplementation + Send + Sync + 'static>,
+    /// Callback invoked with the exit code when the guest calls `proc_exit`
+    #[derivative(Debug = "ignore")]
+    pub(crate) on_exit: Option<Arc<dyn Fn(syscalls::types::__wasi_exitcode_t) + Send + Sync>>,
+    /// Rewrites or rejects the paths passed to the path syscalls
+    #[derivative(Debug = "ignore")]
+    #[allow(clippy::type_complexity)]
+    pub(crate) path_mapper: Option<Arc<dyn Fn(&str) -> Option<String> + Send + Sync>>,
+    /// Callback invoked with every syscall made by the guest
+    #[derivative(Debug = "ignore")]
+    #[allow(clippy::type_complexity)]
+    pub(crate) syscall_tracer: Option<Arc<Mutex<Box<dyn FnMut(SyscallEvent) + Send>>>>,
+    /// Receives the bytes returned by `random_get`
+    pub(crate) randomness_recorder: Option<Arc<Mutex<Vec<u8>>>>,
+    /// The bytes left to return from `random_get` in place of randomness
+    pub(crate) randomness_replay: Option<Arc<Mutex<VecDeque<u8>>>>,
 }
 
 impl WasiEnv {
@@ -241,6 +312,97 @@ impl WasiEnv {
             malloc: None,
             free: None,
             runtime: Arc::new(PluggableRuntimeImplementation::default()),
+            on_exit: None,
+            path_mapper: None,
+            syscall_tracer: None,
+            randomness_recorder: None,
+            randomness_replay: None,
+        }
+    }
+
+    /// Reads the clock `clock_id` of the [`WasiClock`] of the state, the
+    /// host clocks unless another one was set with
+    /// [`WasiStateBuilder::clock`] or [`WasiStateBuilder::deterministic`]
+    pub(crate) fn clock_time_get(
+        &self,
+        clock_id: types::__wasi_clockid_t,
+    ) -> Result<types::__wasi_timestamp_t, types::__wasi_errno_t> {
+        self.state.clock.now(clock_id)
+    }
+
+    /// Fills `buf` for `random_get`, with the next bytes given to
+    /// [`WasiStateBuilder::replay_randomness`] if any, from the randomness
+    /// of the state otherwise, and appends them to the buffer given to
+    /// [`WasiStateBuilder::record_randomness`].
+    ///
+    /// Fails with `__WASI_EIO` when fewer than `buf.len()` bytes are left
+    /// to replay, the bytes left are not consumed.
+    pub(crate) fn fill_random(&self, buf: &mut [u8]) -> Result<(), types::__wasi_errno_t> {
+        match (
+            self.randomness_replay.as_ref(),
+            self.state.deterministic.as_ref(),
+        ) {
+            (Some(replay), _) => {
+                let mut replay = replay.lock().unwrap();
+                let len = buf.len();
+                if replay.len() < len {
+                    return Err(types::__WASI_EIO);
+                }
+                buf.copy_from_slice(&replay.drain(..len).collect::<Vec<_>>());
+            }
+            (None, Some(deterministic)) => deterministic.lock().unwrap().fill_random(buf),
+            (None, None) => getrandom::getrandom(buf).map_err(|_| types::__WASI_EIO)?,
+        }
+        if let Some(recorder) = self.randomness_recorder.as_ref() {
+            recorder.lock().unwrap().extend_from_slice(buf);
+        }
+        Ok(())
+    }
+
+    /// Passes a guest path through the path mapper set with
+    /// [`WasiStateBuilder::path_mapper`], paths it rejects are denied with
+    /// `__WASI_EACCES`.
+    pub(crate) fn map_path(&self, path: String) -> Result<String, types::__wasi_errno_t> {
+        match self.path_mapper.as_ref() {
+            Some(path_mapper) => path_mapper(&path).ok_or(types::__WASI_EACCES),
+            None => Ok(path),
+        }
+    }
+
+    /// Describes a call to the syscall `name` for the tracer set with
+    /// [`WasiStateBuilder::syscall_tracer`], the paths are read from the
+    /// guest memory now as the syscall may overwrite them.
+    pub(crate) fn syscall_event(
+        &self,
+        store: &impl AsStoreRef,
+        name: &'static str,
+        args: Vec<(&'static str, u64)>,
+    ) -> SyscallEvent {
+        // paths longer than this are truncated
+        const MAX_PATH_LEN: u64 = 4096;
+
+        let mut paths = Vec::new();
+        // the `path` of these syscalls is a buffer they write to
+        let output_path = matches!(name, "fd_prestat_dir_name" | "getcwd");
+        if let Some(memory) = self.memory.as_ref().filter(|_| !output_path) {
+            for (arg, offset) in args.iter().filter(|(arg, _)| arg.ends_with("path")) {
+                let len = args
+                    .iter()
+                    .find(|(len_arg, _)| len_arg.strip_suffix("_len") == Some(*arg))
+                    .map(|(_, len)| (*len).min(MAX_PATH_LEN));
+                if let Some(len) = len {
+                    let mut buf = vec![0; len as usize];
+                    if memory.read(store, *offset, &mut buf).is_ok() {
+                        paths.push((*arg, String::from_utf8_lossy(&buf).into_owned()));
+                    }
+                }
+            }
+        }
+        SyscallEvent {
+            name,
+            args,
+            paths,
+            errno: None,
         }
     }
 
@@ -293,13 +455,14 @@ impl WasiEnv {
         Ok(())
     }
 
-    // Sleeps for a period of time
+    // Sleeps for a period of time, on the clock of the state
     pub fn sleep(&self, duration: Duration) -> Result<(), WasiError> {
+        let clock = self.state.clock.clone();
         let duration = duration.as_nanos();
-        let start = platform_clock_time_get(__WASI_CLOCK_MONOTONIC, 1_000_000).unwrap() as u128;
+        let start = clock.now(__WASI_CLOCK_MONOTONIC).unwrap() as u128;
         self.yield_now()?;
         loop {
-            let now = platform_clock_time_get(__WASI_CLOCK_MONOTONIC, 1_000_000).unwrap() as u128;
+            let now = clock.now(__WASI_CLOCK_MONOTONIC).unwrap() as u128;
             let delta = match now.checked_sub(start) {
                 Some(a) => a,
                 None => {
@@ -315,7 +478,7 @@ impl WasiEnv {
                     break;
                 }
             };
-            std::thread::sleep(remaining.min(Duration::from_millis(10)));
+            clock.sleep(remaining.min(Duration::from_millis(10)));
             self.yield_now()?;
         }
         Ok(())
@@ -330,6 +493,13 @@ impl WasiEnv {
     pub fn bus(&self) -> &(dyn VirtualBus) {
         self.runtime.bus()
     }
+
+    /// Returns the memory of the instance along with the WASI state.
+    ///
+    /// Views created from the memory (`WasmRef`, `WasmSlice`) are only valid
+    /// until the memory grows, which other threads can do whenever the
+    /// syscall yields or sleeps. Syscalls that wait resolve the memory again
+    /// before writing their results.
     pub(crate) fn get_memory_and_wasi_state(&self, _mem_index: u32) -> (&Memory, &WasiState) {
         let memory = self.memory();
         let state = self.state.deref();
@@ -349,11 +519,61 @@ impl WasiEnv {
         self.memory.as_ref().unwrap()
     }
 
+    /// Grows the memory of the instance by `pages`, e.g. to grow it upfront
+    /// instead of while the guest runs, and returns its previous size.
+    ///
+    /// Fails with [`MemoryError::Generic`] if no memory was set, and like
+    /// [`Memory::grow`] if the memory can't grow that much.
+    pub fn grow_memory(
+        &self,
+        store: &mut impl AsStoreMut,
+        pages: Pages,
+    ) -> Result<Pages, MemoryError> {
+        let memory = self
+            .memory
+            .as_ref()
+            .ok_or_else(|| MemoryError::Generic("no memory was set on the WasiEnv".to_string()))?;
+        memory.grow(store, pages)
+    }
+
     /// Get the WASI state
     pub fn state(&self) -> &WasiState {
         &self.state
     }
 
+    /// Reads `len` bytes at `ptr` from the memory of the instance, which
+    /// needs to have been set first.
+    ///
+    /// Returns [`FsError::InvalidInput`] if the range does not fit in the
+    /// memory.
+    pub fn read_bytes<M: MemorySize>(
+        &self,
+        store: &impl AsStoreRef,
+        ptr: WasmPtr<u8, M>,
+        len: M::Offset,
+    ) -> Result<Vec<u8>, FsError> {
+        ptr.slice(store, self.memory(), len)
+            .and_then(|slice| slice.read_to_vec())
+            .map_err(|_| FsError::InvalidInput)
+    }
+
+    /// Writes `data` at `ptr` to the memory of the instance, which needs to
+    /// have been set first.
+    ///
+    /// Returns [`FsError::InvalidInput`] if the range does not fit in the
+    /// memory, nothing is written then.
+    pub fn write_bytes<M: MemorySize>(
+        &self,
+        store: &impl AsStoreRef,
+        ptr: WasmPtr<u8, M>,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        let len: M::Offset = data.len().try_into().map_err(|_| FsError::InvalidInput)?;
+        ptr.slice(store, self.memory(), len)
+            .and_then(|slice| slice.write_slice(data))
+            .map_err(|_| FsError::InvalidInput)
+    }
+
     pub fn get_memory_and_wasi_state_and_inodes(
         &self,
         _mem_index: u32,
@@ -383,61 +603,165 @@ pub fn generate_import_object_from_env(
 ) -> Imports {
     match version {
         WasiVersion::Snapshot0 => generate_import_object_snapshot0(store, ctx),
-        WasiVersion::Snapshot1 | WasiVersion::Latest => {
-            generate_import_object_snapshot1(store, ctx)
-        }
+        // `Latest` is an alias for the most recent snapshot; callers that
+        // need to know what the module actually targeted should detect it
+        // with `get_wasi_version_strict`.
+        WasiVersion::Snapshot1 => generate_import_object_snapshot1(store, ctx),
+        WasiVersion::Latest => generate_import_object_latest(store, ctx),
         WasiVersion::Wasix32v1 => generate_import_object_wasix32_v1(store, ctx),
         WasiVersion::Wasix64v1 => generate_import_object_wasix64_v1(store, ctx),
     }
 }
 
+/// The syscalls that can be stubbed with
+/// [`WasiStateBuilder::stub_syscalls`], every syscall of every WASI version
+/// except `proc_exit` and the bus calls
+pub(crate) const STUBBABLE_SYSCALLS: &[&str] = &[
+    "args_get",
+    "args_sizes_get",
+    "clock_res_get",
+    "clock_time_get",
+    "environ_get",
+    "environ_sizes_get",
+    "fd_advise",
+    "fd_allocate",
+    "fd_close",
+    "fd_datasync",
+    "fd_fdstat_get",
+    "fd_fdstat_set_flags",
+    "fd_fdstat_set_rights",
+    "fd_filestat_get",
+    "fd_filestat_set_size",
+    "fd_filestat_set_times",
+    "fd_pread",
+    "fd_prestat_get",
+    "fd_prestat_dir_name",
+    "fd_pwrite",
+    "fd_read",
+    "fd_readdir",
+    "fd_renumber",
+    "fd_seek",
+    "fd_sync",
+    "fd_tell",
+    "fd_write",
+    "path_create_directory",
+    "path_filestat_get",
+    "path_filestat_set_times",
+    "path_link",
+    "path_open",
+    "path_readlink",
+    "path_remove_directory",
+    "path_rename",
+    "path_symlink",
+    "path_unlink_file",
+    "poll_oneoff",
+    "proc_raise",
+    "random_get",
+    "sched_yield",
+    "sock_recv",
+    "sock_send",
+    "sock_shutdown",
+    "fd_dup",
+    "fd_event",
+    "fd_pipe",
+    "tty_get",
+    "tty_set",
+    "getcwd",
+    "chdir",
+    "thread_spawn",
+    "thread_sleep",
+    "thread_id",
+    "thread_join",
+    "thread_parallelism",
+    "thread_exit",
+    "getpid",
+    "ws_connect",
+    "http_request",
+    "http_status",
+    "port_bridge",
+    "port_unbridge",
+    "port_dhcp_acquire",
+    "port_addr_add",
+    "port_addr_remove",
+    "port_addr_clear",
+    "port_addr_list",
+    "port_mac",
+    "port_gateway_set",
+    "port_route_add",
+    "port_route_remove",
+    "port_route_clear",
+    "port_route_list",
+    "sock_status",
+    "sock_addr_local",
+    "sock_addr_peer",
+    "sock_open",
+    "sock_set_opt_flag",
+    "sock_get_opt_flag",
+    "sock_set_opt_time",
+    "sock_get_opt_time",
+    "sock_set_opt_size",
+    "sock_get_opt_size",
+    "sock_join_multicast_v4",
+    "sock_leave_multicast_v4",
+    "sock_join_multicast_v6",
+    "sock_leave_multicast_v6",
+    "sock_bind",
+    "sock_listen",
+    "sock_accept",
+    "sock_connect",
+    "sock_recv_from",
+    "sock_send_to",
+    "sock_send_file",
+    "resolve",
+];
+
 fn wasi_unstable_exports(mut store: &mut impl AsStoreMut, ctx: &FunctionEnv<WasiEnv>) -> Exports {
     let namespace = namespace! {
-        "args_get" => Function::new_native(&mut store, ctx, args_get::<Memory32>),
-        "args_sizes_get" => Function::new_native(&mut store, ctx, args_sizes_get::<Memory32>),
-        "clock_res_get" => Function::new_native(&mut store, ctx, clock_res_get::<Memory32>),
-        "clock_time_get" => Function::new_native(&mut store, ctx, clock_time_get::<Memory32>),
-        "environ_get" => Function::new_native(&mut store, ctx, environ_get::<Memory32>),
-        "environ_sizes_get" => Function::new_native(&mut store, ctx, environ_sizes_get::<Memory32>),
-        "fd_advise" => Function::new_native(&mut store, ctx, fd_advise),
-        "fd_allocate" => Function::new_native(&mut store, ctx, fd_allocate),
-        "fd_close" => Function::new_native(&mut store, ctx, fd_close),
-        "fd_datasync" => Function::new_native(&mut store, ctx, fd_datasync),
-        "fd_fdstat_get" => Function::new_native(&mut store, ctx, fd_fdstat_get::<Memory32>),
-        "fd_fdstat_set_flags" => Function::new_native(&mut store, ctx, fd_fdstat_set_flags),
-        "fd_fdstat_set_rights" => Function::new_native(&mut store, ctx, fd_fdstat_set_rights),
-        "fd_filestat_get" => Function::new_native(&mut store, ctx, legacy::snapshot0::fd_filestat_get),
-        "fd_filestat_set_size" => Function::new_native(&mut store, ctx, fd_filestat_set_size),
-        "fd_filestat_set_times" => Function::new_native(&mut store, ctx, fd_filestat_set_times),
-        "fd_pread" => Function::new_native(&mut store, ctx, fd_pread::<Memory32>),
-        "fd_prestat_get" => Function::new_native(&mut store, ctx, fd_prestat_get::<Memory32>),
-        "fd_prestat_dir_name" => Function::new_native(&mut store, ctx, fd_prestat_dir_name::<Memory32>),
-        "fd_pwrite" => Function::new_native(&mut store, ctx, fd_pwrite::<Memory32>),
-        "fd_read" => Function::new_native(&mut store, ctx, fd_read::<Memory32>),
-        "fd_readdir" => Function::new_native(&mut store, ctx, fd_readdir::<Memory32>),
-        "fd_renumber" => Function::new_native(&mut store, ctx, fd_renumber),
-        "fd_seek" => Function::new_native(&mut store, ctx, legacy::snapshot0::fd_seek),
-        "fd_sync" => Function::new_native(&mut store, ctx, fd_sync),
-        "fd_tell" => Function::new_native(&mut store, ctx, fd_tell::<Memory32>),
-        "fd_write" => Function::new_native(&mut store, ctx, fd_write::<Memory32>),
-        "path_create_directory" => Function::new_native(&mut store, ctx, path_create_directory::<Memory32>),
-        "path_filestat_get" => Function::new_native(&mut store, ctx, legacy::snapshot0::path_filestat_get),
-        "path_filestat_set_times" => Function::new_native(&mut store, ctx, path_filestat_set_times::<Memory32>),
-        "path_link" => Function::new_native(&mut store, ctx, path_link::<Memory32>),
-        "path_open" => Function::new_native(&mut store, ctx, path_open::<Memory32>),
-        "path_readlink" => Function::new_native(&mut store, ctx, path_readlink::<Memory32>),
-        "path_remove_directory" => Function::new_native(&mut store, ctx, path_remove_directory::<Memory32>),
-        "path_rename" => Function::new_native(&mut store, ctx, path_rename::<Memory32>),
-        "path_symlink" => Function::new_native(&mut store, ctx, path_symlink::<Memory32>),
-        "path_unlink_file" => Function::new_native(&mut store, ctx, path_unlink_file::<Memory32>),
-        "poll_oneoff" => Function::new_native(&mut store, ctx, legacy::snapshot0::poll_oneoff),
+        "args_get" => syscall!(store, ctx, "args_get", args_get::<Memory32>, (argv, argv_buf)),
+        "args_sizes_get" => syscall!(store, ctx, "args_sizes_get", args_sizes_get::<Memory32>, (argc, argv_buf_size)),
+        "clock_res_get" => syscall!(store, ctx, "clock_res_get", clock_res_get::<Memory32>, (clock_id, resolution)),
+        "clock_time_get" => syscall!(store, ctx, "clock_time_get", clock_time_get::<Memory32>, (clock_id, precision, time)),
+        "environ_get" => syscall!(store, ctx, "environ_get", environ_get::<Memory32>, (environ, environ_buf)),
+        "environ_sizes_get" => syscall!(store, ctx, "environ_sizes_get", environ_sizes_get::<Memory32>, (environ_count, environ_buf_size)),
+        "fd_advise" => syscall!(store, ctx, "fd_advise", fd_advise, (fd, offset, len, advice)),
+        "fd_allocate" => syscall!(store, ctx, "fd_allocate", fd_allocate, (fd, offset, len)),
+        "fd_close" => syscall!(store, ctx, "fd_close", fd_close, (fd)),
+        "fd_datasync" => syscall!(store, ctx, "fd_datasync", fd_datasync, (fd)),
+        "fd_fdstat_get" => syscall!(store, ctx, "fd_fdstat_get", fd_fdstat_get::<Memory32>, (fd, buf_ptr)),
+        "fd_fdstat_set_flags" => syscall!(store, ctx, "fd_fdstat_set_flags", fd_fdstat_set_flags, (fd, flags)),
+        "fd_fdstat_set_rights" => syscall!(store, ctx, "fd_fdstat_set_rights", fd_fdstat_set_rights, (fd, fs_rights_base, fs_rights_inheriting)),
+        "fd_filestat_get" => syscall!(store, ctx, "fd_filestat_get", legacy::snapshot0::fd_filestat_get, (fd, buf)),
+        "fd_filestat_set_size" => syscall!(store, ctx, "fd_filestat_set_size", fd_filestat_set_size, (fd, st_size)),
+        "fd_filestat_set_times" => syscall!(store, ctx, "fd_filestat_set_times", fd_filestat_set_times, (fd, st_atim, st_mtim, fst_flags)),
+        "fd_pread" => syscall!(store, ctx, "fd_pread", fd_pread::<Memory32>, (fd, iovs, iovs_len, offset, nread)),
+        "fd_prestat_get" => syscall!(store, ctx, "fd_prestat_get", fd_prestat_get::<Memory32>, (fd, buf)),
+        "fd_prestat_dir_name" => syscall!(store, ctx, "fd_prestat_dir_name", fd_prestat_dir_name::<Memory32>, (fd, path, path_len)),
+        "fd_pwrite" => syscall!(store, ctx, "fd_pwrite", fd_pwrite::<Memory32>, (fd, iovs, iovs_len, offset, nwritten)),
+        "fd_read" => syscall!(store, ctx, "fd_read", fd_read::<Memory32>, (fd, iovs, iovs_len, nread)),
+        "fd_readdir" => syscall!(store, ctx, "fd_readdir", fd_readdir::<Memory32>, (fd, buf, buf_len, cookie, bufused)),
+        "fd_renumber" => syscall!(store, ctx, "fd_renumber", fd_renumber, (from, to)),
+        "fd_seek" => syscall!(store, ctx, "fd_seek", legacy::snapshot0::fd_seek, (fd, offset, whence, newoffset)),
+        "fd_sync" => syscall!(store, ctx, "fd_sync", fd_sync, (fd)),
+        "fd_tell" => syscall!(store, ctx, "fd_tell", fd_tell::<Memory32>, (fd, offset)),
+        "fd_write" => syscall!(store, ctx, "fd_write", fd_write::<Memory32>, (fd, iovs, iovs_len, nwritten)),
+        "path_create_directory" => syscall!(store, ctx, "path_create_directory", path_create_directory::<Memory32>, (fd, path, path_len)),
+        "path_filestat_get" => syscall!(store, ctx, "path_filestat_get", legacy::snapshot0::path_filestat_get, (fd, flags, path, path_len, buf)),
+        "path_filestat_set_times" => syscall!(store, ctx, "path_filestat_set_times", path_filestat_set_times::<Memory32>, (fd, flags, path, path_len, st_atim, st_mtim, fst_flags)),
+        "path_link" => syscall!(store, ctx, "path_link", path_link::<Memory32>, (old_fd, old_flags, old_path, old_path_len, new_fd, new_path, new_path_len)),
+        "path_open" => syscall!(store, ctx, "path_open", path_open::<Memory32>, (dirfd, dirflags, path, path_len, o_flags, fs_rights_base, fs_rights_inheriting, fs_flags, fd)),
+        "path_readlink" => syscall!(store, ctx, "path_readlink", path_readlink::<Memory32>, (dir_fd, path, path_len, buf, buf_len, buf_used)),
+        "path_remove_directory" => syscall!(store, ctx, "path_remove_directory", path_remove_directory::<Memory32>, (fd, path, path_len)),
+        "path_rename" => syscall!(store, ctx, "path_rename", path_rename::<Memory32>, (old_fd, old_path, old_path_len, new_fd, new_path, new_path_len)),
+        "path_symlink" => syscall!(store, ctx, "path_symlink", path_symlink::<Memory32>, (old_path, old_path_len, fd, new_path, new_path_len)),
+        "path_unlink_file" => syscall!(store, ctx, "path_unlink_file", path_unlink_file::<Memory32>, (fd, path, path_len)),
+        "poll_oneoff" => syscall!(store, ctx, "poll_oneoff", legacy::snapshot0::poll_oneoff, (in_, out_, nsubscriptions, nevents)),
         "proc_exit" => Function::new_native(&mut store, ctx, proc_exit),
-        "proc_raise" => Function::new_native(&mut store, ctx, proc_raise),
-        "random_get" => Function::new_native(&mut store, ctx, random_get::<Memory32>),
-        "sched_yield" => Function::new_native(&mut store, ctx, sched_yield),
-        "sock_recv" => Function::new_native(&mut store, ctx, sock_recv::<Memory32>),
-        "sock_send" => Function::new_native(&mut store, ctx, sock_send::<Memory32>),
-        "sock_shutdown" => Function::new_native(&mut store, ctx, sock_shutdown),
+        "proc_raise" => syscall!(store, ctx, "proc_raise", proc_raise, (sig)),
+        "random_get" => syscall!(store, ctx, "random_get", random_get::<Memory32>, (buf, buf_len)),
+        "sched_yield" => syscall!(store, ctx, "sched_yield", sched_yield, ()),
+        "sock_recv" => syscall!(store, ctx, "sock_recv", sock_recv::<Memory32>, (sock, ri_data, ri_data_len, ri_flags, ro_data_len, ro_flags)),
+        "sock_send" => syscall!(store, ctx, "sock_send", sock_send::<Memory32>, (sock, si_data, si_data_len, si_flags, ret_data_len)),
+        "sock_shutdown" => syscall!(store, ctx, "sock_shutdown", sock_shutdown, (sock, how)),
     };
     namespace
 }
@@ -447,51 +771,51 @@ fn wasi_snapshot_preview1_exports(
     ctx: &FunctionEnv<WasiEnv>,
 ) -> Exports {
     let namespace = namespace! {
-        "args_get" => Function::new_native(&mut store, ctx, args_get::<Memory32>),
-        "args_sizes_get" => Function::new_native(&mut store, ctx, args_sizes_get::<Memory32>),
-        "clock_res_get" => Function::new_native(&mut store, ctx, clock_res_get::<Memory32>),
-        "clock_time_get" => Function::new_native(&mut store, ctx, clock_time_get::<Memory32>),
-        "environ_get" => Function::new_native(&mut store, ctx, environ_get::<Memory32>),
-        "environ_sizes_get" => Function::new_native(&mut store, ctx, environ_sizes_get::<Memory32>),
-        "fd_advise" => Function::new_native(&mut store, ctx, fd_advise),
-        "fd_allocate" => Function::new_native(&mut store, ctx, fd_allocate),
-        "fd_close" => Function::new_native(&mut store, ctx, fd_close),
-        "fd_datasync" => Function::new_native(&mut store, ctx, fd_datasync),
-        "fd_fdstat_get" => Function::new_native(&mut store, ctx, fd_fdstat_get::<Memory32>),
-        "fd_fdstat_set_flags" => Function::new_native(&mut store, ctx, fd_fdstat_set_flags),
-        "fd_fdstat_set_rights" => Function::new_native(&mut store, ctx, fd_fdstat_set_rights),
-        "fd_filestat_get" => Function::new_native(&mut store, ctx, fd_filestat_get::<Memory32>),
-        "fd_filestat_set_size" => Function::new_native(&mut store, ctx, fd_filestat_set_size),
-        "fd_filestat_set_times" => Function::new_native(&mut store, ctx, fd_filestat_set_times),
-        "fd_pread" => Function::new_native(&mut store, ctx, fd_pread::<Memory32>),
-        "fd_prestat_get" => Function::new_native(&mut store, ctx, fd_prestat_get::<Memory32>),
-        "fd_prestat_dir_name" => Function::new_native(&mut store, ctx, fd_prestat_dir_name::<Memory32>),
-        "fd_pwrite" => Function::new_native(&mut store, ctx, fd_pwrite::<Memory32>),
-        "fd_read" => Function::new_native(&mut store, ctx, fd_read::<Memory32>),
-        "fd_readdir" => Function::new_native(&mut store, ctx, fd_readdir::<Memory32>),
-        "fd_renumber" => Function::new_native(&mut store, ctx, fd_renumber),
-        "fd_seek" => Function::new_native(&mut store, ctx, fd_seek::<Memory32>),
-        "fd_sync" => Function::new_native(&mut store, ctx, fd_sync),
-        "fd_tell" => Function::new_native(&mut store, ctx, fd_tell::<Memory32>),
-        "fd_write" => Function::new_native(&mut store, ctx, fd_write::<Memory32>),
-        "path_create_directory" => Function::new_native(&mut store, ctx, path_create_directory::<Memory32>),
-        "path_filestat_get" => Function::new_native(&mut store, ctx, path_filestat_get::<Memory32>),
-        "path_filestat_set_times" => Function::new_native(&mut store, ctx, path_filestat_set_times::<Memory32>),
-        "path_link" => Function::new_native(&mut store, ctx, path_link::<Memory32>),
-        "path_open" => Function::new_native(&mut store, ctx, path_open::<Memory32>),
-        "path_readlink" => Function::new_native(&mut store, ctx, path_readlink::<Memory32>),
-        "path_remove_directory" => Function::new_native(&mut store, ctx, path_remove_directory::<Memory32>),
-        "path_rename" => Function::new_native(&mut store, ctx, path_rename::<Memory32>),
-        "path_symlink" => Function::new_native(&mut store, ctx, path_symlink::<Memory32>),
-        "path_unlink_file" => Function::new_native(&mut store, ctx, path_unlink_file::<Memory32>),
-        "poll_oneoff" => Function::new_native(&mut store, ctx, poll_oneoff::<Memory32>),
+        "args_get" => syscall!(store, ctx, "args_get", args_get::<Memory32>, (argv, argv_buf)),
+        "args_sizes_get" => syscall!(store, ctx, "args_sizes_get", args_sizes_get::<Memory32>, (argc, argv_buf_size)),
+        "clock_res_get" => syscall!(store, ctx, "clock_res_get", clock_res_get::<Memory32>, (clock_id, resolution)),
+        "clock_time_get" => syscall!(store, ctx, "clock_time_get", clock_time_get::<Memory32>, (clock_id, precision, time)),
+        "environ_get" => syscall!(store, ctx, "environ_get", environ_get::<Memory32>, (environ, environ_buf)),
+        "environ_sizes_get" => syscall!(store, ctx, "environ_sizes_get", environ_sizes_get::<Memory32>, (environ_count, environ_buf_size)),
+        "fd_advise" => syscall!(store, ctx, "fd_advise", fd_advise, (fd, offset, len, advice)),
+        "fd_allocate" => syscall!(store, ctx, "fd_allocate", fd_allocate, (fd, offset, len)),
+        "fd_close" => syscall!(store, ctx, "fd_close", fd_close, (fd)),
+        "fd_datasync" => syscall!(store, ctx, "fd_datasync", fd_datasync, (fd)),
+        "fd_fdstat_get" => syscall!(store, ctx, "fd_fdstat_get", fd_fdstat_get::<Memory32>, (fd, buf_ptr)),
+        "fd_fdstat_set_flags" => syscall!(store, ctx, "fd_fdstat_set_flags", fd_fdstat_set_flags, (fd, flags)),
+        "fd_fdstat_set_rights" => syscall!(store, ctx, "fd_fdstat_set_rights", fd_fdstat_set_rights, (fd, fs_rights_base, fs_rights_inheriting)),
+        "fd_filestat_get" => syscall!(store, ctx, "fd_filestat_get", fd_filestat_get::<Memory32>, (fd, buf)),
+        "fd_filestat_set_size" => syscall!(store, ctx, "fd_filestat_set_size", fd_filestat_set_size, (fd, st_size)),
+        "fd_filestat_set_times" => syscall!(store, ctx, "fd_filestat_set_times", fd_filestat_set_times, (fd, st_atim, st_mtim, fst_flags)),
+        "fd_pread" => syscall!(store, ctx, "fd_pread", fd_pread::<Memory32>, (fd, iovs, iovs_len, offset, nread)),
+        "fd_prestat_get" => syscall!(store, ctx, "fd_prestat_get", fd_prestat_get::<Memory32>, (fd, buf)),
+        "fd_prestat_dir_name" => syscall!(store, ctx, "fd_prestat_dir_name", fd_prestat_dir_name::<Memory32>, (fd, path, path_len)),
+        "fd_pwrite" => syscall!(store, ctx, "fd_pwrite", fd_pwrite::<Memory32>, (fd, iovs, iovs_len, offset, nwritten)),
+        "fd_read" => syscall!(store, ctx, "fd_read", fd_read::<Memory32>, (fd, iovs, iovs_len, nread)),
+        "fd_readdir" => syscall!(store, ctx, "fd_readdir", fd_readdir::<Memory32>, (fd, buf, buf_len, cookie, bufused)),
+        "fd_renumber" => syscall!(store, ctx, "fd_renumber", fd_renumber, (from, to)),
+        "fd_seek" => syscall!(store, ctx, "fd_seek", fd_seek::<Memory32>, (fd, offset, whence, newoffset)),
+        "fd_sync" => syscall!(store, ctx, "fd_sync", fd_sync, (fd)),
+        "fd_tell" => syscall!(store, ctx, "fd_tell", fd_tell::<Memory32>, (fd, offset)),
+        "fd_write" => syscall!(store, ctx, "fd_write", fd_write::<Memory32>, (fd, iovs, iovs_len, nwritten)),
+        "path_create_directory" => syscall!(store, ctx, "path_create_directory", path_create_directory::<Memory32>, (fd, path, path_len)),
+        "path_filestat_get" => syscall!(store, ctx, "path_filestat_get", path_filestat_get::<Memory32>, (fd, flags, path, path_len, buf)),
+        "path_filestat_set_times" => syscall!(store, ctx, "path_filestat_set_times", path_filestat_set_times::<Memory32>, (fd, flags, path, path_len, st_atim, st_mtim, fst_flags)),
+        "path_link" => syscall!(store, ctx, "path_link", path_link::<Memory32>, (old_fd, old_flags, old_path, old_path_len, new_fd, new_path, new_path_len)),
+        "path_open" => syscall!(store, ctx, "path_open", path_open::<Memory32>, (dirfd, dirflags, path, path_len, o_flags, fs_rights_base, fs_rights_inheriting, fs_flags, fd)),
+        "path_readlink" => syscall!(store, ctx, "path_readlink", path_readlink::<Memory32>, (dir_fd, path, path_len, buf, buf_len, buf_used)),
+        "path_remove_directory" => syscall!(store, ctx, "path_remove_directory", path_remove_directory::<Memory32>, (fd, path, path_len)),
+        "path_rename" => syscall!(store, ctx, "path_rename", path_rename::<Memory32>, (old_fd, old_path, old_path_len, new_fd, new_path, new_path_len)),
+        "path_symlink" => syscall!(store, ctx, "path_symlink", path_symlink::<Memory32>, (old_path, old_path_len, fd, new_path, new_path_len)),
+        "path_unlink_file" => syscall!(store, ctx, "path_unlink_file", path_unlink_file::<Memory32>, (fd, path, path_len)),
+        "poll_oneoff" => syscall!(store, ctx, "poll_oneoff", poll_oneoff::<Memory32>, (in_, out_, nsubscriptions, nevents)),
         "proc_exit" => Function::new_native(&mut store, ctx, proc_exit),
-        "proc_raise" => Function::new_native(&mut store, ctx, proc_raise),
-        "random_get" => Function::new_native(&mut store, ctx, random_get::<Memory32>),
-        "sched_yield" => Function::new_native(&mut store, ctx, sched_yield),
-        "sock_recv" => Function::new_native(&mut store, ctx, sock_recv::<Memory32>),
-        "sock_send" => Function::new_native(&mut store, ctx, sock_send::<Memory32>),
-        "sock_shutdown" => Function::new_native(&mut store, ctx, sock_shutdown),
+        "proc_raise" => syscall!(store, ctx, "proc_raise", proc_raise, (sig)),
+        "random_get" => syscall!(store, ctx, "random_get", random_get::<Memory32>, (buf, buf_len)),
+        "sched_yield" => syscall!(store, ctx, "sched_yield", sched_yield, ()),
+        "sock_recv" => syscall!(store, ctx, "sock_recv", sock_recv::<Memory32>, (sock, ri_data, ri_data_len, ri_flags, ro_data_len, ro_flags)),
+        "sock_send" => syscall!(store, ctx, "sock_send", sock_send::<Memory32>, (sock, si_data, si_data_len, si_flags, ret_data_len)),
+        "sock_shutdown" => syscall!(store, ctx, "sock_shutdown", sock_shutdown, (sock, how)),
     };
     namespace
 }
@@ -528,6 +852,20 @@ fn generate_import_object_snapshot1(
     }
 }
 
+/// Like [`generate_import_object_snapshot1`], the snapshot 1 imports are
+/// also exposed under the bare `wasi` namespace
+fn generate_import_object_latest(
+    store: &mut impl AsStoreMut,
+    ctx: &FunctionEnv<WasiEnv>,
+) -> Imports {
+    let wasi_snapshot_preview1_exports = wasi_snapshot_preview1_exports(store, ctx);
+    let wasi_exports = wasi_snapshot_preview1_exports.clone();
+    imports! {
+        "wasi_snapshot_preview1" => wasi_snapshot_preview1_exports,
+        utils::BARE_NAMESPACE => wasi_exports,
+    }
+}
+
 /// Combines a state generating function with the import list for snapshot 1
 fn generate_import_object_wasix32_v1(
     mut store: &mut impl AsStoreMut,
@@ -536,62 +874,62 @@ fn generate_import_object_wasix32_v1(
     use self::wasix32::*;
     imports! {
         "wasix_32v1" => {
-            "args_get" => Function::new_native(&mut store, ctx, args_get),
-            "args_sizes_get" => Function::new_native(&mut store, ctx, args_sizes_get),
-            "clock_res_get" => Function::new_native(&mut store, ctx, clock_res_get),
-            "clock_time_get" => Function::new_native(&mut store, ctx, clock_time_get),
-            "environ_get" => Function::new_native(&mut store, ctx, environ_get),
-            "environ_sizes_get" => Function::new_native(&mut store, ctx, environ_sizes_get),
-            "fd_advise" => Function::new_native(&mut store, ctx, fd_advise),
-            "fd_allocate" => Function::new_native(&mut store, ctx, fd_allocate),
-            "fd_close" => Function::new_native(&mut store, ctx, fd_close),
-            "fd_datasync" => Function::new_native(&mut store, ctx, fd_datasync),
-            "fd_fdstat_get" => Function::new_native(&mut store, ctx, fd_fdstat_get),
-            "fd_fdstat_set_flags" => Function::new_native(&mut store, ctx, fd_fdstat_set_flags),
-            "fd_fdstat_set_rights" => Function::new_native(&mut store, ctx, fd_fdstat_set_rights),
-            "fd_filestat_get" => Function::new_native(&mut store, ctx, fd_filestat_get),
-            "fd_filestat_set_size" => Function::new_native(&mut store, ctx, fd_filestat_set_size),
-            "fd_filestat_set_times" => Function::new_native(&mut store, ctx, fd_filestat_set_times),
-            "fd_pread" => Function::new_native(&mut store, ctx, fd_pread),
-            "fd_prestat_get" => Function::new_native(&mut store, ctx, fd_prestat_get),
-            "fd_prestat_dir_name" => Function::new_native(&mut store, ctx, fd_prestat_dir_name),
-            "fd_pwrite" => Function::new_native(&mut store, ctx, fd_pwrite),
-            "fd_read" => Function::new_native(&mut store, ctx, fd_read),
-            "fd_readdir" => Function::new_native(&mut store, ctx, fd_readdir),
-            "fd_renumber" => Function::new_native(&mut store, ctx, fd_renumber),
-            "fd_dup" => Function::new_native(&mut store, ctx, fd_dup),
-            "fd_event" => Function::new_native(&mut store, ctx, fd_event),
-            "fd_seek" => Function::new_native(&mut store, ctx, fd_seek),
-            "fd_sync" => Function::new_native(&mut store, ctx, fd_sync),
-            "fd_tell" => Function::new_native(&mut store, ctx, fd_tell),
-            "fd_write" => Function::new_native(&mut store, ctx, fd_write),
-            "fd_pipe" => Function::new_native(&mut store, ctx, fd_pipe),
-            "path_create_directory" => Function::new_native(&mut store, ctx, path_create_directory),
-            "path_filestat_get" => Function::new_native(&mut store, ctx, path_filestat_get),
-            "path_filestat_set_times" => Function::new_native(&mut store, ctx, path_filestat_set_times),
-            "path_link" => Function::new_native(&mut store, ctx, path_link),
-            "path_open" => Function::new_native(&mut store, ctx, path_open),
-            "path_readlink" => Function::new_native(&mut store, ctx, path_readlink),
-            "path_remove_directory" => Function::new_native(&mut store, ctx, path_remove_directory),
-            "path_rename" => Function::new_native(&mut store, ctx, path_rename),
-            "path_symlink" => Function::new_native(&mut store, ctx, path_symlink),
-            "path_unlink_file" => Function::new_native(&mut store, ctx, path_unlink_file),
-            "poll_oneoff" => Function::new_native(&mut store, ctx, poll_oneoff),
+            "args_get" => syscall!(store, ctx, "args_get", args_get, (argv, argv_buf)),
+            "args_sizes_get" => syscall!(store, ctx, "args_sizes_get", args_sizes_get, (argc, argv_buf_size)),
+            "clock_res_get" => syscall!(store, ctx, "clock_res_get", clock_res_get, (clock_id, resolution)),
+            "clock_time_get" => syscall!(store, ctx, "clock_time_get", clock_time_get, (clock_id, precision, time)),
+            "environ_get" => syscall!(store, ctx, "environ_get", environ_get, (environ, environ_buf)),
+            "environ_sizes_get" => syscall!(store, ctx, "environ_sizes_get", environ_sizes_get, (environ_count, environ_buf_size)),
+            "fd_advise" => syscall!(store, ctx, "fd_advise", fd_advise, (fd, offset, len, advice)),
+            "fd_allocate" => syscall!(store, ctx, "fd_allocate", fd_allocate, (fd, offset, len)),
+            "fd_close" => syscall!(store, ctx, "fd_close", fd_close, (fd)),
+            "fd_datasync" => syscall!(store, ctx, "fd_datasync", fd_datasync, (fd)),
+            "fd_fdstat_get" => syscall!(store, ctx, "fd_fdstat_get", fd_fdstat_get, (fd, buf_ptr)),
+            "fd_fdstat_set_flags" => syscall!(store, ctx, "fd_fdstat_set_flags", fd_fdstat_set_flags, (fd, flags)),
+            "fd_fdstat_set_rights" => syscall!(store, ctx, "fd_fdstat_set_rights", fd_fdstat_set_rights, (fd, fs_rights_base, fs_rights_inheriting)),
+            "fd_filestat_get" => syscall!(store, ctx, "fd_filestat_get", fd_filestat_get, (fd, buf)),
+            "fd_filestat_set_size" => syscall!(store, ctx, "fd_filestat_set_size", fd_filestat_set_size, (fd, st_size)),
+            "fd_filestat_set_times" => syscall!(store, ctx, "fd_filestat_set_times", fd_filestat_set_times, (fd, st_atim, st_mtim, fst_flags)),
+            "fd_pread" => syscall!(store, ctx, "fd_pread", fd_pread, (fd, iovs, iovs_len, offset, nread)),
+            "fd_prestat_get" => syscall!(store, ctx, "fd_prestat_get", fd_prestat_get, (fd, buf)),
+            "fd_prestat_dir_name" => syscall!(store, ctx, "fd_prestat_dir_name", fd_prestat_dir_name, (fd, path, path_len)),
+            "fd_pwrite" => syscall!(store, ctx, "fd_pwrite", fd_pwrite, (fd, iovs, iovs_len, offset, nwritten)),
+            "fd_read" => syscall!(store, ctx, "fd_read", fd_read, (fd, iovs, iovs_len, nread)),
+            "fd_readdir" => syscall!(store, ctx, "fd_readdir", fd_readdir, (fd, buf, buf_len, cookie, bufused)),
+            "fd_renumber" => syscall!(store, ctx, "fd_renumber", fd_renumber, (from, to)),
+            "fd_dup" => syscall!(store, ctx, "fd_dup", fd_dup, (fd, ret_fd)),
+            "fd_event" => syscall!(store, ctx, "fd_event", fd_event, (initial_val, flags, ret_fd)),
+            "fd_seek" => syscall!(store, ctx, "fd_seek", fd_seek, (fd, offset, whence, newoffset)),
+            "fd_sync" => syscall!(store, ctx, "fd_sync", fd_sync, (fd)),
+            "fd_tell" => syscall!(store, ctx, "fd_tell", fd_tell, (fd, offset)),
+            "fd_write" => syscall!(store, ctx, "fd_write", fd_write, (fd, iovs, iovs_len, nwritten)),
+            "fd_pipe" => syscall!(store, ctx, "fd_pipe", fd_pipe, (ro_fd1, ro_fd2)),
+            "path_create_directory" => syscall!(store, ctx, "path_create_directory", path_create_directory, (fd, path, path_len)),
+            "path_filestat_get" => syscall!(store, ctx, "path_filestat_get", path_filestat_get, (fd, flags, path, path_len, buf)),
+            "path_filestat_set_times" => syscall!(store, ctx, "path_filestat_set_times", path_filestat_set_times, (fd, flags, path, path_len, st_atim, st_mtim, fst_flags)),
+            "path_link" => syscall!(store, ctx, "path_link", path_link, (old_fd, old_flags, old_path, old_path_len, new_fd, new_path, new_path_len)),
+            "path_open" => syscall!(store, ctx, "path_open", path_open, (dirfd, dirflags, path, path_len, o_flags, fs_rights_base, fs_rights_inheriting, fs_flags, fd)),
+            "path_readlink" => syscall!(store, ctx, "path_readlink", path_readlink, (dir_fd, path, path_len, buf, buf_len, buf_used)),
+            "path_remove_directory" => syscall!(store, ctx, "path_remove_directory", path_remove_directory, (fd, path, path_len)),
+            "path_rename" => syscall!(store, ctx, "path_rename", path_rename, (old_fd, old_path, old_path_len, new_fd, new_path, new_path_len)),
+            "path_symlink" => syscall!(store, ctx, "path_symlink", path_symlink, (old_path, old_path_len, fd, new_path, new_path_len)),
+            "path_unlink_file" => syscall!(store, ctx, "path_unlink_file", path_unlink_file, (fd, path, path_len)),
+            "poll_oneoff" => syscall!(store, ctx, "poll_oneoff", poll_oneoff, (in_, out_, nsubscriptions, nevents)),
             "proc_exit" => Function::new_native(&mut store, ctx, proc_exit),
-            "proc_raise" => Function::new_native(&mut store, ctx, proc_raise),
-            "random_get" => Function::new_native(&mut store, ctx, random_get),
-            "tty_get" => Function::new_native(&mut store, ctx, tty_get),
-            "tty_set" => Function::new_native(&mut store, ctx, tty_set),
-            "getcwd" => Function::new_native(&mut store, ctx, getcwd),
-            "chdir" => Function::new_native(&mut store, ctx, chdir),
-            "thread_spawn" => Function::new_native(&mut store, ctx, thread_spawn),
-            "thread_sleep" => Function::new_native(&mut store, ctx, thread_sleep),
-            "thread_id" => Function::new_native(&mut store, ctx, thread_id),
-            "thread_join" => Function::new_native(&mut store, ctx, thread_join),
-            "thread_parallelism" => Function::new_native(&mut store, ctx, thread_parallelism),
-            "thread_exit" => Function::new_native(&mut store, ctx, thread_exit),
-            "sched_yield" => Function::new_native(&mut store, ctx, sched_yield),
-            "getpid" => Function::new_native(&mut store, ctx, getpid),
+            "proc_raise" => syscall!(store, ctx, "proc_raise", proc_raise, (sig)),
+            "random_get" => syscall!(store, ctx, "random_get", random_get, (buf, buf_len)),
+            "tty_get" => syscall!(store, ctx, "tty_get", tty_get, (tty_state)),
+            "tty_set" => syscall!(store, ctx, "tty_set", tty_set, (tty_state)),
+            "getcwd" => syscall!(store, ctx, "getcwd", getcwd, (path, path_len)),
+            "chdir" => syscall!(store, ctx, "chdir", chdir, (path, path_len)),
+            "thread_spawn" => syscall!(store, ctx, "thread_spawn", thread_spawn, (method, method_len, user_data, reactor, ret_tid)),
+            "thread_sleep" => syscall!(store, ctx, "thread_sleep", thread_sleep, (duration)),
+            "thread_id" => syscall!(store, ctx, "thread_id", thread_id, (ret_tid)),
+            "thread_join" => syscall!(store, ctx, "thread_join", thread_join, (tid)),
+            "thread_parallelism" => syscall!(store, ctx, "thread_parallelism", thread_parallelism, (ret_parallelism)),
+            "thread_exit" => syscall!(store, ctx, "thread_exit", thread_exit, (exitcode)),
+            "sched_yield" => syscall!(store, ctx, "sched_yield", sched_yield, ()),
+            "getpid" => syscall!(store, ctx, "getpid", getpid, (ret_pid)),
             "process_spawn" => Function::new_native(&mut store, ctx, process_spawn),
             "bus_open_local" => Function::new_native(&mut store, ctx, bus_open_local),
             "bus_open_remote" => Function::new_native(&mut store, ctx, bus_open_remote),
@@ -602,47 +940,47 @@ fn generate_import_object_wasix32_v1(
             "call_reply" => Function::new_native(&mut store, ctx, call_reply),
             "call_fault" => Function::new_native(&mut store, ctx, call_fault),
             "call_close" => Function::new_native(&mut store, ctx, call_close),
-            "ws_connect" => Function::new_native(&mut store, ctx, ws_connect),
-            "http_request" => Function::new_native(&mut store, ctx, http_request),
-            "http_status" => Function::new_native(&mut store, ctx, http_status),
-            "port_bridge" => Function::new_native(&mut store, ctx, port_bridge),
-            "port_unbridge" => Function::new_native(&mut store, ctx, port_unbridge),
-            "port_dhcp_acquire" => Function::new_native(&mut store, ctx, port_dhcp_acquire),
-            "port_addr_add" => Function::new_native(&mut store, ctx, port_addr_add),
-            "port_addr_remove" => Function::new_native(&mut store, ctx, port_addr_remove),
-            "port_addr_clear" => Function::new_native(&mut store, ctx, port_addr_clear),
-            "port_addr_list" => Function::new_native(&mut store, ctx, port_addr_list),
-            "port_mac" => Function::new_native(&mut store, ctx, port_mac),
-            "port_gateway_set" => Function::new_native(&mut store, ctx, port_gateway_set),
-            "port_route_add" => Function::new_native(&mut store, ctx, port_route_add),
-            "port_route_remove" => Function::new_native(&mut store, ctx, port_route_remove),
-            "port_route_clear" => Function::new_native(&mut store, ctx, port_route_clear),
-            "port_route_list" => Function::new_native(&mut store, ctx, port_route_list),
-            "sock_status" => Function::new_native(&mut store, ctx, sock_status),
-            "sock_addr_local" => Function::new_native(&mut store, ctx, sock_addr_local),
-            "sock_addr_peer" => Function::new_native(&mut store, ctx, sock_addr_peer),
-            "sock_open" => Function::new_native(&mut store, ctx, sock_open),
-            "sock_set_opt_flag" => Function::new_native(&mut store, ctx, sock_set_opt_flag),
-            "sock_get_opt_flag" => Function::new_native(&mut store, ctx, sock_get_opt_flag),
-            "sock_set_opt_time" => Function::new_native(&mut store, ctx, sock_set_opt_time),
-            "sock_get_opt_time" => Function::new_native(&mut store, ctx, sock_get_opt_time),
-            "sock_set_opt_size" => Function::new_native(&mut store, ctx, sock_set_opt_size),
-            "sock_get_opt_size" => Function::new_native(&mut store, ctx, sock_get_opt_size),
-            "sock_join_multicast_v4" => Function::new_native(&mut store, ctx, sock_join_multicast_v4),
-            "sock_leave_multicast_v4" => Function::new_native(&mut store, ctx, sock_leave_multicast_v4),
-            "sock_join_multicast_v6" => Function::new_native(&mut store, ctx, sock_join_multicast_v6),
-            "sock_leave_multicast_v6" => Function::new_native(&mut store, ctx, sock_leave_multicast_v6),
-            "sock_bind" => Function::new_native(&mut store, ctx, sock_bind),
-            "sock_listen" => Function::new_native(&mut store, ctx, sock_listen),
-            "sock_accept" => Function::new_native(&mut store, ctx, sock_accept),
-            "sock_connect" => Function::new_native(&mut store, ctx, sock_connect),
-            "sock_recv" => Function::new_native(&mut store, ctx, sock_recv),
-            "sock_recv_from" => Function::new_native(&mut store, ctx, sock_recv_from),
-            "sock_send" => Function::new_native(&mut store, ctx, sock_send),
-            "sock_send_to" => Function::new_native(&mut store, ctx, sock_send_to),
-            "sock_send_file" => Function::new_native(&mut store, ctx, sock_send_file),
-            "sock_shutdown" => Function::new_native(&mut store, ctx, sock_shutdown),
-            "resolve" => Function::new_native(&mut store, ctx, resolve),
+            "ws_connect" => syscall!(store, ctx, "ws_connect", ws_connect, (url, url_len, ret_sock)),
+            "http_request" => syscall!(store, ctx, "http_request", http_request, (url, url_len, method, method_len, headers, headers_len, gzip, ret_handles)),
+            "http_status" => syscall!(store, ctx, "http_status", http_status, (sock, status, status_text, status_text_len, headers, headers_len)),
+            "port_bridge" => syscall!(store, ctx, "port_bridge", port_bridge, (network, network_len, token, token_len, security)),
+            "port_unbridge" => syscall!(store, ctx, "port_unbridge", port_unbridge, ()),
+            "port_dhcp_acquire" => syscall!(store, ctx, "port_dhcp_acquire", port_dhcp_acquire, ()),
+            "port_addr_add" => syscall!(store, ctx, "port_addr_add", port_addr_add, (addr)),
+            "port_addr_remove" => syscall!(store, ctx, "port_addr_remove", port_addr_remove, (addr)),
+            "port_addr_clear" => syscall!(store, ctx, "port_addr_clear", port_addr_clear, ()),
+            "port_addr_list" => syscall!(store, ctx, "port_addr_list", port_addr_list, (addrs, naddrs)),
+            "port_mac" => syscall!(store, ctx, "port_mac", port_mac, (ret_mac)),
+            "port_gateway_set" => syscall!(store, ctx, "port_gateway_set", port_gateway_set, (ip)),
+            "port_route_add" => syscall!(store, ctx, "port_route_add", port_route_add, (cidr, via_router, preferred_until, expires_at)),
+            "port_route_remove" => syscall!(store, ctx, "port_route_remove", port_route_remove, (ip)),
+            "port_route_clear" => syscall!(store, ctx, "port_route_clear", port_route_clear, ()),
+            "port_route_list" => syscall!(store, ctx, "port_route_list", port_route_list, (routes, nroutes)),
+            "sock_status" => syscall!(store, ctx, "sock_status", sock_status, (sock, ret_status)),
+            "sock_addr_local" => syscall!(store, ctx, "sock_addr_local", sock_addr_local, (sock, ret_addr)),
+            "sock_addr_peer" => syscall!(store, ctx, "sock_addr_peer", sock_addr_peer, (sock, ro_addr)),
+            "sock_open" => syscall!(store, ctx, "sock_open", sock_open, (af, ty, pt, ro_sock)),
+            "sock_set_opt_flag" => syscall!(store, ctx, "sock_set_opt_flag", sock_set_opt_flag, (sock, opt, flag)),
+            "sock_get_opt_flag" => syscall!(store, ctx, "sock_get_opt_flag", sock_get_opt_flag, (sock, opt, ret_flag)),
+            "sock_set_opt_time" => syscall!(store, ctx, "sock_set_opt_time", sock_set_opt_time, (sock, opt, time)),
+            "sock_get_opt_time" => syscall!(store, ctx, "sock_get_opt_time", sock_get_opt_time, (sock, opt, ret_time)),
+            "sock_set_opt_size" => syscall!(store, ctx, "sock_set_opt_size", sock_set_opt_size, (sock, opt, size)),
+            "sock_get_opt_size" => syscall!(store, ctx, "sock_get_opt_size", sock_get_opt_size, (sock, opt, ret_size)),
+            "sock_join_multicast_v4" => syscall!(store, ctx, "sock_join_multicast_v4", sock_join_multicast_v4, (sock, multiaddr, iface)),
+            "sock_leave_multicast_v4" => syscall!(store, ctx, "sock_leave_multicast_v4", sock_leave_multicast_v4, (sock, multiaddr, iface)),
+            "sock_join_multicast_v6" => syscall!(store, ctx, "sock_join_multicast_v6", sock_join_multicast_v6, (sock, multiaddr, iface)),
+            "sock_leave_multicast_v6" => syscall!(store, ctx, "sock_leave_multicast_v6", sock_leave_multicast_v6, (sock, multiaddr, iface)),
+            "sock_bind" => syscall!(store, ctx, "sock_bind", sock_bind, (sock, addr)),
+            "sock_listen" => syscall!(store, ctx, "sock_listen", sock_listen, (sock, backlog)),
+            "sock_accept" => syscall!(store, ctx, "sock_accept", sock_accept, (sock, fd_flags, ro_fd, ro_addr)),
+            "sock_connect" => syscall!(store, ctx, "sock_connect", sock_connect, (sock, addr)),
+            "sock_recv" => syscall!(store, ctx, "sock_recv", sock_recv, (sock, ri_data, ri_data_len, ri_flags, ro_data_len, ro_flags)),
+            "sock_recv_from" => syscall!(store, ctx, "sock_recv_from", sock_recv_from, (sock, ri_data, ri_data_len, ri_flags, ro_data_len, ro_flags, ro_addr)),
+            "sock_send" => syscall!(store, ctx, "sock_send", sock_send, (sock, si_data, si_data_len, si_flags, ret_data_len)),
+            "sock_send_to" => syscall!(store, ctx, "sock_send_to", sock_send_to, (sock, si_data, si_data_len, si_flags, addr, ret_data_len)),
+            "sock_send_file" => syscall!(store, ctx, "sock_send_file", sock_send_file, (out_fd, in_fd, offset, count, ret_sent)),
+            "sock_shutdown" => syscall!(store, ctx, "sock_shutdown", sock_shutdown, (sock, how)),
+            "resolve" => syscall!(store, ctx, "resolve", resolve, (host, host_len, port, ips, nips, ret_nips)),
         }
     }
 }
@@ -654,62 +992,62 @@ fn generate_import_object_wasix64_v1(
     use self::wasix64::*;
     imports! {
         "wasix_64v1" => {
-            "args_get" => Function::new_native(&mut store, ctx, args_get),
-            "args_sizes_get" => Function::new_native(&mut store, ctx, args_sizes_get),
-            "clock_res_get" => Function::new_native(&mut store, ctx, clock_res_get),
-            "clock_time_get" => Function::new_native(&mut store, ctx, clock_time_get),
-            "environ_get" => Function::new_native(&mut store, ctx, environ_get),
-            "environ_sizes_get" => Function::new_native(&mut store, ctx, environ_sizes_get),
-            "fd_advise" => Function::new_native(&mut store, ctx, fd_advise),
-            "fd_allocate" => Function::new_native(&mut store, ctx, fd_allocate),
-            "fd_close" => Function::new_native(&mut store, ctx, fd_close),
-            "fd_datasync" => Function::new_native(&mut store, ctx, fd_datasync),
-            "fd_fdstat_get" => Function::new_native(&mut store, ctx, fd_fdstat_get),
-            "fd_fdstat_set_flags" => Function::new_native(&mut store, ctx, fd_fdstat_set_flags),
-            "fd_fdstat_set_rights" => Function::new_native(&mut store, ctx, fd_fdstat_set_rights),
-            "fd_filestat_get" => Function::new_native(&mut store, ctx, fd_filestat_get),
-            "fd_filestat_set_size" => Function::new_native(&mut store, ctx, fd_filestat_set_size),
-            "fd_filestat_set_times" => Function::new_native(&mut store, ctx, fd_filestat_set_times),
-            "fd_pread" => Function::new_native(&mut store, ctx, fd_pread),
-            "fd_prestat_get" => Function::new_native(&mut store, ctx, fd_prestat_get),
-            "fd_prestat_dir_name" => Function::new_native(&mut store, ctx, fd_prestat_dir_name),
-            "fd_pwrite" => Function::new_native(&mut store, ctx, fd_pwrite),
-            "fd_read" => Function::new_native(&mut store, ctx, fd_read),
-            "fd_readdir" => Function::new_native(&mut store, ctx, fd_readdir),
-            "fd_renumber" => Function::new_native(&mut store, ctx, fd_renumber),
-            "fd_dup" => Function::new_native(&mut store, ctx, fd_dup),
-            "fd_event" => Function::new_native(&mut store, ctx, fd_event),
-            "fd_seek" => Function::new_native(&mut store, ctx, fd_seek),
-            "fd_sync" => Function::new_native(&mut store, ctx, fd_sync),
-            "fd_tell" => Function::new_native(&mut store, ctx, fd_tell),
-            "fd_write" => Function::new_native(&mut store, ctx, fd_write),
-            "fd_pipe" => Function::new_native(&mut store, ctx, fd_pipe),
-            "path_create_directory" => Function::new_native(&mut store, ctx, path_create_directory),
-            "path_filestat_get" => Function::new_native(&mut store, ctx, path_filestat_get),
-            "path_filestat_set_times" => Function::new_native(&mut store, ctx, path_filestat_set_times),
-            "path_link" => Function::new_native(&mut store, ctx, path_link),
-            "path_open" => Function::new_native(&mut store, ctx, path_open),
-            "path_readlink" => Function::new_native(&mut store, ctx, path_readlink),
-            "path_remove_directory" => Function::new_native(&mut store, ctx, path_remove_directory),
-            "path_rename" => Function::new_native(&mut store, ctx, path_rename),
-            "path_symlink" => Function::new_native(&mut store, ctx, path_symlink),
-            "path_unlink_file" => Function::new_native(&mut store, ctx, path_unlink_file),
-            "poll_oneoff" => Function::new_native(&mut store, ctx, poll_oneoff),
+            "args_get" => syscall!(store, ctx, "args_get", args_get, (argv, argv_buf)),
+            "args_sizes_get" => syscall!(store, ctx, "args_sizes_get", args_sizes_get, (argc, argv_buf_size)),
+            "clock_res_get" => syscall!(store, ctx, "clock_res_get", clock_res_get, (clock_id, resolution)),
+            "clock_time_get" => syscall!(store, ctx, "clock_time_get", clock_time_get, (clock_id, precision, time)),
+            "environ_get" => syscall!(store, ctx, "environ_get", environ_get, (environ, environ_buf)),
+            "environ_sizes_get" => syscall!(store, ctx, "environ_sizes_get", environ_sizes_get, (environ_count, environ_buf_size)),
+            "fd_advise" => syscall!(store, ctx, "fd_advise", fd_advise, (fd, offset, len, advice)),
+            "fd_allocate" => syscall!(store, ctx, "fd_allocate", fd_allocate, (fd, offset, len)),
+            "fd_close" => syscall!(store, ctx, "fd_close", fd_close, (fd)),
+            "fd_datasync" => syscall!(store, ctx, "fd_datasync", fd_datasync, (fd)),
+            "fd_fdstat_get" => syscall!(store, ctx, "fd_fdstat_get", fd_fdstat_get, (fd, buf_ptr)),
+            "fd_fdstat_set_flags" => syscall!(store, ctx, "fd_fdstat_set_flags", fd_fdstat_set_flags, (fd, flags)),
+            "fd_fdstat_set_rights" => syscall!(store, ctx, "fd_fdstat_set_rights", fd_fdstat_set_rights, (fd, fs_rights_base, fs_rights_inheriting)),
+            "fd_filestat_get" => syscall!(store, ctx, "fd_filestat_get", fd_filestat_get, (fd, buf)),
+            "fd_filestat_set_size" => syscall!(store, ctx, "fd_filestat_set_size", fd_filestat_set_size, (fd, st_size)),
+            "fd_filestat_set_times" => syscall!(store, ctx, "fd_filestat_set_times", fd_filestat_set_times, (fd, st_atim, st_mtim, fst_flags)),
+            "fd_pread" => syscall!(store, ctx, "fd_pread", fd_pread, (fd, iovs, iovs_len, offset, nread)),
+            "fd_prestat_get" => syscall!(store, ctx, "fd_prestat_get", fd_prestat_get, (fd, buf)),
+            "fd_prestat_dir_name" => syscall!(store, ctx, "fd_prestat_dir_name", fd_prestat_dir_name, (fd, path, path_len)),
+            "fd_pwrite" => syscall!(store, ctx, "fd_pwrite", fd_pwrite, (fd, iovs, iovs_len, offset, nwritten)),
+            "fd_read" => syscall!(store, ctx, "fd_read", fd_read, (fd, iovs, iovs_len, nread)),
+            "fd_readdir" => syscall!(store, ctx, "fd_readdir", fd_readdir, (fd, buf, buf_len, cookie, bufused)),
+            "fd_renumber" => syscall!(store, ctx, "fd_renumber", fd_renumber, (from, to)),
+            "fd_dup" => syscall!(store, ctx, "fd_dup", fd_dup, (fd, ret_fd)),
+            "fd_event" => syscall!(store, ctx, "fd_event", fd_event, (initial_val, flags, ret_fd)),
+            "fd_seek" => syscall!(store, ctx, "fd_seek", fd_seek, (fd, offset, whence, newoffset)),
+            "fd_sync" => syscall!(store, ctx, "fd_sync", fd_sync, (fd)),
+            "fd_tell" => syscall!(store, ctx, "fd_tell", fd_tell, (fd, offset)),
+            "fd_write" => syscall!(store, ctx, "fd_write", fd_write, (fd, iovs, iovs_len, nwritten)),
+            "fd_pipe" => syscall!(store, ctx, "fd_pipe", fd_pipe, (ro_fd1, ro_fd2)),
+            "path_create_directory" => syscall!(store, ctx, "path_create_directory", path_create_directory, (fd, path, path_len)),
+            "path_filestat_get" => syscall!(store, ctx, "path_filestat_get", path_filestat_get, (fd, flags, path, path_len, buf)),
+            "path_filestat_set_times" => syscall!(store, ctx, "path_filestat_set_times", path_filestat_set_times, (fd, flags, path, path_len, st_atim, st_mtim, fst_flags)),
+            "path_link" => syscall!(store, ctx, "path_link", path_link, (old_fd, old_flags, old_path, old_path_len, new_fd, new_path, new_path_len)),
+            "path_open" => syscall!(store, ctx, "path_open", path_open, (dirfd, dirflags, path, path_len, o_flags, fs_rights_base, fs_rights_inheriting, fs_flags, fd)),
+            "path_readlink" => syscall!(store, ctx, "path_readlink", path_readlink, (dir_fd, path, path_len, buf, buf_len, buf_used)),
+            "path_remove_directory" => syscall!(store, ctx, "path_remove_directory", path_remove_directory, (fd, path, path_len)),
+            "path_rename" => syscall!(store, ctx, "path_rename", path_rename, (old_fd, old_path, old_path_len, new_fd, new_path, new_path_len)),
+            "path_symlink" => syscall!(store, ctx, "path_symlink", path_symlink, (old_path, old_path_len, fd, new_path, new_path_len)),
+            "path_unlink_file" => syscall!(store, ctx, "path_unlink_file", path_unlink_file, (fd, path, path_len)),
+            "poll_oneoff" => syscall!(store, ctx, "poll_oneoff", poll_oneoff, (in_, out_, nsubscriptions, nevents)),
             "proc_exit" => Function::new_native(&mut store, ctx, proc_exit),
-            "proc_raise" => Function::new_native(&mut store, ctx, proc_raise),
-            "random_get" => Function::new_native(&mut store, ctx, random_get),
-            "tty_get" => Function::new_native(&mut store, ctx, tty_get),
-            "tty_set" => Function::new_native(&mut store, ctx, tty_set),
-            "getcwd" => Function::new_native(&mut store, ctx, getcwd),
-            "chdir" => Function::new_native(&mut store, ctx, chdir),
-            "thread_spawn" => Function::new_native(&mut store, ctx, thread_spawn),
-            "thread_sleep" => Function::new_native(&mut store, ctx, thread_sleep),
-            "thread_id" => Function::new_native(&mut store, ctx, thread_id),
-            "thread_join" => Function::new_native(&mut store, ctx, thread_join),
-            "thread_parallelism" => Function::new_native(&mut store, ctx, thread_parallelism),
-            "thread_exit" => Function::new_native(&mut store, ctx, thread_exit),
-            "sched_yield" => Function::new_native(&mut store, ctx, sched_yield),
-            "getpid" => Function::new_native(&mut store, ctx, getpid),
+            "proc_raise" => syscall!(store, ctx, "proc_raise", proc_raise, (sig)),
+            "random_get" => syscall!(store, ctx, "random_get", random_get, (buf, buf_len)),
+            "tty_get" => syscall!(store, ctx, "tty_get", tty_get, (tty_state)),
+            "tty_set" => syscall!(store, ctx, "tty_set", tty_set, (tty_state)),
+            "getcwd" => syscall!(store, ctx, "getcwd", getcwd, (path, path_len)),
+            "chdir" => syscall!(store, ctx, "chdir", chdir, (path, path_len)),
+            "thread_spawn" => syscall!(store, ctx, "thread_spawn", thread_spawn, (method, method_len, user_data, reactor, ret_tid)),
+            "thread_sleep" => syscall!(store, ctx, "thread_sleep", thread_sleep, (duration)),
+            "thread_id" => syscall!(store, ctx, "thread_id", thread_id, (ret_tid)),
+            "thread_join" => syscall!(store, ctx, "thread_join", thread_join, (tid)),
+            "thread_parallelism" => syscall!(store, ctx, "thread_parallelism", thread_parallelism, (ret_parallelism)),
+            "thread_exit" => syscall!(store, ctx, "thread_exit", thread_exit, (exitcode)),
+            "sched_yield" => syscall!(store, ctx, "sched_yield", sched_yield, ()),
+            "getpid" => syscall!(store, ctx, "getpid", getpid, (ret_pid)),
             "process_spawn" => Function::new_native(&mut store, ctx, process_spawn),
             "bus_open_local" => Function::new_native(&mut store, ctx, bus_open_local),
             "bus_open_remote" => Function::new_native(&mut store, ctx, bus_open_remote),
@@ -720,47 +1058,47 @@ fn generate_import_object_wasix64_v1(
             "call_reply" => Function::new_native(&mut store, ctx, call_reply),
             "call_fault" => Function::new_native(&mut store, ctx, call_fault),
             "call_close" => Function::new_native(&mut store, ctx, call_close),
-            "ws_connect" => Function::new_native(&mut store, ctx, ws_connect),
-            "http_request" => Function::new_native(&mut store, ctx, http_request),
-            "http_status" => Function::new_native(&mut store, ctx, http_status),
-            "port_bridge" => Function::new_native(&mut store, ctx, port_bridge),
-            "port_unbridge" => Function::new_native(&mut store, ctx, port_unbridge),
-            "port_dhcp_acquire" => Function::new_native(&mut store, ctx, port_dhcp_acquire),
-            "port_addr_add" => Function::new_native(&mut store, ctx, port_addr_add),
-            "port_addr_remove" => Function::new_native(&mut store, ctx, port_addr_remove),
-            "port_addr_clear" => Function::new_native(&mut store, ctx, port_addr_clear),
-            "port_addr_list" => Function::new_native(&mut store, ctx, port_addr_list),
-            "port_mac" => Function::new_native(&mut store, ctx, port_mac),
-            "port_gateway_set" => Function::new_native(&mut store, ctx, port_gateway_set),
-            "port_route_add" => Function::new_native(&mut store, ctx, port_route_add),
-            "port_route_remove" => Function::new_native(&mut store, ctx, port_route_remove),
-            "port_route_clear" => Function::new_native(&mut store, ctx, port_route_clear),
-            "port_route_list" => Function::new_native(&mut store, ctx, port_route_list),
-            "sock_status" => Function::new_native(&mut store, ctx, sock_status),
-            "sock_addr_local" => Function::new_native(&mut store, ctx, sock_addr_local),
-            "sock_addr_peer" => Function::new_native(&mut store, ctx, sock_addr_peer),
-            "sock_open" => Function::new_native(&mut store, ctx, sock_open),
-            "sock_set_opt_flag" => Function::new_native(&mut store, ctx, sock_set_opt_flag),
-            "sock_get_opt_flag" => Function::new_native(&mut store, ctx, sock_get_opt_flag),
-            "sock_set_opt_time" => Function::new_native(&mut store, ctx, sock_set_opt_time),
-            "sock_get_opt_time" => Function::new_native(&mut store, ctx, sock_get_opt_time),
-            "sock_set_opt_size" => Function::new_native(&mut store, ctx, sock_set_opt_size),
-            "sock_get_opt_size" => Function::new_native(&mut store, ctx, sock_get_opt_size),
-            "sock_join_multicast_v4" => Function::new_native(&mut store, ctx, sock_join_multicast_v4),
-            "sock_leave_multicast_v4" => Function::new_native(&mut store, ctx, sock_leave_multicast_v4),
-            "sock_join_multicast_v6" => Function::new_native(&mut store, ctx, sock_join_multicast_v6),
-            "sock_leave_multicast_v6" => Function::new_native(&mut store, ctx, sock_leave_multicast_v6),
-            "sock_bind" => Function::new_native(&mut store, ctx, sock_bind),
-            "sock_listen" => Function::new_native(&mut store, ctx, sock_listen),
-            "sock_accept" => Function::new_native(&mut store, ctx, sock_accept),
-            "sock_connect" => Function::new_native(&mut store, ctx, sock_connect),
-            "sock_recv" => Function::new_native(&mut store, ctx, sock_recv),
-            "sock_recv_from" => Function::new_native(&mut store, ctx, sock_recv_from),
-            "sock_send" => Function::new_native(&mut store, ctx, sock_send),
-            "sock_send_to" => Function::new_native(&mut store, ctx, sock_send_to),
-            "sock_send_file" => Function::new_native(&mut store, ctx, sock_send_file),
-            "sock_shutdown" => Function::new_native(&mut store, ctx, sock_shutdown),
-            "resolve" => Function::new_native(&mut store, ctx, resolve),
+            "ws_connect" => syscall!(store, ctx, "ws_connect", ws_connect, (url, url_len, ret_sock)),
+            "http_request" => syscall!(store, ctx, "http_request", http_request, (url, url_len, method, method_len, headers, headers_len, gzip, ret_handles)),
+            "http_status" => syscall!(store, ctx, "http_status", http_status, (sock, status, status_text, status_text_len, headers, headers_len)),
+            "port_bridge" => syscall!(store, ctx, "port_bridge", port_bridge, (network, network_len, token, token_len, security)),
+            "port_unbridge" => syscall!(store, ctx, "port_unbridge", port_unbridge, ()),
+            "port_dhcp_acquire" => syscall!(store, ctx, "port_dhcp_acquire", port_dhcp_acquire, ()),
+            "port_addr_add" => syscall!(store, ctx, "port_addr_add", port_addr_add, (addr)),
+            "port_addr_remove" => syscall!(store, ctx, "port_addr_remove", port_addr_remove, (addr)),
+            "port_addr_clear" => syscall!(store, ctx, "port_addr_clear", port_addr_clear, ()),
+            "port_addr_list" => syscall!(store, ctx, "port_addr_list", port_addr_list, (addrs, naddrs)),
+            "port_mac" => syscall!(store, ctx, "port_mac", port_mac, (ret_mac)),
+            "port_gateway_set" => syscall!(store, ctx, "port_gateway_set", port_gateway_set, (ip)),
+            "port_route_add" => syscall!(store, ctx, "port_route_add", port_route_add, (cidr, via_router, preferred_until, expires_at)),
+            "port_route_remove" => syscall!(store, ctx, "port_route_remove", port_route_remove, (ip)),
+            "port_route_clear" => syscall!(store, ctx, "port_route_clear", port_route_clear, ()),
+            "port_route_list" => syscall!(store, ctx, "port_route_list", port_route_list, (routes, nroutes)),
+            "sock_status" => syscall!(store, ctx, "sock_status", sock_status, (sock, ret_status)),
+            "sock_addr_local" => syscall!(store, ctx, "sock_addr_local", sock_addr_local, (sock, ret_addr)),
+            "sock_addr_peer" => syscall!(store, ctx, "sock_addr_peer", sock_addr_peer, (sock, ro_addr)),
+            "sock_open" => syscall!(store, ctx, "sock_open", sock_open, (af, ty, pt, ro_sock)),
+            "sock_set_opt_flag" => syscall!(store, ctx, "sock_set_opt_flag", sock_set_opt_flag, (sock, opt, flag)),
+            "sock_get_opt_flag" => syscall!(store, ctx, "sock_get_opt_flag", sock_get_opt_flag, (sock, opt, ret_flag)),
+            "sock_set_opt_time" => syscall!(store, ctx, "sock_set_opt_time", sock_set_opt_time, (sock, opt, time)),
+            "sock_get_opt_time" => syscall!(store, ctx, "sock_get_opt_time", sock_get_opt_time, (sock, opt, ret_time)),
+            "sock_set_opt_size" => syscall!(store, ctx, "sock_set_opt_size", sock_set_opt_size, (sock, opt, size)),
+            "sock_get_opt_size" => syscall!(store, ctx, "sock_get_opt_size", sock_get_opt_size, (sock, opt, ret_size)),
+            "sock_join_multicast_v4" => syscall!(store, ctx, "sock_join_multicast_v4", sock_join_multicast_v4, (sock, multiaddr, iface)),
+            "sock_leave_multicast_v4" => syscall!(store, ctx, "sock_leave_multicast_v4", sock_leave_multicast_v4, (sock, multiaddr, iface)),
+            "sock_join_multicast_v6" => syscall!(store, ctx, "sock_join_multicast_v6", sock_join_multicast_v6, (sock, multiaddr, iface)),
+            "sock_leave_multicast_v6" => syscall!(store, ctx, "sock_leave_multicast_v6", sock_leave_multicast_v6, (sock, multiaddr, iface)),
+            "sock_bind" => syscall!(store, ctx, "sock_bind", sock_bind, (sock, addr)),
+            "sock_listen" => syscall!(store, ctx, "sock_listen", sock_listen, (sock, backlog)),
+            "sock_accept" => syscall!(store, ctx, "sock_accept", sock_accept, (sock, fd_flags, ro_fd, ro_addr)),
+            "sock_connect" => syscall!(store, ctx, "sock_connect", sock_connect, (sock, addr)),
+            "sock_recv" => syscall!(store, ctx, "sock_recv", sock_recv, (sock, ri_data, ri_data_len, ri_flags, ro_data_len, ro_flags)),
+            "sock_recv_from" => syscall!(store, ctx, "sock_recv_from", sock_recv_from, (sock, ri_data, ri_data_len, ri_flags, ro_data_len, ro_flags, ro_addr)),
+            "sock_send" => syscall!(store, ctx, "sock_send", sock_send, (sock, si_data, si_data_len, si_flags, ret_data_len)),
+            "sock_send_to" => syscall!(store, ctx, "sock_send_to", sock_send_to, (sock, si_data, si_data_len, si_flags, addr, ret_data_len)),
+            "sock_send_file" => syscall!(store, ctx, "sock_send_file", sock_send_file, (out_fd, in_fd, offset, count, ret_sent)),
+            "sock_shutdown" => syscall!(store, ctx, "sock_shutdown", sock_shutdown, (sock, how)),
+            "resolve" => syscall!(store, ctx, "resolve", resolve, (host, host_len, port, ips, nips, ret_nips)),
         }
     }
 }
@@ -769,7 +1107,7 @@ fn mem_error_to_wasi(err: MemoryAccessError) -> types::__wasi_errno_t {
     match err {
         MemoryAccessError::HeapOutOfBounds => types::__WASI_EFAULT,
         MemoryAccessError::Overflow => types::__WASI_EOVERFLOW,
-        MemoryAccessError::NonUtf8String => types::__WASI_EINVAL,
+        MemoryAccessError::NonUtf8String => types::__WASI_EILSEQ,
         _ => types::__WASI_EINVAL,
     }
 }
@@ -782,3 +1120,22 @@ fn mem_error_to_bus(err: MemoryAccessError) -> types::__bus_errno_t {
         _ => types::__BUS_EUNKNOWN,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stubbable_syscalls_match_the_imports() {
+        let mut imported = include_str!("lib.rs")
+            .split("syscall!(store, ctx, \"")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .collect::<Vec<_>>();
+        imported.sort_unstable();
+        imported.dedup();
+        let mut stubbable = STUBBABLE_SYSCALLS.to_vec();
+        stubbable.sort_unstable();
+        assert_eq!(imported, stubbable);
+    }
+}
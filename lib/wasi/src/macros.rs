@@ -108,3 +108,113 @@ macro_rules! get_input_str_bus {
         wasi_try_mem_bus!($data.read_utf8_string($ctx, $memory, $len))
     }};
 }
+
+/// Wraps a syscall into a host function for an import namespace. The errno
+/// returned by the syscall is recorded in the [`WasiState`] of the calling
+/// environment before it is handed back to the guest.
+///
+/// Every syscall is charged to the instruction budget of the environment,
+/// and traps with [`WasiError::OutOfFuel`] instead of running once it is
+/// exhausted.
+///
+/// If a syscall tracer is set, it is called with the arguments and the
+/// result of the syscall once it returned.
+///
+/// [`WasiState`]: crate::WasiState
+/// [`WasiError::OutOfFuel`]: crate::WasiError::OutOfFuel
+macro_rules! syscall {
+    ($store:ident, $ctx:ident, $name:literal, $func:path, ($($arg:ident),*)) => {{
+        Function::new_native(
+            &mut $store,
+            $ctx,
+            |ctx: FunctionEnvMut<'_, WasiEnv>, $($arg),*| {
+                let state = ctx.data().state.clone();
+                state.charge_syscall()?;
+                let trace = ctx.data().syscall_tracer.clone().map(|tracer| {
+                    let args = vec![$((stringify!($arg), crate::syscalls::SyscallArg::raw(&$arg))),*];
+                    (tracer, ctx.data().syscall_event(&ctx, $name, args))
+                });
+                let ret = match state.stubbed_syscall($name) {
+                    Some(errno) => Ok(errno),
+                    None => crate::syscalls::SyscallReturn::into_result($func(ctx, $($arg),*)),
+                };
+                let errno = crate::syscalls::SyscallReturn::errno(&ret);
+                state.record_syscall_result($name, errno);
+                if let Some((tracer, mut event)) = trace {
+                    event.errno = errno;
+                    (tracer.lock().unwrap())(event);
+                }
+                ret
+            },
+        )
+    }};
+}
+
+/// Implements [`VirtualFile`] for a wrapper around the file in its `inner`
+/// field, e.g. [`CountingFile`]. Every method is forwarded to `inner` but
+/// `set_len` and `try_clone`, which the wrapper implements in the block.
+///
+/// [`VirtualFile`]: wasmer_vfs::VirtualFile
+/// [`CountingFile`]: crate::CountingFile
+macro_rules! forward_virtual_file {
+    ($wrapper:ty { $($methods:tt)* }) => {
+        impl wasmer_vfs::VirtualFile for $wrapper {
+            fn last_accessed(&self) -> u64 {
+                self.inner.last_accessed()
+            }
+            fn last_modified(&self) -> u64 {
+                self.inner.last_modified()
+            }
+            fn created_time(&self) -> u64 {
+                self.inner.created_time()
+            }
+            fn set_last_accessed(&mut self, last_accessed: u64) -> Result<(), wasmer_vfs::FsError> {
+                self.inner.set_last_accessed(last_accessed)
+            }
+            fn set_last_modified(&mut self, last_modified: u64) -> Result<(), wasmer_vfs::FsError> {
+                self.inner.set_last_modified(last_modified)
+            }
+            fn size(&self) -> u64 {
+                self.inner.size()
+            }
+            fn current_len(&self) -> Result<u64, wasmer_vfs::FsError> {
+                self.inner.current_len()
+            }
+            fn unlink(&mut self) -> Result<(), wasmer_vfs::FsError> {
+                self.inner.unlink()
+            }
+            fn sync_to_disk(&self) -> Result<(), wasmer_vfs::FsError> {
+                self.inner.sync_to_disk()
+            }
+            fn sync_data_to_disk(&self) -> Result<(), wasmer_vfs::FsError> {
+                self.inner.sync_data_to_disk()
+            }
+            fn bytes_available(&self) -> Result<usize, wasmer_vfs::FsError> {
+                self.inner.bytes_available()
+            }
+            fn bytes_available_read(&self) -> Result<Option<usize>, wasmer_vfs::FsError> {
+                self.inner.bytes_available_read()
+            }
+            fn bytes_available_write(&self) -> Result<Option<usize>, wasmer_vfs::FsError> {
+                self.inner.bytes_available_write()
+            }
+            fn is_open(&self) -> bool {
+                self.inner.is_open()
+            }
+            fn get_fd(&self) -> Option<wasmer_vfs::FileDescriptor> {
+                self.inner.get_fd()
+            }
+            fn content_hash(&self) -> Option<[u8; 32]> {
+                self.inner.content_hash()
+            }
+            fn io_block_size(&self) -> Option<u32> {
+                self.inner.io_block_size()
+            }
+            fn is_seekable(&self) -> bool {
+                self.inner.is_seekable()
+            }
+
+            $($methods)*
+        }
+    };
+}
@@ -1,14 +1,25 @@
 //! Builder system for configuring a [`WasiState`] and creating it.
 
-use crate::state::{default_fs_backing, WasiFs, WasiState};
-use crate::syscalls::types::{__WASI_STDERR_FILENO, __WASI_STDIN_FILENO, __WASI_STDOUT_FILENO};
+use crate::state::{
+    default_clock, default_fs_backing, CallbackFile, Deterministic, DeterministicClock, NullFile,
+    SyscallEvent, WasiClock, WasiFs, WasiState, ZeroFile, DEFAULT_MAX_PATH_LEN,
+};
+#[cfg(feature = "mem-fs-mounts")]
+use crate::state::{
+    FsSnapshot, MountedFileSystem, OverlayFileSystem, ReadOnlyFileSystem, TarFileSystem,
+    FD_REFLECTION_DIR,
+};
+use crate::syscalls::types::{
+    __wasi_errno_t, __wasi_exitcode_t, __WASI_ENOSYS, __WASI_STDERR_FILENO, __WASI_STDIN_FILENO,
+    __WASI_STDOUT_FILENO,
+};
 use crate::{WasiEnv, WasiFunctionEnv, WasiInodes};
 use generational_arena::Arena;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 use std::sync::RwLock;
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 use wasmer::AsStoreMut;
 use wasmer_vfs::{FsError, VirtualFile};
@@ -43,6 +54,7 @@ pub(crate) fn create_wasi_state(program_name: &str) -> WasiStateBuilder {
 pub struct WasiStateBuilder {
     args: Vec<Vec<u8>>,
     envs: Vec<(Vec<u8>, Vec<u8>)>,
+    inherit_env: bool,
     preopens: Vec<PreopenedDir>,
     vfs_preopens: Vec<String>,
     #[allow(clippy::type_complexity)]
@@ -51,21 +63,90 @@ pub struct WasiStateBuilder {
     stderr_override: Option<Box<dyn VirtualFile + Send + Sync + 'static>>,
     stdin_override: Option<Box<dyn VirtualFile + Send + Sync + 'static>>,
     fs_override: Option<Box<dyn wasmer_vfs::FileSystem>>,
+    #[cfg(feature = "mem-fs-mounts")]
+    shared_readonly_fs: Option<Arc<FsSnapshot>>,
     runtime_override: Option<Arc<dyn crate::WasiRuntimeImplementation + Send + Sync + 'static>>,
+    on_exit: Option<Arc<dyn Fn(__wasi_exitcode_t) + Send + Sync>>,
+    #[allow(clippy::type_complexity)]
+    path_mapper: Option<Arc<dyn Fn(&str) -> Option<String> + Send + Sync>>,
+    #[allow(clippy::type_complexity)]
+    syscall_tracer: Option<Arc<Mutex<Box<dyn FnMut(SyscallEvent) + Send>>>>,
+    clock: Option<Arc<dyn WasiClock>>,
+    randomness_recorder: Option<Arc<Mutex<Vec<u8>>>>,
+    replayed_randomness: Option<Vec<u8>>,
+    stubbed_syscalls: HashMap<String, __wasi_errno_t>,
+    readonly_root: Option<(PathBuf, String)>,
+    devfs: bool,
+    current_dir: Option<String>,
+    max_read_size: Option<usize>,
+    nonblocking: bool,
+    deterministic_seed: Option<u64>,
+    default_dir_mode: Option<u32>,
+    file_umask: Option<u32>,
+    max_instructions: Option<u64>,
+    max_open_fds: Option<usize>,
+    max_path_len: Option<usize>,
+    fd_reflection: bool,
+    io_block_size: Option<u32>,
+    no_follow_symlinks: bool,
+    tar_mounts: Vec<(String, Vec<u8>)>,
 }
 
 impl std::fmt::Debug for WasiStateBuilder {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // TODO: update this when stable
-        f.debug_struct("WasiStateBuilder")
+        let mut debug = f.debug_struct("WasiStateBuilder");
+        debug
             .field("args", &self.args)
             .field("envs", &self.envs)
+            .field("inherit_env", &self.inherit_env)
             .field("preopens", &self.preopens)
+            .field("readonly_root", &self.readonly_root)
+            .field("devfs", &self.devfs);
+        #[cfg(feature = "mem-fs-mounts")]
+        debug.field(
+            "shared_readonly_fs exists",
+            &self.shared_readonly_fs.is_some(),
+        );
+        debug
+            .field("current_dir", &self.current_dir)
+            .field("max_read_size", &self.max_read_size)
+            .field("nonblocking", &self.nonblocking)
+            .field("deterministic_seed", &self.deterministic_seed)
+            .field("default_dir_mode", &self.default_dir_mode)
+            .field("file_umask", &self.file_umask)
+            .field("max_instructions", &self.max_instructions)
+            .field("max_open_fds", &self.max_open_fds)
+            .field("max_path_len", &self.max_path_len)
+            .field("fd_reflection", &self.fd_reflection)
+            .field("io_block_size", &self.io_block_size)
+            .field("no_follow_symlinks", &self.no_follow_symlinks)
+            .field(
+                "tar_mounts",
+                &self
+                    .tar_mounts
+                    .iter()
+                    .map(|(path, _)| path)
+                    .collect::<Vec<_>>(),
+            )
             .field("setup_fs_fn exists", &self.setup_fs_fn.is_some())
             .field("stdout_override exists", &self.stdout_override.is_some())
             .field("stderr_override exists", &self.stderr_override.is_some())
             .field("stdin_override exists", &self.stdin_override.is_some())
             .field("runtime_override_exists", &self.runtime_override.is_some())
+            .field("on_exit exists", &self.on_exit.is_some())
+            .field("path_mapper exists", &self.path_mapper.is_some())
+            .field("syscall_tracer exists", &self.syscall_tracer.is_some())
+            .field("clock exists", &self.clock.is_some())
+            .field(
+                "randomness_recorder exists",
+                &self.randomness_recorder.is_some(),
+            )
+            .field(
+                "replayed_randomness_len",
+                &self.replayed_randomness.as_ref().map(Vec::len),
+            )
+            .field("stubbed_syscalls", &self.stubbed_syscalls)
             .finish()
     }
 }
@@ -81,12 +162,18 @@ pub enum WasiStateCreationError {
     PreopenedDirectoryNotFound(PathBuf),
     #[error("preopened directory error: `{0}`")]
     PreopenedDirectoryError(String),
-    #[error("mapped dir alias has wrong format: `{0}`")]
+    #[error("mapped dir alias is invalid: `{0}`")]
     MappedDirAliasFormattingError(String),
     #[error("wasi filesystem creation error: `{0}`")]
     WasiFsCreationError(String),
     #[error("wasi filesystem setup error: `{0}`")]
     WasiFsSetupError(String),
+    #[error("current directory is not under a preopened directory: `{0}`")]
+    CurrentDirNotPreopened(String),
+    #[error("not a syscall that can be stubbed: `{0}`")]
+    InvalidStubbedSyscall(String),
+    #[error("the maximum read size must not be zero")]
+    ZeroMaxReadSize,
     #[error(transparent)]
     FileSystemError(FsError),
 }
@@ -98,6 +185,40 @@ fn validate_mapped_dir_alias(alias: &str) -> Result<(), WasiStateCreationError>
         ));
     }
 
+    // Aliases are paths from the virtual root (with or without the leading
+    // `/`), `.` is kept as the conventional alias of the current directory
+    let path = alias.trim_matches('/');
+    if !path.is_empty()
+        && path != "."
+        && path
+            .split('/')
+            .any(|component| component.is_empty() || component == "." || component == "..")
+    {
+        return Err(WasiStateCreationError::MappedDirAliasFormattingError(
+            format!("Alias \"{}\" is not a normalized absolute path", alias),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that no two preopened directories are exposed at the same place
+/// in the virtual root
+fn validate_preopen_aliases(preopens: &[PreopenedDir]) -> Result<(), WasiStateCreationError> {
+    let mut seen = HashSet::new();
+    for preopen in preopens {
+        let alias = match &preopen.alias {
+            Some(alias) => alias.clone(),
+            None => preopen.path.to_string_lossy().into_owned(),
+        };
+        if !seen.insert(alias.trim_matches('/').to_string()) {
+            return Err(WasiStateCreationError::PreopenedDirectoryError(format!(
+                "Found duplicate entry for alias `{}`",
+                alias
+            )));
+        }
+    }
+
     Ok(())
 }
 
@@ -106,9 +227,10 @@ fn validate_mapped_dir_alias(alias: &str) -> Result<(), WasiStateCreationError>
 impl WasiStateBuilder {
     /// Add an environment variable pair.
     ///
-    /// Both the key and value of an environment variable must not
-    /// contain a nul byte (`0x0`), and the key must not contain the
-    /// `=` byte (`0x3d`).
+    /// The key and value are passed to the guest as raw bytes, they don't
+    /// have to be valid UTF-8. Both the key and value of an environment
+    /// variable must not contain a nul byte (`0x0`), and the key must not
+    /// contain the `=` byte (`0x3d`).
     pub fn env<Key, Value>(&mut self, key: Key, value: Value) -> &mut Self
     where
         Key: AsRef<[u8]>,
@@ -120,9 +242,23 @@ impl WasiStateBuilder {
         self
     }
 
+    /// Passes the environment variables of the host process to the guest
+    /// too, as read with [`std::env::vars_os`] when the state is built.
+    ///
+    /// The variables added with [`WasiStateBuilder::env`] take precedence
+    /// over the inherited ones with the same key. The inherited variables
+    /// are validated like the others, the ones whose key or value is not
+    /// valid unicode are skipped.
+    pub fn inherit_env(&mut self) -> &mut Self {
+        self.inherit_env = true;
+
+        self
+    }
+
     /// Add an argument.
     ///
-    /// Arguments must not contain the nul (0x0) byte
+    /// Arguments are passed to the guest as raw bytes, they don't have to
+    /// be valid UTF-8 but must not contain the nul (0x0) byte
     pub fn arg<Arg>(&mut self, arg: Arg) -> &mut Self
     where
         Arg: AsRef<[u8]>,
@@ -165,6 +301,25 @@ impl WasiStateBuilder {
         self
     }
 
+    /// Set the program name, the first argument returned by `args_get`.
+    ///
+    /// It defaults to the name given to [`WasiState::new`], the arguments
+    /// added with [`Self::arg`] and [`Self::args`] follow it. The name must
+    /// not contain the nul (0x0) byte
+    pub fn program_name<Name>(&mut self, name: Name) -> &mut Self
+    where
+        Name: AsRef<[u8]>,
+    {
+        let name = name.as_ref().to_vec();
+        if self.args.is_empty() {
+            self.args.push(name);
+        } else {
+            self.args[0] = name;
+        }
+
+        self
+    }
+
     /// Preopen a directory
     ///
     /// This opens the given directory at the virtual root, `/`, and allows
@@ -244,7 +399,32 @@ impl WasiStateBuilder {
         Ok(self)
     }
 
+    /// Preopens the content of the tar archive `tar_bytes` read-only at
+    /// `guest_path`, without extracting it to disk.
+    ///
+    /// The archive is read into memory when the state is built, which fails
+    /// with [`FsError::InvalidData`] if it is not a valid tar archive. Its
+    /// symlinks become virtual symlinks, like the ones created with
+    /// `path_symlink`. Modifying the mounted entries fails with
+    /// `__WASI_EROFS`.
+    ///
+    /// See [`TarFileSystem`] for the supported formats.
+    #[cfg(feature = "mem-fs-mounts")]
+    pub fn mount_tar(&mut self, guest_path: &str, tar_bytes: Vec<u8>) -> &mut Self {
+        self.tar_mounts.push((guest_path.to_string(), tar_bytes));
+
+        self
+    }
+
     /// Preopen a directory with a different name exposed to the WASI.
+    ///
+    /// The guest sees the host directory `po_dir` at `alias` in the virtual
+    /// root: `data` and `/data` are equivalent, and `/` exposes it at the
+    /// root itself. The alias must not contain `.` or `..` components (`.`
+    /// alone is accepted and means the current directory). Mapping two
+    /// directories at the same alias makes [`WasiStateBuilder::build`] fail.
+    /// Aliases can be nested, e.g. `/data` and `/data/sub`: paths are
+    /// resolved against the deepest one.
     pub fn map_dir<FilePath>(
         &mut self,
         alias: &str,
@@ -283,6 +463,242 @@ impl WasiStateBuilder {
         Ok(self)
     }
 
+    /// Preopens every directory of `mapped_dirs` under its alias, like
+    /// [`WasiStateBuilder::map_dirs`].
+    ///
+    /// The directories are preopened in the order of their aliases, so the
+    /// fds they get don't depend on the iteration order of the map. The
+    /// first invalid alias is returned as an error.
+    pub fn preopens_from_map(
+        &mut self,
+        mapped_dirs: &HashMap<String, PathBuf>,
+    ) -> Result<&mut Self, WasiStateCreationError> {
+        let mut mapped_dirs = mapped_dirs.iter().collect::<Vec<_>>();
+        mapped_dirs.sort();
+
+        self.map_dirs(
+            mapped_dirs
+                .into_iter()
+                .map(|(alias, dir)| (alias.clone(), dir)),
+        )
+    }
+
+    /// Exposes the directory `root` of the file system read-only at `/` and
+    /// mounts a writable in-memory scratch directory at `scratch` (for
+    /// example `/tmp`).
+    ///
+    /// Everything outside of the scratch directory becomes read-only, this
+    /// includes the other preopened directories. Modifying it fails with
+    /// `__WASI_EPERM`. The content of the scratch directory is lost when
+    /// the [`WasiState`] is dropped.
+    #[cfg(feature = "mem-fs-mounts")]
+    pub fn readonly_root_with_scratch<FilePath>(
+        &mut self,
+        root: FilePath,
+        scratch: &str,
+    ) -> Result<&mut Self, WasiStateCreationError>
+    where
+        FilePath: AsRef<Path>,
+    {
+        validate_mapped_dir_alias(scratch)?;
+        let scratch = scratch.trim_start_matches('/');
+        if scratch.is_empty() {
+            return Err(WasiStateCreationError::MappedDirAliasFormattingError(
+                "The scratch directory can not be the root directory".to_string(),
+            ));
+        }
+        self.readonly_root = Some((root.as_ref().to_path_buf(), scratch.to_string()));
+
+        Ok(self)
+    }
+
+    /// Preopens a virtual `/dev` directory holding the devices `/dev/null`,
+    /// which discards writes and is always at EOF, and `/dev/zero`, which
+    /// discards writes and reads as zeros.
+    ///
+    /// Nothing from the `/dev` of the file system backing is visible to the
+    /// guest.
+    #[cfg(feature = "mem-fs-mounts")]
+    pub fn with_devfs(&mut self) -> &mut Self {
+        self.devfs = true;
+
+        self
+    }
+
+    /// Sets the working directory of the guest, as a path seen by the guest.
+    ///
+    /// WASI has no working directory, relative paths given to `path_open`
+    /// and the other path syscalls are resolved against the directory fd
+    /// they are given. With a working directory set, relative paths given
+    /// with the fd of the preopened directory containing it are resolved
+    /// against the working directory instead. WASIX guests also read it
+    /// with `getcwd` and change it with `chdir`, see [`WasiFs::set_cwd`].
+    ///
+    /// [`WasiStateBuilder::build`] fails if the working directory isn't
+    /// under a preopened directory.
+    pub fn current_dir(&mut self, current_dir: &str) -> &mut Self {
+        self.current_dir = Some(current_dir.to_string());
+
+        self
+    }
+
+    /// Caps the number of bytes a single `fd_read` call reads from a file
+    /// or stdin, so that a guest can not force a huge host allocation by
+    /// requesting a gigantic read.
+    ///
+    /// Larger reads are clamped and produce short reads, which compliant
+    /// guests must handle anyway. [`WasiStateBuilder::build`] fails if
+    /// `max_read_size` is 0, as every read would then look like the end of
+    /// the file.
+    pub fn max_read_size(&mut self, max_read_size: usize) -> &mut Self {
+        self.max_read_size = Some(max_read_size);
+
+        self
+    }
+
+    /// Makes `fd_read` return `__WASI_EAGAIN` instead of blocking when no
+    /// data is available, so that the guest can be driven from an async
+    /// reactor that retries the call later.
+    ///
+    /// This applies to every fd, as if it was opened with
+    /// `__WASI_FDFLAG_NONBLOCK`. Host files that are always ready to be
+    /// read are not affected.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.nonblocking = nonblocking;
+
+        self
+    }
+
+    /// Makes the guest's randomness and clocks reproducible from `seed`, so
+    /// that a whole run can be replayed, e.g. when fuzzing.
+    ///
+    /// `random_get` returns the bytes of a SplitMix64 stream seeded with
+    /// `seed`, each 64-bit output in little-endian order.
+    ///
+    /// The guest no longer reads the host clocks: all clocks share a single
+    /// virtual time that starts at 0 and advances by `1 + seed % 1_000_000`
+    /// nanoseconds every time it is read, whatever the clock and precision
+    /// asked for. Sleeping moves the virtual time forward instead of
+    /// waiting.
+    pub fn deterministic(&mut self, seed: u64) -> &mut Self {
+        self.deterministic_seed = Some(seed);
+
+        self
+    }
+
+    /// Sets the Unix permission bits of the directories the guest creates
+    /// with `path_create_directory`, e.g. `0o700`.
+    ///
+    /// Host directories are created with these bits, minus the process
+    /// umask, and the in-memory file system reports them in
+    /// [`Metadata::mode`]. By default directories are created with the
+    /// default permissions of the file system.
+    ///
+    /// [`Metadata::mode`]: wasmer_vfs::Metadata::mode
+    pub fn default_dir_mode(&mut self, mode: u32) -> &mut Self {
+        self.default_dir_mode = Some(mode);
+
+        self
+    }
+
+    /// Sets the umask of the files the guest creates with `path_open`, they
+    /// get the Unix permission bits `0o666 & !umask`, e.g. `0o077` makes
+    /// them private to their owner.
+    ///
+    /// Host files are created with these bits, minus the process umask,
+    /// and the in-memory file system reports them in [`Metadata::mode`].
+    /// By default only the process umask applies.
+    ///
+    /// [`Metadata::mode`]: wasmer_vfs::Metadata::mode
+    pub fn file_umask(&mut self, umask: u32) -> &mut Self {
+        self.file_umask = Some(umask);
+
+        self
+    }
+
+    /// Bounds how long the guest runs: every syscall made by the guest
+    /// charges [`SYSCALL_INSTRUCTIONS`] to a budget of `max_instructions`,
+    /// and once it is exhausted the next syscall traps with
+    /// [`WasiError::OutOfFuel`] instead of running. The embedder can charge
+    /// the instructions run between syscalls with
+    /// [`WasiState::consume_instructions`], e.g. the points consumed by the
+    /// metering middleware.
+    ///
+    /// This is a cooperative limit that is only checked at syscall
+    /// boundaries, not after every instruction: a guest that stops making
+    /// syscalls keeps running. Use the metering middleware of the compiler
+    /// for a per-instruction limit.
+    ///
+    /// [`SYSCALL_INSTRUCTIONS`]: crate::SYSCALL_INSTRUCTIONS
+    /// [`WasiError::OutOfFuel`]: crate::WasiError::OutOfFuel
+    pub fn max_instructions(&mut self, max_instructions: u64) -> &mut Self {
+        self.max_instructions = Some(max_instructions);
+
+        self
+    }
+
+    /// Caps the number of fds the guest can have open at once, so that a
+    /// guest leaking fds can not exhaust the host's file descriptors.
+    ///
+    /// Once `max_open_fds` fds are open, `path_open`, `fd_dup` and
+    /// `fd_renumber` fail with `__WASI_EMFILE` until one of them is closed.
+    /// Stdio, the virtual root and the preopened directories don't count.
+    pub fn max_open_fds(&mut self, max_open_fds: usize) -> &mut Self {
+        self.max_open_fds = Some(max_open_fds);
+
+        self
+    }
+
+    /// Sets the length in bytes of the longest path the guest can pass to
+    /// the path syscalls ([`DEFAULT_MAX_PATH_LEN`] by default).
+    ///
+    /// Longer paths are rejected with `__WASI_ENAMETOOLONG` before they are
+    /// copied out of the guest memory, so that a guest can not make the host
+    /// allocate a huge buffer for a path.
+    pub fn max_path_len(&mut self, max_path_len: usize) -> &mut Self {
+        self.max_path_len = Some(max_path_len);
+
+        self
+    }
+
+    /// Preopens a read-only virtual `/proc/self/fd` directory listing the
+    /// preopened directories the guest has open, for debugging guests.
+    ///
+    /// `fd_readdir` lists one symlink per open fd of a preopened directory,
+    /// named after the fd, and `path_readlink` on it returns the name of the
+    /// preopen (as returned by `fd_prestat_dir_name`). The listing follows
+    /// the fds being closed, duplicated or renumbered while the guest runs.
+    #[cfg(feature = "mem-fs-mounts")]
+    pub fn with_fd_reflection(&mut self) -> &mut Self {
+        self.fd_reflection = true;
+
+        self
+    }
+
+    /// Sets the block size [`WasiFs::preferred_io_block_size`] reports for
+    /// files that have no preference of their own, like in-memory files.
+    /// Defaults to [`DEFAULT_IO_BLOCK_SIZE`](crate::state::DEFAULT_IO_BLOCK_SIZE).
+    ///
+    /// Host files report their own block size on Unix.
+    pub fn io_block_size(&mut self, io_block_size: u32) -> &mut Self {
+        self.io_block_size = Some(io_block_size);
+
+        self
+    }
+
+    /// Refuses to resolve paths through symlinks, so that a symlink can not
+    /// lead the guest out of a preopened directory.
+    ///
+    /// Resolving a path that goes through a symlink, or that ends with one
+    /// that would be followed, fails with `__WASI_ELOOP` whatever the
+    /// `__WASI_LOOKUP_SYMLINK_FOLLOW` flag of the call. Symlinks themselves
+    /// can still be created, read with `path_readlink` and removed.
+    pub fn no_follow_symlinks(&mut self) -> &mut Self {
+        self.no_follow_symlinks = true;
+
+        self
+    }
+
     /// Overwrite the default WASI `stdout`, if you want to hold on to the
     /// original `stdout` use [`WasiFs::swap_file`] after building.
     pub fn stdout(&mut self, new_file: Box<dyn VirtualFile + Send + Sync + 'static>) -> &mut Self {
@@ -299,6 +715,18 @@ impl WasiStateBuilder {
         self
     }
 
+    /// Streams the guest's `stdout` to `callback`, which is called with the
+    /// bytes of every write as soon as it is made.
+    pub fn stdout_callback(&mut self, callback: Box<dyn FnMut(&[u8]) + Send>) -> &mut Self {
+        self.stdout(Box::new(CallbackFile::new(callback)))
+    }
+
+    /// Streams the guest's `stderr` to `callback`, like
+    /// [`WasiStateBuilder::stdout_callback`].
+    pub fn stderr_callback(&mut self, callback: Box<dyn FnMut(&[u8]) + Send>) -> &mut Self {
+        self.stderr(Box::new(CallbackFile::new(callback)))
+    }
+
     /// Overwrite the default WASI `stdin`, if you want to hold on to the
     /// original `stdin` use [`WasiFs::swap_file`] after building.
     pub fn stdin(&mut self, new_file: Box<dyn VirtualFile + Send + Sync + 'static>) -> &mut Self {
@@ -316,6 +744,21 @@ impl WasiStateBuilder {
         self
     }
 
+    /// Uses `fs`, a snapshot that can be shared with other instances, as
+    /// the file system backing. It can't be combined with
+    /// [`WasiStateBuilder::set_fs`], `build` fails if both are set.
+    ///
+    /// The snapshot itself is never modified: a file is copied to a private
+    /// in-memory layer of this instance the first time it is opened for
+    /// writing, and removed entries are only hidden from this instance. See
+    /// [`OverlayFileSystem`].
+    #[cfg(feature = "mem-fs-mounts")]
+    pub fn shared_readonly_fs(&mut self, fs: Arc<FsSnapshot>) -> &mut Self {
+        self.shared_readonly_fs = Some(fs);
+
+        self
+    }
+
     /// Configure the WASI filesystem before running.
     // TODO: improve ergonomics on this function
     pub fn setup_fs(
@@ -337,6 +780,118 @@ impl WasiStateBuilder {
         self
     }
 
+    /// Sets a callback that is invoked with the exit code when the guest
+    /// calls `proc_exit`.
+    ///
+    /// The callback runs before the [`WasiError::Exit`] trap is raised, the
+    /// trap is still raised afterwards to unwind the guest.
+    ///
+    /// [`WasiError::Exit`]: crate::WasiError::Exit
+    pub fn on_exit(
+        &mut self,
+        on_exit: Box<dyn Fn(__wasi_exitcode_t) + Send + Sync + 'static>,
+    ) -> &mut Self {
+        self.on_exit = Some(Arc::from(on_exit));
+        self
+    }
+
+    /// Sets a function that rewrites the paths given to `path_open` and the
+    /// other path syscalls before they are resolved, e.g. to present a
+    /// fixture file in place of `/etc/hosts`.
+    ///
+    /// Returning `None` rejects the path with `__WASI_EACCES`. The rewritten
+    /// path is resolved like any other guest path, so it can not escape the
+    /// preopened directories.
+    pub fn path_mapper(
+        &mut self,
+        path_mapper: Box<dyn Fn(&str) -> Option<String> + Send + Sync + 'static>,
+    ) -> &mut Self {
+        self.path_mapper = Some(Arc::from(path_mapper));
+        self
+    }
+
+    /// Sets a callback that is invoked with every syscall made by the guest,
+    /// once the syscall returned, e.g. to keep an audit trail.
+    ///
+    /// The [`SyscallEvent`] holds the name of the syscall, its arguments and
+    /// the errno returned to the guest. The paths passed to the syscall are
+    /// read from the guest memory before the syscall runs. Syscalls made by
+    /// every thread of the guest go through the same callback, one at a
+    /// time.
+    pub fn syscall_tracer(
+        &mut self,
+        syscall_tracer: Box<dyn FnMut(SyscallEvent) + Send + 'static>,
+    ) -> &mut Self {
+        self.syscall_tracer = Some(Arc::new(Mutex::new(syscall_tracer)));
+        self
+    }
+
+    /// Makes the syscalls named in `syscalls` return `__WASI_ENOSYS` to the
+    /// guest without doing anything, e.g. `&["sock_send", "sock_recv"]` to
+    /// deny networking to a guest that handles the error.
+    ///
+    /// See [`Self::stub_syscalls_with_errno`] to return another errno.
+    pub fn stub_syscalls(&mut self, syscalls: &[&str]) -> &mut Self {
+        self.stub_syscalls_with_errno(syscalls, __WASI_ENOSYS)
+    }
+
+    /// Makes the syscalls named in `syscalls` return `errno` to the guest
+    /// without doing anything.
+    ///
+    /// The stubbed syscalls are still counted in the syscall error stats
+    /// and reported to the syscall tracer. `proc_exit` and the bus calls
+    /// can't be stubbed, building the state fails with
+    /// [`WasiStateCreationError::InvalidStubbedSyscall`] if one of them or a
+    /// name that is not a syscall is passed.
+    pub fn stub_syscalls_with_errno(
+        &mut self,
+        syscalls: &[&str],
+        errno: __wasi_errno_t,
+    ) -> &mut Self {
+        self.stubbed_syscalls
+            .extend(syscalls.iter().map(|syscall| (syscall.to_string(), errno)));
+        self
+    }
+
+    /// Sets the clock the guest reads in place of the host clocks, e.g. a
+    /// [`ManualClock`](crate::ManualClock) to fire the timers of the guest
+    /// from a test. It is read by `clock_time_get`, `clock_res_get`, the
+    /// clock subscriptions of `poll_oneoff` and the `*_NOW` flags of the
+    /// `*_filestat_set_times` syscalls, and the guest sleeps on it.
+    ///
+    /// It takes precedence over the virtual clock of
+    /// [`Self::deterministic`]. `poll_oneoff` waits until the clock reaches
+    /// the earliest deadline, or until an fd is ready; sleeping on a
+    /// [`ManualClock`](crate::ManualClock) moves it forward instead of
+    /// waiting.
+    pub fn clock(&mut self, clock: impl WasiClock + 'static) -> &mut Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Appends every byte returned by `random_get` to `recorder`, e.g. to
+    /// replay a run that behaved unexpectedly with
+    /// [`Self::replay_randomness`].
+    ///
+    /// The bytes are recorded in the order the guest received them, from
+    /// all its threads.
+    pub fn record_randomness(&mut self, recorder: Arc<Mutex<Vec<u8>>>) -> &mut Self {
+        self.randomness_recorder = Some(recorder);
+        self
+    }
+
+    /// Makes `random_get` return the bytes of `randomness`, in order, in
+    /// place of random bytes, e.g. the bytes recorded with
+    /// [`Self::record_randomness`] during a previous run.
+    ///
+    /// It takes precedence over the randomness of [`Self::deterministic`].
+    /// Once the guest asks for more bytes than are left, `random_get` fails
+    /// with `__WASI_EIO`.
+    pub fn replay_randomness(&mut self, randomness: Vec<u8>) -> &mut Self {
+        self.replayed_randomness = Some(randomness);
+        self
+    }
+
     /// Consumes the [`WasiStateBuilder`] and produces a [`WasiState`]
     ///
     /// Returns the error from `WasiFs::new` if there's an error
@@ -357,20 +912,26 @@ impl WasiStateBuilder {
     /// to `mut self` for every _builder method_, but it will break
     /// existing code. It will be addressed in a next major release.
     pub fn build(&mut self) -> Result<WasiState, WasiStateCreationError> {
-        for (i, arg) in self.args.iter().enumerate() {
-            for b in arg.iter() {
-                if *b == 0 {
-                    return Err(WasiStateCreationError::ArgumentContainsNulByte(
-                        std::str::from_utf8(arg)
-                            .unwrap_or(if i == 0 {
-                                "Inner error: program name is invalid utf8!"
-                            } else {
-                                "Inner error: arg is invalid utf8!"
-                            })
-                            .to_string(),
-                    ));
-                }
-            }
+        // the arguments don't have to be valid UTF-8, the invalid sequences
+        // are only replaced in the error message
+        if let Some(arg) = self.args.iter().find(|arg| arg.contains(&0)) {
+            return Err(WasiStateCreationError::ArgumentContainsNulByte(
+                String::from_utf8_lossy(arg).into_owned(),
+            ));
+        }
+
+        if let Some(syscall) = self
+            .stubbed_syscalls
+            .keys()
+            .find(|syscall| !crate::STUBBABLE_SYSCALLS.contains(&syscall.as_str()))
+        {
+            return Err(WasiStateCreationError::InvalidStubbedSyscall(
+                syscall.clone(),
+            ));
+        }
+
+        if self.max_read_size == Some(0) {
+            return Err(WasiStateCreationError::ZeroMaxReadSize);
         }
 
         enum InvalidCharacter {
@@ -378,7 +939,24 @@ impl WasiStateBuilder {
             Equal,
         }
 
-        for (env_key, env_value) in self.envs.iter() {
+        let mut envs = Vec::new();
+        if self.inherit_env {
+            envs.extend(
+                std::env::vars_os()
+                    // skips the variables that are not valid unicode, on
+                    // which `std::env::vars` would panic
+                    .filter_map(|(key, value)| {
+                        Some((
+                            key.into_string().ok()?.into_bytes(),
+                            value.into_string().ok()?.into_bytes(),
+                        ))
+                    })
+                    .filter(|(key, _)| !self.envs.iter().any(|(env_key, _)| env_key == key)),
+            );
+        }
+        envs.extend(self.envs.iter().cloned());
+
+        for (env_key, env_value) in envs.iter() {
             match env_key.iter().find_map(|&ch| {
                 if ch == 0 {
                     Some(InvalidCharacter::Nul)
@@ -419,7 +997,114 @@ impl WasiStateBuilder {
             }
         }
 
-        let fs_backing = self.fs_override.take().unwrap_or_else(default_fs_backing);
+        let fs_override = self.fs_override.take();
+        #[cfg(feature = "mem-fs-mounts")]
+        let fs_override = match (&self.shared_readonly_fs, fs_override) {
+            (None, fs_override) => fs_override,
+            (Some(snapshot), None) => Some(Box::new(OverlayFileSystem::new(snapshot.clone())) as _),
+            (Some(_), Some(_)) => {
+                return Err(WasiStateCreationError::WasiFsCreationError(
+                    "`set_fs` and `shared_readonly_fs` can't be used together".to_string(),
+                ))
+            }
+        };
+        // the builder options below mount in-memory file systems
+        #[cfg_attr(not(feature = "mem-fs-mounts"), allow(unused_mut))]
+        let mut fs_backing = fs_override.unwrap_or_else(default_fs_backing);
+        let mut preopens = Vec::new();
+        #[cfg(feature = "mem-fs-mounts")]
+        if let Some((root, scratch)) = &self.readonly_root {
+            let mount_point = root.join(scratch);
+            let mut mounted = MountedFileSystem::new(Box::new(ReadOnlyFileSystem::new(fs_backing)));
+            mounted.mount(
+                &mount_point,
+                Box::new(wasmer_vfs::mem_fs::FileSystem::default()),
+            );
+            fs_backing = Box::new(mounted);
+
+            preopens.push(PreopenedDir {
+                path: root.clone(),
+                alias: Some("/".to_string()),
+                read: true,
+                ..Default::default()
+            });
+            preopens.push(PreopenedDir {
+                path: mount_point,
+                alias: Some(scratch.clone()),
+                read: true,
+                write: true,
+                create: true,
+            });
+        }
+        #[cfg(feature = "mem-fs-mounts")]
+        if self.devfs {
+            let mut mounted = MountedFileSystem::new(fs_backing);
+            mounted.mount("/dev", Box::new(wasmer_vfs::mem_fs::FileSystem::default()));
+            fs_backing = Box::new(mounted);
+
+            preopens.push(PreopenedDir {
+                path: PathBuf::from("/dev"),
+                alias: Some("dev".to_string()),
+                read: true,
+                write: true,
+                ..Default::default()
+            });
+        }
+        #[cfg(feature = "mem-fs-mounts")]
+        if self.fd_reflection {
+            let mut mounted = MountedFileSystem::new(fs_backing);
+            mounted.mount(
+                FD_REFLECTION_DIR,
+                Box::new(wasmer_vfs::mem_fs::FileSystem::default()),
+            );
+            fs_backing = Box::new(mounted);
+
+            preopens.push(PreopenedDir {
+                path: PathBuf::from(FD_REFLECTION_DIR),
+                alias: Some(FD_REFLECTION_DIR.trim_start_matches('/').to_string()),
+                read: true,
+                ..Default::default()
+            });
+        }
+        // the symlinks of the archives are created once the preopens are
+        #[cfg_attr(not(feature = "mem-fs-mounts"), allow(unused_mut))]
+        #[cfg_attr(not(feature = "mem-fs-mounts"), allow(unused_mut))]
+        let mut tar_symlinks: Vec<(String, Vec<(PathBuf, PathBuf)>)> = Vec::new();
+        #[cfg_attr(not(feature = "mem-fs-mounts"), allow(unused_mut))]
+        let mut read_only_dirs = Vec::new();
+        #[cfg(feature = "mem-fs-mounts")]
+        for (guest_path, tar_bytes) in &self.tar_mounts {
+            validate_mapped_dir_alias(guest_path)?;
+            let tar_fs =
+                TarFileSystem::new(tar_bytes).map_err(WasiStateCreationError::FileSystemError)?;
+            let alias = match guest_path.trim_matches('/') {
+                "" => "/",
+                alias => alias,
+            };
+            let mount_point = Path::new("/").join(guest_path.trim_start_matches('/'));
+            tar_symlinks.push((alias.to_string(), tar_fs.symlinks().to_vec()));
+            read_only_dirs.push(mount_point.clone());
+
+            let mut mounted = MountedFileSystem::new(fs_backing);
+            mounted.mount(&mount_point, Box::new(tar_fs));
+            fs_backing = Box::new(mounted);
+
+            preopens.push(PreopenedDir {
+                path: mount_point,
+                alias: Some(alias.to_string()),
+                read: true,
+                ..Default::default()
+            });
+        }
+        preopens.extend(self.preopens.iter().cloned());
+        validate_preopen_aliases(&preopens)?;
+        for preopen in &preopens {
+            if let Err(FsError::EntityNotFound) = fs_backing.metadata(&preopen.path) {
+                return Err(WasiStateCreationError::PreopenedDirectoryNotFound(
+                    preopen.path.clone(),
+                ));
+            }
+        }
 
         // self.preopens are checked in [`PreopenDirBuilder::build`]
         let inodes = RwLock::new(crate::state::WasiInodes {
@@ -432,11 +1117,58 @@ impl WasiStateBuilder {
             // self.preopens are checked in [`PreopenDirBuilder::build`]
             let mut wasi_fs = WasiFs::new_with_preopen(
                 inodes.deref_mut(),
-                &self.preopens,
+                &preopens,
                 &self.vfs_preopens,
                 fs_backing,
             )
             .map_err(WasiStateCreationError::WasiFsCreationError)?;
+            if let Some(io_block_size) = self.io_block_size {
+                wasi_fs.io_block_size = io_block_size;
+            }
+            wasi_fs.no_follow_symlinks = self.no_follow_symlinks;
+
+            for (alias, symlinks) in tar_symlinks {
+                let fd = wasi_fs
+                    .open_preopens(inodes.deref())
+                    .into_iter()
+                    .find(|(_, name)| *name == alias)
+                    .map(|(fd, _)| fd)
+                    .expect("the archive was preopened");
+                for (path, target) in symlinks {
+                    wasi_fs
+                        .create_symlink(inodes.deref_mut(), fd, &path, &target)
+                        .map_err(|errno| {
+                            WasiStateCreationError::WasiFsSetupError(format!(
+                                "could not create the symlink `{}` in `{}`: errno {}",
+                                path.display(),
+                                alias,
+                                errno
+                            ))
+                        })?;
+                }
+            }
+            // set once the symlinks of the archives are created
+            wasi_fs.read_only_dirs = read_only_dirs;
+
+            if self.devfs {
+                for (name, device) in [
+                    (
+                        "null",
+                        Box::new(NullFile) as Box<dyn VirtualFile + Send + Sync>,
+                    ),
+                    ("zero", Box::new(ZeroFile)),
+                ] {
+                    wasi_fs
+                        .add_device(inodes.deref_mut(), "dev", name, device)
+                        .map_err(WasiStateCreationError::FileSystemError)?;
+                }
+            }
+
+            if let Some(current_dir) = &self.current_dir {
+                wasi_fs.set_cwd(inodes.deref(), current_dir).map_err(|_| {
+                    WasiStateCreationError::CurrentDirNotPreopened(current_dir.clone())
+                })?;
+            }
 
             // set up the file system, overriding base files and calling the setup function
             if let Some(stdin_override) = self.stdin_override.take() {
@@ -469,8 +1201,25 @@ impl WasiStateBuilder {
             inodes: Arc::new(inodes),
             args: self.args.clone(),
             threading: Default::default(),
-            envs: self
-                .envs
+            syscall_errors: Default::default(),
+            max_read_size: self.max_read_size,
+            nonblocking: self.nonblocking,
+            deterministic: self
+                .deterministic_seed
+                .map(|seed| Mutex::new(Deterministic::new(seed))),
+            clock: match (self.clock.as_ref(), self.deterministic_seed) {
+                (Some(clock), _) => clock.clone(),
+                (None, Some(seed)) => Arc::new(DeterministicClock::new(seed)),
+                (None, None) => default_clock(),
+            },
+            default_dir_mode: self.default_dir_mode,
+            file_umask: self.file_umask,
+            remaining_instructions: self.max_instructions.map(Mutex::new),
+            max_open_fds: self.max_open_fds,
+            max_path_len: self.max_path_len.unwrap_or(DEFAULT_MAX_PATH_LEN),
+            fd_reflection: self.fd_reflection,
+            stubbed_syscalls: self.stubbed_syscalls.clone(),
+            envs: envs
                 .iter()
                 .map(|(key, value)| {
                     let mut env = Vec::with_capacity(key.len() + value.len() + 1);
@@ -504,6 +1253,14 @@ impl WasiStateBuilder {
         if let Some(runtime) = self.runtime_override.as_ref() {
             env.runtime = runtime.clone();
         }
+        env.on_exit = self.on_exit.clone();
+        env.path_mapper = self.path_mapper.clone();
+        env.syscall_tracer = self.syscall_tracer.clone();
+        env.randomness_recorder = self.randomness_recorder.clone();
+        env.randomness_replay = self
+            .replayed_randomness
+            .clone()
+            .map(|randomness| Arc::new(Mutex::new(randomness.into())));
         Ok(WasiFunctionEnv::new(store, env))
     }
 }
@@ -519,7 +1276,7 @@ pub struct PreopenDirBuilder {
 }
 
 /// The built version of `PreopenDirBuilder`
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub(crate) struct PreopenedDir {
     pub(crate) path: PathBuf,
     pub(crate) alias: Option<String>,
@@ -550,6 +1307,11 @@ impl PreopenDirBuilder {
         // We mount at preopened dirs at `/` by default and multiple `/` in a row
         // are equal to a single `/`.
         let alias = alias.trim_start_matches('/');
+        let alias = if alias.len() > 1 {
+            alias.trim_end_matches('/')
+        } else {
+            alias
+        };
         self.alias = Some(alias.to_string());
 
         self
@@ -592,14 +1354,10 @@ impl PreopenDirBuilder {
                 "Preopened directories must point to a host directory".to_string(),
             ));
         }
+        // the path is checked against the file system backing in
+        // [`WasiStateBuilder::build`], it may not exist on the host
         let path = self.path.clone().unwrap();
 
-        /*
-        if !path.exists() {
-            return Err(WasiStateCreationError::PreopenedDirectoryNotFound(path));
-        }
-        */
-
         if let Some(alias) = &self.alias {
             validate_mapped_dir_alias(alias)?;
         }
@@ -657,6 +1415,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn inherited_env_vars() {
+        std::env::set_var("WASMER_WASI_INHERITED", "host");
+        std::env::set_var("WASMER_WASI_OVERRIDDEN", "host");
+        let state = create_wasi_state("test_prog")
+            .env("WASMER_WASI_OVERRIDDEN", "guest")
+            .inherit_env()
+            .build()
+            .unwrap();
+        assert!(state.envs.contains(&b"WASMER_WASI_INHERITED=host".to_vec()));
+        assert!(state
+            .envs
+            .contains(&b"WASMER_WASI_OVERRIDDEN=guest".to_vec()));
+        assert!(!state
+            .envs
+            .contains(&b"WASMER_WASI_OVERRIDDEN=host".to_vec()));
+
+        // Without `inherit_env` the guest sees no host variable
+        let state = create_wasi_state("test_prog").build().unwrap();
+        assert!(state.envs.is_empty());
+    }
+
     #[test]
     fn nul_character_in_args() {
         let output = create_wasi_state("test_prog").arg("--h\0elp").build();
@@ -671,5 +1451,93 @@ mod test {
             Err(WasiStateCreationError::ArgumentContainsNulByte(_)) => assert!(true),
             _ => assert!(false),
         }
+
+        // The invalid UTF-8 sequences of the argument are replaced in the
+        // error
+        let output = create_wasi_state("test_prog").arg(b"\xff-\0").build();
+        assert_eq!(
+            output.err(),
+            Some(WasiStateCreationError::ArgumentContainsNulByte(
+                "\u{fffd}-\0".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn non_utf8_args_and_envs() {
+        let state = create_wasi_state("test_prog")
+            .arg(b"\xff\xfe")
+            .env(b"KEY\xff", b"\xc3(")
+            .build()
+            .unwrap();
+        assert_eq!(state.args[1], b"\xff\xfe".to_vec());
+        assert_eq!(state.envs, vec![b"KEY\xff=\xc3(".to_vec()]);
+    }
+
+    #[test]
+    fn missing_preopened_directory() {
+        let mut builder = create_wasi_state("test_prog");
+        builder
+            .preopen_dir("/this/directory/does/not/exist")
+            .unwrap();
+        let output = builder.build();
+        assert_eq!(
+            output.err(),
+            Some(WasiStateCreationError::PreopenedDirectoryNotFound(
+                "/this/directory/does/not/exist".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn preopens_from_map_are_sorted() {
+        let mapped_dirs = ["/c", "/a", "/b"]
+            .iter()
+            .map(|alias| (alias.to_string(), PathBuf::from(".")))
+            .collect::<HashMap<_, _>>();
+        let mut builder = create_wasi_state("test_prog");
+        builder.preopens_from_map(&mapped_dirs).unwrap();
+        let aliases = builder
+            .preopens
+            .iter()
+            .map(|preopen| preopen.alias.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(aliases, ["a", "b", "c"]);
+
+        let mut mapped_dirs = mapped_dirs;
+        mapped_dirs.insert("da\0ta".to_string(), PathBuf::from("."));
+        assert!(matches!(
+            create_wasi_state("test_prog").preopens_from_map(&mapped_dirs),
+            Err(WasiStateCreationError::MappedDirAliasFormattingError(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_map_dir_alias() {
+        assert!(matches!(
+            create_wasi_state("test_prog").map_dir("da\0ta", "."),
+            Err(WasiStateCreationError::MappedDirAliasFormattingError(_))
+        ));
+    }
+
+    #[cfg(feature = "mem-fs-mounts")]
+    #[test]
+    fn current_dir_outside_preopens() {
+        use wasmer_vfs::FileSystem;
+
+        let fs = wasmer_vfs::mem_fs::FileSystem::default();
+        fs.create_dir(Path::new("/data")).unwrap();
+        let mut builder = create_wasi_state("test_prog");
+        builder
+            .set_fs(Box::new(fs))
+            .map_dir("data", "/data")
+            .unwrap()
+            .current_dir("/elsewhere");
+        assert_eq!(
+            builder.build().err(),
+            Some(WasiStateCreationError::CurrentDirNotPreopened(
+                "/elsewhere".to_string()
+            ))
+        );
     }
 }
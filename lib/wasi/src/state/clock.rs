@@ -0,0 +1,137 @@
+//! The clocks read by the guest, see
+//! [`WasiStateBuilder::clock`](crate::WasiStateBuilder::clock).
+
+use crate::syscalls::types::*;
+use crate::syscalls::{platform_clock_res_get, platform_clock_time_get};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A source of time for the guest, read by `clock_time_get`,
+/// `clock_res_get`, the clock subscriptions of `poll_oneoff`, the sleeps of
+/// the guest and the `*_NOW` flags of the `*_filestat_set_times` syscalls
+pub trait WasiClock: Debug + Send + Sync {
+    /// Returns the current time of the clock `clock_id`, in nanoseconds, or
+    /// `__WASI_EINVAL` if the clock is not supported
+    fn now(&self, clock_id: __wasi_clockid_t) -> Result<__wasi_timestamp_t, __wasi_errno_t>;
+
+    /// Returns the resolution of the clock `clock_id`, in nanoseconds, or
+    /// `__WASI_EINVAL` if the clock is not supported
+    fn resolution(&self, clock_id: __wasi_clockid_t) -> Result<__wasi_timestamp_t, __wasi_errno_t>;
+
+    /// Waits until `duration` passed on the clock. Clocks that don't follow
+    /// the host time move forward by `duration` and return right away.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The clocks of the host, used unless another clock is set
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostClock;
+
+impl WasiClock for HostClock {
+    fn now(&self, clock_id: __wasi_clockid_t) -> Result<__wasi_timestamp_t, __wasi_errno_t> {
+        platform_clock_time_get(clock_id, 1).map(|time| time as __wasi_timestamp_t)
+    }
+
+    fn resolution(&self, clock_id: __wasi_clockid_t) -> Result<__wasi_timestamp_t, __wasi_errno_t> {
+        platform_clock_res_get(clock_id).map(|resolution| resolution as __wasi_timestamp_t)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when [`ManualClock::advance`] is called or when
+/// the guest sleeps on it, e.g. so that the timers of a guest fire at a
+/// precise point of a test without waiting for them.
+///
+/// All the clocks of the guest read the same time, which starts at 0.
+/// Clones share the time, so a clone kept by the host can move the clock
+/// of a running guest.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Creates a clock at time 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the time of the clock
+    pub fn time(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+    /// Moves the time of the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl WasiClock for ManualClock {
+    fn now(&self, clock_id: __wasi_clockid_t) -> Result<__wasi_timestamp_t, __wasi_errno_t> {
+        check_clock_id(clock_id)?;
+        Ok(self.nanos.load(Ordering::SeqCst))
+    }
+
+    fn resolution(&self, clock_id: __wasi_clockid_t) -> Result<__wasi_timestamp_t, __wasi_errno_t> {
+        check_clock_id(clock_id)?;
+        Ok(1)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// The virtual clock of
+/// [`WasiStateBuilder::deterministic`](crate::WasiStateBuilder::deterministic),
+/// all clocks share a time that starts at 0 and advances by a step derived
+/// from the seed every time it is read
+#[derive(Debug)]
+pub(crate) struct DeterministicClock {
+    nanos: AtomicU64,
+    step: u64,
+}
+
+impl DeterministicClock {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            nanos: AtomicU64::new(0),
+            step: 1 + seed % 1_000_000,
+        }
+    }
+}
+
+impl WasiClock for DeterministicClock {
+    fn now(&self, clock_id: __wasi_clockid_t) -> Result<__wasi_timestamp_t, __wasi_errno_t> {
+        check_clock_id(clock_id)?;
+        Ok(self.nanos.fetch_add(self.step, Ordering::SeqCst))
+    }
+
+    fn resolution(&self, clock_id: __wasi_clockid_t) -> Result<__wasi_timestamp_t, __wasi_errno_t> {
+        check_clock_id(clock_id)?;
+        Ok(1)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+/// Fails with `__WASI_EINVAL` if `clock_id` is not one of the WASI clocks
+fn check_clock_id(clock_id: __wasi_clockid_t) -> Result<(), __wasi_errno_t> {
+    match clock_id {
+        __WASI_CLOCK_REALTIME
+        | __WASI_CLOCK_MONOTONIC
+        | __WASI_CLOCK_PROCESS_CPUTIME_ID
+        | __WASI_CLOCK_THREAD_CPUTIME_ID => Ok(()),
+        _ => Err(__WASI_EINVAL),
+    }
+}
@@ -0,0 +1,114 @@
+//! A [`VirtualFile`] wrapper that counts the bytes read from and written to
+//! it.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use wasmer_vfs::{FsError, VirtualFile};
+
+/// Forwards everything to the wrapped file and counts the bytes that went
+/// through it, e.g. to report the throughput of stdin and stdout.
+///
+/// The data is not altered. Since the file is usually handed over to the
+/// [`WasiState`](crate::WasiState), the counters can be read after the run
+/// through the [`ByteCounters`] returned by [`CountingFile::counters`].
+/// Duplicates of the file made with
+/// [`VirtualFile::try_clone`](wasmer_vfs::VirtualFile::try_clone), e.g. by
+/// `fork`, share the counters of the original file.
+#[derive(Debug)]
+pub struct CountingFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    counters: ByteCounters,
+}
+
+/// The counters of a [`CountingFile`], they keep counting while the file is
+/// in use
+#[derive(Debug, Clone, Default)]
+pub struct ByteCounters {
+    read: Arc<AtomicU64>,
+    written: Arc<AtomicU64>,
+}
+
+impl ByteCounters {
+    /// Returns the number of bytes read from the file so far
+    pub fn bytes_read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes written to the file so far
+    pub fn bytes_written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+impl CountingFile {
+    /// Wraps `inner`, with both counters starting at zero
+    pub fn new(inner: Box<dyn VirtualFile + Send + Sync + 'static>) -> Self {
+        Self {
+            inner,
+            counters: ByteCounters::default(),
+        }
+    }
+
+    /// Returns the number of bytes read from the file so far
+    pub fn bytes_read(&self) -> u64 {
+        self.counters.bytes_read()
+    }
+
+    /// Returns the number of bytes written to the file so far
+    pub fn bytes_written(&self) -> u64 {
+        self.counters.bytes_written()
+    }
+
+    /// Returns a handle to the counters of the file, which can be kept
+    /// after the file is moved
+    pub fn counters(&self) -> ByteCounters {
+        self.counters.clone()
+    }
+
+    /// Returns the wrapped file
+    pub fn into_inner(self) -> Box<dyn VirtualFile + Send + Sync + 'static> {
+        self.inner
+    }
+}
+
+impl Read for CountingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.counters.read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.counters
+            .written
+            .fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for CountingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+forward_virtual_file!(CountingFile {
+    fn set_len(&mut self, new_size: u64) -> Result<(), FsError> {
+        self.inner.set_len(new_size)
+    }
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let inner = self.inner.try_clone()?;
+        Some(Box::new(Self {
+            inner,
+            counters: self.counters.clone(),
+        }))
+    }
+});
@@ -0,0 +1,36 @@
+//! The seeded randomness behind
+//! [`WasiStateBuilder::deterministic`](crate::WasiStateBuilder::deterministic),
+//! the virtual clock is a [`DeterministicClock`](super::DeterministicClock).
+
+#[cfg(feature = "enable-serde")]
+use serde::{Deserialize, Serialize};
+
+/// Replaces the host randomness so that a whole run is reproducible from a
+/// single seed
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub(crate) struct Deterministic {
+    rng_state: u64,
+}
+
+impl Deterministic {
+    pub fn new(seed: u64) -> Self {
+        Self { rng_state: seed }
+    }
+
+    /// Fills `buf` with the next bytes of the SplitMix64 stream
+    pub fn fill_random(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,101 @@
+//! Virtual device files installed in `/dev` by [`WasiStateBuilder::with_devfs`].
+//!
+//! [`WasiStateBuilder::with_devfs`]: crate::WasiStateBuilder::with_devfs
+
+use std::io::{self, Read, Seek, Write};
+use wasmer_vfs::{FsError, VirtualFile};
+
+/// `/dev/null`: discards everything written to it and is always at EOF
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullFile;
+
+/// `/dev/zero`: discards everything written to it and reads as an endless
+/// stream of zeros
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZeroFile;
+
+impl Read for NullFile {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Read for ZeroFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+}
+
+impl Write for NullFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Devices have no position, every seek lands at the start
+impl Seek for NullFile {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl VirtualFile for NullFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+impl Write for ZeroFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ZeroFile {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl VirtualFile for ZeroFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
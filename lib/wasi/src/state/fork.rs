@@ -0,0 +1,238 @@
+//! Copying a [`WasiState`] for a child instance, see [`WasiState::fork`].
+
+use super::*;
+
+impl WasiState {
+    /// Creates the state of a child instance, like `fork` does for a
+    /// process: the child gets a copy of the arguments, the environment
+    /// variables and the fd table, and it gets its own memory when it is
+    /// instantiated.
+    ///
+    /// The inodes are deep-copied, so the child can open, close and
+    /// renumber fds independently of the parent. Open files are duplicated
+    /// with [`VirtualFile::try_clone`](wasmer_vfs::VirtualFile::try_clone),
+    /// the duplicate shares the underlying file (e.g. the OS file of a host
+    /// file); files that can't be duplicated are closed in the child. The
+    /// file system backing is shared through
+    /// [`FileSystem::try_clone`](wasmer_vfs::FileSystem::try_clone), forking
+    /// fails with [`WasiStateCreationError::WasiFsCreationError`] when it
+    /// can't be shared. An in-memory backing is copied instead, so that the
+    /// child and the parent don't see each other's writes; the files open in
+    /// it are reopened in the copy.
+    ///
+    /// Sockets and the pipes created by `fd_pipe` are not forked: they are
+    /// dropped in the child, along with their fds.
+    pub fn fork(&self) -> Result<WasiState, WasiStateCreationError> {
+        let inodes = self.inodes.read().unwrap();
+        let (fs, inodes) = self.fs.fork(inodes.deref())?;
+
+        Ok(WasiState {
+            fs,
+            inodes: Arc::new(RwLock::new(inodes)),
+            threading: Default::default(),
+            syscall_errors: Default::default(),
+            max_read_size: self.max_read_size,
+            nonblocking: self.nonblocking,
+            deterministic: self
+                .deterministic
+                .as_ref()
+                .map(|deterministic| Mutex::new(deterministic.lock().unwrap().clone())),
+            clock: self.clock.clone(),
+            default_dir_mode: self.default_dir_mode,
+            file_umask: self.file_umask,
+            remaining_instructions: self
+                .remaining_instructions
+                .as_ref()
+                .map(|remaining| Mutex::new(*remaining.lock().unwrap())),
+            max_open_fds: self.max_open_fds,
+            max_path_len: self.max_path_len,
+            fd_reflection: self.fd_reflection,
+            stubbed_syscalls: self.stubbed_syscalls.clone(),
+            args: self.args.clone(),
+            envs: self.envs.clone(),
+        })
+    }
+}
+
+impl WasiFs {
+    /// Copies the fd table and `inodes`, the inodes are given new indices
+    /// in the copy
+    pub(crate) fn fork(
+        &self,
+        inodes: &WasiInodes,
+    ) -> Result<(WasiFs, WasiInodes), WasiStateCreationError> {
+        // the child must not escape to another file system than the one of
+        // its parent
+        let fs_backing = self.fs_backing.try_clone().ok_or_else(|| {
+            WasiStateCreationError::WasiFsCreationError(
+                "the file system backing can't be shared with the child".to_string(),
+            )
+        })?;
+
+        let mut arena = Arena::new();
+        let mut orphan_fds = HashMap::new();
+        let mut remapped = HashMap::new();
+        for (inode, val) in inodes.arena.iter() {
+            if let Some(val) = val.fork() {
+                remapped.insert(inode, arena.insert(val));
+            }
+        }
+        for (inode, val) in inodes.orphan_fds.iter() {
+            if let Some(val) = val.fork() {
+                // an orphan keeps an index that is no longer used by the arena
+                let orphan = arena.insert(val);
+                let val = arena.remove(orphan).unwrap();
+                orphan_fds.insert(orphan, val);
+                remapped.insert(*inode, orphan);
+            }
+        }
+        for val in arena
+            .iter_mut()
+            .map(|(_, val)| val)
+            .chain(orphan_fds.values_mut())
+        {
+            val.kind.get_mut().unwrap().remap_inodes(&remapped);
+        }
+
+        // an in-memory backing is copied rather than shared, so that the
+        // writes of the child aren't visible to its parent, the open files
+        // are reopened in the copy
+        #[cfg(feature = "mem-fs-mounts")]
+        let fs_backing: Box<dyn FileSystem> = match self
+            .fs_backing
+            .downcast_ref::<wasmer_vfs::mem_fs::FileSystem>()
+        {
+            Some(backing) => {
+                let copy = backing.deep_copy();
+                for val in arena
+                    .iter_mut()
+                    .map(|(_, val)| val)
+                    .chain(orphan_fds.values_mut())
+                {
+                    if let Kind::File {
+                        handle: Some(handle),
+                        ..
+                    } = val.kind.get_mut().unwrap()
+                    {
+                        if let Some(reopened) = copy.reopen(handle.as_ref()) {
+                            *handle = reopened;
+                        }
+                    }
+                }
+                Box::new(copy)
+            }
+            None => fs_backing,
+        };
+
+        let fd_map = self
+            .fd_map
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(fd, entry)| {
+                let inode = *remapped.get(&entry.inode)?;
+                Some((*fd, Fd { inode, ..*entry }))
+            })
+            .collect();
+        let name_map = self
+            .name_map
+            .iter()
+            .filter_map(|(name, inode)| Some((name.clone(), *remapped.get(inode)?)))
+            .collect();
+
+        let fs = WasiFs {
+            preopen_fds: RwLock::new(self.preopen_fds.read().unwrap().clone()),
+            name_map,
+            fd_map: RwLock::new(fd_map),
+            next_fd: AtomicU32::new(self.next_fd.load(Ordering::Acquire)),
+            inode_counter: AtomicU64::new(self.inode_counter.load(Ordering::Acquire)),
+            current_dir: Mutex::new(self.current_dir.lock().unwrap().clone()),
+            is_wasix: AtomicBool::new(self.is_wasix.load(Ordering::Acquire)),
+            io_block_size: self.io_block_size,
+            no_follow_symlinks: self.no_follow_symlinks,
+            read_only_dirs: self.read_only_dirs.clone(),
+            fs_backing,
+        };
+        Ok((fs, WasiInodes { arena, orphan_fds }))
+    }
+}
+
+impl InodeVal {
+    /// Returns `None` for the inodes that are not forked
+    fn fork(&self) -> Option<InodeVal> {
+        let kind = self.read().fork()?;
+        Some(InodeVal {
+            stat: RwLock::new(*self.stat.read().unwrap()),
+            is_preopened: self.is_preopened,
+            name: self.name.clone(),
+            kind: RwLock::new(kind),
+        })
+    }
+}
+
+impl Kind {
+    /// Returns `None` for sockets and pipes, which are not forked, the
+    /// inodes in the copy still have to be remapped
+    fn fork(&self) -> Option<Kind> {
+        Some(match self {
+            Kind::File { handle, path, fd } => Kind::File {
+                handle: handle.as_ref().and_then(|handle| handle.try_clone()),
+                path: path.clone(),
+                fd: *fd,
+            },
+            Kind::Socket { .. } | Kind::Pipe { .. } => return None,
+            Kind::Dir {
+                parent,
+                path,
+                entries,
+            } => Kind::Dir {
+                parent: *parent,
+                path: path.clone(),
+                entries: entries.clone(),
+            },
+            Kind::Root { entries } => Kind::Root {
+                entries: entries.clone(),
+            },
+            Kind::Symlink {
+                base_po_dir,
+                path_to_symlink,
+                relative_path,
+            } => Kind::Symlink {
+                base_po_dir: *base_po_dir,
+                path_to_symlink: path_to_symlink.clone(),
+                relative_path: relative_path.clone(),
+            },
+            Kind::Buffer { buffer } => Kind::Buffer {
+                buffer: buffer.clone(),
+            },
+            Kind::EventNotifications {
+                counter,
+                is_semaphore,
+                wakers,
+            } => Kind::EventNotifications {
+                counter: counter.clone(),
+                is_semaphore: *is_semaphore,
+                wakers: wakers.clone(),
+            },
+        })
+    }
+
+    /// Replaces the inodes this one refers to, dropping the directory
+    /// entries whose inode was not forked
+    fn remap_inodes(&mut self, remapped: &HashMap<Inode, Inode>) {
+        let entries = match self {
+            Kind::Dir {
+                parent, entries, ..
+            } => {
+                *parent = parent.and_then(|parent| remapped.get(&parent).copied());
+                entries
+            }
+            Kind::Root { entries } => entries,
+            _ => return,
+        };
+        *entries = entries
+            .drain()
+            .filter_map(|(name, inode)| Some((name, *remapped.get(&inode)?)))
+            .collect();
+    }
+}
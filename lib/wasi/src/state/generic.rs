@@ -0,0 +1,125 @@
+//! A [`VirtualFile`] adapter for any host stream.
+
+use std::any::type_name;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use wasmer_vfs::{FsError, VirtualFile};
+
+/// Streams that can tell how many bytes can be read without blocking, see
+/// [`GenericFile::with_bytes_available`]
+pub trait BytesAvailable {
+    /// Returns the number of bytes that can be read without blocking, or
+    /// `None` if it is unknown. This function must not block
+    fn bytes_available_read(&self) -> Option<usize>;
+}
+
+/// Exposes any host stream, e.g. a TLS stream or a decoder, to the guest.
+///
+/// The timestamps are zero, the size is found by seeking to the end of the
+/// stream, and the stream can't be resized. There is no host fd to poll, and
+/// the number of bytes available is unknown (reported as zero) unless the
+/// file is created with [`GenericFile::with_bytes_available`].
+pub struct GenericFile<T> {
+    // Behind a lock so that `size`, which takes `&self`, can seek
+    inner: Mutex<T>,
+    bytes_available_read: Option<fn(&T) -> Option<usize>>,
+}
+
+impl<T> GenericFile<T>
+where
+    T: Read + Write + Seek + Send + 'static,
+{
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: Mutex::new(inner),
+            bytes_available_read: None,
+        }
+    }
+
+    /// Returns the wrapped stream
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner().unwrap()
+    }
+}
+
+impl<T> GenericFile<T>
+where
+    T: Read + Write + Seek + Send + BytesAvailable + 'static,
+{
+    /// Like [`GenericFile::new`], the number of bytes available is the one
+    /// reported by the stream
+    pub fn with_bytes_available(inner: T) -> Self {
+        Self {
+            inner: Mutex::new(inner),
+            bytes_available_read: Some(T::bytes_available_read),
+        }
+    }
+}
+
+impl<T> fmt::Debug for GenericFile<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GenericFile")
+            .field("inner", &type_name::<T>())
+            .finish()
+    }
+}
+
+impl<T: Read> Read for GenericFile<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.get_mut().unwrap().read(buf)
+    }
+}
+
+impl<T: Write> Write for GenericFile<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.get_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.get_mut().unwrap().flush()
+    }
+}
+
+impl<T: Seek> Seek for GenericFile<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.get_mut().unwrap().seek(pos)
+    }
+}
+
+impl<T> VirtualFile for GenericFile<T>
+where
+    T: Read + Write + Seek + Send + 'static,
+{
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    /// Seeks to the end of the stream and back, zero if the stream can't seek
+    fn size(&self) -> u64 {
+        stream_len(&mut *self.inner.lock().unwrap()).unwrap_or(0)
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn bytes_available_read(&self) -> Result<Option<usize>, FsError> {
+        Ok(self
+            .bytes_available_read
+            .and_then(|bytes_available_read| bytes_available_read(&self.inner.lock().unwrap())))
+    }
+}
+
+fn stream_len(stream: &mut impl Seek) -> io::Result<u64> {
+    let cursor = stream.stream_position()?;
+    let end = stream.seek(SeekFrom::End(0))?;
+    stream.seek(SeekFrom::Start(cursor))?;
+    Ok(end)
+}
@@ -137,6 +137,16 @@ impl VirtualFile for WasiStateFileGuard {
         }
     }
 
+    fn current_len(&self) -> Result<u64, FsError> {
+        let inodes = self.inodes.read().unwrap();
+        let guard = self.lock_read(&inodes);
+        if let Some(file) = guard.deref() {
+            file.current_len()
+        } else {
+            Err(FsError::IOError)
+        }
+    }
+
     fn set_len(&mut self, new_size: u64) -> Result<(), FsError> {
         let inodes = self.inodes.read().unwrap();
         let mut guard = self.lock_write(&inodes);
@@ -167,6 +177,16 @@ impl VirtualFile for WasiStateFileGuard {
         }
     }
 
+    fn sync_data_to_disk(&self) -> Result<(), FsError> {
+        let inodes = self.inodes.read().unwrap();
+        let guard = self.lock_read(&inodes);
+        if let Some(file) = guard.deref() {
+            file.sync_data_to_disk()
+        } else {
+            Err(FsError::IOError)
+        }
+    }
+
     fn bytes_available(&self) -> Result<usize, FsError> {
         let inodes = self.inodes.read().unwrap();
         let guard = self.lock_read(&inodes);
@@ -216,6 +236,16 @@ impl VirtualFile for WasiStateFileGuard {
             None
         }
     }
+
+    fn is_seekable(&self) -> bool {
+        let inodes = self.inodes.read().unwrap();
+        let guard = self.lock_read(&inodes);
+        if let Some(file) = guard.deref() {
+            file.is_seekable()
+        } else {
+            false
+        }
+    }
 }
 
 impl Write for WasiStateFileGuard {
@@ -0,0 +1,105 @@
+//! A read-only [`VirtualFile`] over bytes shared between files.
+
+use std::io::{self, Read, Seek, Write};
+use std::sync::Arc;
+use wasmer_vfs::{FsError, VirtualFile};
+
+/// Reads from bytes shared with other files without copying them, e.g. to
+/// feed the same fixture input to the stdin of many instances.
+///
+/// Every `StaticInput` has its own cursor, clones start where the original
+/// is. Writes fail with [`io::ErrorKind::PermissionDenied`] and the file
+/// can't be resized. The timestamps are zero.
+#[derive(Debug, Clone)]
+pub struct StaticInput {
+    data: Arc<[u8]>,
+    cursor: u64,
+}
+
+impl StaticInput {
+    /// Reads from `data`, which is only copied if it isn't an `Arc<[u8]>`
+    /// already
+    pub fn new(data: impl Into<Arc<[u8]>>) -> Self {
+        Self {
+            data: data.into(),
+            cursor: 0,
+        }
+    }
+
+    /// Returns the bytes the file reads from
+    pub fn data(&self) -> &Arc<[u8]> {
+        &self.data
+    }
+
+    /// Returns the number of bytes left to read
+    fn remaining(&self) -> usize {
+        (self.data.len() as u64).saturating_sub(self.cursor) as usize
+    }
+}
+
+impl Read for StaticInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.data.len() - self.remaining();
+        let read = buf.len().min(self.remaining());
+        buf[..read].copy_from_slice(&self.data[start..][..read]);
+        self.cursor += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for StaticInput {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let cursor = match pos {
+            io::SeekFrom::Start(offset) => offset as i128,
+            io::SeekFrom::End(offset) => self.data.len() as i128 + offset as i128,
+            io::SeekFrom::Current(offset) => self.cursor as i128 + offset as i128,
+        };
+        if cursor < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seeking before the byte 0",
+            ));
+        }
+        self.cursor = cursor as u64;
+        Ok(self.cursor)
+    }
+}
+
+impl Write for StaticInput {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "can not write to a static input",
+        ))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl VirtualFile for StaticInput {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn bytes_available(&self) -> Result<usize, FsError> {
+        Ok(self.remaining())
+    }
+    fn bytes_available_read(&self) -> Result<Option<usize>, FsError> {
+        Ok(Some(self.remaining()))
+    }
+}
@@ -16,19 +16,50 @@
 #![allow(clippy::cognitive_complexity, clippy::too_many_arguments)]
 
 mod builder;
+mod clock;
+mod counting;
+mod deterministic;
+mod devfs;
+mod fork;
+mod generic;
 mod guard;
+mod input;
+mod mount;
+#[cfg(feature = "mem-fs-mounts")]
+mod overlay;
 mod pipe;
+mod quota;
+mod readonly;
 mod socket;
+#[cfg(feature = "mem-fs-mounts")]
+mod tar;
 mod types;
+mod validating;
 
 pub use self::builder::*;
+pub use self::clock::*;
+pub use self::counting::*;
+pub(crate) use self::deterministic::*;
+pub use self::devfs::*;
+pub use self::generic::*;
 pub use self::guard::*;
+pub use self::input::*;
+pub use self::mount::*;
+#[cfg(feature = "mem-fs-mounts")]
+pub use self::overlay::*;
 pub use self::pipe::*;
+pub use self::quota::*;
+pub use self::readonly::*;
 pub use self::socket::*;
+#[cfg(feature = "mem-fs-mounts")]
+pub use self::tar::*;
 pub use self::types::*;
+pub use self::validating::*;
 use crate::syscalls::types::*;
+use crate::syscalls::{platform_file_name_to_guest, platform_path_from_guest};
 use crate::utils::map_io_err;
 use crate::WasiBusProcessId;
+use crate::WasiError;
 use crate::WasiThread;
 use crate::WasiThreadId;
 use generational_arena::Arena;
@@ -36,13 +67,15 @@ pub use generational_arena::Index as Inode;
 #[cfg(feature = "enable-serde")]
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::{
     borrow::Borrow,
-    io::Write,
+    io::{Read, Write},
     ops::{Deref, DerefMut},
     path::{Path, PathBuf},
     sync::{
@@ -57,8 +90,15 @@ use wasmer_vfs::{FileSystem, FsError, OpenOptions, VirtualFile};
 
 /// the fd value of the virtual root
 pub const VIRTUAL_ROOT_FD: __wasi_fd_t = 3;
+/// the path of the directory installed by [`WasiStateBuilder::with_fd_reflection`]
+pub(crate) const FD_REFLECTION_DIR: &str = "/proc/self/fd";
 /// all the rights enabled
 pub const ALL_RIGHTS: __wasi_rights_t = 0x1FFF_FFFF;
+/// the I/O block size reported for files that have no preference
+pub const DEFAULT_IO_BLOCK_SIZE: u32 = 4096;
+/// the longest guest path accepted by the path syscalls, unless set with
+/// [`WasiStateBuilder::max_path_len`]
+pub const DEFAULT_MAX_PATH_LEN: usize = 4096;
 const STDIN_DEFAULT_RIGHTS: __wasi_rights_t = __WASI_RIGHT_FD_DATASYNC
     | __WASI_RIGHT_FD_READ
     | __WASI_RIGHT_FD_SYNC
@@ -73,9 +113,15 @@ const STDOUT_DEFAULT_RIGHTS: __wasi_rights_t = __WASI_RIGHT_FD_DATASYNC
     | __WASI_RIGHT_POLL_FD_READWRITE;
 const STDERR_DEFAULT_RIGHTS: __wasi_rights_t = STDOUT_DEFAULT_RIGHTS;
 
-/// A completely aribtrary "big enough" number used as the upper limit for
-/// the number of symlinks that can be traversed when resolving a path
-pub const MAX_SYMLINKS: u32 = 128;
+/// The instructions charged to the budget set with
+/// [`WasiStateBuilder::max_instructions`] for every syscall the guest makes
+pub const SYSCALL_INSTRUCTIONS: u64 = 1;
+
+/// The upper limit for the number of symlinks that can be traversed when
+/// resolving a path, like `MAXSYMLINKS` on Linux. Resolving a path that goes
+/// through more symlinks, e.g. a symlink pointing to itself, fails with
+/// `__WASI_ELOOP`.
+pub const MAX_SYMLINKS: u32 = 40;
 
 /// A file that Wasi knows about that may or may not be open
 #[derive(Debug)]
@@ -199,6 +245,42 @@ impl Fd {
     pub const CREATE: u16 = 16;
 }
 
+/// The rights and flags of the fd given to a file the host opens, see
+/// [`WasiFs::open_file_relative`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FdOptions {
+    pub rights: __wasi_rights_t,
+    pub rights_inheriting: __wasi_rights_t,
+    pub flags: __wasi_fdflags_t,
+    /// See [`Fd::open_flags`]
+    pub open_flags: u16,
+}
+
+/// The kind of resource an fd refers to, see [`FdInfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdKind {
+    /// One of stdin, stdout or stderr
+    Stdio,
+    File,
+    Dir,
+    Socket,
+    Pipe,
+    Symlink,
+    Buffer,
+    EventNotifications,
+}
+
+/// A description of an open fd, returned by [`WasiState::fds`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdInfo {
+    pub kind: FdKind,
+    /// The path of the file or directory in the backing filesystem, if any
+    pub path: Option<PathBuf>,
+    pub rights: __wasi_rights_t,
+    pub rights_inheriting: __wasi_rights_t,
+    pub offset: u64,
+}
+
 #[derive(Debug)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct WasiInodes {
@@ -318,6 +400,17 @@ impl WasiInodes {
     }
 }
 
+/// An entry of a directory, as listed by [`WasiFs::read_dir`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The name of the entry in the directory
+    pub name: String,
+    /// The WASI file type of the entry, e.g. `__WASI_FILETYPE_DIRECTORY`
+    pub filetype: __wasi_filetype_t,
+    /// The inode number of the entry, as returned by `path_filestat_get`
+    pub inode: __wasi_inode_t,
+}
+
 /// Warning, modifying these fields directly may cause invariants to break and
 /// should be considered unsafe.  These fields may be made private in a future release
 #[derive(Debug)]
@@ -329,12 +422,28 @@ pub struct WasiFs {
     pub fd_map: RwLock<HashMap<u32, Fd>>,
     pub next_fd: AtomicU32,
     inode_counter: AtomicU64,
+    /// The working directory, as a path seen by the guest, see
+    /// [`WasiFs::set_cwd`]
     pub current_dir: Mutex<String>,
     pub is_wasix: AtomicBool,
+    /// The I/O block size reported for files that have no preference, see
+    /// [`WasiStateBuilder::io_block_size`]
+    pub(crate) io_block_size: u32,
+    /// Makes path resolution fail on symlinks instead of following them, see
+    /// [`WasiStateBuilder::no_follow_symlinks`]
+    pub(crate) no_follow_symlinks: bool,
+    /// The paths of the file system backing where nothing can be modified,
+    /// including the virtual entries, see [`WasiFs::check_dir_writable`]
+    pub(crate) read_only_dirs: Vec<PathBuf>,
     #[cfg_attr(feature = "enable-serde", serde(skip, default = "default_fs_backing"))]
     pub fs_backing: Box<dyn FileSystem>,
 }
 
+/// Returns the clock read by the guest when no other clock is set
+pub(crate) fn default_clock() -> Arc<dyn WasiClock> {
+    Arc::new(HostClock)
+}
+
 /// Returns the default filesystem backing
 pub(crate) fn default_fs_backing() -> Box<dyn wasmer_vfs::FileSystem> {
     cfg_if::cfg_if! {
@@ -364,6 +473,9 @@ impl FileSystem for FallbackFileSystem {
     fn create_dir(&self, _path: &Path) -> Result<(), FsError> {
         Self::fail();
     }
+    fn create_dir_with_mode(&self, _path: &Path, _mode: u32) -> Result<(), FsError> {
+        Self::fail();
+    }
     fn remove_dir(&self, _path: &Path) -> Result<(), FsError> {
         Self::fail();
     }
@@ -379,6 +491,15 @@ impl FileSystem for FallbackFileSystem {
     fn remove_file(&self, _path: &Path) -> Result<(), FsError> {
         Self::fail();
     }
+    fn set_times(
+        &self,
+        _path: &Path,
+        _accessed: Option<u64>,
+        _modified: Option<u64>,
+        _follow_symlinks: bool,
+    ) -> Result<(), FsError> {
+        Self::fail();
+    }
     fn new_open_options(&self) -> wasmer_vfs::OpenOptions {
         Self::fail();
     }
@@ -580,6 +701,9 @@ impl WasiFs {
             inode_counter: AtomicU64::new(1024),
             current_dir: Mutex::new("/".to_string()),
             is_wasix: AtomicBool::new(false),
+            io_block_size: DEFAULT_IO_BLOCK_SIZE,
+            no_follow_symlinks: false,
+            read_only_dirs: Vec::new(),
             fs_backing,
         };
         wasi_fs.create_stdin(inodes);
@@ -720,6 +844,653 @@ impl WasiFs {
         // an explicit choice, so justify it in a comment when we remove this one
         let base_inode = self.get_fd_inode(base).map_err(fs_error_from_wasi_err)?;
 
+        self.open_file_in_dir(
+            inodes,
+            base_inode,
+            file,
+            name,
+            FdOptions {
+                rights,
+                rights_inheriting,
+                flags,
+                open_flags,
+            },
+        )
+    }
+
+    /// Sets the working directory, as a path seen by the guest, which must
+    /// be under one of the preopened directories.
+    ///
+    /// The host and the guest share the working directory: relative paths
+    /// given to host-facing APIs such as [`WasiFs::open_file_relative`]
+    /// resolve against it, and so do the relative paths a WASI guest opens
+    /// from the preopened directory containing it. A WASIX guest reads and
+    /// changes it with `getcwd` and `chdir`.
+    ///
+    /// Returns [`FsError::EntityNotFound`] if `cwd` is not under any
+    /// preopened directory.
+    pub fn set_cwd(&self, inodes: &WasiInodes, cwd: &str) -> Result<(), FsError> {
+        if self.preopen_containing(inodes, cwd).is_none() {
+            return Err(FsError::EntityNotFound);
+        }
+        self.set_current_dir(cwd);
+        Ok(())
+    }
+
+    /// Gets the working directory, see [`WasiFs::set_cwd`]
+    pub fn cwd(&self) -> String {
+        self.current_dir.lock().unwrap().clone()
+    }
+
+    /// Finds the preopened directory containing `path`, a path as seen by
+    /// the guest, and returns its fd with the rest of `path` inside of it
+    fn preopen_containing(
+        &self,
+        inodes: &WasiInodes,
+        path: &str,
+    ) -> Option<(__wasi_fd_t, PathBuf)> {
+        let path = Path::new(path.trim_start_matches('/'));
+        let mut best_match: Option<(__wasi_fd_t, PathBuf, usize)> = None;
+        for po_fd in self.preopen_fds.read().unwrap().iter() {
+            // the virtual root is not preopened by the user
+            if *po_fd == VIRTUAL_ROOT_FD {
+                continue;
+            }
+            // a preopen the guest closed or renumbered is not there anymore
+            let po_inode = match self.fd_map.read().unwrap().get(po_fd) {
+                Some(fd) => fd.inode,
+                None => continue,
+            };
+            let po_name = inodes.arena[po_inode].name.clone();
+            let po_name = po_name.trim_start_matches('/');
+            if let Ok(rel_path) = path.strip_prefix(po_name) {
+                // the deepest preopen wins, like in WASI libc
+                match &best_match {
+                    Some((_, _, max_seen)) if po_name.len() < *max_seen => (),
+                    _ => best_match = Some((*po_fd, rel_path.to_owned(), po_name.len())),
+                }
+            }
+        }
+        best_match.map(|(fd, rel_path, _)| (fd, rel_path))
+    }
+
+    /// Like [`WasiFs::open_file_at`] but with a `path` relative to the
+    /// working directory, see [`WasiFs::set_cwd`]. The parent directories
+    /// of the file must already exist.
+    ///
+    /// Returns [`FsError::InvalidFd`] if the working directory is not under
+    /// a preopened directory and [`FsError::InvalidInput`] if `path` is
+    /// absolute.
+    pub fn open_file_relative(
+        &mut self,
+        inodes: &mut WasiInodes,
+        file: Box<dyn VirtualFile + Send + Sync + 'static>,
+        path: &str,
+        options: FdOptions,
+    ) -> Result<__wasi_fd_t, FsError> {
+        let (cwd_fd, cwd_path) = self
+            .preopen_containing(inodes, &self.cwd())
+            .ok_or(FsError::InvalidFd)?;
+        let path = Path::new(path);
+        if path.has_root() {
+            return Err(FsError::InvalidInput);
+        }
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(FsError::InvalidInput)?;
+
+        let parent = cwd_path.join(path.parent().unwrap_or_else(|| Path::new("")));
+        let cwd_inode = self.get_fd_inode(cwd_fd).map_err(fs_error_from_wasi_err)?;
+        let dir_inode = self
+            .get_inode_at_path_inner(inodes, cwd_inode, &parent.to_string_lossy(), &mut 0, true)
+            .map_err(fs_error_from_wasi_err)?;
+
+        self.open_file_in_dir(inodes, dir_inode, file, name.to_string(), options)
+    }
+
+    /// Creates an in-memory file holding `contents` at `path`, relative to
+    /// the directory `base`, and opens it. The missing parent directories
+    /// are created in memory too; nothing is written to the file system
+    /// backing.
+    ///
+    /// The guest gets the returned fd when it opens `path` with `path_open`.
+    /// Returns [`FsError::AlreadyExists`] if `path` already exists and
+    /// [`FsError::BaseNotDirectory`] if one of its parents is not a
+    /// directory.
+    pub fn create_file(
+        &mut self,
+        inodes: &mut WasiInodes,
+        base: __wasi_fd_t,
+        path: &str,
+        contents: Vec<u8>,
+    ) -> Result<__wasi_fd_t, FsError> {
+        let path = Path::new(path);
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(FsError::InvalidInput)?;
+
+        let mut cur_inode = self.get_fd_inode(base).map_err(fs_error_from_wasi_err)?;
+        for component in path.parent().into_iter().flat_map(Path::components) {
+            let segment_name = component.as_os_str().to_string_lossy().to_string();
+            cur_inode = match self.get_inode_at_path_inner(
+                inodes,
+                cur_inode,
+                &segment_name,
+                &mut 0,
+                true,
+            ) {
+                Ok(inode) => inode,
+                Err(__WASI_ENOENT) => self.create_dir_in_memory(inodes, cur_inode, segment_name)?,
+                Err(err) => return Err(fs_error_from_wasi_err(err)),
+            };
+            if !matches!(
+                inodes.arena[cur_inode].read().deref(),
+                Kind::Dir { .. } | Kind::Root { .. }
+            ) {
+                return Err(FsError::BaseNotDirectory);
+            }
+        }
+        match self.get_inode_at_path_inner(inodes, cur_inode, name, &mut 0, false) {
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(__WASI_ENOENT) => (),
+            Err(err) => return Err(fs_error_from_wasi_err(err)),
+        }
+
+        self.open_file_in_dir(
+            inodes,
+            cur_inode,
+            Box::new(GenericFile::new(std::io::Cursor::new(contents))),
+            name.to_string(),
+            FdOptions {
+                rights: ALL_RIGHTS,
+                rights_inheriting: ALL_RIGHTS,
+                flags: 0,
+                open_flags: Fd::READ | Fd::WRITE,
+            },
+        )
+    }
+
+    /// Removes the file at `path`, relative to the directory `base`, and
+    /// returns its contents. Any fd still open on the file is closed.
+    ///
+    /// The contents of the files created with [`WasiFs::create_file`] and of
+    /// in-memory buffers are moved out without copying them, files living in
+    /// an in-memory file system backing, i.e. a `mem_fs`, are read through
+    /// their [`VirtualFile`] and removed from the backing. Returns
+    /// [`FsError::EntityNotFound`] if `path` doesn't exist,
+    /// [`FsError::NotAFile`] if it is not a file and
+    /// [`FsError::PermissionDenied`] if it lives in any other backing, e.g.
+    /// on the host, where taking it would delete the real file.
+    pub fn take_file(
+        &mut self,
+        inodes: &mut WasiInodes,
+        base: __wasi_fd_t,
+        path: &str,
+    ) -> Result<Vec<u8>, FsError> {
+        type InMemoryFile = GenericFile<std::io::Cursor<Vec<u8>>>;
+
+        let inode = self
+            .get_inode_at_path(inodes, base, path, false)
+            .map_err(fs_error_from_wasi_err)?;
+        let (parent_inode, name) = self
+            .get_parent_inode_at_path(inodes, base, Path::new(path), false)
+            .map_err(fs_error_from_wasi_err)?;
+
+        let contents = {
+            let mut guard = inodes.arena[inode].write();
+            let is_in_memory = matches!(
+                guard.deref(),
+                Kind::File { handle: Some(h), .. } if (**h).upcast_any_ref().is::<InMemoryFile>()
+            );
+            match guard.deref_mut() {
+                Kind::Buffer { buffer } => std::mem::take(buffer),
+                Kind::File { handle, .. } if is_in_memory => {
+                    let file = handle
+                        .take()
+                        .unwrap()
+                        .upcast_any_box()
+                        .downcast::<InMemoryFile>()
+                        .unwrap();
+                    file.into_inner().into_inner()
+                }
+                Kind::File { handle, path, .. } => {
+                    #[cfg(feature = "mem-fs-mounts")]
+                    let in_memory_backing = self
+                        .fs_backing
+                        .upcast_any_ref()
+                        .is::<wasmer_vfs::mem_fs::FileSystem>();
+                    #[cfg(not(feature = "mem-fs-mounts"))]
+                    let in_memory_backing = false;
+                    if !in_memory_backing {
+                        return Err(FsError::PermissionDenied);
+                    }
+                    // The guest's handle may not be readable, the contents
+                    // are read through a handle of our own
+                    if let Some(handle) = handle {
+                        handle.flush()?;
+                    }
+                    let mut contents = Vec::new();
+                    self.fs_backing
+                        .new_open_options()
+                        .read(true)
+                        .open(path.as_path())?
+                        .read_to_end(&mut contents)?;
+                    match handle {
+                        Some(handle) => handle.unlink()?,
+                        None => self.fs_backing.remove_file(path)?,
+                    }
+                    *handle = None;
+                    contents
+                }
+                _ => return Err(FsError::NotAFile),
+            }
+        };
+
+        match inodes.arena[parent_inode].write().deref_mut() {
+            Kind::Dir { entries, .. } | Kind::Root { entries } => {
+                entries.remove(&name);
+            }
+            _ => unreachable!("Fatal internal logic error: parent of inode is not a directory"),
+        }
+        self.fd_map
+            .write()
+            .unwrap()
+            .retain(|_, fd| fd.inode != inode);
+        unsafe { self.remove_inode(inodes, inode) };
+
+        Ok(contents)
+    }
+
+    /// Gets the metadata of `path`, relative to the directory `base`, as
+    /// the guest sees it with `path_filestat_get`: device id, inode, file
+    /// type, link count, size and timestamps.
+    ///
+    /// The last symlink of `path` is followed if `follow_symlinks` is set.
+    /// Rights are not checked, that is up to the caller.
+    pub fn filestat(
+        &self,
+        inodes: &mut WasiInodes,
+        base: __wasi_fd_t,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<__wasi_filestat_t, FsError> {
+        self.get_stat_at_path(inodes, base, path, follow_symlinks)
+            .map_err(fs_error_from_wasi_err)
+    }
+
+    /// Lists the entries of the directory at `path`, relative to the
+    /// directory `base`, sorted by name, e.g. to browse the file system of
+    /// the guest from the host.
+    ///
+    /// `path` is resolved as the guest would, so it can't leave the
+    /// preopened directories. The entries of the file system backing are
+    /// listed along with the in-memory files and the preopened directories.
+    /// Returns [`FsError::BaseNotDirectory`] if `path` is not a directory.
+    pub fn read_dir(
+        &self,
+        inodes: &mut WasiInodes,
+        base: __wasi_fd_t,
+        path: &str,
+    ) -> Result<Vec<DirEntry>, FsError> {
+        let dir = self
+            .get_inode_at_path(inodes, base, path, true)
+            .map_err(fs_error_from_wasi_err)?;
+
+        // the inode of the entries already loaded, the others are loaded
+        // below
+        let mut entries = BTreeMap::new();
+        match inodes.arena[dir].read().deref() {
+            Kind::Dir {
+                path,
+                entries: loaded,
+                ..
+            } => {
+                // the directories created in memory are not in the backing
+                match self.fs_backing.read_dir(path) {
+                    Ok(read_dir) => {
+                        for entry in read_dir {
+                            let name = platform_file_name_to_guest(&entry?.file_name())
+                                .map_err(fs_error_from_wasi_err)?;
+                            entries.insert(name, None);
+                        }
+                    }
+                    // `mem_fs` reports a missing directory as not a file
+                    Err(FsError::EntityNotFound) | Err(FsError::NotAFile) => (),
+                    Err(err) => return Err(err),
+                }
+                entries.extend(
+                    loaded
+                        .iter()
+                        .map(|(name, inode)| (name.clone(), Some(*inode))),
+                );
+            }
+            Kind::Root { entries: loaded } => {
+                entries.extend(
+                    loaded
+                        .iter()
+                        .map(|(name, inode)| (name.clone(), Some(*inode))),
+                );
+            }
+            _ => return Err(FsError::BaseNotDirectory),
+        }
+
+        entries
+            .into_iter()
+            .map(|(name, inode)| {
+                let inode = match inode {
+                    Some(inode) => inode,
+                    None => self
+                        .get_inode_at_path_inner(inodes, dir, &name, &mut 0, false)
+                        .map_err(fs_error_from_wasi_err)?,
+                };
+                // the same stat as `filestat`, for the inode numbers to match
+                let stat = self
+                    .get_stat_for_inode(inodes, inode)
+                    .map_err(fs_error_from_wasi_err)?;
+                Ok(DirEntry {
+                    name,
+                    filetype: stat.st_filetype,
+                    inode: stat.st_ino,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn get_stat_at_path(
+        &self,
+        inodes: &mut WasiInodes,
+        base: __wasi_fd_t,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<__wasi_filestat_t, __wasi_errno_t> {
+        let inode = self.get_inode_at_path(inodes, base, path, follow_symlinks)?;
+        self.get_stat_for_inode(inodes, inode)
+    }
+
+    /// The stat of `inode`: the one of the entry in the file system backing,
+    /// except for the preopened directories
+    fn get_stat_for_inode(
+        &self,
+        inodes: &WasiInodes,
+        inode: Inode,
+    ) -> Result<__wasi_filestat_t, __wasi_errno_t> {
+        if inodes.arena[inode].is_preopened {
+            Ok(*inodes.arena[inode].stat.read().unwrap().deref())
+        } else {
+            let guard = inodes.arena[inode].read();
+            self.get_stat_for_kind(inodes, guard.deref())
+        }
+    }
+
+    /// Moves `old_path`, relative to the directory `old_fd`, to `new_path`,
+    /// relative to the directory `new_fd`.
+    ///
+    /// If `new_path` already exists it is replaced when `overwrite` is set,
+    /// like POSIX `rename`, otherwise [`__WASI_EEXIST`] is returned, like
+    /// `renameat2` with `RENAME_NOREPLACE`. A directory can only replace an
+    /// empty directory and a file can only replace a file. The file system
+    /// backing replaces the entry as part of its rename, so a failed rename
+    /// leaves the target untouched.
+    ///
+    /// Rights are not checked, that is up to the caller.
+    pub fn rename(
+        &self,
+        inodes: &mut WasiInodes,
+        old_fd: __wasi_fd_t,
+        old_path: &str,
+        new_fd: __wasi_fd_t,
+        new_path: &str,
+        overwrite: bool,
+    ) -> Result<(), __wasi_errno_t> {
+        // resolving the source loads it into its parent's entries if it has
+        // not been accessed before
+        let source_inode = self.get_inode_at_path(inodes, old_fd, old_path, false)?;
+        let (source_parent_inode, source_entry_name) =
+            self.get_parent_inode_at_path(inodes, old_fd, Path::new(old_path), true)?;
+        let (target_parent_inode, target_entry_name) =
+            self.get_parent_inode_at_path(inodes, new_fd, Path::new(new_path), true)?;
+        self.check_dir_writable(inodes, source_parent_inode)?;
+        self.check_dir_writable(inodes, target_parent_inode)?;
+
+        let host_adjusted_target_path = {
+            let guard = inodes.arena[target_parent_inode].read();
+            match guard.deref() {
+                Kind::Dir { path, .. } => path.join(&target_entry_name),
+                Kind::Root { .. } => return Err(__WASI_ENOTCAPABLE),
+                Kind::Socket { .. } | Kind::Pipe { .. } | Kind::EventNotifications { .. } => {
+                    return Err(__WASI_EINVAL)
+                }
+                Kind::Symlink { .. } | Kind::File { .. } | Kind::Buffer { .. } => {
+                    unreachable!("Fatal internal logic error: parent of inode is not a directory")
+                }
+            }
+        };
+
+        // like the source, the target has to be loaded to know if it exists
+        let target_inode = match self.get_inode_at_path_inner(
+            inodes,
+            target_parent_inode,
+            &target_entry_name,
+            &mut 0,
+            false,
+        ) {
+            Ok(target_inode) if target_inode == source_inode => return Ok(()),
+            Ok(_) if !overwrite => return Err(__WASI_EEXIST),
+            Ok(target_inode) => {
+                self.check_rename_target(inodes, source_inode, target_inode)?;
+                Some(target_inode)
+            }
+            Err(__WASI_ENOENT) => None,
+            Err(err) => return Err(err),
+        };
+
+        // the backing replaces the target when it renames the source, but
+        // sources it doesn't know can't replace anything there
+        let source_in_backing = match inodes.arena[source_inode].read().deref() {
+            Kind::File { path, .. } => !path.as_os_str().is_empty(),
+            Kind::Dir { .. } => true,
+            _ => false,
+        };
+        if let (Some(target_inode), false) = (target_inode, source_in_backing) {
+            if let Kind::File { path, .. } = inodes.arena[target_inode].read().deref() {
+                if !path.as_os_str().is_empty() {
+                    self.fs_backing
+                        .remove_file(path)
+                        .map_err(fs_error_into_wasi_err)?;
+                }
+            }
+        }
+
+        let source_entry = {
+            let mut guard = inodes.arena[source_parent_inode].write();
+            match guard.deref_mut() {
+                Kind::Dir { entries, .. } => {
+                    entries.remove(&source_entry_name).ok_or(__WASI_ENOENT)?
+                }
+                Kind::Root { .. } => return Err(__WASI_ENOTCAPABLE),
+                Kind::Socket { .. } | Kind::Pipe { .. } | Kind::EventNotifications { .. } => {
+                    return Err(__WASI_EINVAL)
+                }
+                Kind::Symlink { .. } | Kind::File { .. } | Kind::Buffer { .. } => {
+                    unreachable!("Fatal internal logic error: parent of inode is not a directory")
+                }
+            }
+        };
+
+        {
+            let mut guard = inodes.arena[source_entry].write();
+            match guard.deref_mut() {
+                Kind::File { ref path, .. } => {
+                    let path_clone = path.clone();
+                    drop(guard);
+                    // files without a host path only live in the inode tree, so
+                    // moving the entry below is all that has to happen for them
+                    if !path_clone.as_os_str().is_empty() {
+                        // if the rename fails we have to revert the previous change and then fail
+                        if let Err(e) = self
+                            .fs_backing
+                            .rename(&path_clone, &host_adjusted_target_path)
+                        {
+                            let mut guard = inodes.arena[source_parent_inode].write();
+                            if let Kind::Dir { entries, .. } = guard.deref_mut() {
+                                entries.insert(source_entry_name, source_entry);
+                            }
+                            return Err(fs_error_into_wasi_err(e));
+                        }
+                        let mut guard = inodes.arena[source_entry].write();
+                        if let Kind::File { ref mut path, .. } = guard.deref_mut() {
+                            *path = host_adjusted_target_path;
+                        } else {
+                            unreachable!()
+                        }
+                    }
+                }
+                Kind::Dir { ref path, .. } => {
+                    let cloned_path = path.clone();
+                    drop(guard);
+                    if let Err(e) = self
+                        .fs_backing
+                        .rename(&cloned_path, &host_adjusted_target_path)
+                    {
+                        let mut guard = inodes.arena[source_parent_inode].write();
+                        if let Kind::Dir { entries, .. } = guard.deref_mut() {
+                            entries.insert(source_entry_name, source_entry);
+                        }
+                        return Err(fs_error_into_wasi_err(e));
+                    }
+                    {
+                        let mut guard = inodes.arena[source_entry].write();
+                        if let Kind::Dir { path, .. } = guard.deref_mut() {
+                            *path = host_adjusted_target_path;
+                        }
+                    }
+                }
+                Kind::Buffer { .. } => {}
+                Kind::Symlink { .. } => {}
+                Kind::Socket { .. } => {}
+                Kind::Pipe { .. } => {}
+                Kind::EventNotifications { .. } => {}
+                Kind::Root { .. } => unreachable!("The root can not be moved"),
+            }
+        }
+
+        {
+            let mut guard = inodes.arena[target_parent_inode].write();
+            if let Kind::Dir { entries, .. } = guard.deref_mut() {
+                // the replaced target is only dropped once the rename is done
+                if target_inode.is_some() {
+                    entries.remove(&target_entry_name);
+                }
+                let result = entries.insert(target_entry_name, source_entry);
+                assert!(
+                    result.is_none(),
+                    "Fatal error: race condition on filesystem detected or internal logic error"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `source_inode` can replace the existing entry
+    /// `target_inode`, which the file system backing removes as part of
+    /// the rename
+    fn check_rename_target(
+        &self,
+        inodes: &WasiInodes,
+        source_inode: Inode,
+        target_inode: Inode,
+    ) -> Result<(), __wasi_errno_t> {
+        let source_is_dir = matches!(inodes.arena[source_inode].read().deref(), Kind::Dir { .. });
+        let guard = inodes.arena[target_inode].read();
+        match guard.deref() {
+            Kind::Dir { entries, .. } => {
+                if !source_is_dir {
+                    return Err(__WASI_EISDIR);
+                }
+                // the backing also refuses directories with entries that
+                // have not been loaded yet
+                if !entries.is_empty() {
+                    return Err(__WASI_ENOTEMPTY);
+                }
+            }
+            Kind::Root { .. } => return Err(__WASI_ENOTCAPABLE),
+            _ if source_is_dir => return Err(__WASI_ENOTDIR),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Adds the character device `name` to the directory at `dir` (relative
+    /// to the virtual root), every open of the device shares `handle`
+    pub(crate) fn add_device(
+        &self,
+        inodes: &mut WasiInodes,
+        dir: &str,
+        name: &str,
+        handle: Box<dyn VirtualFile + Send + Sync + 'static>,
+    ) -> Result<(), FsError> {
+        let parent = self
+            .get_inode_at_path(inodes, VIRTUAL_ROOT_FD, dir, false)
+            .map_err(fs_error_from_wasi_err)?;
+        let kind = Kind::File {
+            handle: Some(handle),
+            path: PathBuf::new(),
+            fd: None,
+        };
+        let stat = __wasi_filestat_t {
+            st_filetype: __WASI_FILETYPE_CHARACTER_DEVICE,
+            ..__wasi_filestat_t::default()
+        };
+        let inode = self.create_inode_with_stat(inodes, kind, false, name.to_string(), stat);
+        match inodes.arena[parent].write().deref_mut() {
+            Kind::Dir { entries, .. } | Kind::Root { entries } => {
+                entries.insert(name.to_string(), inode);
+                Ok(())
+            }
+            _ => Err(FsError::BaseNotDirectory),
+        }
+    }
+
+    /// Adds the directory `name` to the directory `parent`, the new
+    /// directory maps to the same path in the file system backing
+    fn create_dir_in_memory(
+        &self,
+        inodes: &mut WasiInodes,
+        parent: Inode,
+        name: String,
+    ) -> Result<Inode, FsError> {
+        let path = match inodes.arena[parent].read().deref() {
+            Kind::Dir { path, .. } => path.join(&name),
+            Kind::Root { .. } => PathBuf::from(&name),
+            _ => return Err(FsError::BaseNotDirectory),
+        };
+        let kind = Kind::Dir {
+            parent: Some(parent),
+            path,
+            entries: HashMap::new(),
+        };
+        let inode = self.create_inode_with_default_stat(inodes, kind, false, name.clone());
+        match inodes.arena[parent].write().deref_mut() {
+            Kind::Dir { entries, .. } | Kind::Root { entries } => {
+                entries.insert(name, inode);
+            }
+            _ => unreachable!("Dir or Root became not Dir or Root"),
+        }
+        Ok(inode)
+    }
+
+    /// Adds the user-supplied file `name` to the directory `base_inode`
+    fn open_file_in_dir(
+        &mut self,
+        inodes: &mut WasiInodes,
+        base_inode: Inode,
+        file: Box<dyn VirtualFile + Send + Sync + 'static>,
+        name: String,
+        options: FdOptions,
+    ) -> Result<__wasi_fd_t, FsError> {
         let guard = inodes.arena[base_inode].read();
         match guard.deref() {
             Kind::Dir { ref entries, .. } | Kind::Root { ref entries } => {
@@ -752,8 +1523,14 @@ impl WasiFs {
                     }
                 }
 
-                self.create_fd(rights, rights_inheriting, flags, open_flags, inode)
-                    .map_err(fs_error_from_wasi_err)
+                self.create_fd(
+                    options.rights,
+                    options.rights_inheriting,
+                    options.flags,
+                    options.open_flags,
+                    inode,
+                )
+                .map_err(fs_error_from_wasi_err)
             }
             _ => Err(FsError::BaseNotDirectory),
         }
@@ -842,7 +1619,7 @@ impl WasiFs {
         &self,
         inodes: &mut WasiInodes,
         base: __wasi_fd_t,
-        symlink_count: u32,
+        mut symlink_count: u32,
     ) -> Result<(Inode, String), __wasi_errno_t> {
         let current_dir = {
             let guard = self.current_dir.lock().unwrap();
@@ -853,7 +1630,7 @@ impl WasiFs {
             inodes,
             cur_inode,
             current_dir.as_str(),
-            symlink_count,
+            &mut symlink_count,
             true,
         )?;
         Ok((inode, current_dir))
@@ -877,14 +1654,11 @@ impl WasiFs {
         inodes: &mut WasiInodes,
         mut cur_inode: generational_arena::Index,
         path: &str,
-        mut symlink_count: u32,
+        symlink_count: &mut u32,
         follow_symlinks: bool,
     ) -> Result<Inode, __wasi_errno_t> {
-        if symlink_count > MAX_SYMLINKS {
-            return Err(__WASI_EMLINK);
-        }
-
-        let path: &Path = Path::new(path);
+        let host_path = platform_path_from_guest(path);
+        let path: &Path = &host_path;
         let n_components = path.components().count();
 
         // TODO: rights checks
@@ -893,7 +1667,7 @@ impl WasiFs {
             let last_component = i + 1 == n_components;
             // for each component traverse file structure
             // loading inodes as necessary
-            'symlink_resolution: while symlink_count < MAX_SYMLINKS {
+            'symlink_resolution: loop {
                 let mut guard = inodes.arena[cur_inode].write();
                 match guard.deref_mut() {
                     Kind::Buffer { .. } => unimplemented!("state::get_inode_at_path for buffers"),
@@ -915,8 +1689,6 @@ impl WasiFs {
                             "." => continue 'path_iter,
                             _ => (),
                         }
-                        // used for full resolution of symlinks
-                        let mut loop_for_symlink = false;
                         if let Some(entry) =
                             entries.get(component.as_os_str().to_string_lossy().as_ref())
                         {
@@ -933,12 +1705,7 @@ impl WasiFs {
                                 .ok()
                                 .ok_or(__WASI_ENOENT)?;
                             let file_type = metadata.file_type();
-                            // we want to insert newly opened dirs and files, but not transient symlinks
-                            // TODO: explain why (think about this deeply when well rested)
-                            let should_insert;
-
                             let kind = if file_type.is_dir() {
-                                should_insert = true;
                                 // load DIR
                                 Kind::Dir {
                                     parent: Some(cur_inode),
@@ -946,7 +1713,6 @@ impl WasiFs {
                                     entries: Default::default(),
                                 }
                             } else if file_type.is_file() {
-                                should_insert = true;
                                 // load file
                                 Kind::File {
                                     handle: None,
@@ -954,22 +1720,37 @@ impl WasiFs {
                                     fd: None,
                                 }
                             } else if file_type.is_symlink() {
-                                should_insert = false;
+                                // only a trailing symlink that is not followed can be resolved
+                                if self.no_follow_symlinks && (follow_symlinks || !last_component) {
+                                    return Err(__WASI_ELOOP);
+                                }
                                 let link_value = file.read_link().map_err(map_io_err)?;
                                 debug!("attempting to decompose path {:?}", link_value);
 
+                                // the directory may be a preopen, which is
+                                // looked at to find where the symlink lives
+                                drop(guard);
                                 let (pre_open_dir_fd, relative_path) = if link_value.is_relative() {
                                     self.path_into_pre_open_and_relative_path(inodes, &file)?
                                 } else {
                                     unimplemented!("Absolute symlinks are not yet supported");
                                 };
-                                loop_for_symlink = true;
-                                symlink_count += 1;
-                                Kind::Symlink {
+                                let kind = Kind::Symlink {
                                     base_po_dir: pre_open_dir_fd,
                                     path_to_symlink: relative_path.to_owned(),
                                     relative_path: link_value,
-                                }
+                                };
+                                // transient symlinks are not inserted in the
+                                // directory, they are followed, and counted, when
+                                // a later component goes through them or at the
+                                // end of the path
+                                cur_inode = self.create_inode(
+                                    inodes,
+                                    kind,
+                                    false,
+                                    file.to_string_lossy().to_string(),
+                                )?;
+                                continue 'path_iter;
                             } else {
                                 #[cfg(unix)]
                                 {
@@ -1035,24 +1816,17 @@ impl WasiFs {
                                 false,
                                 file.to_string_lossy().to_string(),
                             )?;
-                            if should_insert {
-                                let mut guard = inodes.arena[cur_inode].write();
-                                if let Kind::Dir {
-                                    ref mut entries, ..
-                                } = guard.deref_mut()
-                                {
-                                    entries.insert(
-                                        component.as_os_str().to_string_lossy().to_string(),
-                                        new_inode,
-                                    );
-                                }
+                            let mut guard = inodes.arena[cur_inode].write();
+                            if let Kind::Dir {
+                                ref mut entries, ..
+                            } = guard.deref_mut()
+                            {
+                                entries.insert(
+                                    component.as_os_str().to_string_lossy().to_string(),
+                                    new_inode,
+                                );
                             }
                             cur_inode = new_inode;
-
-                            if loop_for_symlink && follow_symlinks {
-                                debug!("Following symlink to {:?}", cur_inode);
-                                continue 'symlink_resolution;
-                            }
                         }
                     }
                     Kind::Root { entries } => {
@@ -1064,6 +1838,31 @@ impl WasiFs {
                             _ => (),
                         }
 
+                        // preopens can be nested, e.g. `data` and `data/sub`:
+                        // the deepest one containing the rest of the path wins
+                        let rest: PathBuf = path.components().skip(i).collect();
+                        let deepest_preopen = entries
+                            .iter()
+                            .filter_map(|(name, entry)| {
+                                let name = Path::new(name.trim_start_matches('/'));
+                                let rel_path = rest.strip_prefix(name).ok()?;
+                                Some((name.components().count(), *entry, rel_path.to_owned()))
+                            })
+                            .max_by_key(|(depth, _, _)| *depth);
+                        // preopens named by a single component are found below
+                        if let Some((depth, entry, rel_path)) = deepest_preopen {
+                            if depth > 1 {
+                                drop(guard);
+                                return self.get_inode_at_path_inner(
+                                    inodes,
+                                    entry,
+                                    &rel_path.to_string_lossy(),
+                                    symlink_count,
+                                    follow_symlinks,
+                                );
+                            }
+                        }
+
                         if let Some(entry) =
                             entries.get(component.as_os_str().to_string_lossy().as_ref())
                         {
@@ -1083,6 +1882,9 @@ impl WasiFs {
                         path_to_symlink,
                         relative_path,
                     } => {
+                        if self.no_follow_symlinks {
+                            return Err(__WASI_ELOOP);
+                        }
                         let new_base_dir = *base_po_dir;
                         let new_base_inode = self.get_fd_inode(new_base_dir)?;
 
@@ -1100,23 +1902,20 @@ impl WasiFs {
                         };
                         debug!("Following symlink recursively");
                         drop(guard);
-                        let symlink_inode = self.get_inode_at_path_inner(
+                        *symlink_count += 1;
+                        if *symlink_count > MAX_SYMLINKS {
+                            return Err(__WASI_ELOOP);
+                        }
+                        // the symlink is a directory of the path, so it is
+                        // always followed
+                        cur_inode = self.get_inode_at_path_inner(
                             inodes,
                             new_base_inode,
                             &new_path,
-                            symlink_count + 1,
-                            follow_symlinks,
+                            symlink_count,
+                            true,
                         )?;
-                        cur_inode = symlink_inode;
-                        // if we're at the very end and we found a file, then we're done
-                        // TODO: figure out if this should also happen for directories?
-                        let guard = inodes.arena[cur_inode].read();
-                        if let Kind::File { .. } = guard.deref() {
-                            // check if on last step
-                            if last_component {
-                                break 'symlink_resolution;
-                            }
-                        }
+                        // the component is looked up in the target
                         continue 'symlink_resolution;
                     }
                 }
@@ -1124,6 +1923,45 @@ impl WasiFs {
             }
         }
 
+        // symlinks created with `path_symlink` are stored in the directory
+        // entries, so a trailing one is only found here
+        if follow_symlinks {
+            let target = {
+                let guard = inodes.arena[cur_inode].read();
+                if let Kind::Symlink {
+                    base_po_dir,
+                    path_to_symlink,
+                    relative_path,
+                } = guard.deref()
+                {
+                    let mut new_path = path_to_symlink.clone();
+                    new_path.pop();
+                    new_path.push(relative_path);
+                    Some((*base_po_dir, new_path.to_string_lossy().to_string()))
+                } else {
+                    None
+                }
+            };
+            if let Some((base_po_dir, new_path)) = target {
+                if self.no_follow_symlinks {
+                    return Err(__WASI_ELOOP);
+                }
+                debug!("Following trailing symlink to {}", new_path);
+                *symlink_count += 1;
+                if *symlink_count > MAX_SYMLINKS {
+                    return Err(__WASI_ELOOP);
+                }
+                let new_base_inode = self.get_fd_inode(base_po_dir)?;
+                return self.get_inode_at_path_inner(
+                    inodes,
+                    new_base_inode,
+                    &new_path,
+                    symlink_count,
+                    follow_symlinks,
+                );
+            }
+        }
+
         Ok(cur_inode)
     }
 
@@ -1191,34 +2029,6 @@ impl WasiFs {
         }
     }
 
-    /// finds the number of directories between the fd and the inode if they're connected
-    /// expects inode to point to a directory
-    pub(crate) fn path_depth_from_fd(
-        &self,
-        inodes: &WasiInodes,
-        fd: __wasi_fd_t,
-        inode: Inode,
-    ) -> Result<usize, __wasi_errno_t> {
-        let mut counter = 0;
-        let base_inode = self.get_fd_inode(fd)?;
-        let mut cur_inode = inode;
-
-        while cur_inode != base_inode {
-            counter += 1;
-            let guard = inodes.arena[cur_inode].read();
-            match guard.deref() {
-                Kind::Dir { parent, .. } => {
-                    if let Some(p) = parent {
-                        cur_inode = *p;
-                    }
-                }
-                _ => return Err(__WASI_EINVAL),
-            }
-        }
-
-        Ok(counter)
-    }
-
     /// gets a host file from a base directory and a path
     /// this function ensures the fs remains sandboxed
     // NOTE: follow symlinks is super weird right now
@@ -1239,7 +2049,27 @@ impl WasiFs {
             self.get_fd_inode(base)?
         };
 
-        self.get_inode_at_path_inner(inodes, start_inode, path, 0, follow_symlinks)
+        // relative paths from the preopen containing the working directory
+        // resolve against it
+        let cwd_path = if !path.starts_with('/') && !self.is_wasix.load(Ordering::Acquire) {
+            match self.preopen_containing(inodes, &self.cwd()) {
+                Some((cwd_fd, cwd)) if cwd_fd == base => Some(cwd.join(path)),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        if let Some(path) = cwd_path {
+            return self.get_inode_at_path_inner(
+                inodes,
+                start_inode,
+                &path.to_string_lossy(),
+                &mut 0,
+                follow_symlinks,
+            );
+        }
+
+        self.get_inode_at_path_inner(inodes, start_inode, path, &mut 0, follow_symlinks)
     }
 
     /// Returns the parent Dir or Root that the file at a given path is in and the file name
@@ -1266,6 +2096,124 @@ impl WasiFs {
             .map(|v| (v, new_entity_name))
     }
 
+    /// Creates a virtual symlink at `path` (relative to the directory `base`)
+    /// pointing to `target`.
+    ///
+    /// The target is kept as given, it is resolved relative to the directory
+    /// containing the symlink when the symlink is followed.
+    pub(crate) fn create_symlink(
+        &self,
+        inodes: &mut WasiInodes,
+        base: __wasi_fd_t,
+        path: &Path,
+        target: &Path,
+    ) -> Result<Inode, __wasi_errno_t> {
+        let (parent_inode, entry_name) = self.get_parent_inode_at_path(inodes, base, path, true)?;
+
+        // short circuit if anything is wrong, before we create an inode
+        {
+            let guard = inodes.arena[parent_inode].read();
+            match guard.deref() {
+                Kind::Dir { entries, .. } => {
+                    if entries.contains_key(&entry_name) {
+                        return Err(__WASI_EEXIST);
+                    }
+                }
+                Kind::Root { .. } => return Err(__WASI_ENOTCAPABLE),
+                Kind::Socket { .. } | Kind::Pipe { .. } | Kind::EventNotifications { .. } => {
+                    return Err(__WASI_EINVAL)
+                }
+                Kind::File { .. } | Kind::Symlink { .. } | Kind::Buffer { .. } => {
+                    unreachable!(
+                        "get_parent_inode_at_path returned something other than a Dir or Root"
+                    )
+                }
+            }
+        }
+
+        let kind = Kind::Symlink {
+            base_po_dir: base,
+            path_to_symlink: path.to_owned(),
+            relative_path: target.to_owned(),
+        };
+        let new_inode =
+            self.create_inode_with_default_stat(inodes, kind, false, entry_name.clone());
+
+        let mut guard = inodes.arena[parent_inode].write();
+        if let Kind::Dir {
+            ref mut entries, ..
+        } = guard.deref_mut()
+        {
+            entries.insert(entry_name, new_inode);
+        }
+        Ok(new_inode)
+    }
+
+    /// Fails with `__WASI_EROFS` if the directory `dir` is read-only. The
+    /// file system backing rejects modifications of its own entries, this
+    /// covers the virtual ones (e.g. the symlinks of a tar archive), which
+    /// only live in the inode tree
+    pub(crate) fn check_dir_writable(
+        &self,
+        inodes: &WasiInodes,
+        dir: Inode,
+    ) -> Result<(), __wasi_errno_t> {
+        match inodes.arena[dir].read().deref() {
+            Kind::Dir { path, .. }
+                if self
+                    .read_only_dirs
+                    .iter()
+                    .any(|read_only| path.starts_with(read_only)) =>
+            {
+                Err(__WASI_EROFS)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns whether `fd` counts as opened by the guest, stdio, the
+    /// virtual root and the preopened directories don't
+    pub(crate) fn is_guest_fd(&self, fd: __wasi_fd_t) -> bool {
+        fd > __WASI_STDERR_FILENO
+            && fd != VIRTUAL_ROOT_FD
+            && !self.preopen_fds.read().unwrap().contains(&fd)
+    }
+
+    /// Returns the number of fds opened by the guest
+    pub(crate) fn open_fd_count(&self) -> usize {
+        self.fd_map
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|fd| self.is_guest_fd(**fd))
+            .count()
+    }
+
+    /// Returns the open fds of the preopened directories (including the ones
+    /// they were duplicated or renumbered to) with the name of the preopen,
+    /// sorted by fd
+    pub(crate) fn open_preopens(&self, inodes: &WasiInodes) -> Vec<(__wasi_fd_t, String)> {
+        let mut preopens = self
+            .fd_map
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(fd, _)| **fd != VIRTUAL_ROOT_FD)
+            .filter_map(|(fd, fd_entry)| {
+                let inode_val = &inodes.arena[fd_entry.inode];
+                let is_dir =
+                    inode_val.stat.read().unwrap().st_filetype == __WASI_FILETYPE_DIRECTORY;
+                if inode_val.is_preopened && is_dir {
+                    Some((*fd, inode_val.name.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        preopens.sort_by_key(|(fd, _)| *fd);
+        preopens
+    }
+
     pub fn get_fd(&self, fd: __wasi_fd_t) -> Result<Fd, __wasi_errno_t> {
         self.fd_map
             .read()
@@ -1296,6 +2244,47 @@ impl WasiFs {
         Ok(*inodes.arena[inode].stat.read().unwrap().deref())
     }
 
+    /// Returns the SHA-256 digest of the content of the file opened as `fd`,
+    /// or `None` if the file can't hash its content (see
+    /// [`VirtualFile::content_hash`])
+    pub fn content_hash(
+        &self,
+        inodes: &WasiInodes,
+        fd: __wasi_fd_t,
+    ) -> Result<Option<[u8; 32]>, __wasi_errno_t> {
+        let inode = self.get_fd_inode(fd)?;
+        let guard = inodes.arena[inode].read();
+        match guard.deref() {
+            Kind::File {
+                handle: Some(handle),
+                ..
+            } => Ok(handle.content_hash()),
+            Kind::Dir { .. } | Kind::Root { .. } => Err(__WASI_EISDIR),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the block size preferred for I/O on the file opened as `fd`,
+    /// in bytes. Host files report their `st_blksize` on Unix, other files
+    /// report the size set with [`WasiStateBuilder::io_block_size`]
+    /// ([`DEFAULT_IO_BLOCK_SIZE`] by default)
+    pub fn preferred_io_block_size(
+        &self,
+        inodes: &WasiInodes,
+        fd: __wasi_fd_t,
+    ) -> Result<u32, __wasi_errno_t> {
+        let inode = self.get_fd_inode(fd)?;
+        let guard = inodes.arena[inode].read();
+        let io_block_size = match guard.deref() {
+            Kind::File {
+                handle: Some(handle),
+                ..
+            } => handle.io_block_size(),
+            _ => None,
+        };
+        Ok(io_block_size.unwrap_or(self.io_block_size))
+    }
+
     pub fn fdstat(
         &self,
         inodes: &WasiInodes,
@@ -1306,7 +2295,7 @@ impl WasiFs {
                 return Ok(__wasi_fdstat_t {
                     fs_filetype: __WASI_FILETYPE_CHARACTER_DEVICE,
                     fs_flags: 0,
-                    fs_rights_base: STDIN_DEFAULT_RIGHTS,
+                    fs_rights_base: self.get_fd(fd).map_or(STDIN_DEFAULT_RIGHTS, |fd| fd.rights),
                     fs_rights_inheriting: 0,
                 })
             }
@@ -1314,7 +2303,9 @@ impl WasiFs {
                 return Ok(__wasi_fdstat_t {
                     fs_filetype: __WASI_FILETYPE_CHARACTER_DEVICE,
                     fs_flags: __WASI_FDFLAG_APPEND,
-                    fs_rights_base: STDOUT_DEFAULT_RIGHTS,
+                    fs_rights_base: self
+                        .get_fd(fd)
+                        .map_or(STDOUT_DEFAULT_RIGHTS, |fd| fd.rights),
                     fs_rights_inheriting: 0,
                 })
             }
@@ -1322,7 +2313,9 @@ impl WasiFs {
                 return Ok(__wasi_fdstat_t {
                     fs_filetype: __WASI_FILETYPE_CHARACTER_DEVICE,
                     fs_flags: __WASI_FDFLAG_APPEND,
-                    fs_rights_base: STDERR_DEFAULT_RIGHTS,
+                    fs_rights_base: self
+                        .get_fd(fd)
+                        .map_or(STDERR_DEFAULT_RIGHTS, |fd| fd.rights),
                     fs_rights_inheriting: 0,
                 })
             }
@@ -1340,13 +2333,21 @@ impl WasiFs {
         let fd = self.get_fd(fd)?;
         debug!("fdstat: {:?}", fd);
 
-        let guard = inodes.arena[fd.inode].read();
+        let inode = &inodes.arena[fd.inode];
+        let guard = inode.read();
         Ok(__wasi_fdstat_t {
             fs_filetype: match guard.deref() {
-                Kind::File { .. } => __WASI_FILETYPE_REGULAR_FILE,
-                Kind::Dir { .. } => __WASI_FILETYPE_DIRECTORY,
+                // stdio keeps reporting a character device after being renumbered
+                Kind::File { .. } => match inode.stat.read().unwrap().st_filetype {
+                    __WASI_FILETYPE_CHARACTER_DEVICE => __WASI_FILETYPE_CHARACTER_DEVICE,
+                    _ => __WASI_FILETYPE_REGULAR_FILE,
+                },
+                Kind::Buffer { .. } => __WASI_FILETYPE_REGULAR_FILE,
+                Kind::Dir { .. } | Kind::Root { .. } => __WASI_FILETYPE_DIRECTORY,
                 Kind::Symlink { .. } => __WASI_FILETYPE_SYMBOLIC_LINK,
-                _ => __WASI_FILETYPE_UNKNOWN,
+                Kind::Socket { socket } => socket.filetype(),
+                Kind::Pipe { .. } => __WASI_FILETYPE_CHARACTER_DEVICE,
+                Kind::EventNotifications { .. } => __WASI_FILETYPE_UNKNOWN,
             },
             fs_flags: fd.flags,
             fs_rights_base: fd.rights,
@@ -1375,8 +2376,8 @@ impl WasiFs {
         __wasi_prestat_t {
             pr_type: __WASI_PREOPENTYPE_DIR,
             u: PrestatEnum::Dir {
-                // REVIEW:
-                pr_name_len: inode_val.name.len() as u32 + 1,
+                // the name is written by `fd_prestat_dir_name` without a NUL
+                pr_name_len: inode_val.name.len() as u32,
             }
             .untagged(),
         }
@@ -1419,6 +2420,109 @@ impl WasiFs {
         Ok(())
     }
 
+    /// Writes the content of the preopened directories out to `host_dir`,
+    /// each one in the sub-directory named after its alias (a `/` alias
+    /// maps to `host_dir` itself).
+    ///
+    /// When `host_dir` does not exist yet, the whole tree is first written
+    /// to a sibling staging directory which is then renamed to `host_dir`,
+    /// so the host never sees a partially materialized tree. Otherwise the
+    /// tree is merged into the existing directory: every file is written to
+    /// a temporary file and renamed over its destination, existing host
+    /// files are overwritten, and host files that don't exist in the
+    /// virtual filesystem are left untouched. A destination whose type
+    /// conflicts with the virtual entry (e.g. a host directory where the
+    /// virtual filesystem has a file) makes the call fail.
+    ///
+    /// With `only_dirty`, files whose host copy already has the same content
+    /// are skipped. Symlinks and special files are not materialized.
+    pub fn materialize_to(
+        &self,
+        inodes: &WasiInodes,
+        host_dir: impl AsRef<Path>,
+        only_dirty: bool,
+    ) -> Result<(), FsError> {
+        let host_dir = host_dir.as_ref();
+
+        let mut preopens = Vec::new();
+        for fd in self.preopen_fds.read().unwrap().iter() {
+            let inode = self.get_fd_inode(*fd).map_err(|_| FsError::InvalidFd)?;
+            let inode_val = &inodes.arena[inode];
+            if let Kind::Dir { path, .. } = inode_val.read().deref() {
+                preopens.push((inode_val.name.clone(), path.clone()));
+            }
+        }
+
+        let staging_dir = if host_dir.exists() {
+            None
+        } else {
+            let name = host_dir.file_name().ok_or(FsError::InvalidInput)?;
+            let mut staging_name = std::ffi::OsString::from(".");
+            staging_name.push(name);
+            staging_name.push(".materialize");
+            let staging_dir = host_dir.with_file_name(staging_name);
+            if staging_dir.exists() {
+                std::fs::remove_dir_all(&staging_dir)?;
+            }
+            Some(staging_dir)
+        };
+        let target_dir = staging_dir.as_deref().unwrap_or(host_dir);
+
+        std::fs::create_dir_all(target_dir)?;
+        for (alias, path) in preopens {
+            let alias = alias.trim_start_matches('/');
+            self.materialize_dir(&path, &target_dir.join(alias), only_dirty)?;
+        }
+
+        if let Some(staging_dir) = staging_dir {
+            std::fs::rename(staging_dir, host_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Recursively copies the directory `path` of the backing filesystem to
+    /// the host directory `host_dir`
+    fn materialize_dir(
+        &self,
+        path: &Path,
+        host_dir: &Path,
+        only_dirty: bool,
+    ) -> Result<(), FsError> {
+        std::fs::create_dir_all(host_dir)?;
+        for entry in self.fs_backing.read_dir(path)? {
+            let entry = entry?;
+            let name = entry.path.file_name().ok_or(FsError::InvalidInput)?;
+            let host_path = host_dir.join(name);
+            let metadata = entry.metadata?;
+
+            if metadata.is_dir() {
+                self.materialize_dir(&entry.path, &host_path, only_dirty)?;
+            } else if metadata.is_file() {
+                let mut data = Vec::with_capacity(metadata.len() as usize);
+                self.fs_backing
+                    .new_open_options()
+                    .read(true)
+                    .open(&entry.path)?
+                    .read_to_end(&mut data)?;
+
+                if only_dirty && std::fs::read(&host_path).ok().as_ref() == Some(&data) {
+                    continue;
+                }
+
+                let mut tmp_name = std::ffi::OsString::from(".");
+                tmp_name.push(name);
+                tmp_name.push(".materialize");
+                let tmp_path = host_dir.join(tmp_name);
+                std::fs::write(&tmp_path, &data)?;
+                if let Err(err) = std::fs::rename(&tmp_path, &host_path) {
+                    let _ = std::fs::remove_file(&tmp_path);
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Creates an inode and inserts it given a Kind and some extra data
     pub(crate) fn create_inode(
         &self,
@@ -1680,11 +2784,19 @@ impl WasiFs {
         let inodeval = inodes.get_inodeval(inode)?;
         let is_preopened = inodeval.is_preopened;
 
+        let mut result = Ok(());
         let mut guard = inodeval.write();
         match guard.deref_mut() {
             Kind::File { ref mut handle, .. } => {
                 let mut empty_handle = None;
                 std::mem::swap(handle, &mut empty_handle);
+                // validating files commit their content when they're closed
+                if let Some(file) = empty_handle
+                    .as_deref_mut()
+                    .and_then(|h| h.upcast_any_mut().downcast_mut::<ValidatingFile>())
+                {
+                    result = file.finish().map_err(fs_error_into_wasi_err);
+                }
             }
             Kind::Socket { ref mut socket, .. } => {
                 let mut closed_socket = InodeSocket::new(InodeSocketKind::Closed);
@@ -1740,8 +2852,10 @@ impl WasiFs {
             Kind::Root { .. } => return Err(__WASI_EACCES),
             Kind::Symlink { .. } | Kind::Buffer { .. } => return Err(__WASI_EINVAL),
         }
+        // directories were already removed above
+        self.fd_map.write().unwrap().remove(&fd);
 
-        Ok(())
+        result
     }
 }
 
@@ -1758,10 +2872,11 @@ impl WasiState {
     }
 
     pub(crate) fn fs_create_dir<P: AsRef<Path>>(&self, path: P) -> Result<(), __wasi_errno_t> {
-        self.fs
-            .fs_backing
-            .create_dir(path.as_ref())
-            .map_err(fs_error_into_wasi_err)
+        match self.default_dir_mode {
+            Some(mode) => self.fs.fs_backing.create_dir_with_mode(path.as_ref(), mode),
+            None => self.fs.fs_backing.create_dir(path.as_ref()),
+        }
+        .map_err(fs_error_into_wasi_err)
     }
 
     pub(crate) fn fs_remove_dir<P: AsRef<Path>>(&self, path: P) -> Result<(), __wasi_errno_t> {
@@ -1771,17 +2886,6 @@ impl WasiState {
             .map_err(fs_error_into_wasi_err)
     }
 
-    pub(crate) fn fs_rename<P: AsRef<Path>, Q: AsRef<Path>>(
-        &self,
-        from: P,
-        to: Q,
-    ) -> Result<(), __wasi_errno_t> {
-        self.fs
-            .fs_backing
-            .rename(from.as_ref(), to.as_ref())
-            .map_err(fs_error_into_wasi_err)
-    }
-
     pub(crate) fn fs_remove_file<P: AsRef<Path>>(&self, path: P) -> Result<(), __wasi_errno_t> {
         self.fs
             .fs_backing
@@ -1790,7 +2894,100 @@ impl WasiState {
     }
 
     pub(crate) fn fs_new_open_options(&self) -> OpenOptions {
-        self.fs.fs_backing.new_open_options()
+        let mut open_options = self.fs.fs_backing.new_open_options();
+        if let Some(umask) = self.file_umask {
+            open_options.mode(0o666 & !umask);
+        }
+        open_options
+    }
+
+    /// Fails with `__WASI_EMFILE` if the guest can't open another fd
+    /// without going over [`WasiStateBuilder::max_open_fds`]
+    pub(crate) fn check_open_fds(&self) -> Result<(), __wasi_errno_t> {
+        match self.max_open_fds {
+            Some(max_open_fds) if self.fs.open_fd_count() >= max_open_fds => Err(__WASI_EMFILE),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with `__WASI_ENAMETOOLONG` if a guest path of `len` bytes is
+    /// longer than [`WasiStateBuilder::max_path_len`], checked before the
+    /// path is read from the guest memory
+    pub(crate) fn check_path_len(&self, len: u64) -> Result<(), __wasi_errno_t> {
+        if len > self.max_path_len as u64 {
+            return Err(__WASI_ENAMETOOLONG);
+        }
+        Ok(())
+    }
+
+    /// Returns whether `inode` is the directory installed by
+    /// [`WasiStateBuilder::with_fd_reflection`]
+    pub(crate) fn is_fd_reflection_dir(&self, inodes: &WasiInodes, inode: Inode) -> bool {
+        // preopen names are unique, so no other preopen can have its name
+        let inode_val = &inodes.arena[inode];
+        self.fd_reflection
+            && inode_val.is_preopened
+            && inode_val.name == FD_REFLECTION_DIR.trim_start_matches('/')
+    }
+
+    /// Returns the target of the link `path` in the directory installed by
+    /// [`WasiStateBuilder::with_fd_reflection`], that is the name of the
+    /// preopen open at the fd `path` names
+    pub(crate) fn fd_reflection_target(
+        &self,
+        inodes: &WasiInodes,
+        dir_inode: Inode,
+        path: &str,
+    ) -> Option<String> {
+        if !self.is_fd_reflection_dir(inodes, dir_inode) {
+            return None;
+        }
+        let fd = path.trim_end_matches('/').parse::<__wasi_fd_t>().ok()?;
+        self.fs
+            .open_preopens(inodes)
+            .into_iter()
+            .find(|(preopen_fd, _)| *preopen_fd == fd)
+            .map(|(_, name)| name)
+    }
+}
+
+/// Number of times each syscall returned each errno, keyed by the name of
+/// the syscall and then by the errno (`__WASI_ESUCCESS` is not recorded)
+pub type SyscallErrorStats = HashMap<String, HashMap<__wasi_errno_t, u64>>;
+
+/// A syscall made by the guest, passed to the tracer set with
+/// [`WasiStateBuilder::syscall_tracer`] once the syscall returned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallEvent {
+    /// Name of the syscall, e.g. `path_open`
+    pub name: &'static str,
+    /// The arguments of the syscall in order, by parameter name. Pointers
+    /// are given as offsets in the guest memory and signed integers as
+    /// their two's complement.
+    pub args: Vec<(&'static str, u64)>,
+    /// The paths passed to the syscall, by parameter name, as they were in
+    /// the guest memory when the syscall was called. Paths that could not
+    /// be read are left out.
+    pub paths: Vec<(&'static str, String)>,
+    /// The errno returned to the guest, `None` if the syscall trapped
+    pub errno: Option<__wasi_errno_t>,
+}
+
+impl SyscallEvent {
+    /// Returns the value of the argument called `name`, e.g. `fd`
+    pub fn arg(&self, name: &str) -> Option<u64> {
+        self.args
+            .iter()
+            .find(|(arg, _)| *arg == name)
+            .map(|(_, value)| *value)
+    }
+
+    /// Returns the path passed as the argument called `name`, e.g. `path`
+    pub fn path(&self, name: &str) -> Option<&str> {
+        self.paths
+            .iter()
+            .find(|(arg, _)| *arg == name)
+            .map(|(_, path)| path.as_str())
     }
 }
 
@@ -1842,6 +3039,19 @@ pub struct WasiState {
     pub fs: WasiFs,
     pub inodes: Arc<RwLock<WasiInodes>>,
     pub(crate) threading: Mutex<WasiStateThreading>,
+    pub(crate) syscall_errors: Mutex<SyscallErrorStats>,
+    pub(crate) max_read_size: Option<usize>,
+    pub(crate) nonblocking: bool,
+    pub(crate) deterministic: Option<Mutex<Deterministic>>,
+    #[cfg_attr(feature = "enable-serde", serde(skip, default = "default_clock"))]
+    pub(crate) clock: Arc<dyn WasiClock>,
+    pub(crate) default_dir_mode: Option<u32>,
+    pub(crate) file_umask: Option<u32>,
+    pub(crate) remaining_instructions: Option<Mutex<u64>>,
+    pub(crate) max_open_fds: Option<usize>,
+    pub(crate) max_path_len: usize,
+    pub(crate) fd_reflection: bool,
+    pub(crate) stubbed_syscalls: HashMap<String, __wasi_errno_t>,
     pub args: Vec<Vec<u8>>,
     pub envs: Vec<Vec<u8>>,
 }
@@ -1866,6 +3076,194 @@ impl WasiState {
         bincode::deserialize(bytes).ok()
     }
 
+    /// Replaces the arguments returned by `args_get` and `args_sizes_get`,
+    /// the first argument is the program name.
+    ///
+    /// Returns [`FsError::InvalidInput`] if an argument contains a nul byte.
+    pub fn set_args(&mut self, args: &[Vec<u8>]) -> Result<(), FsError> {
+        if args.iter().any(|arg| arg.contains(&0)) {
+            return Err(FsError::InvalidInput);
+        }
+        self.args = args.to_vec();
+        Ok(())
+    }
+
+    /// Replaces the environment variables returned by `environ_get` and
+    /// `environ_sizes_get`, each entry is of the form `key=value`.
+    ///
+    /// Returns [`FsError::InvalidInput`] if an entry doesn't have a single
+    /// `=` separating a non-empty key from the value, or contains a nul byte.
+    pub fn set_envs(&mut self, envs: &[Vec<u8>]) -> Result<(), FsError> {
+        for env in envs {
+            let separators = env.iter().filter(|&&b| b == b'=').count();
+            if separators != 1 || env.first() == Some(&b'=') || env.contains(&0) {
+                return Err(FsError::InvalidInput);
+            }
+        }
+        self.envs = envs.to_vec();
+        Ok(())
+    }
+
+    /// Lists the open fds, sorted by fd, to inspect the fd table of the
+    /// guest (e.g. to find fds it never closed)
+    pub fn fds(&self) -> Vec<(__wasi_fd_t, FdInfo)> {
+        let inodes = self.inodes.read().unwrap();
+        let mut fds = self
+            .fs
+            .fd_map
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(fd, entry)| {
+                let (kind, path) = match inodes.arena[entry.inode].read().deref() {
+                    _ if *fd <= __WASI_STDERR_FILENO => (FdKind::Stdio, None),
+                    Kind::File { path, .. } => (FdKind::File, Some(path.clone())),
+                    Kind::Dir { path, .. } => (FdKind::Dir, Some(path.clone())),
+                    Kind::Root { .. } => (FdKind::Dir, Some(PathBuf::from("/"))),
+                    Kind::Socket { .. } => (FdKind::Socket, None),
+                    Kind::Pipe { .. } => (FdKind::Pipe, None),
+                    Kind::Symlink {
+                        path_to_symlink, ..
+                    } => (FdKind::Symlink, Some(path_to_symlink.clone())),
+                    Kind::Buffer { .. } => (FdKind::Buffer, None),
+                    Kind::EventNotifications { .. } => (FdKind::EventNotifications, None),
+                };
+                let info = FdInfo {
+                    kind,
+                    path,
+                    rights: entry.rights,
+                    rights_inheriting: entry.rights_inheriting,
+                    offset: entry.offset,
+                };
+                (*fd, info)
+            })
+            .collect::<Vec<_>>();
+        fds.sort_by_key(|(fd, _)| *fd);
+        fds
+    }
+
+    /// Closes `fd` the way the guest's `fd_close` does, e.g. to take away
+    /// the input of a guest that is still running. The file behind the fd
+    /// is dropped and the guest gets `__WASI_EBADF` when it uses the fd
+    /// afterwards.
+    ///
+    /// Fails with [`FsError::InvalidFd`] if `fd` is not open.
+    pub fn close_fd(&self, fd: __wasi_fd_t) -> Result<(), FsError> {
+        let inodes = self.inodes.read().unwrap();
+        self.fs
+            .close_fd(inodes.deref(), fd)
+            .map_err(fs_error_from_wasi_err)
+    }
+
+    /// Flushes every file the guest can write to, so that buffered writes
+    /// reach their destination before the state is torn down. Validating
+    /// files are committed.
+    ///
+    /// This also happens when the [`WasiState`] is dropped, but errors are
+    /// ignored there. Every file is flushed even if one fails, the error of
+    /// the lowest fd is returned.
+    pub fn flush_all(&mut self) -> Result<(), FsError> {
+        let inodes = self.inodes.read().unwrap();
+        let fd_map = self.fs.fd_map.read().unwrap();
+        let mut fds = fd_map
+            .iter()
+            .filter(|(_, entry)| entry.rights & __WASI_RIGHT_FD_WRITE != 0)
+            .collect::<Vec<_>>();
+        fds.sort_by_key(|(fd, _)| **fd);
+
+        let mut result = Ok(());
+        let mut flushed = HashSet::new();
+        for (_, entry) in fds {
+            // duplicated fds share their file
+            if !flushed.insert(entry.inode) {
+                continue;
+            }
+            // a file the guest unlinked while it still has it open is an
+            // orphan
+            let inode = match inodes.get_inodeval(entry.inode) {
+                Ok(inode) => inode,
+                Err(_) => continue,
+            };
+            if let Kind::File {
+                handle: Some(handle),
+                ..
+            } = inode.write().deref_mut()
+            {
+                let flushed = match (**handle).upcast_any_mut().downcast_mut::<ValidatingFile>() {
+                    Some(file) => file.finish(),
+                    None => handle.flush().map_err(Into::into),
+                };
+                if let Err(err) = flushed {
+                    result = result.and(Err(err));
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns how many times each syscall returned an error to the guest,
+    /// broken down per errno
+    pub fn syscall_error_stats(&self) -> SyscallErrorStats {
+        self.syscall_errors.lock().unwrap().clone()
+    }
+
+    /// Charges `count` instructions to the budget set with
+    /// [`WasiStateBuilder::max_instructions`], does nothing if no budget was
+    /// set.
+    ///
+    /// The WASI layer only charges [`SYSCALL_INSTRUCTIONS`] for every syscall,
+    /// the instructions the guest executes in between are charged by the
+    /// embedder, e.g. from the points consumed by the metering middleware.
+    pub fn consume_instructions(&self, count: u64) {
+        if let Some(remaining) = self.remaining_instructions.as_ref() {
+            let mut remaining = remaining.lock().unwrap();
+            *remaining = remaining.saturating_sub(count);
+        }
+    }
+
+    /// Returns the number of instructions left in the budget set with
+    /// [`WasiStateBuilder::max_instructions`], `None` if no budget was set.
+    pub fn remaining_instructions(&self) -> Option<u64> {
+        self.remaining_instructions
+            .as_ref()
+            .map(|remaining| *remaining.lock().unwrap())
+    }
+
+    /// Charges [`SYSCALL_INSTRUCTIONS`] to the instruction budget before
+    /// every syscall, fails with [`WasiError::OutOfFuel`] once the budget is
+    /// exhausted
+    pub(crate) fn charge_syscall(&self) -> Result<(), WasiError> {
+        if let Some(remaining) = self.remaining_instructions.as_ref() {
+            let mut remaining = remaining.lock().unwrap();
+            if *remaining == 0 {
+                return Err(WasiError::OutOfFuel);
+            }
+            *remaining = remaining.saturating_sub(SYSCALL_INSTRUCTIONS);
+        }
+        Ok(())
+    }
+
+    /// Returns the errno the syscall `syscall` was stubbed with by
+    /// [`WasiStateBuilder::stub_syscalls`], `None` if it is not stubbed
+    pub(crate) fn stubbed_syscall(&self, syscall: &str) -> Option<__wasi_errno_t> {
+        self.stubbed_syscalls.get(syscall).copied()
+    }
+
+    /// Records the errno returned by a syscall, errors are counted while
+    /// successes and traps (`None`) are ignored
+    pub(crate) fn record_syscall_result(&self, syscall: &str, errno: Option<__wasi_errno_t>) {
+        let errno = match errno {
+            Some(__WASI_ESUCCESS) | None => return,
+            Some(errno) => errno,
+        };
+        let mut guard = self.syscall_errors.lock().unwrap();
+        *guard
+            .entry(syscall.to_string())
+            .or_default()
+            .entry(errno)
+            .or_insert(0) += 1;
+    }
+
     /// Get the `VirtualFile` object at stdout
     pub fn stdout(&self) -> Result<Option<Box<dyn VirtualFile + Send + Sync + 'static>>, FsError> {
         self.std_dev_get(__WASI_STDOUT_FILENO)
@@ -1926,6 +3324,15 @@ impl WasiState {
     }
 }
 
+impl Drop for WasiState {
+    fn drop(&mut self) {
+        // the locks may be poisoned when unwinding
+        if !std::thread::panicking() {
+            let _ = self.flush_all();
+        }
+    }
+}
+
 pub fn virtual_file_type_to_wasi_file_type(file_type: wasmer_vfs::FileType) -> __wasi_filetype_t {
     // TODO: handle other file types
     if file_type.is_dir() {
@@ -0,0 +1,162 @@
+//! A [`FileSystem`] that dispatches paths to other file systems mounted
+//! under given prefixes.
+
+use std::path::{Path, PathBuf};
+use wasmer_vfs::{
+    FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir, VirtualFile,
+};
+
+/// Routes every path below a mount point to the file system mounted there
+/// (with the mount point stripped from the path), all other paths go to
+/// the base file system.
+#[derive(Debug)]
+pub struct MountedFileSystem {
+    base: Box<dyn FileSystem>,
+    mounts: Vec<(PathBuf, Box<dyn FileSystem>)>,
+}
+
+impl MountedFileSystem {
+    pub fn new(base: Box<dyn FileSystem>) -> Self {
+        Self {
+            base,
+            mounts: Vec::new(),
+        }
+    }
+
+    /// Mounts `fs` at `path`. Paths are served by the longest matching
+    /// mount point, later mounts shadow earlier ones at the same mount point
+    pub fn mount(&mut self, path: impl Into<PathBuf>, fs: Box<dyn FileSystem>) -> &mut Self {
+        self.mounts.push((path.into(), fs));
+        self
+    }
+
+    /// Returns the file system serving `path`, the path within it, and the
+    /// mount point (if any)
+    fn route<'a>(&'a self, path: &Path) -> (&'a dyn FileSystem, PathBuf, Option<&'a Path>) {
+        self.mounts
+            .iter()
+            .filter_map(|(mount_point, fs)| {
+                path.strip_prefix(mount_point)
+                    .ok()
+                    .map(|rest| (fs, Path::new("/").join(rest), mount_point))
+            })
+            .max_by_key(|(_, _, mount_point)| mount_point.components().count())
+            .map(|(fs, path, mount_point)| (fs.as_ref(), path, Some(mount_point.as_path())))
+            .unwrap_or_else(|| (self.base.as_ref(), path.to_path_buf(), None))
+    }
+}
+
+impl FileSystem for MountedFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        let (fs, inner_path, mount_point) = self.route(path);
+        let read_dir = fs.read_dir(&inner_path)?;
+        match mount_point {
+            // Report the entries with their path as seen through the mount
+            Some(mount_point) => Ok(ReadDir::new(
+                read_dir
+                    .map(|entry| {
+                        entry.map(|mut entry| {
+                            if let Ok(rest) = entry.path.strip_prefix("/") {
+                                entry.path = mount_point.join(rest);
+                            }
+                            entry
+                        })
+                    })
+                    .collect::<Result<Vec<_>, FsError>>()?,
+            )),
+            None => Ok(read_dir),
+        }
+    }
+    fn create_dir(&self, path: &Path) -> Result<(), FsError> {
+        let (fs, path, _) = self.route(path);
+        fs.create_dir(&path)
+    }
+    fn create_dir_with_mode(&self, path: &Path, mode: u32) -> Result<(), FsError> {
+        let (fs, path, _) = self.route(path);
+        fs.create_dir_with_mode(&path, mode)
+    }
+    fn remove_dir(&self, path: &Path) -> Result<(), FsError> {
+        let (fs, path, _) = self.route(path);
+        fs.remove_dir(&path)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), FsError> {
+        let (from_fs, from, from_mount) = self.route(from);
+        let (_, to, to_mount) = self.route(to);
+        if from_mount != to_mount {
+            // Entries can not be moved between file systems
+            return Err(FsError::CrossDevice);
+        }
+        from_fs.rename(&from, &to)
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        let (fs, path, _) = self.route(path);
+        fs.metadata(&path)
+    }
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        let (fs, path, _) = self.route(path);
+        fs.symlink_metadata(&path)
+    }
+    fn remove_file(&self, path: &Path) -> Result<(), FsError> {
+        let (fs, path, _) = self.route(path);
+        fs.remove_file(&path)
+    }
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<u64>,
+        modified: Option<u64>,
+        follow_symlinks: bool,
+    ) -> Result<(), FsError> {
+        let (fs, path, _) = self.route(path);
+        fs.set_times(&path, accessed, modified, follow_symlinks)
+    }
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(Box::new(MountedFileOpener {
+            base: self.base.new_open_options(),
+            mounts: self
+                .mounts
+                .iter()
+                .map(|(mount_point, fs)| (mount_point.clone(), fs.new_open_options()))
+                .collect(),
+        }))
+    }
+
+    /// Only succeeds if the base and every mounted file system can be shared
+    fn try_clone(&self) -> Option<Box<dyn FileSystem>> {
+        let mounts = self
+            .mounts
+            .iter()
+            .map(|(mount_point, fs)| Some((mount_point.clone(), fs.try_clone()?)))
+            .collect::<Option<_>>()?;
+        Some(Box::new(Self {
+            base: self.base.try_clone()?,
+            mounts,
+        }))
+    }
+}
+
+struct MountedFileOpener {
+    base: OpenOptions,
+    mounts: Vec<(PathBuf, OpenOptions)>,
+}
+
+impl FileOpener for MountedFileOpener {
+    fn open(
+        &mut self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, FsError> {
+        let mount = self
+            .mounts
+            .iter_mut()
+            .filter(|(mount_point, _)| path.starts_with(mount_point))
+            .max_by_key(|(mount_point, _)| mount_point.components().count());
+        match mount {
+            Some((mount_point, options)) => {
+                let path = Path::new("/").join(path.strip_prefix(mount_point).unwrap());
+                options.options(conf.clone()).open(path)
+            }
+            None => self.base.options(conf.clone()).open(path),
+        }
+    }
+}
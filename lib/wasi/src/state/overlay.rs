@@ -0,0 +1,445 @@
+//! A read-only snapshot of a file tree that is shared between instances,
+//! and a copy-on-write [`FileSystem`] layered on top of it.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use wasmer_vfs::{
+    mem_fs, DirEntry, FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig,
+    ReadDir, VirtualFile,
+};
+
+#[derive(Debug)]
+enum SnapshotNode {
+    Dir {
+        metadata: Metadata,
+        children: Vec<PathBuf>,
+    },
+    File {
+        metadata: Metadata,
+        data: Arc<[u8]>,
+    },
+}
+
+impl SnapshotNode {
+    fn metadata(&self) -> &Metadata {
+        match self {
+            Self::Dir { metadata, .. } | Self::File { metadata, .. } => metadata,
+        }
+    }
+}
+
+/// An immutable in-memory copy of a file tree, loaded once and shared by
+/// any number of instances through an `Arc`.
+///
+/// Instances see it through an [`OverlayFileSystem`], see
+/// [`WasiStateBuilder::shared_readonly_fs`].
+///
+/// [`WasiStateBuilder::shared_readonly_fs`]: crate::WasiStateBuilder::shared_readonly_fs
+#[derive(Debug)]
+pub struct FsSnapshot {
+    nodes: HashMap<PathBuf, SnapshotNode>,
+}
+
+impl FsSnapshot {
+    /// Copies the tree found at `root` in `fs`, `root` becomes the `/` of
+    /// the snapshot
+    pub fn from_fs(fs: &dyn FileSystem, root: impl AsRef<Path>) -> Result<Self, FsError> {
+        let mut nodes = HashMap::new();
+        Self::load(fs, root.as_ref(), Path::new("/"), &mut nodes)?;
+        Ok(Self { nodes })
+    }
+
+    /// Copies the tree found at `root` on the host
+    #[cfg(feature = "host-fs")]
+    pub fn from_dir(root: impl AsRef<Path>) -> Result<Self, FsError> {
+        Self::from_fs(&wasmer_vfs::host_fs::FileSystem, root)
+    }
+
+    fn load(
+        fs: &dyn FileSystem,
+        source: &Path,
+        path: &Path,
+        nodes: &mut HashMap<PathBuf, SnapshotNode>,
+    ) -> Result<(), FsError> {
+        let metadata = fs.metadata(source)?;
+        let node = if metadata.is_dir() {
+            let mut children = Vec::new();
+            for entry in fs.read_dir(source)? {
+                let name = entry?.file_name();
+                let child = path.join(&name);
+                Self::load(fs, &source.join(&name), &child, nodes)?;
+                children.push(child);
+            }
+            children.sort();
+            SnapshotNode::Dir { metadata, children }
+        } else {
+            let mut data = Vec::with_capacity(metadata.len as usize);
+            fs.new_open_options()
+                .read(true)
+                .open(source)?
+                .read_to_end(&mut data)?;
+            SnapshotNode::File {
+                metadata,
+                data: data.into(),
+            }
+        };
+        nodes.insert(path.to_path_buf(), node);
+        Ok(())
+    }
+}
+
+/// Makes `path` absolute and removes its `.` and `..` components
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => (),
+        }
+    }
+    normalized
+}
+
+/// A copy-on-write view of a shared [`FsSnapshot`].
+///
+/// Reads are served from the snapshot until an entry is modified: files are
+/// copied to a private in-memory layer the first time they are opened for
+/// writing, and removed entries are hidden without touching the snapshot.
+/// Clones share the same private layer.
+#[derive(Debug, Clone)]
+pub struct OverlayFileSystem {
+    lower: Arc<FsSnapshot>,
+    upper: mem_fs::FileSystem,
+    /// Paths of the snapshot hidden from this file system, along with
+    /// everything below them
+    whiteouts: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl OverlayFileSystem {
+    pub fn new(lower: Arc<FsSnapshot>) -> Self {
+        Self {
+            lower,
+            upper: mem_fs::FileSystem::default(),
+            whiteouts: Default::default(),
+        }
+    }
+
+    /// Returns the entry of the snapshot at `path` if it isn't hidden
+    fn lower_node(&self, path: &Path) -> Option<&SnapshotNode> {
+        let path = normalize(path);
+        let whiteouts = self.whiteouts.lock().unwrap();
+        if path
+            .ancestors()
+            .any(|ancestor| whiteouts.contains(ancestor))
+        {
+            return None;
+        }
+        self.lower.nodes.get(&path)
+    }
+
+    fn hide_lower(&self, path: &Path) {
+        let path = normalize(path);
+        if self.lower.nodes.contains_key(&path) {
+            self.whiteouts.lock().unwrap().insert(path);
+        }
+    }
+
+    fn in_upper(&self, path: &Path) -> bool {
+        self.upper.metadata(path).is_ok()
+    }
+
+    /// Creates the directories of the snapshot leading to `dir` (included)
+    /// in the private layer
+    fn copy_up_dirs(&self, dir: &Path) -> Result<(), FsError> {
+        let dir = normalize(dir);
+        let mut ancestors = dir.ancestors().collect::<Vec<_>>();
+        ancestors.reverse();
+        for ancestor in ancestors.into_iter().skip(1) {
+            if self.in_upper(ancestor) {
+                continue;
+            }
+            match self.lower_node(ancestor) {
+                Some(SnapshotNode::Dir { .. }) => self.upper.create_dir(ancestor)?,
+                Some(SnapshotNode::File { .. }) => return Err(FsError::BaseNotDirectory),
+                None => return Err(FsError::EntityNotFound),
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the entry of the snapshot at `path` to the private layer,
+    /// which then shadows it. The entries below a directory stay in the
+    /// snapshot.
+    fn copy_up(&self, path: &Path) -> Result<(), FsError> {
+        let path = normalize(path);
+        match self.lower_node(&path) {
+            Some(SnapshotNode::File { data, .. }) if !self.in_upper(&path) => {
+                let data = data.clone();
+                self.copy_up_dirs(path.parent().unwrap_or_else(|| Path::new("/")))?;
+                self.upper
+                    .new_open_options()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?
+                    .write_all(&data)?;
+                self.hide_lower(&path);
+            }
+            // the directory of the private layer is merged with the one of
+            // the snapshot, which is left visible
+            Some(SnapshotNode::Dir { .. }) => self.copy_up_dirs(&path)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Copies the entry of the snapshot at `path` and everything below it
+    /// to the private layer, so that the whole tree can be moved there
+    fn copy_up_tree(&self, path: &Path) -> Result<(), FsError> {
+        let path = normalize(path);
+        match self.lower_node(&path) {
+            Some(SnapshotNode::Dir { children, .. }) => {
+                let children = children.clone();
+                self.copy_up_dirs(&path)?;
+                for child in children {
+                    if self.lower_node(&child).is_some() {
+                        self.copy_up_tree(&child)?;
+                    }
+                }
+                self.hide_lower(&path);
+            }
+            _ => self.copy_up(&path)?,
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for OverlayFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        let mut entries = BTreeMap::new();
+        let lower_dir = match self.lower_node(path) {
+            Some(SnapshotNode::Dir { children, .. }) => {
+                for child in children {
+                    if let Some(node) = self.lower_node(child) {
+                        let name = child.file_name().unwrap_or_default().to_owned();
+                        entries.insert(
+                            name,
+                            DirEntry {
+                                path: path.join(child.file_name().unwrap_or_default()),
+                                metadata: Ok(node.metadata().clone()),
+                            },
+                        );
+                    }
+                }
+                true
+            }
+            _ => false,
+        };
+        match self.upper.read_dir(path) {
+            Ok(read_dir) => {
+                for entry in read_dir {
+                    let entry = entry?;
+                    let name = entry.file_name();
+                    entries.insert(
+                        name.clone(),
+                        DirEntry {
+                            path: path.join(name),
+                            metadata: entry.metadata,
+                        },
+                    );
+                }
+            }
+            Err(_) if lower_dir => (),
+            Err(err) => {
+                return Err(match self.lower_node(path) {
+                    Some(_) => FsError::BaseNotDirectory,
+                    None => err,
+                })
+            }
+        }
+        Ok(ReadDir::new(entries.into_values().collect()))
+    }
+    fn create_dir(&self, path: &Path) -> Result<(), FsError> {
+        if self.metadata(path).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+        self.copy_up_dirs(path.parent().unwrap_or_else(|| Path::new("/")))?;
+        self.upper.create_dir(path)
+    }
+    fn create_dir_with_mode(&self, path: &Path, mode: u32) -> Result<(), FsError> {
+        if self.metadata(path).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+        self.copy_up_dirs(path.parent().unwrap_or_else(|| Path::new("/")))?;
+        self.upper.create_dir_with_mode(path, mode)
+    }
+    fn remove_dir(&self, path: &Path) -> Result<(), FsError> {
+        if !self.metadata(path)?.is_dir() {
+            return Err(FsError::BaseNotDirectory);
+        }
+        if self.read_dir(path)?.next().is_some() {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+        if self.in_upper(path) {
+            self.upper.remove_dir(path)?;
+        }
+        self.hide_lower(path);
+        Ok(())
+    }
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), FsError> {
+        self.metadata(from)?;
+        self.copy_up_tree(from)?;
+        self.copy_up_dirs(to.parent().unwrap_or_else(|| Path::new("/")))?;
+        self.upper.rename(from, to)
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        match self.upper.metadata(path) {
+            Ok(metadata) => Ok(metadata),
+            Err(err) => match self.lower_node(path) {
+                Some(node) => Ok(node.metadata().clone()),
+                None if err == FsError::Lock => Err(err),
+                None => Err(FsError::EntityNotFound),
+            },
+        }
+    }
+    fn remove_file(&self, path: &Path) -> Result<(), FsError> {
+        if self.metadata(path)?.is_dir() {
+            return Err(FsError::NotAFile);
+        }
+        if self.in_upper(path) {
+            self.upper.remove_file(path)?;
+        }
+        self.hide_lower(path);
+        Ok(())
+    }
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<u64>,
+        modified: Option<u64>,
+        follow_symlinks: bool,
+    ) -> Result<(), FsError> {
+        self.metadata(path)?;
+        self.copy_up(path)?;
+        self.upper
+            .set_times(path, accessed, modified, follow_symlinks)
+    }
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(Box::new(OverlayFileOpener { fs: self.clone() }))
+    }
+    fn try_clone(&self) -> Option<Box<dyn FileSystem>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+struct OverlayFileOpener {
+    fs: OverlayFileSystem,
+}
+
+impl FileOpener for OverlayFileOpener {
+    fn open(
+        &mut self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, FsError> {
+        let fs = &self.fs;
+        let modifies =
+            conf.write() || conf.append() || conf.truncate() || conf.create() || conf.create_new();
+        if !modifies && !fs.in_upper(path) {
+            return match fs.lower_node(path) {
+                Some(SnapshotNode::File { metadata, data }) => Ok(Box::new(SnapshotFile {
+                    data: data.clone(),
+                    metadata: metadata.clone(),
+                    cursor: 0,
+                    fs: fs.clone(),
+                    path: path.to_path_buf(),
+                })),
+                Some(SnapshotNode::Dir { .. }) => Err(FsError::NotAFile),
+                None => Err(FsError::EntityNotFound),
+            };
+        }
+
+        if conf.create_new() && fs.metadata(path).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+        // writes only ever reach the private layer
+        fs.copy_up(path)?;
+        fs.copy_up_dirs(path.parent().unwrap_or_else(|| Path::new("/")))?;
+        fs.upper.new_open_options().options(conf.clone()).open(path)
+    }
+}
+
+/// A file of the snapshot opened for reading
+#[derive(Debug)]
+struct SnapshotFile {
+    data: Arc<[u8]>,
+    metadata: Metadata,
+    cursor: u64,
+    fs: OverlayFileSystem,
+    path: PathBuf,
+}
+
+impl Read for SnapshotFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = (self.cursor as usize).min(self.data.len());
+        let read = buf.len().min(self.data.len() - start);
+        buf[..read].copy_from_slice(&self.data[start..][..read]);
+        self.cursor += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for SnapshotFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let cursor = match pos {
+            io::SeekFrom::Start(offset) => offset as i128,
+            io::SeekFrom::End(offset) => self.data.len() as i128 + offset as i128,
+            io::SeekFrom::Current(offset) => self.cursor as i128 + offset as i128,
+        };
+        if cursor < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seeking before the byte 0",
+            ));
+        }
+        self.cursor = cursor as u64;
+        Ok(self.cursor)
+    }
+}
+
+impl Write for SnapshotFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the file was opened read-only",
+        ))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl VirtualFile for SnapshotFile {
+    fn last_accessed(&self) -> u64 {
+        self.metadata.accessed
+    }
+    fn last_modified(&self) -> u64 {
+        self.metadata.modified
+    }
+    fn created_time(&self) -> u64 {
+        self.metadata.created
+    }
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        self.fs.remove_file(&self.path)
+    }
+}
@@ -1,5 +1,5 @@
 use crate::syscalls::types::*;
-use crate::syscalls::{read_bytes, write_bytes};
+use crate::syscalls::{read_bytes_to_iovs, write_bytes};
 use crate::WasiEnv;
 use bytes::{Buf, Bytes};
 use std::convert::TryInto;
@@ -8,7 +8,7 @@ use std::ops::DerefMut;
 use std::sync::mpsc;
 use std::sync::Mutex;
 use wasmer::MemorySize;
-use wasmer::{FunctionEnvMut, Memory, WasmSlice};
+use wasmer::{FunctionEnvMut, Memory, WasmPtr, WasmSlice};
 
 #[derive(Debug)]
 pub struct WasiPipe {
@@ -40,18 +40,20 @@ impl WasiPipe {
         (pipe1, pipe2)
     }
 
+    /// Receives bytes from the pipe into the buffers of the `iovs_len`
+    /// iovecs at `iovs`, waiting for the other end to send some
     pub fn recv<M: MemorySize>(
         &mut self,
         ctx: &FunctionEnvMut<'_, WasiEnv>,
-        memory: &Memory,
-        iov: WasmSlice<__wasi_iovec_t<M>>,
+        iovs: WasmPtr<__wasi_iovec_t<M>, M>,
+        iovs_len: M::Offset,
     ) -> Result<usize, __wasi_errno_t> {
         loop {
             if let Some(buf) = self.read_buffer.as_mut() {
                 let buf_len = buf.len();
                 if buf_len > 0 {
                     let reader = buf.as_ref();
-                    let read = read_bytes(ctx, reader, memory, iov).map(|_| buf_len as usize)?;
+                    let read = read_bytes_to_iovs(ctx, reader, iovs, iovs_len)?;
                     buf.advance(read);
                     return Ok(read);
                 }
@@ -62,6 +64,28 @@ impl WasiPipe {
         }
     }
 
+    /// Returns the number of bytes that can be read without blocking, the
+    /// next message is moved to the read buffer if there is one. Returns
+    /// `None` if the other end of the pipe is closed
+    pub fn bytes_available_read(&mut self) -> Option<usize> {
+        loop {
+            if let Some(buf) = self.read_buffer.as_ref() {
+                if !buf.is_empty() {
+                    return Some(buf.len());
+                }
+            }
+            let rx = self.rx.lock().unwrap();
+            match rx.try_recv() {
+                Ok(data) => {
+                    drop(rx);
+                    self.read_buffer.replace(Bytes::from(data));
+                }
+                Err(mpsc::TryRecvError::Empty) => return Some(0),
+                Err(mpsc::TryRecvError::Disconnected) => return None,
+            }
+        }
+    }
+
     pub fn send<M: MemorySize>(
         &mut self,
         ctx: &FunctionEnvMut<'_, WasiEnv>,
@@ -76,6 +100,10 @@ impl WasiPipe {
         let buf_len: usize = buf_len.try_into().map_err(|_| __WASI_EINVAL)?;
         let mut buf = Vec::with_capacity(buf_len);
         write_bytes(ctx, &mut buf, memory, iov)?;
+        // an empty message would be taken as data by `recv`
+        if buf.is_empty() {
+            return Ok(0);
+        }
         let tx = self.tx.lock().unwrap();
         tx.send(buf).map_err(|_| __WASI_EIO)?;
         Ok(buf_len)
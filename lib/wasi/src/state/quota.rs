@@ -0,0 +1,104 @@
+//! A [`VirtualFile`] wrapper that caps the number of bytes written to it.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use wasmer_vfs::{FsError, VirtualFile};
+
+/// Forwards everything to the wrapped file but stops accepting writes once
+/// `limit` bytes in total were written through it.
+///
+/// A write that would exceed the quota writes the bytes that still fit, and
+/// the next one fails with [`io::ErrorKind::WriteZero`], which the guest
+/// sees as `__WASI_ENOSPC`. Growing the file with `set_len` counts as
+/// writing the added bytes and fails with [`FsError::WriteZero`] past the
+/// quota. Overwriting existing bytes still counts against the quota.
+/// Duplicates of the file made with
+/// [`VirtualFile::try_clone`](wasmer_vfs::VirtualFile::try_clone), e.g. by
+/// `fork`, share the quota of the original file.
+#[derive(Debug)]
+pub struct QuotaFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    limit: u64,
+    written: Arc<AtomicU64>,
+}
+
+impl QuotaFile {
+    /// Wraps `inner`, allowing `limit` bytes to be written to it
+    pub fn new(inner: Box<dyn VirtualFile + Send + Sync + 'static>, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            written: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the number of bytes written so far
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes that can still be written
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.written())
+    }
+
+    /// Returns the wrapped file
+    pub fn into_inner(self) -> Box<dyn VirtualFile + Send + Sync + 'static> {
+        self.inner
+    }
+}
+
+impl Read for QuotaFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for QuotaFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = (buf.len() as u64).min(self.remaining()) as usize;
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "the write quota of the file is exhausted",
+            ));
+        }
+        let written = self.inner.write(&buf[..len])?;
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for QuotaFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+forward_virtual_file!(QuotaFile {
+    fn set_len(&mut self, new_size: u64) -> Result<(), FsError> {
+        let grown = new_size.saturating_sub(self.inner.current_len()?);
+        if grown > self.remaining() {
+            return Err(FsError::WriteZero);
+        }
+        self.inner.set_len(new_size)?;
+        self.written.fetch_add(grown, Ordering::Relaxed);
+        Ok(())
+    }
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let inner = self.inner.try_clone()?;
+        Some(Box::new(Self {
+            inner,
+            limit: self.limit,
+            written: self.written.clone(),
+        }))
+    }
+});
@@ -0,0 +1,80 @@
+//! A [`FileSystem`] wrapper that rejects every modification.
+
+use std::path::Path;
+use wasmer_vfs::{
+    FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir, VirtualFile,
+};
+
+/// Exposes the wrapped file system read-only, any operation that would
+/// modify it fails with [`FsError::PermissionDenied`]
+#[derive(Debug)]
+pub struct ReadOnlyFileSystem {
+    inner: Box<dyn FileSystem>,
+}
+
+impl ReadOnlyFileSystem {
+    pub fn new(inner: Box<dyn FileSystem>) -> Self {
+        Self { inner }
+    }
+}
+
+impl FileSystem for ReadOnlyFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        self.inner.read_dir(path)
+    }
+    fn create_dir(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn create_dir_with_mode(&self, _path: &Path, _mode: u32) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn remove_dir(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn rename(&self, _from: &Path, _to: &Path) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.inner.metadata(path)
+    }
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.inner.symlink_metadata(path)
+    }
+    fn remove_file(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn set_times(
+        &self,
+        _path: &Path,
+        _accessed: Option<u64>,
+        _modified: Option<u64>,
+        _follow_symlinks: bool,
+    ) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(Box::new(ReadOnlyFileOpener {
+            inner: self.inner.new_open_options(),
+        }))
+    }
+    fn try_clone(&self) -> Option<Box<dyn FileSystem>> {
+        Some(Box::new(Self::new(self.inner.try_clone()?)))
+    }
+}
+
+struct ReadOnlyFileOpener {
+    inner: OpenOptions,
+}
+
+impl FileOpener for ReadOnlyFileOpener {
+    fn open(
+        &mut self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, FsError> {
+        if conf.write() || conf.append() || conf.truncate() || conf.create() || conf.create_new() {
+            return Err(FsError::PermissionDenied);
+        }
+        self.inner.options(conf.clone()).open(path)
+    }
+}
@@ -1,6 +1,6 @@
 use super::types::net_error_into_wasi_err;
 use crate::syscalls::types::*;
-use crate::syscalls::{read_bytes, write_bytes};
+use crate::syscalls::{read_bytes_to_iovs, write_bytes};
 use crate::WasiEnv;
 use bytes::{Buf, Bytes};
 use std::convert::TryInto;
@@ -14,8 +14,8 @@ use tracing::{debug, error, info, warn};
 use wasmer::{FunctionEnvMut, Memory, MemorySize, WasmPtr, WasmSlice};
 use wasmer_vnet::{net_error_into_io_err, TimeType};
 use wasmer_vnet::{
-    IpCidr, IpRoute, SocketHttpRequest, VirtualIcmpSocket, VirtualNetworking, VirtualRawSocket,
-    VirtualTcpListener, VirtualTcpSocket, VirtualUdpSocket, VirtualWebSocket,
+    IpCidr, IpRoute, NetworkError, SocketHttpRequest, VirtualIcmpSocket, VirtualNetworking,
+    VirtualRawSocket, VirtualTcpListener, VirtualTcpSocket, VirtualUdpSocket, VirtualWebSocket,
 };
 
 #[cfg(feature = "enable-serde")]
@@ -148,6 +148,7 @@ pub struct InodeSocket {
     kind: InodeSocketKind,
     read_buffer: Option<Bytes>,
     read_addr: Option<SocketAddr>,
+    nonblocking: bool,
 }
 
 impl InodeSocket {
@@ -156,6 +157,31 @@ impl InodeSocket {
             kind,
             read_buffer: None,
             read_addr: None,
+            nonblocking: false,
+        }
+    }
+
+    /// Switches the socket in or out of non-blocking mode, where receiving
+    /// and sending fail with `__WASI_EAGAIN` instead of blocking. Sockets
+    /// that don't support it keep blocking
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), __wasi_errno_t> {
+        if self.nonblocking == nonblocking {
+            return Ok(());
+        }
+        let result = match &mut self.kind {
+            InodeSocketKind::TcpStream(sock) => sock.set_nonblocking(nonblocking),
+            InodeSocketKind::UdpSocket(sock) => sock.set_nonblocking(nonblocking),
+            InodeSocketKind::Raw(sock) => sock.set_nonblocking(nonblocking),
+            InodeSocketKind::Icmp(sock) => sock.set_nonblocking(nonblocking),
+            _ => Err(NetworkError::Unsupported),
+        };
+        match result {
+            Ok(()) => {
+                self.nonblocking = nonblocking;
+                Ok(())
+            }
+            Err(NetworkError::Unsupported) => Ok(()),
+            Err(err) => Err(net_error_into_wasi_err(err)),
         }
     }
 
@@ -342,6 +368,19 @@ impl InodeSocket {
         })
     }
 
+    /// Returns the file type reported for this socket, datagram sockets
+    /// are `__WASI_FILETYPE_SOCKET_DGRAM` and all others are streams
+    pub fn filetype(&self) -> __wasi_filetype_t {
+        match &self.kind {
+            InodeSocketKind::PreSocket {
+                ty: __WASI_SOCK_TYPE_DGRAM,
+                ..
+            }
+            | InodeSocketKind::UdpSocket(_) => __WASI_FILETYPE_SOCKET_DGRAM,
+            _ => __WASI_FILETYPE_SOCKET_STREAM,
+        }
+    }
+
     pub fn http_status(&self) -> Result<WasiHttpStatus, __wasi_errno_t> {
         Ok(match &self.kind {
             InodeSocketKind::HttpRequest(http, ..) => {
@@ -886,21 +925,57 @@ impl InodeSocket {
     pub fn recv<M: MemorySize>(
         &mut self,
         ctx: &FunctionEnvMut<WasiEnv>,
-        memory: &Memory,
-        iov: WasmSlice<__wasi_iovec_t<M>>,
+        iovs: WasmPtr<__wasi_iovec_t<M>, M>,
+        iovs_len: M::Offset,
     ) -> Result<usize, __wasi_errno_t> {
+        self.recv_with_flags(ctx, iovs, iovs_len, 0)
+            .map(|(read, _)| read)
+    }
+
+    /// Like [`InodeSocket::recv`], with `__WASI_SOCK_RECV_INPUT_PEEK` the
+    /// data is left in the socket (using `MSG_PEEK` on the host when the
+    /// socket supports it). The output flags have
+    /// `__WASI_SOCK_RECV_OUTPUT_DATA_TRUNCATED` when a datagram didn't fit
+    /// in the iovecs, the rest of the datagram is dropped unless peeking
+    pub fn recv_with_flags<M: MemorySize>(
+        &mut self,
+        ctx: &FunctionEnvMut<WasiEnv>,
+        iovs: WasmPtr<__wasi_iovec_t<M>, M>,
+        iovs_len: M::Offset,
+        ri_flags: __wasi_riflags_t,
+    ) -> Result<(usize, __wasi_roflags_t), __wasi_errno_t> {
+        if ri_flags & !__WASI_SOCK_RECV_INPUT_PEEK != 0 {
+            return Err(__WASI_ENOTSUP);
+        }
+        let peek = ri_flags & __WASI_SOCK_RECV_INPUT_PEEK != 0;
+        let is_stream = matches!(self.kind, InodeSocketKind::TcpStream(..));
+        let mut truncated = false;
         loop {
             if let Some(buf) = self.read_buffer.as_mut() {
-                let buf_len = buf.len();
-                if buf_len > 0 {
-                    let reader = buf.as_ref();
-                    let read = read_bytes(ctx, reader, memory, iov).map(|_| buf_len)?;
-                    if let InodeSocketKind::TcpStream(..) = &self.kind {
-                        buf.advance(read);
-                    } else {
-                        buf.clear();
+                if !buf.is_empty() {
+                    let read = read_bytes_to_iovs(ctx, buf.as_ref(), iovs, iovs_len)?;
+                    truncated |= !is_stream && read < buf.len();
+                    if !peek {
+                        if is_stream {
+                            buf.advance(read);
+                        } else {
+                            buf.clear();
+                        }
                     }
-                    return Ok(read);
+                    return Ok((read, roflags(truncated)));
+                }
+            }
+            if peek {
+                let rcv = match &mut self.kind {
+                    InodeSocketKind::TcpStream(sock) => Some(sock.peek()),
+                    InodeSocketKind::UdpSocket(sock) => Some(sock.peek()),
+                    _ => None,
+                };
+                if let Some(rcv) = rcv {
+                    let rcv = rcv.map_err(net_error_into_wasi_err)?;
+                    let read = read_bytes_to_iovs(ctx, rcv.data.as_ref(), iovs, iovs_len)?;
+                    let truncated = !is_stream && (rcv.truncated || read < rcv.data.len());
+                    return Ok((read, roflags(truncated)));
                 }
             }
             let data = match &mut self.kind {
@@ -930,10 +1005,12 @@ impl InodeSocket {
                 }
                 InodeSocketKind::WebSocket(sock) => {
                     let read = sock.recv().map_err(net_error_into_wasi_err)?;
+                    truncated = read.truncated;
                     read.data
                 }
                 InodeSocketKind::Raw(sock) => {
                     let read = sock.recv().map_err(net_error_into_wasi_err)?;
+                    truncated = read.truncated;
                     read.data
                 }
                 InodeSocketKind::TcpStream(sock) => {
@@ -942,6 +1019,7 @@ impl InodeSocket {
                 }
                 InodeSocketKind::UdpSocket(sock) => {
                     let read = sock.recv().map_err(net_error_into_wasi_err)?;
+                    truncated = read.truncated;
                     read.data
                 }
                 InodeSocketKind::PreSocket { .. } => return Err(__WASI_ENOTCONN),
@@ -953,34 +1031,59 @@ impl InodeSocket {
         }
     }
 
+    /// Like [`InodeSocket::recv_with_flags`], the address of the peer is
+    /// written to `addr`
     pub fn recv_from<M: MemorySize>(
         &mut self,
         ctx: &FunctionEnvMut<WasiEnv>,
-        memory: &Memory,
-        iov: WasmSlice<__wasi_iovec_t<M>>,
+        iovs: WasmPtr<__wasi_iovec_t<M>, M>,
+        iovs_len: M::Offset,
+        ri_flags: __wasi_riflags_t,
         addr: WasmPtr<__wasi_addr_port_t, M>,
-    ) -> Result<usize, __wasi_errno_t> {
+    ) -> Result<(usize, __wasi_roflags_t), __wasi_errno_t> {
+        if ri_flags & !__WASI_SOCK_RECV_INPUT_PEEK != 0 {
+            return Err(__WASI_ENOTSUP);
+        }
+        let peek = ri_flags & __WASI_SOCK_RECV_INPUT_PEEK != 0;
+        let mut truncated = false;
         loop {
             if let Some(buf) = self.read_buffer.as_mut() {
                 if !buf.is_empty() {
-                    let reader = buf.as_ref();
-                    let ret = read_bytes(ctx, reader, memory, iov)?;
+                    let read = read_bytes_to_iovs(ctx, buf.as_ref(), iovs, iovs_len)?;
+                    truncated |= read < buf.len();
+                    if !peek {
+                        buf.clear();
+                    }
                     let peer = self
                         .read_addr
                         .unwrap_or_else(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
-                    write_ip_port(ctx, memory, addr, peer.ip(), peer.port())?;
-                    return Ok(ret);
+                    write_ip_port(ctx, ctx.data().memory(), addr, peer.ip(), peer.port())?;
+                    return Ok((read, roflags(truncated)));
                 }
             }
             let rcv = match &mut self.kind {
-                InodeSocketKind::Icmp(sock) => sock.recv_from().map_err(net_error_into_wasi_err)?,
-                InodeSocketKind::UdpSocket(sock) => {
-                    sock.recv_from().map_err(net_error_into_wasi_err)?
-                }
+                InodeSocketKind::Icmp(sock) if peek => sock.peek_from(),
+                InodeSocketKind::UdpSocket(sock) if peek => sock.peek_from(),
+                InodeSocketKind::Icmp(sock) => sock.recv_from(),
+                InodeSocketKind::UdpSocket(sock) => sock.recv_from(),
                 InodeSocketKind::PreSocket { .. } => return Err(__WASI_ENOTCONN),
                 InodeSocketKind::Closed => return Err(__WASI_EIO),
                 _ => return Err(__WASI_ENOTSUP),
-            };
+            }
+            .map_err(net_error_into_wasi_err)?;
+            if peek {
+                let read = read_bytes_to_iovs(ctx, rcv.data.as_ref(), iovs, iovs_len)?;
+                write_ip_port(
+                    ctx,
+                    ctx.data().memory(),
+                    addr,
+                    rcv.addr.ip(),
+                    rcv.addr.port(),
+                )?;
+                let truncated = rcv.truncated || read < rcv.data.len();
+                return Ok((read, roflags(truncated)));
+            }
+            truncated = rcv.truncated;
             self.read_buffer.replace(rcv.data);
             self.read_addr.replace(rcv.addr);
         }
@@ -1309,6 +1412,14 @@ pub(crate) fn read_ip_port<M: MemorySize>(
     })
 }
 
+fn roflags(truncated: bool) -> __wasi_roflags_t {
+    if truncated {
+        __WASI_SOCK_RECV_OUTPUT_DATA_TRUNCATED
+    } else {
+        0
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) fn write_ip_port<M: MemorySize>(
     ctx: &FunctionEnvMut<WasiEnv>,
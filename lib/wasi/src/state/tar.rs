@@ -0,0 +1,257 @@
+//! A read-only [`FileSystem`] holding the entries of a tar archive.
+
+use std::io::{self, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, EntryType};
+use wasmer_vfs::{
+    mem_fs, FileOpener, FileSystem, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir,
+    VirtualFile,
+};
+
+/// Serves the directories and files of a tar archive from memory, see
+/// [`WasiStateBuilder::mount_tar`](crate::WasiStateBuilder::mount_tar).
+///
+/// Any operation that would modify the file system or one of its files
+/// fails with [`FsError::ReadOnly`]. The symlinks of the archive are not
+/// part of the file system, they are returned by [`TarFileSystem::symlinks`]
+/// so that they can be created as virtual symlinks.
+///
+/// The archive is read with the [`tar`] crate, which supports the ustar,
+/// GNU (long names) and pax (`path` and `linkpath` records) formats. Hard
+/// links become copies of the file they link to, other special files are
+/// skipped.
+#[derive(Debug, Clone)]
+pub struct TarFileSystem {
+    inner: mem_fs::FileSystem,
+    symlinks: Vec<(PathBuf, PathBuf)>,
+}
+
+impl TarFileSystem {
+    /// Reads the entries of `archive`, fails with [`FsError::InvalidData`]
+    /// if it is not a valid tar archive or has an entry outside of its root
+    pub fn new(archive: &[u8]) -> Result<Self, FsError> {
+        let inner = mem_fs::FileSystem::default();
+        let mut symlinks = Vec::new();
+
+        let mut archive = Archive::new(archive);
+        for entry in archive.entries().map_err(invalid_data)? {
+            let mut entry = entry.map_err(invalid_data)?;
+            let path = entry_path(&entry.path().map_err(invalid_data)?)?;
+            match entry.header().entry_type() {
+                EntryType::Directory => create_dir_all(&inner, &path)?,
+                EntryType::Regular | EntryType::Continuous => {
+                    let mut content = Vec::new();
+                    entry.read_to_end(&mut content).map_err(invalid_data)?;
+                    write_file(&inner, &path, &content)?;
+                }
+                EntryType::Link => {
+                    let mut content = Vec::new();
+                    inner
+                        .new_open_options()
+                        .read(true)
+                        .open(entry_path(&link_name(&entry)?)?)?
+                        .read_to_end(&mut content)?;
+                    write_file(&inner, &path, &content)?;
+                }
+                EntryType::Symlink => {
+                    symlinks.push((
+                        path.strip_prefix("/").unwrap().to_owned(),
+                        link_name(&entry)?,
+                    ));
+                }
+                // devices and fifos can't be represented
+                _ => (),
+            }
+        }
+
+        Ok(Self { inner, symlinks })
+    }
+
+    /// Returns the symlinks of the archive, as their path relative to the
+    /// root of the archive and their target
+    pub fn symlinks(&self) -> &[(PathBuf, PathBuf)] {
+        &self.symlinks
+    }
+}
+
+fn invalid_data(_err: io::Error) -> FsError {
+    FsError::InvalidData
+}
+
+/// Returns the target of a link entry
+fn link_name<R: Read>(entry: &tar::Entry<'_, R>) -> Result<PathBuf, FsError> {
+    entry
+        .link_name()
+        .map_err(invalid_data)?
+        .map(|link| link.into_owned())
+        .ok_or(FsError::InvalidData)
+}
+
+/// Turns the path of an entry into an absolute path in the file system,
+/// paths leading out of the archive are rejected
+fn entry_path(name: &Path) -> Result<PathBuf, FsError> {
+    let mut path = PathBuf::from("/");
+    for component in name.components() {
+        match component {
+            Component::Normal(component) => path.push(component),
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir | Component::Prefix(_) => return Err(FsError::InvalidData),
+        }
+    }
+    Ok(path)
+}
+
+fn create_dir_all(fs: &mem_fs::FileSystem, path: &Path) -> Result<(), FsError> {
+    let mut ancestors = path.ancestors().collect::<Vec<_>>();
+    ancestors.reverse();
+    for dir in ancestors.into_iter().skip(1) {
+        if fs.metadata(dir).is_err() {
+            fs.create_dir(dir)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_file(fs: &mem_fs::FileSystem, path: &Path, content: &[u8]) -> Result<(), FsError> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(fs, parent)?;
+    }
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?
+        .write_all(content)?;
+    Ok(())
+}
+
+impl FileSystem for TarFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        self.inner.read_dir(path)
+    }
+    fn create_dir(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn create_dir_with_mode(&self, _path: &Path, _mode: u32) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn remove_dir(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn rename(&self, _from: &Path, _to: &Path) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        self.inner.metadata(path)
+    }
+    fn remove_file(&self, _path: &Path) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn set_times(
+        &self,
+        _path: &Path,
+        _accessed: Option<u64>,
+        _modified: Option<u64>,
+        _follow_symlinks: bool,
+    ) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(Box::new(TarFileOpener {
+            inner: self.inner.new_open_options(),
+        }))
+    }
+    fn try_clone(&self) -> Option<Box<dyn FileSystem>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+struct TarFileOpener {
+    inner: OpenOptions,
+}
+
+impl FileOpener for TarFileOpener {
+    fn open(
+        &mut self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, FsError> {
+        if conf.write() || conf.append() || conf.truncate() || conf.create() || conf.create_new() {
+            return Err(FsError::ReadOnly);
+        }
+        let inner = self.inner.options(conf.clone()).open(path)?;
+        Ok(Box::new(TarFile { inner }))
+    }
+}
+
+/// A file of the archive, which can't be modified through its handle
+/// either
+#[derive(Debug)]
+struct TarFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+}
+
+impl Read for TarFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for TarFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Write for TarFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "can not write to a file of a tar archive",
+        ))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl VirtualFile for TarFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+    fn set_last_accessed(&mut self, _last_accessed: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn set_last_modified(&mut self, _last_modified: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+    fn bytes_available(&self) -> Result<usize, FsError> {
+        self.inner.bytes_available()
+    }
+    fn get_fd(&self) -> Option<wasmer_vfs::FileDescriptor> {
+        self.inner.get_fd()
+    }
+    fn content_hash(&self) -> Option<[u8; 32]> {
+        self.inner.content_hash()
+    }
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        Some(Box::new(TarFile {
+            inner: self.inner.try_clone()?,
+        }))
+    }
+}
@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 use std::{
     collections::VecDeque,
+    fmt,
     io::{self, Read, Seek, Write},
     sync::{Arc, Mutex},
     time::Duration,
@@ -14,7 +15,7 @@ use wasmer_vbus::BusError;
 
 #[cfg(feature = "host-fs")]
 pub use wasmer_vfs::host_fs::{Stderr, Stdin, Stdout};
-#[cfg(feature = "mem-fs")]
+#[cfg(all(feature = "mem-fs", not(feature = "host-fs")))]
 pub use wasmer_vfs::mem_fs::{Stderr, Stdin, Stdout};
 
 use wasmer_vfs::{FsError, VirtualFile};
@@ -37,11 +38,15 @@ pub fn fs_error_from_wasi_err(err: __wasi_errno_t) -> FsError {
         __WASI_ENODEV => FsError::NoDevice,
         __WASI_ENOENT => FsError::EntityNotFound,
         __WASI_EPERM => FsError::PermissionDenied,
+        __WASI_ENOTDIR => FsError::BaseNotDirectory,
         __WASI_ETIMEDOUT => FsError::TimedOut,
         __WASI_EPROTO => FsError::UnexpectedEof,
         __WASI_EAGAIN => FsError::WouldBlock,
         __WASI_ENOSPC => FsError::WriteZero,
         __WASI_ENOTEMPTY => FsError::DirectoryNotEmpty,
+        __WASI_EXDEV => FsError::CrossDevice,
+        __WASI_ENOSYS => FsError::Unsupported,
+        __WASI_EROFS => FsError::ReadOnly,
         _ => FsError::UnknownError,
     }
 }
@@ -71,10 +76,22 @@ pub fn fs_error_into_wasi_err(fs_error: FsError) -> __wasi_errno_t {
         FsError::WouldBlock => __WASI_EAGAIN,
         FsError::WriteZero => __WASI_ENOSPC,
         FsError::DirectoryNotEmpty => __WASI_ENOTEMPTY,
-        FsError::Lock | FsError::UnknownError => __WASI_EIO,
+        FsError::CrossDevice => __WASI_EXDEV,
+        FsError::Unsupported => __WASI_ENOSYS,
+        FsError::ReadOnly => __WASI_EROFS,
+        FsError::Lock | FsError::UnknownError | FsError::OsError(_) => __WASI_EIO,
     }
 }
 
+/// Returns the errno for an I/O error of kind `kind`, the same one as
+/// going through [`FsError`] and [`fs_error_into_wasi_err`], e.g. for a
+/// custom [`VirtualFile`] that reports the errors of the host.
+///
+/// Kinds without an equivalent errno map to `__WASI_EIO`.
+pub fn errno_from_io_error_kind(kind: io::ErrorKind) -> __wasi_errno_t {
+    fs_error_into_wasi_err(FsError::from(io::Error::from(kind)))
+}
+
 pub fn net_error_into_wasi_err(net_error: NetworkError) -> __wasi_errno_t {
     match net_error {
         NetworkError::InvalidFd => __WASI_EBADF,
@@ -152,7 +169,7 @@ pub fn wasi_error_into_bus_err(bus_error: __bus_errno_t) -> BusError {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(clippy::enum_variant_names)]
 pub enum PollEvent {
     /// Data available to read
@@ -218,6 +235,12 @@ pub fn iterate_poll_events(pes: PollEventSet) -> PollEventIter {
     PollEventIter { pes, i: 0 }
 }
 
+/// Like [`iterate_poll_events`], but each event comes with the index of its
+/// bit in `pes`
+pub fn iterate_poll_events_indexed(pes: PollEventSet) -> impl Iterator<Item = (usize, PollEvent)> {
+    (0..16).filter_map(move |i| PollEvent::from_i16(pes & (1 << i)).map(|event| (i, event)))
+}
+
 #[cfg(all(unix, feature = "sys-poll"))]
 fn poll_event_set_to_platform_poll_events(mut pes: PollEventSet) -> i16 {
     let mut out = 0;
@@ -446,6 +469,172 @@ impl VirtualFile for Pipe {
         let buffer = self.buffer.lock().unwrap();
         Ok(Some(buffer.len()))
     }
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    /// The new pipe shares the buffer
+    fn try_clone(&self) -> Option<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+/// For streaming stdio output. Every write is forwarded to the callback as
+/// soon as the guest makes it, see
+/// [`WasiStateBuilder::stdout_callback`](crate::WasiStateBuilder::stdout_callback)
+pub struct CallbackFile {
+    callback: Mutex<Box<dyn FnMut(&[u8]) + Send>>,
+}
+
+impl CallbackFile {
+    pub fn new(callback: Box<dyn FnMut(&[u8]) + Send>) -> Self {
+        Self {
+            callback: Mutex::new(callback),
+        }
+    }
+}
+
+impl fmt::Debug for CallbackFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackFile").finish()
+    }
+}
+
+impl Read for CallbackFile {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "can not read from a callback file",
+        ))
+    }
+}
+
+impl Write for CallbackFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.callback.get_mut().unwrap())(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for CallbackFile {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "can not seek in a callback file",
+        ))
+    }
+}
+
+impl VirtualFile for CallbackFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn is_seekable(&self) -> bool {
+        false
+    }
+}
+
+/// Forwards every write to two files, e.g. to print the guest's output on
+/// the terminal while capturing it with a [`Pipe`](crate::Pipe)
+#[derive(Debug)]
+pub struct Tee {
+    first: Box<dyn VirtualFile + Send + Sync + 'static>,
+    second: Box<dyn VirtualFile + Send + Sync + 'static>,
+}
+
+impl Tee {
+    /// Writes go to `first`, then to `second`
+    pub fn new(
+        first: Box<dyn VirtualFile + Send + Sync + 'static>,
+        second: Box<dyn VirtualFile + Send + Sync + 'static>,
+    ) -> Self {
+        Self { first, second }
+    }
+}
+
+impl Read for Tee {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "can not read from a tee",
+        ))
+    }
+}
+
+// Both files always see the same data, even when one of them fails the
+// other one is still written to, and the first error is returned
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let first = self.first.write_all(buf);
+        let second = self.second.write_all(buf);
+        first.and(second).map(|()| buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        let first = self.first.flush();
+        let second = self.second.flush();
+        first.and(second)
+    }
+}
+
+impl Seek for Tee {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "can not seek in a tee",
+        ))
+    }
+}
+
+impl VirtualFile for Tee {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn is_seekable(&self) -> bool {
+        false
+    }
+    fn sync_to_disk(&self) -> Result<(), FsError> {
+        let first = self.first.sync_to_disk();
+        let second = self.second.sync_to_disk();
+        first.and(second)
+    }
+    fn sync_data_to_disk(&self) -> Result<(), FsError> {
+        let first = self.first.sync_data_to_disk();
+        let second = self.second.sync_data_to_disk();
+        first.and(second)
+    }
 }
 
 /*
@@ -457,3 +646,55 @@ trait WasiFdBacking: std::fmt::Debug {
     fn get_name(&self) -> &str;
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_events_indexed() {
+        let pes = PollEventBuilder::new()
+            .add(PollEvent::PollHangUp)
+            .add(PollEvent::PollIn)
+            .build();
+        assert_eq!(
+            iterate_poll_events_indexed(pes).collect::<Vec<_>>(),
+            [(0, PollEvent::PollIn), (3, PollEvent::PollHangUp)]
+        );
+
+        // Bits that are not events are skipped but still counted
+        assert_eq!(
+            iterate_poll_events_indexed(pes | 1 << 8).collect::<Vec<_>>(),
+            [(0, PollEvent::PollIn), (3, PollEvent::PollHangUp)]
+        );
+        assert_eq!(iterate_poll_events_indexed(0).next(), None);
+    }
+
+    #[test]
+    fn errno_from_io_error_kinds() {
+        assert_eq!(
+            errno_from_io_error_kind(io::ErrorKind::NotFound),
+            __WASI_ENOENT
+        );
+        assert_eq!(
+            errno_from_io_error_kind(io::ErrorKind::InvalidInput),
+            __WASI_EINVAL
+        );
+        assert_eq!(
+            errno_from_io_error_kind(io::ErrorKind::WouldBlock),
+            __WASI_EAGAIN
+        );
+        assert_eq!(errno_from_io_error_kind(io::ErrorKind::Other), __WASI_EIO);
+        assert_eq!(
+            errno_from_io_error_kind(io::ErrorKind::Unsupported),
+            __WASI_EIO
+        );
+
+        // The same errno as for an error going through `FsError`
+        let error = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        assert_eq!(
+            errno_from_io_error_kind(error.kind()),
+            fs_error_into_wasi_err(error.into())
+        );
+    }
+}
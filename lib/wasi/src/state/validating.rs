@@ -0,0 +1,178 @@
+//! A [`VirtualFile`] wrapper that validates its content before committing it.
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use wasmer_vfs::{FsError, VirtualFile};
+
+/// Checks the whole content of a [`ValidatingFile`], returning the error
+/// to report when the content is invalid
+pub type ContentValidator = Box<dyn Fn(&[u8]) -> Result<(), FsError> + Send + Sync + 'static>;
+
+/// Buffers the content written to the wrapped file and only writes it out
+/// once it passes the validator.
+///
+/// Writes, reads and seeks operate on an in-memory copy of the content,
+/// which is validated once, when the file is committed: by [`finish`],
+/// when the guest closes its fd, by [`WasiState::flush_all`] and on
+/// `sync_to_disk` and `sync_data_to_disk`. `flush` doesn't validate anything. When the
+/// content passes, the wrapped file is overwritten with it; otherwise the
+/// wrapped file is left untouched and the error returned by the validator
+/// is reported (by `fd_close`, `fd_sync` or `fd_datasync`). The content
+/// stays buffered, so later writes can still fix it. Content that is never
+/// committed is discarded when the file is dropped.
+///
+/// [`finish`]: ValidatingFile::finish
+/// [`WasiState::flush_all`]: crate::WasiState::flush_all
+pub struct ValidatingFile {
+    // Behind a lock so that `sync_to_disk`, which takes `&self`, can commit
+    inner: Mutex<Box<dyn VirtualFile + Send + Sync + 'static>>,
+    validator: ContentValidator,
+    data: Vec<u8>,
+    cursor: usize,
+    /// Whether `inner` holds the buffered content
+    committed: Mutex<bool>,
+}
+
+impl ValidatingFile {
+    /// Wraps `inner`, whose current content is read into the buffer
+    pub fn new(
+        mut inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+        validator: ContentValidator,
+    ) -> Result<Self, FsError> {
+        let mut data = Vec::new();
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_to_end(&mut data)?;
+
+        Ok(Self {
+            inner: Mutex::new(inner),
+            validator,
+            committed: Mutex::new(true),
+            data,
+            cursor: 0,
+        })
+    }
+
+    /// Validates the buffered content and writes it to the wrapped file,
+    /// returning the error of the validator if it doesn't pass. Does
+    /// nothing if nothing was written since the last commit
+    pub fn finish(&mut self) -> Result<(), FsError> {
+        self.commit()
+    }
+
+    /// Validates the buffered content and writes it to the wrapped file
+    fn commit(&self) -> Result<(), FsError> {
+        let mut committed = self.committed.lock().unwrap();
+        if *committed {
+            return Ok(());
+        }
+        (self.validator)(&self.data)?;
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.seek(SeekFrom::Start(0))?;
+        inner.set_len(0)?;
+        inner.write_all(&self.data)?;
+        inner.flush()?;
+        *committed = true;
+
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        *self.committed.get_mut().unwrap() = false;
+    }
+}
+
+impl fmt::Debug for ValidatingFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValidatingFile")
+            .field("inner", &self.inner)
+            .field("len", &self.data.len())
+            .field("cursor", &self.cursor)
+            .field("committed", &self.committed)
+            .finish()
+    }
+}
+
+impl Read for ValidatingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.cursor.min(self.data.len());
+        let amt = buf.len().min(self.data.len() - start);
+        buf[..amt].copy_from_slice(&self.data[start..start + amt]);
+        self.cursor += amt;
+        Ok(amt)
+    }
+}
+
+impl Write for ValidatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.cursor + buf.len();
+        if end > self.data.len() {
+            self.data.resize(end, 0);
+        }
+        self.data[self.cursor..end].copy_from_slice(buf);
+        self.cursor = end;
+        self.invalidate();
+        Ok(buf.len())
+    }
+
+    /// The content is only validated when it's committed, see
+    /// [`ValidatingFile::finish`]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ValidatingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let cursor = match pos {
+            SeekFrom::Start(offset) => Some(offset as i64),
+            SeekFrom::End(offset) => (self.data.len() as i64).checked_add(offset),
+            SeekFrom::Current(offset) => (self.cursor as i64).checked_add(offset),
+        };
+        match cursor {
+            Some(cursor) if cursor >= 0 => {
+                self.cursor = cursor as usize;
+                Ok(cursor as u64)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl VirtualFile for ValidatingFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.lock().unwrap().last_accessed()
+    }
+    fn last_modified(&self) -> u64 {
+        self.inner.lock().unwrap().last_modified()
+    }
+    fn created_time(&self) -> u64 {
+        self.inner.lock().unwrap().created_time()
+    }
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+    fn set_len(&mut self, new_size: u64) -> Result<(), FsError> {
+        self.data.resize(new_size as usize, 0);
+        self.invalidate();
+        Ok(())
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        self.inner.get_mut().unwrap().unlink()
+    }
+    fn sync_to_disk(&self) -> Result<(), FsError> {
+        self.commit()?;
+        self.inner.lock().unwrap().sync_to_disk()
+    }
+    fn sync_data_to_disk(&self) -> Result<(), FsError> {
+        self.commit()?;
+        self.inner.lock().unwrap().sync_data_to_disk()
+    }
+    fn bytes_available_read(&self) -> Result<Option<usize>, FsError> {
+        Ok(Some(self.data.len().saturating_sub(self.cursor)))
+    }
+}
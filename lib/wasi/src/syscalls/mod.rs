@@ -48,7 +48,7 @@ use std::time::Duration;
 use tracing::{debug, error, trace, warn};
 use wasmer::{
     AsStoreMut, FunctionEnvMut, Memory, Memory32, Memory64, MemorySize, RuntimeError, Value,
-    WasmPtr, WasmSlice,
+    ValueType, WasmPtr, WasmSlice,
 };
 use wasmer_vbus::{FileDescriptor, StdioMode};
 use wasmer_vfs::{FsError, VirtualFile};
@@ -90,6 +90,9 @@ fn write_bytes_inner<T: Write, M: MemorySize>(
         let bytes = WasmPtr::<u8, M>::new(iov_inner.buf)
             .slice(ctx, memory, iov_inner.buf_len)
             .map_err(mem_error_to_wasi)?;
+        if iov_inner.buf_len == M::ZERO {
+            continue;
+        }
         let bytes = bytes.read_to_vec().map_err(mem_error_to_wasi)?;
         write_loc.write_all(&bytes).map_err(map_io_err)?;
 
@@ -98,6 +101,9 @@ fn write_bytes_inner<T: Write, M: MemorySize>(
     Ok(bytes_written)
 }
 
+/// Writes all the iovecs to `write_loc`: short writes are continued and
+/// interrupted writes are retried until every byte is written or an error
+/// occurs.
 pub(crate) fn write_bytes<T: Write, M: MemorySize>(
     ctx: &FunctionEnvMut<'_, WasiEnv>,
     mut write_loc: T,
@@ -109,11 +115,138 @@ pub(crate) fn write_bytes<T: Write, M: MemorySize>(
     result
 }
 
+/// Resolves the buffers of all the iovecs up front, so a bad iovec is
+/// reported before any I/O is done
+fn iovec_slices<'a, M: MemorySize>(
+    ctx: &'a FunctionEnvMut<'_, WasiEnv>,
+    memory: &'a Memory,
+    iovs: &[(M::Offset, M::Offset)],
+) -> Result<(Vec<WasmSlice<'a, u8>>, usize), __wasi_errno_t> {
+    let mut slices = Vec::with_capacity(iovs.len());
+    let mut total_len = 0usize;
+    for (buf, buf_len) in iovs.iter() {
+        slices.push(
+            WasmPtr::<u8, M>::new(*buf)
+                .slice(ctx, memory, *buf_len)
+                .map_err(mem_error_to_wasi)?,
+        );
+        total_len = total_len
+            .checked_add(from_offset::<M>(*buf_len)?)
+            .ok_or(__WASI_EOVERFLOW)?;
+    }
+    Ok((slices, total_len))
+}
+
+/// Like [`write_bytes`] but gathers all the iovecs in a single buffer which
+/// is handed to the writer at once. This saves a host syscall per iovec
+/// for host-backed files.
+pub(crate) fn write_bytes_bulk<T: Write, M: MemorySize>(
+    ctx: &FunctionEnvMut<'_, WasiEnv>,
+    mut write_loc: T,
+    memory: &Memory,
+    iovs_arr: WasmSlice<__wasi_ciovec_t<M>>,
+) -> Result<usize, __wasi_errno_t> {
+    let iovs = iovs_arr
+        .read_to_vec()
+        .map_err(mem_error_to_wasi)?
+        .into_iter()
+        .map(|iov| (iov.buf, iov.buf_len))
+        .collect::<Vec<_>>();
+    let (slices, total_len) = iovec_slices::<M>(ctx, memory, &iovs)?;
+    if total_len == 0 {
+        return Ok(0);
+    }
+
+    let mut bytes = vec![0; total_len];
+    let mut pos = 0;
+    for slice in slices {
+        let len = slice.len() as usize;
+        slice
+            .read_slice(&mut bytes[pos..pos + len])
+            .map_err(mem_error_to_wasi)?;
+        pos += len;
+    }
+
+    let result = write_loc.write_all(&bytes).map_err(map_io_err);
+    write_loc.flush();
+    result.map(|_| total_len)
+}
+
+/// Like [`read_bytes_limited`] but reads into a single buffer with one call
+/// to the reader, which is then scattered into the iovecs. This saves a
+/// host syscall per iovec for host-backed files.
+pub(crate) fn read_bytes_bulk<T: Read, M: MemorySize>(
+    ctx: &FunctionEnvMut<'_, WasiEnv>,
+    mut reader: T,
+    memory: &Memory,
+    iovs_arr: WasmSlice<__wasi_iovec_t<M>>,
+    max_bytes: usize,
+) -> Result<usize, __wasi_errno_t> {
+    let iovs = iovs_arr
+        .read_to_vec()
+        .map_err(mem_error_to_wasi)?
+        .into_iter()
+        .map(|iov| (iov.buf, iov.buf_len))
+        .collect::<Vec<_>>();
+    let (slices, total_len) = iovec_slices::<M>(ctx, memory, &iovs)?;
+    if total_len.min(max_bytes) == 0 {
+        return Ok(0);
+    }
+
+    let mut raw_bytes = vec![0; total_len.min(max_bytes)];
+    let bytes_read = reader.read(&mut raw_bytes).map_err(map_io_err)?;
+
+    // the read may have blocked while the guest grew its memory, the views
+    // of the buffers are taken again
+    let (slices, _) = iovec_slices::<M>(ctx, memory, &iovs)?;
+    let mut pos = 0;
+    for slice in slices {
+        if pos == bytes_read {
+            break;
+        }
+        let len = (slice.len() as usize).min(bytes_read - pos);
+        slice
+            .subslice(0..len as u64)
+            .write_slice(&raw_bytes[pos..pos + len])
+            .map_err(mem_error_to_wasi)?;
+        pos += len;
+    }
+    Ok(bytes_read)
+}
+
 pub(crate) fn read_bytes<T: Read, M: MemorySize>(
+    ctx: &FunctionEnvMut<'_, WasiEnv>,
+    reader: T,
+    memory: &Memory,
+    iovs_arr: WasmSlice<__wasi_iovec_t<M>>,
+) -> Result<usize, __wasi_errno_t> {
+    read_bytes_limited(ctx, reader, memory, iovs_arr, usize::MAX)
+}
+
+/// Like [`read_bytes`] for the bytes received after waiting: the iovecs are
+/// resolved from `iovs` when the bytes are copied, as the guest may have
+/// grown its memory while the syscall was blocked
+pub(crate) fn read_bytes_to_iovs<T: Read, M: MemorySize>(
+    ctx: &FunctionEnvMut<'_, WasiEnv>,
+    reader: T,
+    iovs: WasmPtr<__wasi_iovec_t<M>, M>,
+    iovs_len: M::Offset,
+) -> Result<usize, __wasi_errno_t> {
+    let memory = ctx.data().memory();
+    let iovs_arr = iovs
+        .slice(ctx, memory, iovs_len)
+        .map_err(mem_error_to_wasi)?;
+    read_bytes(ctx, reader, memory, iovs_arr)
+}
+
+/// Like [`read_bytes`] but reads at most `max_bytes` bytes, stops at the
+/// first short read
+pub(crate) fn read_bytes_limited<T: Read, M: MemorySize>(
     ctx: &FunctionEnvMut<'_, WasiEnv>,
     mut reader: T,
     memory: &Memory,
     iovs_arr: WasmSlice<__wasi_iovec_t<M>>,
+    max_bytes: usize,
 ) -> Result<usize, __wasi_errno_t> {
     let mut bytes_read = 0usize;
 
@@ -121,25 +254,137 @@ pub(crate) fn read_bytes<T: Read, M: MemorySize>(
     // N times in the loop.
     let mut raw_bytes: Vec<u8> = vec![0; 1024];
 
-    for iov in iovs_arr.iter() {
-        let iov_inner = iov.read().map_err(mem_error_to_wasi)?;
+    // the iovecs are read upfront, the view of `iovs_arr` no longer holds
+    // once a read blocked and the guest grew its memory meanwhile
+    let iovs = iovs_arr.read_to_vec().map_err(mem_error_to_wasi)?;
+    for iov_inner in iovs {
+        if iov_inner.buf_len == M::ZERO {
+            // there is nothing to read into, the buffer is only checked
+            WasmPtr::<u8, M>::new(iov_inner.buf)
+                .slice(ctx, memory, iov_inner.buf_len)
+                .map_err(mem_error_to_wasi)?;
+            continue;
+        }
+        let buf_len = from_offset::<M>(iov_inner.buf_len)?.min(max_bytes - bytes_read);
         raw_bytes.clear();
-        raw_bytes.resize(from_offset::<M>(iov_inner.buf_len)?, 0);
-        bytes_read += reader.read(&mut raw_bytes).map_err(map_io_err)?;
+        raw_bytes.resize(buf_len, 0);
+        let read = reader.read(&mut raw_bytes).map_err(map_io_err)?;
+        bytes_read += read;
 
         let buf = WasmPtr::<u8, M>::new(iov_inner.buf)
-            .slice(ctx, memory, iov_inner.buf_len)
+            .slice(ctx, memory, to_offset::<M>(read)?)
+            .map_err(mem_error_to_wasi)?;
+        buf.write_slice(&raw_bytes[..read])
             .map_err(mem_error_to_wasi)?;
-        buf.write_slice(&raw_bytes).map_err(mem_error_to_wasi)?;
+        if read < buf_len || bytes_read == max_bytes {
+            break;
+        }
     }
     Ok(bytes_read)
 }
 
+/// Maps the errno of an I/O operation on an open file handle.
+///
+/// A handle whose backing file was removed from the host keeps working on
+/// Unix, but some hosts (Windows) may report the file as missing; as the
+/// fd is still open this is reported as `__WASI_EBADF` rather than
+/// `__WASI_ENOENT`, which is reserved for path lookups.
+fn open_file_errno(errno: __wasi_errno_t) -> __wasi_errno_t {
+    match errno {
+        __WASI_ENOENT => __WASI_EBADF,
+        errno => errno,
+    }
+}
+
+/// Checks whether reading from `file` would block, for fds in non-blocking
+/// mode: streams that report no bytes available would block, files that
+/// don't know (e.g. host files, which are always ready) would not.
+/// Seekable files never block, having no bytes left means they're at EOF
+fn read_would_block(file: &(dyn VirtualFile + Send + Sync + 'static)) -> bool {
+    !file.is_seekable() && matches!(file.bytes_available_read(), Ok(Some(0)))
+}
+
+/// Checks whether writing to `file` would block, like [`read_would_block`]
+fn write_would_block(file: &(dyn VirtualFile + Send + Sync + 'static)) -> bool {
+    matches!(file.bytes_available_write(), Ok(Some(0)))
+}
+
+/// Syncs `file` after a write through an fd with `__WASI_FDFLAG_SYNC` or
+/// `__WASI_FDFLAG_DSYNC` set in `flags`
+fn sync_after_write(
+    file: &(dyn VirtualFile + Send + Sync + 'static),
+    flags: __wasi_fdflags_t,
+) -> Result<(), __wasi_errno_t> {
+    if flags & __WASI_FDFLAG_SYNC != 0 {
+        file.sync_to_disk().map_err(fs_error_into_wasi_err)
+    } else if flags & __WASI_FDFLAG_DSYNC != 0 {
+        file.sync_data_to_disk().map_err(fs_error_into_wasi_err)
+    } else {
+        Ok(())
+    }
+}
+
 /// checks that `rights_check_set` is a subset of `rights_set`
 fn has_rights(rights_set: __wasi_rights_t, rights_check_set: __wasi_rights_t) -> bool {
     rights_set | rights_check_set == rights_set
 }
 
+/// Values that a syscall can hand back to the guest, used to extract the
+/// errno for the syscall statistics
+pub(crate) trait SyscallReturn {
+    /// The errno returned to the guest, `None` if the syscall trapped
+    fn errno(&self) -> Option<__wasi_errno_t>;
+
+    /// The errno returned to the guest or the trap raised by the syscall
+    fn into_result(self) -> Result<__wasi_errno_t, WasiError>;
+}
+
+impl SyscallReturn for __wasi_errno_t {
+    fn errno(&self) -> Option<__wasi_errno_t> {
+        Some(*self)
+    }
+
+    fn into_result(self) -> Result<__wasi_errno_t, WasiError> {
+        Ok(self)
+    }
+}
+
+impl SyscallReturn for Result<__wasi_errno_t, WasiError> {
+    fn errno(&self) -> Option<__wasi_errno_t> {
+        self.as_ref().ok().copied()
+    }
+
+    fn into_result(self) -> Result<__wasi_errno_t, WasiError> {
+        self
+    }
+}
+
+/// An argument of a syscall, as reported to the syscall tracer
+pub(crate) trait SyscallArg {
+    /// The value of the argument widened to 64 bits
+    fn raw(&self) -> u64;
+}
+
+macro_rules! impl_syscall_arg {
+    ($($ty:ty),*) => {
+        $(
+            impl SyscallArg for $ty {
+                fn raw(&self) -> u64 {
+                    *self as u64
+                }
+            }
+        )*
+    };
+}
+
+impl_syscall_arg!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl<T: ValueType, M: MemorySize> SyscallArg for WasmPtr<T, M> {
+    fn raw(&self) -> u64 {
+        self.offset().into()
+    }
+}
+
 fn __sock_actor<T, F>(
     ctx: &FunctionEnvMut<'_, WasiEnv>,
     sock: __wasi_fd_t,
@@ -253,8 +498,6 @@ fn write_buffer_array<M: MemorySize>(
     ptr_buffer: WasmPtr<WasmPtr<u8, M>, M>,
     buffer: WasmPtr<u8, M>,
 ) -> __wasi_errno_t {
-    let env = ctx.data();
-    let memory = env.memory();
     let ptrs = wasi_try_mem!(ptr_buffer.slice(ctx, memory, wasi_try!(to_offset::<M>(from.len()))));
 
     let mut current_buffer_offset = 0usize;
@@ -279,12 +522,10 @@ fn write_buffer_array<M: MemorySize>(
     __WASI_ESUCCESS
 }
 
-fn get_current_time_in_nanos() -> Result<__wasi_timestamp_t, __wasi_errno_t> {
-    let now = std::time::SystemTime::now();
-    let duration = now
-        .duration_since(std::time::SystemTime::UNIX_EPOCH)
-        .map_err(|_| __WASI_EIO)?;
-    Ok(duration.as_nanos() as __wasi_timestamp_t)
+/// Number of bytes `write_buffer_array` writes for `from`: every entry is
+/// followed by a NUL terminator
+fn buffer_array_size(from: &[Vec<u8>]) -> usize {
+    from.iter().map(|v| v.len() + 1).sum()
 }
 
 /// ### `args_get()`
@@ -341,7 +582,7 @@ pub fn args_sizes_get<M: MemorySize>(
     let argv_buf_size = argv_buf_size.deref(&ctx, memory);
 
     let argc_val: M::Offset = wasi_try!(state.args.len().try_into().map_err(|_| __WASI_EOVERFLOW));
-    let argv_buf_size_val: usize = state.args.iter().map(|v| v.len() + 1).sum();
+    let argv_buf_size_val = buffer_array_size(&state.args);
     let argv_buf_size_val: M::Offset =
         wasi_try!(argv_buf_size_val.try_into().map_err(|_| __WASI_EOVERFLOW));
     wasi_try_mem!(argc.write(argc_val));
@@ -360,6 +601,11 @@ pub fn args_sizes_get<M: MemorySize>(
 /// Output:
 /// - `__wasi_timestamp_t *resolution`
 ///     The resolution of the clock in nanoseconds
+/// Errors:
+/// - `__WASI_EINVAL`
+///     `clock_id` is not a known clock
+/// - `__WASI_ENOTSUP`
+///     The host has no CPU-time clock
 pub fn clock_res_get<M: MemorySize>(
     mut ctx: FunctionEnvMut<'_, WasiEnv>,
     clock_id: __wasi_clockid_t,
@@ -369,9 +615,8 @@ pub fn clock_res_get<M: MemorySize>(
     let env = ctx.data();
     let memory = env.memory();
 
-    let out_addr = resolution.deref(&ctx, memory);
-    let t_out = wasi_try!(platform_clock_res_get(clock_id, out_addr));
-    wasi_try_mem!(resolution.write(&ctx, memory, t_out as __wasi_timestamp_t));
+    let t_out = wasi_try!(env.state.clock.resolution(clock_id));
+    wasi_try_mem!(resolution.write(&ctx, memory, t_out));
     __WASI_ESUCCESS
 }
 
@@ -398,8 +643,8 @@ pub fn clock_time_get<M: MemorySize>(
     let env = ctx.data();
     let memory = env.memory();
 
-    let t_out = wasi_try!(platform_clock_time_get(clock_id, precision));
-    wasi_try_mem!(time.write(&ctx, memory, t_out as __wasi_timestamp_t));
+    let t_out = wasi_try!(env.clock_time_get(clock_id));
+    wasi_try_mem!(time.write(&ctx, memory, t_out));
 
     let result = __WASI_ESUCCESS;
     trace!(
@@ -455,7 +700,7 @@ pub fn environ_sizes_get<M: MemorySize>(
 
     let env_var_count: M::Offset =
         wasi_try!(state.envs.len().try_into().map_err(|_| __WASI_EOVERFLOW));
-    let env_buf_size: usize = state.envs.iter().map(|v| v.len() + 1).sum();
+    let env_buf_size = buffer_array_size(&state.envs);
     let env_buf_size: M::Offset = wasi_try!(env_buf_size.try_into().map_err(|_| __WASI_EOVERFLOW));
     wasi_try_mem!(environ_count.write(env_var_count));
     wasi_try_mem!(environ_buf_size.write(env_buf_size));
@@ -503,6 +748,13 @@ pub fn fd_advise(
 ///     The offset from the start marking the beginning of the allocation
 /// - `__wasi_filesize_t len`
 ///     The length from the offset marking the end of the allocation
+///
+/// A file shorter than `offset + len` is extended with zeros, a longer one
+/// is left untouched.
+/// Errors:
+/// - `__WASI_ENOSPC`
+///     The file can't grow that much, e.g. when it is capped and its
+///     `set_len` fails with `FsError::WriteZero`
 pub fn fd_allocate(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -519,12 +771,17 @@ pub fn fd_allocate(
         return __WASI_EACCES;
     }
     let new_size = wasi_try!(offset.checked_add(len).ok_or(__WASI_EINVAL));
-    {
+    let new_size = {
         let mut guard = inodes.arena[inode].write();
         match guard.deref_mut() {
             Kind::File { handle, .. } => {
                 if let Some(handle) = handle {
+                    let current_size = handle.size();
+                    if new_size <= current_size {
+                        return __WASI_ESUCCESS;
+                    }
                     wasi_try!(handle.set_len(new_size).map_err(fs_error_into_wasi_err));
+                    new_size
                 } else {
                     return __WASI_EBADF;
                 }
@@ -532,13 +789,17 @@ pub fn fd_allocate(
             Kind::Socket { .. } => return __WASI_EBADF,
             Kind::Pipe { .. } => return __WASI_EBADF,
             Kind::Buffer { buffer } => {
-                buffer.resize(new_size as usize, 0);
+                let new_size: usize = wasi_try!(new_size.try_into().map_err(|_| __WASI_ENOSPC));
+                if new_size > buffer.len() {
+                    buffer.resize(new_size, 0);
+                }
+                buffer.len() as __wasi_filesize_t
             }
             Kind::Symlink { .. } => return __WASI_EBADF,
             Kind::EventNotifications { .. } => return __WASI_EBADF,
             Kind::Dir { .. } | Kind::Root { .. } => return __WASI_EISDIR,
         }
-    }
+    };
     inodes.arena[inode].stat.write().unwrap().st_size = new_size;
     debug!("New file size: {}", new_size);
 
@@ -581,11 +842,17 @@ pub fn fd_datasync(ctx: FunctionEnvMut<'_, WasiEnv>, fd: __wasi_fd_t) -> __wasi_
         return __WASI_EACCES;
     }
 
-    if let Err(e) = state.fs.flush(inodes.deref(), fd) {
-        e
-    } else {
-        __WASI_ESUCCESS
+    wasi_try!(state.fs.flush(inodes.deref(), fd));
+    let guard = inodes.arena[fd_entry.inode].read();
+    if let Kind::File {
+        handle: Some(handle),
+        ..
+    } = guard.deref()
+    {
+        wasi_try!(handle.sync_data_to_disk().map_err(fs_error_into_wasi_err));
     }
+
+    __WASI_ESUCCESS
 }
 
 /// ### `fd_fdstat_get()`
@@ -623,7 +890,11 @@ pub fn fd_fdstat_get<M: MemorySize>(
 /// - `__wasi_fd_t fd`
 ///     The file descriptor to apply the new flags to
 /// - `__wasi_fdflags_t flags`
-///     The flags to apply to `fd`
+///     The flags to apply to `fd`, they replace the current flags
+/// Errors:
+/// - `__WASI_EINVAL`
+///     `flags` contains an unknown flag, or `__WASI_FDFLAG_APPEND` while
+///     `fd` can't be written to
 pub fn fd_fdstat_set_flags(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -638,6 +909,17 @@ pub fn fd_fdstat_set_flags(
     if !has_rights(fd_entry.rights, __WASI_RIGHT_FD_FDSTAT_SET_FLAGS) {
         return __WASI_EACCES;
     }
+    const KNOWN_FLAGS: __wasi_fdflags_t = __WASI_FDFLAG_APPEND
+        | __WASI_FDFLAG_DSYNC
+        | __WASI_FDFLAG_NONBLOCK
+        | __WASI_FDFLAG_RSYNC
+        | __WASI_FDFLAG_SYNC;
+    if flags & !KNOWN_FLAGS != 0 {
+        return __WASI_EINVAL;
+    }
+    if flags & __WASI_FDFLAG_APPEND != 0 && !has_rights(fd_entry.rights, __WASI_RIGHT_FD_WRITE) {
+        return __WASI_EINVAL;
+    }
 
     fd_entry.flags = flags;
     __WASI_ESUCCESS
@@ -652,6 +934,9 @@ pub fn fd_fdstat_set_flags(
 ///     The rights to apply to `fd`
 /// - `__wasi_rights_t fs_rights_inheriting`
 ///     The inheriting rights to apply to `fd`
+/// Errors:
+/// - `__WASI_ENOTCAPABLE`
+///     The new rights contain a right `fd` does not currently have
 pub fn fd_fdstat_set_rights(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -698,7 +983,16 @@ pub fn fd_filestat_get<M: MemorySize>(
         return __WASI_EACCES;
     }
 
-    let stat = wasi_try!(state.fs.filestat_fd(inodes.deref(), fd));
+    let mut stat = wasi_try!(state.fs.filestat_fd(inodes.deref(), fd));
+    // the size is read from the file so that writes made through other
+    // handles are visible
+    if let Kind::File {
+        handle: Some(handle),
+        ..
+    } = inodes.arena[fd_entry.inode].read().deref()
+    {
+        stat.st_size = wasi_try!(handle.current_len().map_err(fs_error_into_wasi_err));
+    }
 
     let buf = buf.deref(&ctx, memory);
     wasi_try_mem!(buf.write(stat));
@@ -792,8 +1086,17 @@ pub fn fd_filestat_set_times(
         let time_to_set = if fst_flags & __WASI_FILESTAT_SET_ATIM != 0 {
             st_atim
         } else {
-            wasi_try!(get_current_time_in_nanos())
+            wasi_try!(env.clock_time_get(__WASI_CLOCK_REALTIME))
         };
+        if let Kind::File {
+            handle: Some(handle),
+            ..
+        } = inode.write().deref_mut()
+        {
+            wasi_try!(handle
+                .set_last_accessed(time_to_set)
+                .map_err(fs_error_into_wasi_err));
+        }
         inode.stat.write().unwrap().st_atim = time_to_set;
     }
 
@@ -801,8 +1104,17 @@ pub fn fd_filestat_set_times(
         let time_to_set = if fst_flags & __WASI_FILESTAT_SET_MTIM != 0 {
             st_mtim
         } else {
-            wasi_try!(get_current_time_in_nanos())
+            wasi_try!(env.clock_time_get(__WASI_CLOCK_REALTIME))
         };
+        if let Kind::File {
+            handle: Some(handle),
+            ..
+        } = inode.write().deref_mut()
+        {
+            wasi_try!(handle
+                .set_last_modified(time_to_set)
+                .map_err(fs_error_into_wasi_err));
+        }
         inode.stat.write().unwrap().st_mtim = time_to_set;
     }
 
@@ -811,7 +1123,8 @@ pub fn fd_filestat_set_times(
 
 /// ### `fd_pread()`
 /// Read from the file at the given offset without updating the file cursor.
-/// This acts like a stateless version of Seek + Read
+/// This acts like a stateless version of Seek + Read, reading at or past the
+/// end of the file reads 0 bytes
 /// Inputs:
 /// - `__wasi_fd_t fd`
 ///     The file descriptor to read the data with
@@ -824,6 +1137,12 @@ pub fn fd_filestat_set_times(
 /// Output:
 /// - `size_t nread`
 ///     The number of bytes read
+/// Errors:
+/// - `__WASI_EISDIR`
+///     The file descriptor is a directory
+/// - `__WASI_ESPIPE`
+///     The file descriptor is a pipe, a socket or another stream that can't
+///     seek
 pub fn fd_pread<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -840,6 +1159,14 @@ pub fn fd_pread<M: MemorySize>(
     let nread_ref = nread.deref(&ctx, memory);
 
     let fd_entry = wasi_try_ok!(state.fs.get_fd(fd));
+    // directories are never opened with the read right, check them first so
+    // that reading one fails the same way whatever its rights
+    if matches!(
+        inodes.arena[fd_entry.inode].read().deref(),
+        Kind::Dir { .. } | Kind::Root { .. }
+    ) {
+        return Ok(__WASI_EISDIR);
+    }
     let bytes_read = match fd {
         __WASI_STDIN_FILENO => {
             let mut guard = wasi_try_ok!(
@@ -849,6 +1176,9 @@ pub fn fd_pread<M: MemorySize>(
                 env
             );
             if let Some(ref mut stdin) = guard.deref_mut() {
+                if !stdin.is_seekable() {
+                    return Ok(__WASI_ESPIPE);
+                }
                 wasi_try_ok!(read_bytes(&ctx, stdin, memory, iovs), env)
             } else {
                 return Ok(__WASI_EBADF);
@@ -872,30 +1202,33 @@ pub fn fd_pread<M: MemorySize>(
             match guard.deref_mut() {
                 Kind::File { handle, .. } => {
                     if let Some(h) = handle {
+                        if !h.is_seekable() {
+                            return Ok(__WASI_ESPIPE);
+                        }
                         wasi_try_ok!(
                             h.seek(std::io::SeekFrom::Start(offset as u64))
-                                .map_err(map_io_err),
+                                .map_err(map_io_err)
+                                .map_err(open_file_errno),
                             env
                         );
-                        wasi_try_ok!(read_bytes(&ctx, h, memory, iovs), env)
+                        // Host-backed files are read with a single host syscall
+                        let result = if h.get_fd().is_some() {
+                            read_bytes_bulk(&ctx, h, memory, iovs, usize::MAX)
+                        } else {
+                            read_bytes(&ctx, h, memory, iovs)
+                        };
+                        wasi_try_ok!(result.map_err(open_file_errno), env)
                     } else {
                         return Ok(__WASI_EINVAL);
                     }
                 }
-                Kind::Socket { socket } => {
-                    wasi_try_ok!(socket.recv(&ctx, memory, iovs), env)
-                }
-                Kind::Pipe { pipe } => {
-                    wasi_try_ok!(pipe.recv(&ctx, memory, iovs), env)
-                }
+                Kind::Socket { .. } | Kind::Pipe { .. } => return Ok(__WASI_ESPIPE),
                 Kind::EventNotifications { .. } => return Ok(__WASI_EINVAL),
                 Kind::Dir { .. } | Kind::Root { .. } => return Ok(__WASI_EISDIR),
                 Kind::Symlink { .. } => unimplemented!("Symlinks in wasi::fd_pread"),
                 Kind::Buffer { buffer } => {
-                    wasi_try_ok!(
-                        read_bytes(&ctx, &buffer[(offset as usize)..], memory, iovs),
-                        env
-                    )
+                    let data = buffer.get((offset as usize)..).unwrap_or_default();
+                    wasi_try_ok!(read_bytes(&ctx, data, memory, iovs), env)
                 }
             }
         }
@@ -914,7 +1247,8 @@ pub fn fd_pread<M: MemorySize>(
 ///     The preopened file descriptor to query
 /// Output:
 /// - `__wasi_prestat *buf`
-///     Where the metadata will be written
+///     Where the metadata will be written, `pr_name_len` is the length of
+///     the name in bytes, without a NUL terminator
 pub fn fd_prestat_get<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -930,6 +1264,19 @@ pub fn fd_prestat_get<M: MemorySize>(
     __WASI_ESUCCESS
 }
 
+/// ### `fd_prestat_dir_name()`
+/// Get the name of a preopened directory
+/// Inputs:
+/// - `__wasi_fd_t fd`
+///     The preopened file descriptor to query
+/// - `u8 *path`
+///     Where the name will be written, exactly `pr_name_len` bytes (as
+///     reported by `fd_prestat_get`) without a NUL terminator
+/// - `u32 path_len`
+///     Length of the buffer at `path`
+/// Errors:
+/// - `__WASI_ENAMETOOLONG`
+///     The buffer is smaller than the name
 pub fn fd_prestat_dir_name<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -954,20 +1301,18 @@ pub fn fd_prestat_dir_name<M: MemorySize>(
     let guard = inode_val.read();
     match guard.deref() {
         Kind::Dir { .. } | Kind::Root { .. } => {
-            // TODO: verify this: null termination, etc
             let path_len: u64 = path_len.into();
-            if (inode_val.name.len() as u64) < path_len {
-                wasi_try_mem!(path_chars
-                    .subslice(0..inode_val.name.len() as u64)
-                    .write_slice(inode_val.name.as_bytes()));
-                wasi_try_mem!(path_chars.index(inode_val.name.len() as u64).write(0));
+            let name_len = inode_val.name.len() as u64;
+            if name_len > path_len {
+                return __WASI_ENAMETOOLONG;
+            }
+            wasi_try_mem!(path_chars
+                .subslice(0..name_len)
+                .write_slice(inode_val.name.as_bytes()));
 
-                trace!("=> result: \"{}\"", inode_val.name);
+            trace!("=> result: \"{}\"", inode_val.name);
 
-                __WASI_ESUCCESS
-            } else {
-                __WASI_EOVERFLOW
-            }
+            __WASI_ESUCCESS
         }
         Kind::Symlink { .. }
         | Kind::Buffer { .. }
@@ -979,7 +1324,8 @@ pub fn fd_prestat_dir_name<M: MemorySize>(
 }
 
 /// ### `fd_pwrite()`
-/// Write to a file without adjusting its offset
+/// Write to a file without adjusting its offset, writing past the end of the
+/// file fills the gap with zeros
 /// Inputs:
 /// - `__wasi_fd_t`
 ///     File descriptor (opened with writing) to write to
@@ -992,6 +1338,10 @@ pub fn fd_prestat_dir_name<M: MemorySize>(
 /// Output:
 /// - `u32 *nwritten`
 ///     Number of bytes written
+/// Errors:
+/// - `__WASI_ESPIPE`
+///     The file descriptor is a pipe, a socket or another stream that can't
+///     seek
 pub fn fd_pwrite<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -1008,6 +1358,9 @@ pub fn fd_pwrite<M: MemorySize>(
     let nwritten_ref = nwritten.deref(&ctx, memory);
 
     let fd_entry = wasi_try_ok!(state.fs.get_fd(fd));
+    if fd != __WASI_STDIN_FILENO && !has_rights(fd_entry.rights, __WASI_RIGHT_FD_WRITE) {
+        return Ok(__WASI_EPERM);
+    }
     let bytes_written = match fd {
         __WASI_STDIN_FILENO => return Ok(__WASI_EINVAL),
         __WASI_STDOUT_FILENO => {
@@ -1018,7 +1371,12 @@ pub fn fd_pwrite<M: MemorySize>(
                 env
             );
             if let Some(ref mut stdout) = guard.deref_mut() {
-                wasi_try_ok!(write_bytes(&ctx, stdout, memory, iovs_arr), env)
+                if !stdout.is_seekable() {
+                    return Ok(__WASI_ESPIPE);
+                }
+                let written = wasi_try_ok!(write_bytes(&ctx, &mut **stdout, memory, iovs_arr), env);
+                wasi_try_ok!(sync_after_write(stdout.as_ref(), fd_entry.flags), env);
+                written
             } else {
                 return Ok(__WASI_EBADF);
             }
@@ -1031,7 +1389,12 @@ pub fn fd_pwrite<M: MemorySize>(
                 env
             );
             if let Some(ref mut stderr) = guard.deref_mut() {
-                wasi_try_ok!(write_bytes(&ctx, stderr, memory, iovs_arr), env)
+                if !stderr.is_seekable() {
+                    return Ok(__WASI_ESPIPE);
+                }
+                let written = wasi_try_ok!(write_bytes(&ctx, &mut **stderr, memory, iovs_arr), env);
+                wasi_try_ok!(sync_after_write(stderr.as_ref(), fd_entry.flags), env);
+                written
             } else {
                 return Ok(__WASI_EBADF);
             }
@@ -1050,23 +1413,41 @@ pub fn fd_pwrite<M: MemorySize>(
             match guard.deref_mut() {
                 Kind::File { handle, .. } => {
                     if let Some(handle) = handle {
+                        if !handle.is_seekable() {
+                            return Ok(__WASI_ESPIPE);
+                        }
+                        // Writing past the end leaves a gap of zeros, it is
+                        // allocated upfront as in-memory files can not seek
+                        // past their end
+                        let len =
+                            wasi_try_ok!(handle.current_len().map_err(fs_error_into_wasi_err), env);
+                        if offset > len {
+                            wasi_try_ok!(
+                                handle.set_len(offset).map_err(fs_error_into_wasi_err),
+                                env
+                            );
+                        }
                         wasi_try_ok!(
                             handle
                                 .seek(std::io::SeekFrom::Start(offset as u64))
-                                .map_err(map_io_err),
+                                .map_err(map_io_err)
+                                .map_err(open_file_errno),
                             env
                         );
-                        wasi_try_ok!(write_bytes(&ctx, handle, memory, iovs_arr), env)
+                        // Host-backed files are written with a single host syscall
+                        let result = if handle.get_fd().is_some() {
+                            write_bytes_bulk(&ctx, &mut **handle, memory, iovs_arr)
+                        } else {
+                            write_bytes(&ctx, &mut **handle, memory, iovs_arr)
+                        };
+                        let written = wasi_try_ok!(result.map_err(open_file_errno), env);
+                        wasi_try_ok!(sync_after_write(handle.as_ref(), fd_entry.flags), env);
+                        written
                     } else {
                         return Ok(__WASI_EINVAL);
                     }
                 }
-                Kind::Socket { socket } => {
-                    wasi_try_ok!(socket.send(&ctx, memory, iovs_arr), env)
-                }
-                Kind::Pipe { pipe } => {
-                    wasi_try_ok!(pipe.send(&ctx, memory, iovs_arr), env)
-                }
+                Kind::Socket { .. } | Kind::Pipe { .. } => return Ok(__WASI_ESPIPE),
                 Kind::Dir { .. } | Kind::Root { .. } => {
                     // TODO: verify
                     return Ok(__WASI_EISDIR);
@@ -1074,6 +1455,9 @@ pub fn fd_pwrite<M: MemorySize>(
                 Kind::EventNotifications { .. } => return Ok(__WASI_EINVAL),
                 Kind::Symlink { .. } => unimplemented!("Symlinks in wasi::fd_pwrite"),
                 Kind::Buffer { buffer } => {
+                    if offset as usize > buffer.len() {
+                        buffer.resize(offset as usize, 0);
+                    }
                     wasi_try_ok!(
                         write_bytes(&ctx, &mut buffer[(offset as usize)..], memory, iovs_arr),
                         env
@@ -1102,6 +1486,16 @@ pub fn fd_pwrite<M: MemorySize>(
 /// Output:
 /// - `u32 *nread`
 ///     Number of bytes read
+/// Errors:
+/// - `__WASI_EBADF`
+///     The fd is not open, or the host reports that its backing file was
+///     removed (on Unix a removed file can still be read until the fd is
+///     closed)
+/// - `__WASI_EAGAIN`
+///     The fd is in non-blocking mode (`__WASI_FDFLAG_NONBLOCK`) and there
+///     is nothing to read yet
+/// - `__WASI_EISDIR`
+///     The fd is a directory
 ///
 pub fn fd_read<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
@@ -1115,9 +1509,34 @@ pub fn fd_read<M: MemorySize>(
     let (memory, mut state, inodes) = env.get_memory_and_wasi_state_and_inodes(0);
 
     let iovs_arr = wasi_try_mem_ok!(iovs.slice(&ctx, memory, iovs_len));
-    let nread_ref = nread.deref(&ctx, memory);
 
     let fd_entry = wasi_try_ok!(state.fs.get_fd(fd));
+    // directories are never opened with the read right, check them first so
+    // that reading one fails the same way whatever its rights
+    if matches!(
+        inodes.arena[fd_entry.inode].read().deref(),
+        Kind::Dir { .. } | Kind::Root { .. }
+    ) {
+        return Ok(__WASI_EISDIR);
+    }
+
+    // Reading into empty buffers only checks them, the file is not touched
+    let iov_list = match iovs_arr.read_to_vec().map_err(mem_error_to_wasi) {
+        Ok(iov_list) => iov_list
+            .into_iter()
+            .map(|iov| (iov.buf, iov.buf_len))
+            .collect::<Vec<_>>(),
+        Err(err) => return Ok(err),
+    };
+    let (_, total_len) = wasi_try_ok!(iovec_slices::<M>(&ctx, memory, &iov_list));
+    if total_len == 0 && has_rights(fd_entry.rights, __WASI_RIGHT_FD_READ) {
+        let zero = wasi_try_ok!(to_offset::<M>(0));
+        wasi_try_mem_ok!(nread.write(&ctx, memory, zero));
+        return Ok(__WASI_ESUCCESS);
+    }
+
+    let max_read_size = state.max_read_size.unwrap_or(usize::MAX);
+    let is_non_blocking = state.nonblocking || fd_entry.flags & __WASI_FDFLAG_NONBLOCK != 0;
     let bytes_read = match fd {
         __WASI_STDIN_FILENO => {
             let mut guard = wasi_try_ok!(
@@ -1127,7 +1546,13 @@ pub fn fd_read<M: MemorySize>(
                 env
             );
             if let Some(ref mut stdin) = guard.deref_mut() {
-                wasi_try_ok!(read_bytes(&ctx, stdin, memory, iovs_arr), env)
+                if is_non_blocking && read_would_block(stdin.as_ref()) {
+                    return Ok(__WASI_EAGAIN);
+                }
+                wasi_try_ok!(
+                    read_bytes_limited(&ctx, stdin, memory, iovs_arr, max_read_size),
+                    env
+                )
             } else {
                 return Ok(__WASI_EBADF);
             }
@@ -1139,7 +1564,6 @@ pub fn fd_read<M: MemorySize>(
                 return Ok(__WASI_EACCES);
             }
 
-            let is_non_blocking = fd_entry.flags & __WASI_FDFLAG_NONBLOCK != 0;
             let offset = fd_entry.offset as usize;
             let inode_idx = fd_entry.inode;
             let inode = &inodes.arena[inode_idx];
@@ -1149,27 +1573,44 @@ pub fn fd_read<M: MemorySize>(
                 match guard.deref_mut() {
                     Kind::File { handle, .. } => {
                         if let Some(handle) = handle {
+                            if is_non_blocking && read_would_block(handle.as_ref()) {
+                                return Ok(__WASI_EAGAIN);
+                            }
                             wasi_try_ok!(
                                 handle
                                     .seek(std::io::SeekFrom::Start(offset as u64))
-                                    .map_err(map_io_err),
+                                    .map_err(map_io_err)
+                                    .map_err(open_file_errno),
                                 env
                             );
-                            wasi_try_ok!(read_bytes(&ctx, handle, memory, iovs_arr), env)
+                            // Host-backed files are read with a single host syscall
+                            let result = if handle.get_fd().is_some() {
+                                read_bytes_bulk(&ctx, handle, memory, iovs_arr, max_read_size)
+                            } else {
+                                read_bytes_limited(&ctx, handle, memory, iovs_arr, max_read_size)
+                            };
+                            wasi_try_ok!(result.map_err(open_file_errno), env)
                         } else {
                             return Ok(__WASI_EINVAL);
                         }
                     }
                     Kind::Socket { socket } => {
-                        wasi_try_ok!(socket.recv(&ctx, memory, iovs_arr), env)
+                        wasi_try_ok!(socket.set_nonblocking(is_non_blocking), env);
+                        wasi_try_ok!(socket.recv(&ctx, iovs, iovs_len), env)
                     }
                     Kind::Pipe { pipe } => {
-                        wasi_try_ok!(pipe.recv(&ctx, memory, iovs_arr), env)
-                    }
-                    Kind::Dir { .. } | Kind::Root { .. } => {
-                        // TODO: verify
-                        return Ok(__WASI_EISDIR);
+                        if is_non_blocking {
+                            match pipe.bytes_available_read() {
+                                Some(0) => return Ok(__WASI_EAGAIN),
+                                // The other end is closed, which is EOF
+                                None => 0,
+                                Some(_) => wasi_try_ok!(pipe.recv(&ctx, iovs, iovs_len), env),
+                            }
+                        } else {
+                            wasi_try_ok!(pipe.recv(&ctx, iovs, iovs_len), env)
+                        }
                     }
+                    Kind::Dir { .. } | Kind::Root { .. } => return Ok(__WASI_EISDIR),
                     Kind::EventNotifications {
                         counter,
                         is_semaphore,
@@ -1201,6 +1642,11 @@ pub fn fd_read<M: MemorySize>(
                                     )
                                     .is_ok()
                                 {
+                                    // The memory may have grown while we were
+                                    // waiting, so the buffers are resolved again
+                                    let memory = env.memory();
+                                    let iovs_arr =
+                                        wasi_try_mem_ok!(iovs.slice(&ctx, memory, iovs_len));
                                     let reader = val.to_ne_bytes();
                                     ret = wasi_try_ok!(
                                         read_bytes(&ctx, &reader[..], memory, iovs_arr),
@@ -1227,7 +1673,16 @@ pub fn fd_read<M: MemorySize>(
                     }
                     Kind::Symlink { .. } => unimplemented!("Symlinks in wasi::fd_read"),
                     Kind::Buffer { buffer } => {
-                        wasi_try_ok!(read_bytes(&ctx, &buffer[offset..], memory, iovs_arr), env)
+                        wasi_try_ok!(
+                            read_bytes_limited(
+                                &ctx,
+                                &buffer[offset..],
+                                memory,
+                                iovs_arr,
+                                max_read_size
+                            ),
+                            env
+                        )
                     }
                 }
             };
@@ -1242,7 +1697,9 @@ pub fn fd_read<M: MemorySize>(
     };
 
     let bytes_read: M::Offset = wasi_try_ok!(bytes_read.try_into().map_err(|_| __WASI_EOVERFLOW));
-    wasi_try_mem_ok!(nread_ref.write(bytes_read));
+    // a blocking read may have let the guest grow its memory, so the result
+    // is written through a fresh view of it
+    wasi_try_mem_ok!(nread.write(&ctx, env.memory(), bytes_read));
 
     Ok(__WASI_ESUCCESS)
 }
@@ -1285,6 +1742,15 @@ pub fn fd_readdir<M: MemorySize>(
     let entries: Vec<(String, u8, u64)> = {
         let guard = inodes.arena[working_dir.inode].read();
         match guard.deref() {
+            Kind::Dir { .. } if state.is_fd_reflection_dir(inodes.deref(), working_dir.inode) => {
+                // the links are made up from the fds open right now
+                state
+                    .fs
+                    .open_preopens(inodes.deref())
+                    .into_iter()
+                    .map(|(fd, _)| (fd.to_string(), __WASI_FILETYPE_SYMBOLIC_LINK, 0))
+                    .collect()
+            }
             Kind::Dir { path, entries, .. } => {
                 debug!("Reading dir {:?}", path);
                 // TODO: refactor this code
@@ -1297,7 +1763,7 @@ pub fn fd_readdir<M: MemorySize>(
                 let mut entry_vec = wasi_try!(fs_info
                     .into_iter()
                     .map(|entry| {
-                        let filename = entry.file_name().to_string_lossy().to_string();
+                        let filename = platform_file_name_to_guest(&entry.file_name())?;
                         debug!("Getting file: {:?}", filename);
                         let filetype = virtual_file_type_to_wasi_file_type(
                             entry.file_type().map_err(fs_error_into_wasi_err)?,
@@ -1385,12 +1851,15 @@ pub fn fd_readdir<M: MemorySize>(
 }
 
 /// ### `fd_renumber()`
-/// Atomically copy file descriptor
+/// Atomically copy file descriptor, `from` is no longer valid afterwards
 /// Inputs:
 /// - `__wasi_fd_t from`
 ///     File descriptor to copy
 /// - `__wasi_fd_t to`
-///     Location to copy file descriptor to
+///     Location to copy file descriptor to, closed first if it is open
+/// Errors:
+/// - `__WASI_EBADF`
+///     `from` is not open
 pub fn fd_renumber(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     from: __wasi_fd_t,
@@ -1398,18 +1867,27 @@ pub fn fd_renumber(
 ) -> __wasi_errno_t {
     debug!("wasi::fd_renumber: from={}, to={}", from, to);
     let env = ctx.data();
-    let (_, mut state) = env.get_memory_and_wasi_state(0);
-
-    let mut fd_map = state.fs.fd_map.write().unwrap();
-    let fd_entry = wasi_try!(fd_map.get_mut(&from).ok_or(__WASI_EBADF));
+    let (_, mut state, inodes) = env.get_memory_and_wasi_state_and_inodes(0);
 
-    let new_fd_entry = Fd {
-        // TODO: verify this is correct
-        rights: fd_entry.rights_inheriting,
-        ..*fd_entry
-    };
+    // The fd is moved as is: its rights, offset and flags are kept
+    let fd_entry = wasi_try!(state.fs.get_fd(from));
+    if from == to {
+        return __WASI_ESUCCESS;
+    }
+    // moving stdio or a preopened directory to a new fd opens one more fd
+    if !state.fs.is_guest_fd(from) && state.fs.is_guest_fd(to) && state.fs.get_fd(to).is_err() {
+        wasi_try!(state.check_open_fds());
+    }
+    // `to` still referring to the same inode means it is a duplicate of
+    // `from`, closing it would close `from` too
+    if let Ok(to_entry) = state.fs.get_fd(to) {
+        if to_entry.inode != fd_entry.inode {
+            wasi_try!(state.fs.close_fd(inodes.deref(), to));
+        }
+    }
 
-    fd_map.insert(to, new_fd_entry);
+    let mut fd_map = state.fs.fd_map.write().unwrap();
+    fd_map.insert(to, fd_entry);
     fd_map.remove(&from);
     __WASI_ESUCCESS
 }
@@ -1431,6 +1909,7 @@ pub fn fd_dup<M: MemorySize>(
 
     let env = ctx.data();
     let (memory, state) = env.get_memory_and_wasi_state(0);
+    wasi_try!(state.check_open_fds());
     let fd = wasi_try!(state.fs.clone_fd(fd));
 
     wasi_try_mem!(ret_fd.write(&ctx, memory, fd));
@@ -1482,7 +1961,11 @@ pub fn fd_event<M: MemorySize>(
 ///     What the offset is relative to
 /// Output:
 /// - `__wasi_filesize_t *fd`
-///     The new offset relative to the start of the file
+///     The new offset relative to the start of the file, it can be past the
+///     end of the file
+/// Errors:
+/// - `__WASI_EINVAL`
+///     The new offset would be before the start of the file
 pub fn fd_seek<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -1505,23 +1988,23 @@ pub fn fd_seek<M: MemorySize>(
         __WASI_WHENCE_CUR => {
             let mut fd_map = state.fs.fd_map.write().unwrap();
             let fd_entry = wasi_try_ok!(fd_map.get_mut(&fd).ok_or(__WASI_EBADF));
-            fd_entry.offset = (fd_entry.offset as i64 + offset) as u64
+            fd_entry.offset = wasi_try_ok!(seek_offset(fd_entry.offset, offset));
         }
         __WASI_WHENCE_END => {
-            use std::io::SeekFrom;
             let inode_idx = fd_entry.inode;
             let mut guard = inodes.arena[inode_idx].write();
             match guard.deref_mut() {
                 Kind::File { ref mut handle, .. } => {
                     if let Some(handle) = handle {
+                        // the length of the file now, which also counts the
+                        // writes made through other fds
                         let end =
-                            wasi_try_ok!(handle.seek(SeekFrom::End(0)).map_err(map_io_err), env);
+                            wasi_try_ok!(handle.current_len().map_err(fs_error_into_wasi_err), env);
 
-                        // TODO: handle case if fd_entry.offset uses 64 bits of a u64
                         drop(guard);
                         let mut fd_map = state.fs.fd_map.write().unwrap();
                         let fd_entry = wasi_try_ok!(fd_map.get_mut(&fd).ok_or(__WASI_EBADF));
-                        fd_entry.offset = (end as i64 + offset) as u64;
+                        fd_entry.offset = wasi_try_ok!(seek_offset(end, offset));
                     } else {
                         return Ok(__WASI_EINVAL);
                     }
@@ -1547,7 +2030,7 @@ pub fn fd_seek<M: MemorySize>(
         __WASI_WHENCE_SET => {
             let mut fd_map = state.fs.fd_map.write().unwrap();
             let fd_entry = wasi_try_ok!(fd_map.get_mut(&fd).ok_or(__WASI_EBADF));
-            fd_entry.offset = offset as u64
+            fd_entry.offset = wasi_try_ok!(seek_offset(0, offset));
         }
         _ => return Ok(__WASI_EINVAL),
     }
@@ -1558,6 +2041,17 @@ pub fn fd_seek<M: MemorySize>(
     Ok(__WASI_ESUCCESS)
 }
 
+/// Moves the offset `base` by `delta` for `fd_seek`, the offset can be past
+/// the end of the file but not before its start
+fn seek_offset(base: u64, delta: __wasi_filedelta_t) -> Result<u64, __wasi_errno_t> {
+    let offset = if delta < 0 {
+        base.checked_sub(delta.unsigned_abs())
+    } else {
+        base.checked_add(delta as u64)
+    };
+    offset.ok_or(__WASI_EINVAL)
+}
+
 /// ### `fd_sync()`
 /// Synchronize file and metadata to disk (TODO: expand upon what this means in our system)
 /// Inputs:
@@ -1643,6 +2137,11 @@ pub fn fd_tell<M: MemorySize>(
 /// - `u32 *nwritten`
 ///     Number of bytes written
 /// Errors:
+/// - `__WASI_EAGAIN`
+///     The fd is in non-blocking mode (`__WASI_FDFLAG_NONBLOCK`) and can't
+///     take more data yet
+/// - `__WASI_EPERM`
+///     The fd does not have the `__WASI_RIGHT_FD_WRITE` right
 ///
 pub fn fd_write<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
@@ -1658,6 +2157,10 @@ pub fn fd_write<M: MemorySize>(
     let nwritten_ref = nwritten.deref(&ctx, memory);
 
     let fd_entry = wasi_try_ok!(state.fs.get_fd(fd));
+    if fd != __WASI_STDIN_FILENO && !has_rights(fd_entry.rights, __WASI_RIGHT_FD_WRITE) {
+        return Ok(__WASI_EPERM);
+    }
+    let is_non_blocking = state.nonblocking || fd_entry.flags & __WASI_FDFLAG_NONBLOCK != 0;
     let bytes_written = match fd {
         __WASI_STDIN_FILENO => return Ok(__WASI_EINVAL),
         __WASI_STDOUT_FILENO => {
@@ -1668,7 +2171,12 @@ pub fn fd_write<M: MemorySize>(
                 env
             );
             if let Some(ref mut stdout) = guard.deref_mut() {
-                wasi_try_ok!(write_bytes(&ctx, stdout, memory, iovs_arr), env)
+                if is_non_blocking && write_would_block(stdout.as_ref()) {
+                    return Ok(__WASI_EAGAIN);
+                }
+                let written = wasi_try_ok!(write_bytes(&ctx, &mut **stdout, memory, iovs_arr), env);
+                wasi_try_ok!(sync_after_write(stdout.as_ref(), fd_entry.flags), env);
+                written
             } else {
                 return Ok(__WASI_EBADF);
             }
@@ -1681,41 +2189,79 @@ pub fn fd_write<M: MemorySize>(
                 env
             );
             if let Some(ref mut stderr) = guard.deref_mut() {
-                wasi_try_ok!(write_bytes(&ctx, stderr, memory, iovs_arr), env)
+                if is_non_blocking && write_would_block(stderr.as_ref()) {
+                    return Ok(__WASI_EAGAIN);
+                }
+                let written = wasi_try_ok!(write_bytes(&ctx, &mut **stderr, memory, iovs_arr), env);
+                wasi_try_ok!(sync_after_write(stderr.as_ref(), fd_entry.flags), env);
+                written
             } else {
                 return Ok(__WASI_EBADF);
             }
         }
         _ => {
-            if !has_rights(fd_entry.rights, __WASI_RIGHT_FD_WRITE) {
-                return Ok(__WASI_EACCES);
-            }
-
-            let offset = fd_entry.offset as usize;
+            let mut offset = fd_entry.offset as usize;
             let inode_idx = fd_entry.inode;
             let inode = &inodes.arena[inode_idx];
 
-            let bytes_written = {
+            let (bytes_written, can_update_cursor) = {
                 let mut guard = inode.write();
                 match guard.deref_mut() {
                     Kind::File { handle, .. } => {
                         if let Some(handle) = handle {
+                            if is_non_blocking && write_would_block(handle.as_ref()) {
+                                return Ok(__WASI_EAGAIN);
+                            }
+                            let len = wasi_try_ok!(
+                                handle.current_len().map_err(fs_error_into_wasi_err),
+                                env
+                            ) as usize;
+                            // in append mode every write goes to the end of
+                            // the file, and moves the cursor there
+                            if fd_entry.flags & __WASI_FDFLAG_APPEND != 0 {
+                                offset = len;
+                            }
+                            // the offset can be past the end after `fd_seek`,
+                            // the gap of zeros is allocated upfront as in
+                            // `fd_pwrite`
+                            if offset > len && handle.is_seekable() {
+                                wasi_try_ok!(
+                                    handle
+                                        .set_len(offset as u64)
+                                        .map_err(fs_error_into_wasi_err),
+                                    env
+                                );
+                            }
                             wasi_try_ok!(
                                 handle
                                     .seek(std::io::SeekFrom::Start(offset as u64))
-                                    .map_err(map_io_err),
+                                    .map_err(map_io_err)
+                                    .map_err(open_file_errno),
                                 env
                             );
-                            wasi_try_ok!(write_bytes(&ctx, handle, memory, iovs_arr), env)
+                            // Host-backed files are written with a single host syscall
+                            let result = if handle.get_fd().is_some() {
+                                write_bytes_bulk(&ctx, &mut **handle, memory, iovs_arr)
+                            } else {
+                                write_bytes(&ctx, &mut **handle, memory, iovs_arr)
+                            };
+                            let written = wasi_try_ok!(result.map_err(open_file_errno), env);
+                            wasi_try_ok!(sync_after_write(handle.as_ref(), fd_entry.flags), env);
+                            (written, true)
                         } else {
                             return Ok(__WASI_EINVAL);
                         }
                     }
                     Kind::Socket { socket } => {
-                        wasi_try_ok!(socket.send(&ctx, memory, iovs_arr), env)
+                        wasi_try_ok!(socket.set_nonblocking(is_non_blocking), env);
+                        (
+                            wasi_try_ok!(socket.send(&ctx, memory, iovs_arr), env),
+                            false,
+                        )
                     }
+                    // pipes are unbounded, sending never blocks
                     Kind::Pipe { pipe } => {
-                        wasi_try_ok!(pipe.send(&ctx, memory, iovs_arr), env)
+                        (wasi_try_ok!(pipe.send(&ctx, memory, iovs_arr), env), false)
                     }
                     Kind::Dir { .. } | Kind::Root { .. } => {
                         // TODO: verify
@@ -1742,25 +2288,29 @@ pub fn fd_write<M: MemorySize>(
                             }
                         }
 
-                        written
+                        (written, false)
                     }
                     Kind::Symlink { .. } => unimplemented!("Symlinks in wasi::fd_write"),
-                    Kind::Buffer { buffer } => {
+                    Kind::Buffer { buffer } => (
                         wasi_try_ok!(
                             write_bytes(&ctx, &mut buffer[offset..], memory, iovs_arr),
                             env
-                        )
-                    }
+                        ),
+                        true,
+                    ),
                 }
             };
 
-            // reborrow
-            {
-                let mut fd_map = state.fs.fd_map.write().unwrap();
-                let fd_entry = wasi_try_ok!(fd_map.get_mut(&fd).ok_or(__WASI_EBADF));
-                fd_entry.offset += bytes_written as u64;
+            // sockets and pipes are streams, they have no cursor or size
+            if can_update_cursor {
+                // reborrow
+                {
+                    let mut fd_map = state.fs.fd_map.write().unwrap();
+                    let fd_entry = wasi_try_ok!(fd_map.get_mut(&fd).ok_or(__WASI_EBADF));
+                    fd_entry.offset = (offset + bytes_written) as u64;
+                }
+                wasi_try_ok!(state.fs.filestat_resync_size(inodes.deref(), fd), env);
             }
-            wasi_try_ok!(state.fs.filestat_resync_size(inodes.deref(), fd), env);
 
             bytes_written
         }
@@ -1837,6 +2387,7 @@ pub fn path_create_directory<M: MemorySize>(
     debug!("wasi::path_create_directory");
     let env = ctx.data();
     let (memory, state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(path_len.into()));
 
     let working_dir = wasi_try!(state.fs.get_fd(fd));
     {
@@ -1849,6 +2400,7 @@ pub fn path_create_directory<M: MemorySize>(
         return __WASI_EACCES;
     }
     let path_string = unsafe { get_input_str!(&ctx, memory, path, path_len) };
+    let path_string = wasi_try!(env.map_path(path_string));
     debug!("=> fd: {}, path: {}", fd, &path_string);
 
     let path = std::path::PathBuf::from(&path_string);
@@ -1858,7 +2410,7 @@ pub fn path_create_directory<M: MemorySize>(
             comp.as_os_str()
                 .to_str()
                 .map(|inner_str| inner_str.to_string())
-                .ok_or(__WASI_EINVAL)
+                .ok_or(__WASI_EILSEQ)
         })
         .collect::<Result<Vec<String>, __wasi_errno_t>>());
     if path_vec.is_empty() {
@@ -1967,8 +2519,10 @@ pub fn path_filestat_get<M: MemorySize>(
     debug!("wasi::path_filestat_get (fd={})", fd);
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(path_len.into()));
 
     let path_string = unsafe { get_input_str!(&ctx, memory, path, path_len) };
+    let path_string = wasi_try!(env.map_path(path_string));
 
     let stat = wasi_try!(path_filestat_get_internal(
         memory,
@@ -2013,18 +2567,12 @@ pub fn path_filestat_get_internal(
     }
     debug!("=> base_fd: {}, path: {}", fd, path_string);
 
-    let file_inode = state.fs.get_inode_at_path(
+    state.fs.get_stat_at_path(
         inodes,
         fd,
         path_string,
         flags & __WASI_LOOKUP_SYMLINK_FOLLOW != 0,
-    )?;
-    if inodes.arena[file_inode].is_preopened {
-        Ok(*inodes.arena[file_inode].stat.read().unwrap().deref())
-    } else {
-        let guard = inodes.arena[file_inode].read();
-        state.fs.get_stat_for_kind(inodes.deref(), guard.deref())
-    }
+    )
 }
 
 /// ### `path_filestat_set_times()`
@@ -2044,6 +2592,11 @@ pub fn path_filestat_get_internal(
 ///     The timestamp that the last modified time attribute is set to
 /// - `__wasi_fstflags_t fst_flags`
 ///     A bitmask controlling which attributes are set
+/// Errors:
+/// - `__WASI_EINVAL`
+///     Both an explicit time and `NOW` were requested for the same attribute
+/// - `__WASI_ENOSYS`
+///     The file system backing the path can not set its times on this platform
 pub fn path_filestat_set_times<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -2057,8 +2610,8 @@ pub fn path_filestat_set_times<M: MemorySize>(
     debug!("wasi::path_filestat_set_times");
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(path_len.into()));
     let fd_entry = wasi_try!(state.fs.get_fd(fd));
-    let fd_inode = fd_entry.inode;
     if !has_rights(fd_entry.rights, __WASI_RIGHT_PATH_FILESTAT_SET_TIMES) {
         return __WASI_EACCES;
     }
@@ -2070,36 +2623,68 @@ pub fn path_filestat_set_times<M: MemorySize>(
     }
 
     let path_string = unsafe { get_input_str!(&ctx, memory, path, path_len) };
+    let path_string = wasi_try!(env.map_path(path_string));
     debug!("=> base_fd: {}, path: {}", fd, &path_string);
 
+    let follow_symlinks = flags & __WASI_LOOKUP_SYMLINK_FOLLOW != 0;
     let file_inode = wasi_try!(state.fs.get_inode_at_path(
         inodes.deref_mut(),
         fd,
         &path_string,
-        flags & __WASI_LOOKUP_SYMLINK_FOLLOW != 0,
+        follow_symlinks,
     ));
-    let stat = {
-        let guard = inodes.arena[file_inode].read();
-        wasi_try!(state.fs.get_stat_for_kind(inodes.deref(), guard.deref()))
+
+    let atime = if fst_flags & __WASI_FILESTAT_SET_ATIM != 0 {
+        Some(st_atim)
+    } else if fst_flags & __WASI_FILESTAT_SET_ATIM_NOW != 0 {
+        Some(wasi_try!(env.clock_time_get(__WASI_CLOCK_REALTIME)))
+    } else {
+        None
+    };
+    let mtime = if fst_flags & __WASI_FILESTAT_SET_MTIM != 0 {
+        Some(st_mtim)
+    } else if fst_flags & __WASI_FILESTAT_SET_MTIM_NOW != 0 {
+        Some(wasi_try!(env.clock_time_get(__WASI_CLOCK_REALTIME)))
+    } else {
+        None
     };
 
-    let inode = &inodes.arena[fd_inode];
+    let inode = &inodes.arena[file_inode];
+    let backing_path = match inode.read().deref() {
+        Kind::File { path, .. } | Kind::Dir { path, .. } if !path.as_os_str().is_empty() => {
+            Some(path.clone())
+        }
+        // Only reached when symlinks are not followed, the link itself is updated
+        Kind::Symlink {
+            base_po_dir,
+            path_to_symlink,
+            ..
+        } => {
+            let base_inode = wasi_try!(state.fs.get_fd_inode(*base_po_dir));
+            let guard = inodes.arena[base_inode].read();
+            match guard.deref() {
+                Kind::Dir { path, .. } => Some(path.join(path_to_symlink)),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    // Entries that live in the file system have their times set there, the
+    // others only exist in the inode table
+    if let Some(backing_path) = backing_path {
+        wasi_try!(state
+            .fs
+            .fs_backing
+            .set_times(&backing_path, atime, mtime, follow_symlinks)
+            .map_err(fs_error_into_wasi_err));
+    }
 
-    if fst_flags & __WASI_FILESTAT_SET_ATIM != 0 || fst_flags & __WASI_FILESTAT_SET_ATIM_NOW != 0 {
-        let time_to_set = if fst_flags & __WASI_FILESTAT_SET_ATIM != 0 {
-            st_atim
-        } else {
-            wasi_try!(get_current_time_in_nanos())
-        };
-        inode.stat.write().unwrap().st_atim = time_to_set;
+    let mut stat = inode.stat.write().unwrap();
+    if let Some(atime) = atime {
+        stat.st_atim = atime;
     }
-    if fst_flags & __WASI_FILESTAT_SET_MTIM != 0 || fst_flags & __WASI_FILESTAT_SET_MTIM_NOW != 0 {
-        let time_to_set = if fst_flags & __WASI_FILESTAT_SET_MTIM != 0 {
-            st_mtim
-        } else {
-            wasi_try!(get_current_time_in_nanos())
-        };
-        inode.stat.write().unwrap().st_mtim = time_to_set;
+    if let Some(mtime) = mtime {
+        stat.st_mtim = mtime;
     }
 
     __WASI_ESUCCESS
@@ -2138,8 +2723,12 @@ pub fn path_link<M: MemorySize>(
     }
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(old_path_len.into()));
+    wasi_try!(state.check_path_len(new_path_len.into()));
     let old_path_str = unsafe { get_input_str!(&ctx, memory, old_path, old_path_len) };
+    let old_path_str = wasi_try!(env.map_path(old_path_str));
     let new_path_str = unsafe { get_input_str!(&ctx, memory, new_path, new_path_len) };
+    let new_path_str = wasi_try!(env.map_path(new_path_str));
     let source_fd = wasi_try!(state.fs.get_fd(old_fd));
     let target_fd = wasi_try!(state.fs.get_fd(new_fd));
     debug!(
@@ -2217,7 +2806,7 @@ pub fn path_link<M: MemorySize>(
 /// - `__wasi_fd_t* fd`
 ///     The new file descriptor
 /// Possible Errors:
-/// - `__WASI_EACCES`, `__WASI_EBADF`, `__WASI_EFAULT`, `__WASI_EFBIG?`, `__WASI_EINVAL`, `__WASI_EIO`, `__WASI_ELOOP`, `__WASI_EMFILE`, `__WASI_ENAMETOOLONG?`, `__WASI_ENFILE`, `__WASI_ENOENT`, `__WASI_ENOTDIR`, `__WASI_EROFS`, and `__WASI_ENOTCAPABLE`
+/// - `__WASI_EACCES`, `__WASI_EBADF`, `__WASI_EEXIST`, `__WASI_EFAULT`, `__WASI_EFBIG?`, `__WASI_EILSEQ`, `__WASI_EINVAL`, `__WASI_EIO`, `__WASI_EISDIR`, `__WASI_ELOOP`, `__WASI_EMFILE`, `__WASI_ENAMETOOLONG?`, `__WASI_ENFILE`, `__WASI_ENOENT`, `__WASI_ENOTDIR`, `__WASI_EROFS`, and `__WASI_ENOTCAPABLE`
 pub fn path_open<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     dirfd: __wasi_fd_t,
@@ -2236,11 +2825,7 @@ pub fn path_open<M: MemorySize>(
     }
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
-    /* TODO: find actual upper bound on name size (also this is a path, not a name :think-fish:) */
-    let path_len64: u64 = path_len.into();
-    if path_len64 > 1024u64 * 1024u64 {
-        return __WASI_ENAMETOOLONG;
-    }
+    wasi_try!(state.check_path_len(path_len.into()));
 
     let fd_ref = fd.deref(&ctx, memory);
 
@@ -2257,7 +2842,9 @@ pub fn path_open<M: MemorySize>(
     if !has_rights(working_dir.rights, __WASI_RIGHT_PATH_OPEN) {
         return __WASI_EACCES;
     }
+    wasi_try!(state.check_open_fds());
     let path_string = unsafe { get_input_str!(&ctx, memory, path, path_len) };
+    let path_string = wasi_try!(env.map_path(path_string));
 
     debug!("=> fd: {}, path: {}", dirfd, &path_string);
 
@@ -2326,25 +2913,30 @@ pub fn path_open<M: MemorySize>(
                 if o_flags & __WASI_O_TRUNC != 0 {
                     open_flags |= Fd::TRUNCATE;
                 }
-                *handle = Some(wasi_try!(open_options
-                    .open(&path)
-                    .map_err(fs_error_into_wasi_err)));
+                // files without a host path (e.g. devices) keep their handle
+                if handle.is_none() || !path.as_os_str().is_empty() {
+                    *handle = Some(wasi_try!(open_options
+                        .open(&path)
+                        .map_err(fs_error_into_wasi_err)));
+                }
             }
             Kind::Buffer { .. } => unimplemented!("wasi::path_open for Buffer type files"),
-            Kind::Dir { .. }
-            | Kind::Root { .. }
-            | Kind::Socket { .. }
-            | Kind::Pipe { .. }
-            | Kind::EventNotifications { .. } => {}
-            Kind::Symlink {
-                base_po_dir,
-                path_to_symlink,
-                relative_path,
-            } => {
-                // I think this should return an error (because symlinks should be resolved away by the path traversal)
-                // TODO: investigate this
-                unimplemented!("SYMLINKS IN PATH_OPEN");
+            Kind::Dir { .. } | Kind::Root { .. } => {
+                if o_flags & __WASI_O_EXCL != 0 {
+                    return __WASI_EEXIST;
+                }
+                // a directory can be opened but not created, truncated or
+                // appended to like a file
+                if o_flags & (__WASI_O_CREAT | __WASI_O_TRUNC) != 0
+                    || fs_flags & __WASI_FDFLAG_APPEND != 0
+                {
+                    return __WASI_EISDIR;
+                }
             }
+            Kind::Socket { .. } | Kind::Pipe { .. } | Kind::EventNotifications { .. } => {}
+            // symlinks are resolved away by the path traversal unless
+            // `__WASI_LOOKUP_SYMLINK_FOLLOW` is cleared
+            Kind::Symlink { .. } => return __WASI_ELOOP,
         }
         inode
     } else {
@@ -2384,7 +2976,10 @@ pub fn path_open<M: MemorySize>(
                     // TODO: ensure these rights are actually valid given parent, etc.
                     // write access is required for creating a file
                     .write(true)
-                    .create_new(true);
+                    // the file may have been created since the lookup, only
+                    // `O_EXCL` fails in that case
+                    .create(true)
+                    .create_new(o_flags & __WASI_O_EXCL != 0);
                 open_flags |= Fd::READ | Fd::WRITE | Fd::CREATE | Fd::TRUNCATE;
 
                 Some(wasi_try!(open_options.open(&new_file_host_path).map_err(
@@ -2461,6 +3056,11 @@ pub fn path_open<M: MemorySize>(
 ///     Pointer to characters containing the path that the symlink points to
 /// - `u32 buf_used`
 ///     The number of bytes written to `buf`
+/// Errors:
+/// - `__WASI_ERANGE`
+///     `buf` is too small to hold the whole target, which was truncated
+/// - `__WASI_EINVAL`
+///     `path` is not a symlink
 pub fn path_readlink<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     dir_fd: __wasi_fd_t,
@@ -2473,45 +3073,62 @@ pub fn path_readlink<M: MemorySize>(
     debug!("wasi::path_readlink");
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(path_len.into()));
 
     let base_dir = wasi_try!(state.fs.get_fd(dir_fd));
     if !has_rights(base_dir.rights, __WASI_RIGHT_PATH_READLINK) {
         return __WASI_EACCES;
     }
     let path_str = unsafe { get_input_str!(&ctx, memory, path, path_len) };
-    let inode = wasi_try!(state
-        .fs
-        .get_inode_at_path(inodes.deref_mut(), dir_fd, &path_str, false));
-
-    {
-        let guard = inodes.arena[inode].read();
-        if let Kind::Symlink { relative_path, .. } = guard.deref() {
-            let rel_path_str = relative_path.to_string_lossy();
-            debug!("Result => {:?}", rel_path_str);
-            let buf_len: u64 = buf_len.into();
-            let bytes = rel_path_str.bytes();
-            if bytes.len() as u64 >= buf_len {
-                return __WASI_EOVERFLOW;
+    let path_str = wasi_try!(env.map_path(path_str));
+    let target = match state.fd_reflection_target(inodes.deref(), base_dir.inode, &path_str) {
+        Some(target) => target,
+        None => {
+            let inode =
+                wasi_try!(state
+                    .fs
+                    .get_inode_at_path(inodes.deref_mut(), dir_fd, &path_str, false));
+            let guard = inodes.arena[inode].read();
+            if let Kind::Symlink { relative_path, .. } = guard.deref() {
+                relative_path.to_string_lossy().into_owned()
+            } else {
+                return __WASI_EINVAL;
             }
-            let bytes: Vec<_> = bytes.collect();
+        }
+    };
 
-            let out =
-                wasi_try_mem!(buf.slice(&ctx, memory, wasi_try!(to_offset::<M>(bytes.len()))));
-            wasi_try_mem!(out.write_slice(&bytes));
-            // should we null terminate this?
+    debug!("Result => {:?}", target);
+    let buf_len: u64 = buf_len.into();
+    let bytes = target.as_bytes();
+    // the target is truncated to fit in the buffer, it is not null terminated
+    let used = bytes.len().min(buf_len.try_into().unwrap_or(usize::MAX));
 
-            let bytes_len: M::Offset =
-                wasi_try!(bytes.len().try_into().map_err(|_| __WASI_EOVERFLOW));
-            wasi_try_mem!(buf_used.deref(&ctx, memory).write(bytes_len));
-        } else {
-            return __WASI_EINVAL;
-        }
+    let out = wasi_try_mem!(buf.slice(&ctx, memory, wasi_try!(to_offset::<M>(used))));
+    wasi_try_mem!(out.write_slice(&bytes[..used]));
+
+    let used_len: M::Offset = wasi_try!(used.try_into().map_err(|_| __WASI_EOVERFLOW));
+    wasi_try_mem!(buf_used.deref(&ctx, memory).write(used_len));
+    if used < bytes.len() {
+        return __WASI_ERANGE;
     }
 
     __WASI_ESUCCESS
 }
 
-/// Returns __WASI_ENOTEMTPY if directory is not empty
+/// ### `path_remove_directory()`
+/// Remove an empty directory
+/// Inputs:
+/// - `__wasi_fd_t fd`
+///     The base directory for `path`
+/// - `const char* path`
+///     Pointer to UTF8 bytes, the directory to remove
+/// - `u32 path_len`
+///     The number of bytes to read from `path`
+/// Errors:
+/// - `__WASI_ENOTEMPTY`
+///     The directory has entries other than `.` and `..`
+/// - `__WASI_ENOTDIR`
+///     `path` is not a directory
 pub fn path_remove_directory<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     fd: __wasi_fd_t,
@@ -2522,9 +3139,11 @@ pub fn path_remove_directory<M: MemorySize>(
     debug!("wasi::path_remove_directory");
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(path_len.into()));
 
     let base_dir = wasi_try!(state.fs.get_fd(fd));
     let path_str = unsafe { get_input_str!(&ctx, memory, path, path_len) };
+    let path_str = wasi_try!(env.map_path(path_str));
 
     let inode = wasi_try!(state
         .fs
@@ -2540,7 +3159,14 @@ pub fn path_remove_directory<M: MemorySize>(
         let guard = inodes.arena[inode].read();
         match guard.deref() {
             Kind::Dir { entries, path, .. } => {
-                if !entries.is_empty() || wasi_try!(state.fs_read_dir(path)).count() != 0 {
+                if !entries.is_empty() {
+                    return __WASI_ENOTEMPTY;
+                }
+                // the entries are loaded lazily, the backing file system has
+                // the last word; some list `.` and `..`, which don't count
+                let mut names = wasi_try!(state.fs_read_dir(path))
+                    .map(|entry| entry.map(|entry| entry.file_name()));
+                if names.any(|name| name.map_or(true, |name| name != "." && name != "..")) {
                     return __WASI_ENOTEMPTY;
                 }
                 path.clone()
@@ -2583,7 +3209,7 @@ pub fn path_remove_directory<M: MemorySize>(
 }
 
 /// ### `path_rename()`
-/// Rename a file or directory
+/// Rename a file or directory, replacing `new_path` if it exists
 /// Inputs:
 /// - `__wasi_fd_t old_fd`
 ///     The base directory for `old_path`
@@ -2597,6 +3223,14 @@ pub fn path_remove_directory<M: MemorySize>(
 ///     Pointer to UTF8 bytes, the new file name
 /// - `u32 new_path_len`
 ///     The number of bytes to read from `new_path`
+/// Errors:
+/// - `__WASI_EXDEV`
+///     `old_path` and `new_path` live on different file systems
+/// - `__WASI_EISDIR`, `__WASI_ENOTDIR`
+///     Only a file can replace a file and only a directory can replace a
+///     directory
+/// - `__WASI_ENOTEMPTY`
+///     `new_path` is a directory with entries
 pub fn path_rename<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     old_fd: __wasi_fd_t,
@@ -2612,10 +3246,12 @@ pub fn path_rename<M: MemorySize>(
     );
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(old_path_len.into()));
+    wasi_try!(state.check_path_len(new_path_len.into()));
     let source_str = unsafe { get_input_str!(&ctx, memory, old_path, old_path_len) };
-    let source_path = std::path::Path::new(&source_str);
+    let source_str = wasi_try!(env.map_path(source_str));
     let target_str = unsafe { get_input_str!(&ctx, memory, new_path, new_path_len) };
-    let target_path = std::path::Path::new(&target_str);
+    let target_str = wasi_try!(env.map_path(target_str));
     debug!("=> rename from {} to {}", &source_str, &target_str);
 
     {
@@ -2625,125 +3261,21 @@ pub fn path_rename<M: MemorySize>(
         }
         let target_fd = wasi_try!(state.fs.get_fd(new_fd));
         if !has_rights(target_fd.rights, __WASI_RIGHT_PATH_RENAME_TARGET) {
+            // a read-only mount can't be modified whatever the rights are
+            wasi_try!(state.fs.check_dir_writable(inodes.deref(), target_fd.inode));
             return __WASI_EACCES;
         }
     }
 
-    let (source_parent_inode, source_entry_name) =
-        wasi_try!(state
-            .fs
-            .get_parent_inode_at_path(inodes.deref_mut(), old_fd, source_path, true));
-    let (target_parent_inode, target_entry_name) =
-        wasi_try!(state
-            .fs
-            .get_parent_inode_at_path(inodes.deref_mut(), new_fd, target_path, true));
-
-    let host_adjusted_target_path = {
-        let guard = inodes.arena[target_parent_inode].read();
-        match guard.deref() {
-            Kind::Dir { entries, path, .. } => {
-                if entries.contains_key(&target_entry_name) {
-                    return __WASI_EEXIST;
-                }
-                let mut out_path = path.clone();
-                out_path.push(std::path::Path::new(&target_entry_name));
-                out_path
-            }
-            Kind::Root { .. } => return __WASI_ENOTCAPABLE,
-            Kind::Socket { .. } | Kind::Pipe { .. } | Kind::EventNotifications { .. } => {
-                return __WASI_EINVAL
-            }
-            Kind::Symlink { .. } | Kind::File { .. } | Kind::Buffer { .. } => {
-                unreachable!("Fatal internal logic error: parent of inode is not a directory")
-            }
-        }
-    };
-
-    let source_entry = {
-        let mut guard = inodes.arena[source_parent_inode].write();
-        match guard.deref_mut() {
-            Kind::Dir { entries, .. } => {
-                wasi_try!(entries.remove(&source_entry_name).ok_or(__WASI_ENOENT))
-            }
-            Kind::Root { .. } => return __WASI_ENOTCAPABLE,
-            Kind::Socket { .. } | Kind::Pipe { .. } | Kind::EventNotifications { .. } => {
-                return __WASI_EINVAL
-            }
-            Kind::Symlink { .. } | Kind::File { .. } | Kind::Buffer { .. } => {
-                unreachable!("Fatal internal logic error: parent of inode is not a directory")
-            }
-        }
-    };
-
-    {
-        let mut guard = inodes.arena[source_entry].write();
-        match guard.deref_mut() {
-            Kind::File {
-                handle, ref path, ..
-            } => {
-                // TODO: investigate why handle is not always there, it probably should be.
-                // My best guess is the fact that a handle means currently open and a path
-                // just means reference to host file on disk. But ideally those concepts
-                // could just be unified even if there's a `Box<dyn VirtualFile>` which just
-                // implements the logic of "I'm not actually a file, I'll try to be as needed".
-                let result = if let Some(h) = handle {
-                    drop(guard);
-                    state.fs_rename(&source_path, &host_adjusted_target_path)
-                } else {
-                    let path_clone = path.clone();
-                    drop(guard);
-                    let out = state.fs_rename(&path_clone, &host_adjusted_target_path);
-                    {
-                        let mut guard = inodes.arena[source_entry].write();
-                        if let Kind::File { ref mut path, .. } = guard.deref_mut() {
-                            *path = host_adjusted_target_path;
-                        } else {
-                            unreachable!()
-                        }
-                    }
-                    out
-                };
-                // if the above operation failed we have to revert the previous change and then fail
-                if let Err(e) = result {
-                    let mut guard = inodes.arena[source_parent_inode].write();
-                    if let Kind::Dir { entries, .. } = guard.deref_mut() {
-                        entries.insert(source_entry_name, source_entry);
-                        return e;
-                    }
-                }
-            }
-            Kind::Dir { ref path, .. } => {
-                let cloned_path = path.clone();
-                if let Err(e) = state.fs_rename(cloned_path, &host_adjusted_target_path) {
-                    return e;
-                }
-                {
-                    drop(guard);
-                    let mut guard = inodes.arena[source_entry].write();
-                    if let Kind::Dir { path, .. } = guard.deref_mut() {
-                        *path = host_adjusted_target_path;
-                    }
-                }
-            }
-            Kind::Buffer { .. } => {}
-            Kind::Symlink { .. } => {}
-            Kind::Socket { .. } => {}
-            Kind::Pipe { .. } => {}
-            Kind::EventNotifications { .. } => {}
-            Kind::Root { .. } => unreachable!("The root can not be moved"),
-        }
-    }
-
-    {
-        let mut guard = inodes.arena[target_parent_inode].write();
-        if let Kind::Dir { entries, .. } = guard.deref_mut() {
-            let result = entries.insert(target_entry_name, source_entry);
-            assert!(
-                result.is_none(),
-                "Fatal error: race condition on filesystem detected or internal logic error"
-            );
-        }
-    }
+    // POSIX `rename` replaces the destination
+    wasi_try!(state.fs.rename(
+        inodes.deref_mut(),
+        old_fd,
+        &source_str,
+        new_fd,
+        &target_str,
+        true
+    ));
 
     __WASI_ESUCCESS
 }
@@ -2772,82 +3304,33 @@ pub fn path_symlink<M: MemorySize>(
     debug!("wasi::path_symlink");
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(old_path_len.into()));
+    wasi_try!(state.check_path_len(new_path_len.into()));
     let old_path_str = unsafe { get_input_str!(&ctx, memory, old_path, old_path_len) };
     let new_path_str = unsafe { get_input_str!(&ctx, memory, new_path, new_path_len) };
+    let new_path_str = wasi_try!(env.map_path(new_path_str));
     let base_fd = wasi_try!(state.fs.get_fd(fd));
     if !has_rights(base_fd.rights, __WASI_RIGHT_PATH_SYMLINK) {
+        // a read-only mount can't be modified whatever the rights are
+        wasi_try!(state.fs.check_dir_writable(inodes.deref(), base_fd.inode));
         return __WASI_EACCES;
     }
 
-    // get the depth of the parent + 1 (UNDER INVESTIGATION HMMMMMMMM THINK FISH ^ THINK FISH)
-    let old_path_path = std::path::Path::new(&old_path_str);
-    let (source_inode, _) =
-        wasi_try!(state
-            .fs
-            .get_parent_inode_at_path(inodes.deref_mut(), fd, old_path_path, true));
-    let depth = wasi_try!(state
-        .fs
-        .path_depth_from_fd(inodes.deref(), fd, source_inode))
-        - 1;
-
-    let new_path_path = std::path::Path::new(&new_path_str);
-    let (target_parent_inode, entry_name) =
-        wasi_try!(state
-            .fs
-            .get_parent_inode_at_path(inodes.deref_mut(), fd, new_path_path, true));
-
-    // short circuit if anything is wrong, before we create an inode
-    {
-        let guard = inodes.arena[target_parent_inode].read();
-        match guard.deref() {
-            Kind::Dir { entries, .. } => {
-                if entries.contains_key(&entry_name) {
-                    return __WASI_EEXIST;
-                }
-            }
-            Kind::Root { .. } => return __WASI_ENOTCAPABLE,
-            Kind::Socket { .. } | Kind::Pipe { .. } | Kind::EventNotifications { .. } => {
-                return __WASI_EINVAL
-            }
-            Kind::File { .. } | Kind::Symlink { .. } | Kind::Buffer { .. } => {
-                unreachable!("get_parent_inode_at_path returned something other than a Dir or Root")
-            }
-        }
-    }
-
-    let mut source_path = std::path::Path::new(&old_path_str);
-    let mut relative_path = std::path::PathBuf::new();
-    for _ in 0..depth {
-        relative_path.push("..");
-    }
-    relative_path.push(source_path);
-    debug!(
-        "Symlinking {} to {}",
-        new_path_str,
-        relative_path.to_string_lossy()
-    );
-
-    let kind = Kind::Symlink {
-        base_po_dir: fd,
-        path_to_symlink: std::path::PathBuf::from(new_path_str),
-        relative_path,
-    };
-    let new_inode = state.fs.create_inode_with_default_stat(
+    let (parent_inode, _) = wasi_try!(state.fs.get_parent_inode_at_path(
         inodes.deref_mut(),
-        kind,
-        false,
-        entry_name.clone(),
-    );
+        fd,
+        std::path::Path::new(&new_path_str),
+        true
+    ));
+    wasi_try!(state.fs.check_dir_writable(inodes.deref(), parent_inode));
 
-    {
-        let mut guard = inodes.arena[target_parent_inode].write();
-        if let Kind::Dir {
-            ref mut entries, ..
-        } = guard.deref_mut()
-        {
-            entries.insert(entry_name, new_inode);
-        }
-    }
+    debug!("Symlinking {} to {}", new_path_str, old_path_str);
+    wasi_try!(state.fs.create_symlink(
+        inodes.deref_mut(),
+        fd,
+        std::path::Path::new(&new_path_str),
+        std::path::Path::new(&old_path_str),
+    ));
 
     __WASI_ESUCCESS
 }
@@ -2870,12 +3353,16 @@ pub fn path_unlink_file<M: MemorySize>(
     debug!("wasi::path_unlink_file");
     let env = ctx.data();
     let (memory, mut state, mut inodes) = env.get_memory_and_wasi_state_and_inodes_mut(0);
+    wasi_try!(state.check_path_len(path_len.into()));
 
     let base_dir = wasi_try!(state.fs.get_fd(fd));
     if !has_rights(base_dir.rights, __WASI_RIGHT_PATH_UNLINK_FILE) {
+        // a read-only mount can't be modified whatever the rights are
+        wasi_try!(state.fs.check_dir_writable(inodes.deref(), base_dir.inode));
         return __WASI_EACCES;
     }
     let path_str = unsafe { get_input_str!(&ctx, memory, path, path_len) };
+    let path_str = wasi_try!(env.map_path(path_str));
     debug!("Requested file: {}", path_str);
 
     let inode = wasi_try!(state
@@ -2887,6 +3374,7 @@ pub fn path_unlink_file<M: MemorySize>(
         std::path::Path::new(&path_str),
         false
     ));
+    wasi_try!(state.fs.check_dir_writable(inodes.deref(), parent_inode));
 
     let removed_inode = {
         let mut guard = inodes.arena[parent_inode].write();
@@ -2972,6 +3460,13 @@ pub fn path_unlink_file<M: MemorySize>(
 /// Output:
 /// - `u32 nevents`
 ///     The number of events seen
+///
+/// Polling an empty set of subscriptions returns immediately with no
+/// events, like `poll` with `nfds = 0`.
+///
+/// The call waits at most until the earliest clock subscription expires,
+/// every clock subscription whose deadline has passed by then produces an
+/// event.
 pub fn poll_oneoff<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     in_: WasmPtr<__wasi_subscription_t, M>,
@@ -2984,17 +3479,23 @@ pub fn poll_oneoff<M: MemorySize>(
     let env = ctx.data();
     let (memory, mut state, inodes) = env.get_memory_and_wasi_state_and_inodes(0);
 
+    if nsubscriptions == M::ZERO {
+        wasi_try_mem_ok!(nevents.write(&ctx, memory, M::ZERO));
+        return Ok(__WASI_ESUCCESS);
+    }
+
     let subscription_array = wasi_try_mem_ok!(in_.slice(&ctx, memory, nsubscriptions));
-    let event_array = wasi_try_mem_ok!(out_.slice(&ctx, memory, nsubscriptions));
     let mut events_seen: u32 = 0;
-    let out_ptr = nevents.deref(&ctx, memory);
 
     let mut fd_guards = vec![];
     let mut clock_subs = vec![];
     let mut in_events = vec![];
-    let mut time_to_sleep = Duration::from_millis(5);
+    // the index of the subscription of every fd in `fd_guards`
+    let mut fd_subscriptions = vec![];
+    // the shortest timeout of all the clock subscriptions
+    let mut clock_timeout: Option<Duration> = None;
 
-    for sub in subscription_array.iter() {
+    for (sub_index, sub) in subscription_array.iter().enumerate() {
         let s: WasiSubscription = wasi_try_ok!(wasi_try_mem_ok!(sub.read()).try_into());
         let mut peb = PollEventBuilder::new();
 
@@ -3029,10 +3530,14 @@ pub fn poll_oneoff<M: MemorySize>(
                 if clock_info.clock_id == __WASI_CLOCK_REALTIME
                     || clock_info.clock_id == __WASI_CLOCK_MONOTONIC
                 {
-                    // this is a hack
-                    // TODO: do this properly
-                    time_to_sleep = Duration::from_nanos(clock_info.timeout);
-                    clock_subs.push((clock_info, s.user_data));
+                    let mut timeout = clock_info.timeout;
+                    if clock_info.flags & __WASI_SUBSCRIPTION_CLOCK_ABSTIME != 0 {
+                        let now = wasi_try_ok!(env.clock_time_get(clock_info.clock_id));
+                        timeout = timeout.saturating_sub(now as __wasi_timestamp_t);
+                    }
+                    let timeout = Duration::from_nanos(timeout);
+                    clock_timeout = Some(clock_timeout.map_or(timeout, |t| t.min(timeout)));
+                    clock_subs.push((timeout, s.user_data));
                     None
                 } else {
                     unimplemented!("Polling not implemented for clocks yet");
@@ -3099,6 +3604,7 @@ pub fn poll_oneoff<M: MemorySize>(
                 }
             };
             fd_guards.push(wasi_file_ref);
+            fd_subscriptions.push(sub_index as u64);
         }
     }
 
@@ -3111,39 +3617,45 @@ pub fn poll_oneoff<M: MemorySize>(
     };
 
     let mut seen_events = vec![Default::default(); in_events.len()];
+    let time_to_sleep = clock_timeout.unwrap_or_else(|| Duration::from_millis(5));
 
-    let start = platform_clock_time_get(__WASI_CLOCK_MONOTONIC, 1_000_000).unwrap() as u128;
-    let mut triggered = 0;
-    while triggered == 0 {
-        let now = platform_clock_time_get(__WASI_CLOCK_MONOTONIC, 1_000_000).unwrap() as u128;
+    let start = wasi_try_ok!(env.clock_time_get(__WASI_CLOCK_MONOTONIC)) as u128;
+    loop {
+        let now = wasi_try_ok!(env.clock_time_get(__WASI_CLOCK_MONOTONIC)) as u128;
         let delta = match now.checked_sub(start) {
             Some(a) => Duration::from_nanos(a as u64),
             None => Duration::ZERO,
         };
+        // The fds are only checked, the wait happens on the clock so that a
+        // clock that doesn't follow the host time moves forward instead
         match poll(
             fds.as_slice(),
             in_events.as_slice(),
             seen_events.as_mut_slice(),
-            Duration::from_millis(1),
+            Duration::ZERO,
         ) {
-            Ok(0) => {
-                env.yield_now()?;
-            }
-            Ok(a) => {
-                triggered = a;
-            }
-            Err(FsError::WouldBlock) => {
-                env.sleep(Duration::from_millis(1))?;
-            }
+            Ok(0) | Err(FsError::WouldBlock) => (),
+            Ok(_) => break,
             Err(err) => {
                 return Ok(fs_error_into_wasi_err(err));
             }
         };
-        if delta > time_to_sleep {
+        if delta >= time_to_sleep {
             break;
         }
+        env.yield_now()?;
+        env.state
+            .clock
+            .sleep(Duration::from_millis(1).min(time_to_sleep - delta));
     }
 
+    // Other threads may have grown the memory while we were polling, the
+    // views taken before the wait can no longer be used
+    let memory = env.memory();
+    let subscription_array = wasi_try_mem_ok!(in_.slice(&ctx, memory, nsubscriptions));
+    let event_array = wasi_try_mem_ok!(out_.slice(&ctx, memory, nsubscriptions));
+    let out_ptr = nevents.deref(&ctx, memory);
+
     for (i, seen_event) in seen_events.into_iter().enumerate() {
         let mut flags = 0;
         let mut error = __WASI_EAGAIN;
@@ -3176,10 +3688,11 @@ pub fn poll_oneoff<M: MemorySize>(
                 }
             }
         }
+        let subscription = wasi_try_mem_ok!(subscription_array.index(fd_subscriptions[i]).read());
         let event = __wasi_event_t {
-            userdata: wasi_try_mem_ok!(subscription_array.index(i as u64).read()).userdata,
+            userdata: subscription.userdata,
             error,
-            type_: wasi_try_mem_ok!(subscription_array.index(i as u64).read()).type_,
+            type_: subscription.type_,
             u: unsafe {
                 __wasi_event_u {
                     fd_readwrite: __wasi_event_fd_readwrite_t {
@@ -3192,8 +3705,11 @@ pub fn poll_oneoff<M: MemorySize>(
         wasi_try_mem_ok!(event_array.index(events_seen as u64).write(event));
         events_seen += 1;
     }
-    if triggered == 0 {
-        for (clock_info, userdata) in clock_subs {
+    // every clock whose deadline has passed fires, whether or not an fd is ready
+    let now = wasi_try_ok!(env.clock_time_get(__WASI_CLOCK_MONOTONIC)) as u128;
+    let elapsed = Duration::from_nanos(now.saturating_sub(start) as u64);
+    for (timeout, userdata) in clock_subs {
+        if timeout <= elapsed {
             let event = __wasi_event_t {
                 userdata,
                 error: __WASI_ESUCCESS,
@@ -3228,6 +3744,9 @@ pub fn proc_exit(
     code: __wasi_exitcode_t,
 ) -> Result<(), WasiError> {
     debug!("wasi::proc_exit, {}", code);
+    if let Some(on_exit) = ctx.data().on_exit.as_ref() {
+        on_exit(code);
+    }
     Err(WasiError::Exit(code))
 }
 
@@ -3258,6 +3777,10 @@ pub fn sched_yield(ctx: FunctionEnvMut<'_, WasiEnv>) -> Result<__wasi_errno_t, W
 ///     A pointer to a buffer where the random bytes will be written
 /// - `size_t buf_len`
 ///     The number of bytes that will be written
+/// Errors:
+/// - `__WASI_EIO`
+///     The host randomness failed, or fewer than `buf_len` bytes are left to
+///     replay (see `WasiStateBuilder::replay_randomness`)
 pub fn random_get<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     buf: WasmPtr<u8, M>,
@@ -3268,15 +3791,10 @@ pub fn random_get<M: MemorySize>(
     let memory = env.memory();
     let buf_len64: u64 = buf_len.into();
     let mut u8_buffer = vec![0; buf_len64 as usize];
-    let res = getrandom::getrandom(&mut u8_buffer);
-    match res {
-        Ok(()) => {
-            let buf = wasi_try_mem!(buf.slice(&ctx, memory, buf_len));
-            wasi_try_mem!(buf.write_slice(&u8_buffer));
-            __WASI_ESUCCESS
-        }
-        Err(_) => __WASI_EIO,
-    }
+    wasi_try!(env.fill_random(&mut u8_buffer));
+    let buf = wasi_try_mem!(buf.slice(&ctx, memory, buf_len));
+    wasi_try_mem!(buf.write_slice(&u8_buffer));
+    __WASI_ESUCCESS
 }
 
 /// ### `tty_get()`
@@ -3423,6 +3941,7 @@ pub fn chdir<M: MemorySize>(
     debug!("wasi::chdir");
     let env = ctx.data();
     let (memory, mut state) = env.get_memory_and_wasi_state(0);
+    wasi_try!(state.check_path_len(path_len.into()));
     let path = unsafe { get_input_str!(&ctx, memory, path, path_len) };
 
     state.fs.set_current_dir(path.as_str());
@@ -5230,17 +5749,21 @@ pub fn sock_connect<M: MemorySize>(
 /// ## Parameters
 ///
 /// * `ri_data` - List of scatter/gather vectors to which to store data.
-/// * `ri_flags` - Message flags.
+/// * `ri_flags` - Message flags, `__WASI_SOCK_RECV_INPUT_PEEK` reads the
+///   data without consuming it. Other flags are not supported
+///   (`__WASI_ENOTSUP`).
 ///
 /// ## Return
 ///
-/// Number of bytes stored in ri_data and message flags.
+/// Number of bytes stored in ri_data and message flags,
+/// `__WASI_SOCK_RECV_OUTPUT_DATA_TRUNCATED` if a datagram didn't fit in
+/// ri_data.
 pub fn sock_recv<M: MemorySize>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
     sock: __wasi_fd_t,
     ri_data: WasmPtr<__wasi_iovec_t<M>, M>,
     ri_data_len: M::Offset,
-    _ri_flags: __wasi_riflags_t,
+    ri_flags: __wasi_riflags_t,
     ro_data_len: WasmPtr<M::Offset, M>,
     ro_flags: WasmPtr<__wasi_roflags_t, M>,
 ) -> Result<__wasi_errno_t, WasiError> {
@@ -5248,17 +5771,19 @@ pub fn sock_recv<M: MemorySize>(
 
     let env = ctx.data();
     let memory = env.memory();
-    let iovs_arr = wasi_try_mem_ok!(ri_data.slice(&ctx, memory, ri_data_len));
+    // the iovecs are only checked here, they are resolved again once data
+    // arrived as the guest may grow its memory while the receive blocks
+    wasi_try_mem_ok!(ri_data.slice(&ctx, memory, ri_data_len));
 
-    let bytes_read = wasi_try_ok!(__sock_actor_mut(
+    let (bytes_read, flags) = wasi_try_ok!(__sock_actor_mut(
         &ctx,
         sock,
         __WASI_RIGHT_SOCK_RECV,
-        |socket| { socket.recv(&ctx, memory, iovs_arr) }
+        |socket| { socket.recv_with_flags(&ctx, ri_data, ri_data_len, ri_flags) }
     ));
     let bytes_read: M::Offset = wasi_try_ok!(bytes_read.try_into().map_err(|_| __WASI_EOVERFLOW));
 
-    wasi_try_mem_ok!(ro_flags.write(&ctx, memory, 0));
+    wasi_try_mem_ok!(ro_flags.write(&ctx, memory, flags));
     wasi_try_mem_ok!(ro_data_len.write(&ctx, memory, bytes_read));
 
     Ok(__WASI_ESUCCESS)
@@ -5272,7 +5797,7 @@ pub fn sock_recv<M: MemorySize>(
 /// ## Parameters
 ///
 /// * `ri_data` - List of scatter/gather vectors to which to store data.
-/// * `ri_flags` - Message flags.
+/// * `ri_flags` - Message flags, as for `sock_recv`.
 ///
 /// ## Return
 ///
@@ -5282,7 +5807,7 @@ pub fn sock_recv_from<M: MemorySize>(
     sock: __wasi_fd_t,
     ri_data: WasmPtr<__wasi_iovec_t<M>, M>,
     ri_data_len: M::Offset,
-    _ri_flags: __wasi_riflags_t,
+    ri_flags: __wasi_riflags_t,
     ro_data_len: WasmPtr<M::Offset, M>,
     ro_flags: WasmPtr<__wasi_roflags_t, M>,
     ro_addr: WasmPtr<__wasi_addr_port_t, M>,
@@ -5291,17 +5816,17 @@ pub fn sock_recv_from<M: MemorySize>(
 
     let env = ctx.data();
     let memory = env.memory();
-    let iovs_arr = wasi_try_mem_ok!(ri_data.slice(&ctx, memory, ri_data_len));
+    wasi_try_mem_ok!(ri_data.slice(&ctx, memory, ri_data_len));
 
-    let bytes_read = wasi_try_ok!(__sock_actor_mut(
+    let (bytes_read, flags) = wasi_try_ok!(__sock_actor_mut(
         &ctx,
         sock,
         __WASI_RIGHT_SOCK_RECV_FROM,
-        |socket| { socket.recv_from(&ctx, memory, iovs_arr, ro_addr) }
+        |socket| { socket.recv_from(&ctx, ri_data, ri_data_len, ri_flags, ro_addr) }
     ));
     let bytes_read: M::Offset = wasi_try_ok!(bytes_read.try_into().map_err(|_| __WASI_EOVERFLOW));
 
-    wasi_try_mem_ok!(ro_flags.write(&ctx, memory, 0));
+    wasi_try_mem_ok!(ro_flags.write(&ctx, memory, flags));
     wasi_try_mem_ok!(ro_data_len.write(&ctx, memory, bytes_read));
 
     Ok(__WASI_ESUCCESS)
@@ -5315,7 +5840,7 @@ pub fn sock_recv_from<M: MemorySize>(
 /// ## Parameters
 ///
 /// * `si_data` - List of scatter/gather vectors to which to retrieve data
-/// * `si_flags` - Message flags.
+/// * `si_flags` - Message flags, none are supported yet (`__WASI_ENOTSUP`).
 ///
 /// ## Return
 ///
@@ -5325,10 +5850,13 @@ pub fn sock_send<M: MemorySize>(
     sock: __wasi_fd_t,
     si_data: WasmPtr<__wasi_ciovec_t<M>, M>,
     si_data_len: M::Offset,
-    _si_flags: __wasi_siflags_t,
+    si_flags: __wasi_siflags_t,
     ret_data_len: WasmPtr<M::Offset, M>,
 ) -> Result<__wasi_errno_t, WasiError> {
     debug!("wasi::sock_send");
+    if si_flags != 0 {
+        return Ok(__WASI_ENOTSUP);
+    }
     let env = ctx.data();
 
     let memory = env.memory();
@@ -5356,7 +5884,7 @@ pub fn sock_send<M: MemorySize>(
 /// ## Parameters
 ///
 /// * `si_data` - List of scatter/gather vectors to which to retrieve data
-/// * `si_flags` - Message flags.
+/// * `si_flags` - Message flags, as for `sock_send`.
 /// * `addr` - Address of the socket to send message to
 ///
 /// ## Return
@@ -5367,11 +5895,14 @@ pub fn sock_send_to<M: MemorySize>(
     sock: __wasi_fd_t,
     si_data: WasmPtr<__wasi_ciovec_t<M>, M>,
     si_data_len: M::Offset,
-    _si_flags: __wasi_siflags_t,
+    si_flags: __wasi_siflags_t,
     addr: WasmPtr<__wasi_addr_port_t, M>,
     ret_data_len: WasmPtr<M::Offset, M>,
 ) -> Result<__wasi_errno_t, WasiError> {
     debug!("wasi::sock_send_to");
+    if si_flags != 0 {
+        return Ok(__WASI_ENOTSUP);
+    }
     let env = ctx.data();
 
     let memory = env.memory();
@@ -3,13 +3,12 @@ use libc::{
     clock_getres, clock_gettime, timespec, CLOCK_MONOTONIC, CLOCK_PROCESS_CPUTIME_ID,
     CLOCK_REALTIME, CLOCK_THREAD_CPUTIME_ID,
 };
+use std::ffi::OsStr;
 use std::mem;
-use wasmer::WasmRef;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
 
-pub fn platform_clock_res_get(
-    clock_id: __wasi_clockid_t,
-    resolution: WasmRef<__wasi_timestamp_t>,
-) -> Result<i64, __wasi_errno_t> {
+pub fn platform_clock_res_get(clock_id: __wasi_clockid_t) -> Result<i64, __wasi_errno_t> {
     let unix_clock_id = match clock_id {
         __WASI_CLOCK_MONOTONIC => CLOCK_MONOTONIC,
         __WASI_CLOCK_PROCESS_CPUTIME_ID => CLOCK_PROCESS_CPUTIME_ID,
@@ -25,6 +24,13 @@ pub fn platform_clock_res_get(
         };
         (clock_getres(unix_clock_id, &mut timespec_out), timespec_out)
     };
+    if output != 0 {
+        return Err(match clock_id {
+            // the CPU-time clocks are optional in POSIX
+            __WASI_CLOCK_PROCESS_CPUTIME_ID | __WASI_CLOCK_THREAD_CPUTIME_ID => __WASI_ENOTSUP,
+            _ => __WASI_EIO,
+        });
+    }
 
     let t_out = (timespec_out.tv_sec * 1_000_000_000).wrapping_add(timespec_out.tv_nsec);
     Ok(t_out)
@@ -56,3 +62,17 @@ pub fn platform_clock_time_get(
     let t_out = (timespec_out.tv_sec * 1_000_000_000).wrapping_add(timespec_out.tv_nsec);
     Ok(t_out)
 }
+
+/// Converts the name of a host file to the name seen by the guest, fails
+/// with `__WASI_EILSEQ` when the bytes of the name are not valid UTF-8
+pub fn platform_file_name_to_guest(name: &OsStr) -> Result<String, __wasi_errno_t> {
+    std::str::from_utf8(name.as_bytes())
+        .map(|name| name.to_string())
+        .map_err(|_| __WASI_EILSEQ)
+}
+
+/// Converts a path of the guest to a path of the host, the UTF-8 bytes of
+/// the guest are used as they are
+pub fn platform_path_from_guest(path: &str) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(path.as_bytes()))
+}
@@ -1,12 +1,10 @@
 use crate::syscalls::types::*;
 use chrono::prelude::*;
+use std::ffi::OsStr;
 use std::mem;
-use wasmer::WasmRef;
+use std::path::PathBuf;
 
-pub fn platform_clock_res_get(
-    clock_id: __wasi_clockid_t,
-    resolution: WasmRef<__wasi_timestamp_t>,
-) -> Result<i64, __wasi_errno_t> {
+pub fn platform_clock_res_get(clock_id: __wasi_clockid_t) -> Result<i64, __wasi_errno_t> {
     let t_out = match clock_id {
         __WASI_CLOCK_MONOTONIC => 10_000_000,
         __WASI_CLOCK_REALTIME => 1,
@@ -24,3 +22,16 @@ pub fn platform_clock_time_get(
     let new_time: DateTime<Local> = Local::now();
     Ok(new_time.timestamp_nanos() as i64)
 }
+
+/// Converts the name of a host file to the name seen by the guest, fails
+/// with `__WASI_EILSEQ` when the name is not valid UTF-8
+pub fn platform_file_name_to_guest(name: &OsStr) -> Result<String, __wasi_errno_t> {
+    name.to_str()
+        .map(|name| name.to_string())
+        .ok_or(__WASI_EILSEQ)
+}
+
+/// Converts a path of the guest to a path of the host
+pub fn platform_path_from_guest(path: &str) -> PathBuf {
+    PathBuf::from(path)
+}
@@ -1,22 +1,18 @@
 use crate::syscalls::types::*;
+use std::ffi::{OsStr, OsString};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
 use tracing::debug;
-use wasmer::WasmRef;
 
-pub fn platform_clock_res_get(
-    clock_id: __wasi_clockid_t,
-    resolution: WasmRef<__wasi_timestamp_t>,
-) -> Result<i64, __wasi_errno_t> {
+pub fn platform_clock_res_get(clock_id: __wasi_clockid_t) -> Result<i64, __wasi_errno_t> {
     let resolution_val = match clock_id {
         // resolution of monotonic clock at 10ms, from:
         // https://docs.microsoft.com/en-us/windows/desktop/api/sysinfoapi/nf-sysinfoapi-gettickcount64
         __WASI_CLOCK_MONOTONIC => 10_000_000,
-        // TODO: verify or compute this
-        __WASI_CLOCK_REALTIME => 1,
-        __WASI_CLOCK_PROCESS_CPUTIME_ID => {
-            return Err(__WASI_EINVAL);
-        }
-        __WASI_CLOCK_THREAD_CPUTIME_ID => {
-            return Err(__WASI_EINVAL);
+        // the system time is read as a `FILETIME`, in 100ns units
+        __WASI_CLOCK_REALTIME => 100,
+        __WASI_CLOCK_PROCESS_CPUTIME_ID | __WASI_CLOCK_THREAD_CPUTIME_ID => {
+            return Err(__WASI_ENOTSUP);
         }
         _ => return Err(__WASI_EINVAL),
     };
@@ -51,3 +47,19 @@ pub fn platform_clock_time_get(
     };
     Ok(nanos as i64)
 }
+
+/// Converts the name of a host file to the name seen by the guest, decoding
+/// the UTF-16 of the Windows APIs. Fails with `__WASI_EILSEQ` when the name
+/// is not valid UTF-16 (has unpaired surrogates), which has no UTF-8
+/// encoding the guest could use to open it again.
+pub fn platform_file_name_to_guest(name: &OsStr) -> Result<String, __wasi_errno_t> {
+    String::from_utf16(&name.encode_wide().collect::<Vec<u16>>()).map_err(|_| __WASI_EILSEQ)
+}
+
+/// Converts a path of the guest to a path of the host, encoding the UTF-8
+/// of the guest to the UTF-16 of the Windows APIs
+pub fn platform_path_from_guest(path: &str) -> PathBuf {
+    PathBuf::from(OsString::from_wide(
+        &path.encode_utf16().collect::<Vec<u16>>(),
+    ))
+}
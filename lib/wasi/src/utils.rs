@@ -38,7 +38,7 @@ pub fn map_io_err(err: std::io::Error) -> __wasi_errno_t {
         ErrorKind::InvalidInput => __WASI_EIO,
         ErrorKind::InvalidData => __WASI_EIO,
         ErrorKind::TimedOut => __WASI_ETIMEDOUT,
-        ErrorKind::WriteZero => __WASI_EIO,
+        ErrorKind::WriteZero => __WASI_ENOSPC,
         ErrorKind::Interrupted => __WASI_EINTR,
         ErrorKind::Other => __WASI_EIO,
         ErrorKind::UnexpectedEof => __WASI_EIO,
@@ -71,8 +71,9 @@ pub enum WasiVersion {
     /// latest WASI version (in case of security issues for instance),
     /// by just updating the runtime.
     ///
-    /// Note that this version is never returned by an API. It is
-    /// provided only by the user.
+    /// Note that this version is only returned by an API for modules
+    /// importing from the bare `wasi` namespace, which some experimental
+    /// toolchains use. Otherwise it is provided only by the user.
     Latest,
 }
 
@@ -145,40 +146,76 @@ const WASIX_32V1_NAMESPACE: &str = "wasix_32v1";
 /// Namespace for the `wasix` version.
 const WASIX_64V1_NAMESPACE: &str = "wasix_64v1";
 
+/// Bare namespace used by some experimental toolchains, it is served the
+/// `Latest` version.
+pub(crate) const BARE_NAMESPACE: &str = "wasi";
+
+/// Get the WASI version whose namespace is exactly `namespace`.
+fn wasi_version_from_namespace(namespace: &str) -> Option<WasiVersion> {
+    match namespace {
+        SNAPSHOT0_NAMESPACE => Some(WasiVersion::Snapshot0),
+        SNAPSHOT1_NAMESPACE => Some(WasiVersion::Snapshot1),
+        WASIX_32V1_NAMESPACE => Some(WasiVersion::Wasix32v1),
+        WASIX_64V1_NAMESPACE => Some(WasiVersion::Wasix64v1),
+        BARE_NAMESPACE => Some(WasiVersion::Latest),
+        _ => None,
+    }
+}
+
 /// Detect the version of WASI being used based on the import
 /// namespaces.
 ///
-/// A strict detection expects that all imports live in a single WASI
-/// namespace. A non-strict detection expects that at least one WASI
-/// namespace exists to detect the version. Note that the strict
-/// detection is faster than the non-strict one.
+/// `strict` selects how the imports are inspected:
+///
+/// * `true`: all function imports must live in a single WASI
+///   namespace, otherwise `None` is returned. This detection is faster
+///   than the non-strict one.
+/// * `false`: at least one WASI namespace must exist, and the first
+///   one found in the import list gives the version. Non-WASI imports
+///   are ignored.
+///
+/// `WasiVersion::Latest` is only returned for the bare `wasi`
+/// namespace, but keep in mind that it compares equal to
+/// `WasiVersion::Snapshot1`. Use [`get_wasi_version_strict`] to get the
+/// exact namespace a module targets.
 pub fn get_wasi_version(module: &Module, strict: bool) -> Option<WasiVersion> {
     let mut imports = module.imports().functions().map(|f| f.module().to_owned());
 
     if strict {
         let first_module = imports.next()?;
         if imports.all(|module| module == first_module) {
-            match first_module.as_str() {
-                SNAPSHOT0_NAMESPACE => Some(WasiVersion::Snapshot0),
-                SNAPSHOT1_NAMESPACE => Some(WasiVersion::Snapshot1),
-                WASIX_32V1_NAMESPACE => Some(WasiVersion::Wasix32v1),
-                WASIX_64V1_NAMESPACE => Some(WasiVersion::Wasix64v1),
-                _ => None,
-            }
+            wasi_version_from_namespace(&first_module)
         } else {
             None
         }
     } else {
         // Check that at least a WASI namespace exists, and use the
         // first one in the list to detect the WASI version.
-        imports.find_map(|module| match module.as_str() {
-            SNAPSHOT0_NAMESPACE => Some(WasiVersion::Snapshot0),
-            SNAPSHOT1_NAMESPACE => Some(WasiVersion::Snapshot1),
-            WASIX_32V1_NAMESPACE => Some(WasiVersion::Wasix32v1),
-            WASIX_64V1_NAMESPACE => Some(WasiVersion::Wasix64v1),
-            _ => None,
-        })
+        imports.find_map(|module| wasi_version_from_namespace(&module))
+    }
+}
+
+/// Detect the exact WASI namespace targeted by a module.
+///
+/// Non-WASI imports are ignored, but all the WASI imports must live in
+/// a single namespace, otherwise `None` is returned. The returned
+/// version is always the one matching that namespace: it is only
+/// `WasiVersion::Latest` for the bare `wasi` namespace, so a module
+/// importing from `wasi_snapshot_preview1` yields
+/// `WasiVersion::Snapshot1`, and matching on the variant (rather than
+/// using `==`, which aliases `Latest` and `Snapshot1`) tells them apart.
+pub fn get_wasi_version_strict(module: &Module) -> Option<WasiVersion> {
+    let mut found = None;
+    for import in module.imports().functions() {
+        if let Some(version) = wasi_version_from_namespace(import.module()) {
+            match found {
+                None => found = Some((import.module().to_owned(), version)),
+                Some((ref namespace, _)) if namespace == import.module() => {}
+                Some(_) => return None,
+            }
+        }
     }
+    found.map(|(_, version)| version)
 }
 
 /// Like [`get_wasi_version`] but detects multiple WASI versions in a single module.
@@ -204,6 +241,11 @@ pub fn get_wasi_versions(module: &Module, strict: bool) -> Option<BTreeSet<WasiV
             WASIX_64V1_NAMESPACE => {
                 out.insert(WasiVersion::Wasix64v1);
             }
+            BARE_NAMESPACE => {
+                // `Latest` equals `Snapshot1` but also covers the bare
+                // namespace, so it takes its place in the set
+                out.replace(WasiVersion::Latest);
+            }
             _ => {
                 non_wasi_seen = true;
             }
@@ -284,4 +326,47 @@ mod test {
         assert!(WasiVersion::Wasix32v1 < WasiVersion::Wasix64v1);
         assert!(WasiVersion::Wasix64v1 > WasiVersion::Wasix32v1);
     }
+
+    #[test]
+    fn wasi_version_strict_detection() {
+        let store = wasmer::Store::default();
+        let module = |wat: &str| Module::new(&store, wat).unwrap();
+
+        let snapshot1 = module(
+            r#"(module
+                (import "env" "f" (func))
+                (import "wasi_snapshot_preview1" "proc_exit" (func (param i32))))"#,
+        );
+        assert!(matches!(
+            get_wasi_version_strict(&snapshot1),
+            Some(WasiVersion::Snapshot1)
+        ));
+
+        let snapshot0 =
+            module(r#"(module (import "wasi_unstable" "proc_exit" (func (param i32))))"#);
+        assert!(matches!(
+            get_wasi_version_strict(&snapshot0),
+            Some(WasiVersion::Snapshot0)
+        ));
+
+        let mixed = module(
+            r#"(module
+                (import "wasi_unstable" "proc_exit" (func (param i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func (param i32))))"#,
+        );
+        assert!(get_wasi_version_strict(&mixed).is_none());
+
+        let none = module(r#"(module (import "env" "f" (func)))"#);
+        assert!(get_wasi_version_strict(&none).is_none());
+
+        let bare = module(r#"(module (import "wasi" "proc_exit" (func (param i32))))"#);
+        assert!(matches!(
+            get_wasi_version_strict(&bare),
+            Some(WasiVersion::Latest)
+        ));
+        assert!(matches!(
+            get_wasi_version(&bare, true),
+            Some(WasiVersion::Latest)
+        ));
+    }
 }
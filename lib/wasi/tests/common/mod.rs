@@ -0,0 +1,91 @@
+//! Helpers shared by the integration tests, each test file only uses some
+//! of them.
+#![allow(dead_code)]
+
+use wasmer::{Instance, Memory, Module, RuntimeError, Store, Value};
+use wasmer_wasi::{WasiEnv, WasiFunctionEnv, WasiState, WasiStateBuilder};
+
+/// A module instantiated with the WASI imports, see [`run_wat`]
+pub struct Guest {
+    pub store: Store,
+    pub instance: Instance,
+    pub memory: Memory,
+    pub wasi_env: WasiFunctionEnv,
+}
+
+/// Instantiates `wat`, a module exporting its memory as `memory`, with a
+/// WASI environment set up by `configure`
+pub fn run_wat(wat: impl AsRef<[u8]>, configure: impl FnOnce(&mut WasiStateBuilder)) -> Guest {
+    let mut builder = WasiState::new("command-name");
+    configure(&mut builder);
+    run_wat_with(wat, &mut builder)
+}
+
+/// Like [`run_wat`] with a builder that is already set up
+pub fn run_wat_with(wat: impl AsRef<[u8]>, builder: &mut WasiStateBuilder) -> Guest {
+    let mut store = Store::default();
+    let module = Module::new(&store, wat).unwrap();
+    let wasi_env = builder.finalize(&mut store).unwrap();
+    let import_object = wasi_env.import_object(&mut store, &module).unwrap();
+    let instance = Instance::new(&mut store, &module, &import_object).unwrap();
+    let memory = instance.exports.get_memory("memory").unwrap().clone();
+    wasi_env.data_mut(&mut store).set_memory(memory.clone());
+
+    Guest {
+        store,
+        instance,
+        memory,
+        wasi_env,
+    }
+}
+
+impl Guest {
+    /// Calls the export `name`, which must not trap
+    pub fn call(&mut self, name: &str, params: &[Value]) -> Box<[Value]> {
+        self.try_call(name, params).unwrap()
+    }
+
+    /// Calls the export `name`
+    pub fn try_call(&mut self, name: &str, params: &[Value]) -> Result<Box<[Value]>, RuntimeError> {
+        self.instance
+            .exports
+            .get_function(name)
+            .unwrap()
+            .call(&mut self.store, params)
+    }
+
+    /// Calls the export `name`, which returns an errno
+    pub fn call_errno(&mut self, name: &str, params: &[Value]) -> u16 {
+        self.call(name, params)[0].unwrap_i32() as u16
+    }
+
+    pub fn read_memory(&self, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        self.memory.read(&self.store, offset, &mut buf).unwrap();
+        buf
+    }
+
+    pub fn read_u32(&self, offset: u64) -> u32 {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(&self.read_memory(offset, 4));
+        u32::from_le_bytes(buf)
+    }
+
+    pub fn read_u64(&self, offset: u64) -> u64 {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(&self.read_memory(offset, 8));
+        u64::from_le_bytes(buf)
+    }
+
+    pub fn write_memory(&self, offset: u64, data: &[u8]) {
+        self.memory.write(&self.store, offset, data).unwrap();
+    }
+
+    pub fn env(&self) -> &WasiEnv {
+        self.wasi_env.env.as_ref(&self.store)
+    }
+
+    pub fn env_mut(&mut self) -> &mut WasiEnv {
+        self.wasi_env.data_mut(&mut self.store)
+    }
+}
@@ -0,0 +1,321 @@
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use wasmer::Value;
+use wasmer_vfs::{mem_fs, FileSystem, FsError, VirtualFile};
+use wasmer_wasi::types::{__WASI_EBADF, __WASI_ESUCCESS};
+use wasmer_wasi::ALL_RIGHTS;
+
+mod common;
+use common::{run_wat, Guest};
+
+const NAMESPACES: [&str; 2] = ["wasi_unstable", "wasi_snapshot_preview1"];
+
+mod sys {
+    #[test]
+    fn test_fd_renumber_preserves_offset() {
+        super::test_fd_renumber_preserves_offset()
+    }
+
+    #[test]
+    fn test_fd_renumber_preserves_rights() {
+        super::test_fd_renumber_preserves_rights()
+    }
+
+    #[test]
+    fn test_fd_renumber_replaces_destination() {
+        super::test_fd_renumber_replaces_destination()
+    }
+
+    #[test]
+    fn test_fd_renumber_same_fd() {
+        super::test_fd_renumber_same_fd()
+    }
+
+    #[test]
+    fn test_fd_renumber_closes_destination() {
+        super::test_fd_renumber_closes_destination()
+    }
+}
+
+#[cfg(feature = "js")]
+mod js {
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    fn test_fd_renumber_preserves_offset() {
+        super::test_fd_renumber_preserves_offset()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fd_renumber_preserves_rights() {
+        super::test_fd_renumber_preserves_rights()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fd_renumber_replaces_destination() {
+        super::test_fd_renumber_replaces_destination()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fd_renumber_same_fd() {
+        super::test_fd_renumber_same_fd()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fd_renumber_closes_destination() {
+        super::test_fd_renumber_closes_destination()
+    }
+}
+
+/// Instantiates a module importing the fd syscalls from a given WASI
+/// namespace, with `/a.txt` and `/b.txt` available in the preopened fd 4
+fn new_guest(namespace: &str) -> Guest {
+    let wat = format!(
+        r#"
+    (module
+        (import "{ns}" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "{ns}" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "{ns}" "fd_tell" (func $fd_tell (param i32 i32) (result i32)))
+        (import "{ns}" "fd_fdstat_get" (func $fd_fdstat_get (param i32 i32) (result i32)))
+        (import "{ns}" "fd_renumber" (func $fd_renumber (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 100) "a.txt")
+        (data (i32.const 110) "b.txt")
+
+        ;; Opens the file named by the 5 bytes at `$path`, the new fd is
+        ;; stored at offset 0
+        (func (export "open") (param $path i32) (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (local.get $path) (i32.const 5) (i32.const 0)
+                (i64.const -1) ;; rights
+                (i64.const 0) ;; inherited rights
+                (i32.const 0) (i32.const 0))
+        )
+
+        ;; Reads up to `$len` bytes into the buffer at offset 64, the number
+        ;; of bytes read is stored at offset 4
+        (func (export "read") (param $fd i32) (param $len i32) (result i32)
+            (i32.store (i32.const 8) (i32.const 64))
+            (i32.store (i32.const 12) (local.get $len))
+            (call $fd_read (local.get $fd) (i32.const 8) (i32.const 1) (i32.const 4))
+        )
+
+        ;; The offset is stored at offset 16
+        (func (export "tell") (param $fd i32) (result i32)
+            (call $fd_tell (local.get $fd) (i32.const 16))
+        )
+
+        ;; The fdstat is stored at offset 32
+        (func (export "fdstat") (param $fd i32) (result i32)
+            (call $fd_fdstat_get (local.get $fd) (i32.const 32))
+        )
+
+        (func (export "renumber") (param $from i32) (param $to i32) (result i32)
+            (call $fd_renumber (local.get $from) (local.get $to))
+        )
+    )
+    "#,
+        ns = namespace
+    );
+
+    let fs = mem_fs::FileSystem::default();
+    for (path, content) in [("/a.txt", b"aaaaaaaa"), ("/b.txt", b"bbbbbbbb")] {
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open(Path::new(path))
+            .unwrap()
+            .write_all(content)
+            .unwrap();
+    }
+
+    run_wat(wat, |builder| {
+        builder.set_fs(Box::new(fs)).preopen_dir("/").unwrap();
+    })
+}
+
+impl Guest {
+    fn open(&mut self, path: i32) -> i32 {
+        assert_eq!(
+            self.call_errno("open", &[Value::I32(path)]),
+            __WASI_ESUCCESS
+        );
+        self.read_u32(0) as i32
+    }
+
+    fn read(&mut self, fd: i32, len: i32) -> Result<Vec<u8>, u16> {
+        match self.call_errno("read", &[Value::I32(fd), Value::I32(len)]) {
+            __WASI_ESUCCESS => Ok(self.read_memory(64, self.read_u32(4) as usize)),
+            errno => Err(errno),
+        }
+    }
+
+    /// Opens `file` as `name` in the preopened fd 4 from the host
+    fn open_host_file(&mut self, name: &str, file: Box<dyn VirtualFile + Send + Sync>) -> i32 {
+        let state = Arc::get_mut(&mut self.env_mut().state).unwrap();
+        let mut inodes = state.inodes.write().unwrap();
+        let fd = state
+            .fs
+            .open_file_at(
+                &mut inodes,
+                4,
+                file,
+                0,
+                name.to_string(),
+                ALL_RIGHTS,
+                ALL_RIGHTS,
+                0,
+            )
+            .unwrap();
+        fd as i32
+    }
+
+    /// Returns the `(fs_rights_base, fs_rights_inheriting)` of `fd`
+    fn rights(&mut self, fd: i32) -> (u64, u64) {
+        assert_eq!(
+            self.call_errno("fdstat", &[Value::I32(fd)]),
+            __WASI_ESUCCESS
+        );
+        (self.read_u64(40), self.read_u64(48))
+    }
+}
+
+/// An empty file sharing `Arc` with the test, which can tell if the file was
+/// dropped from the strong count
+#[derive(Debug)]
+struct TrackedFile(Arc<()>);
+
+impl Read for TrackedFile {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for TrackedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for TrackedFile {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl VirtualFile for TrackedFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+fn test_fd_renumber_preserves_offset() {
+    for namespace in NAMESPACES {
+        let mut guest = new_guest(namespace);
+        let fd = guest.open(100);
+        assert_eq!(guest.read(fd, 3), Ok(b"aaa".to_vec()));
+
+        assert_eq!(
+            guest.call_errno("renumber", &[Value::I32(fd), Value::I32(20)]),
+            __WASI_ESUCCESS
+        );
+        assert_eq!(guest.call_errno("tell", &[Value::I32(20)]), __WASI_ESUCCESS);
+        assert_eq!(guest.read_u64(16), 3, "{}", namespace);
+        assert_eq!(guest.read(20, 8), Ok(b"aaaaa".to_vec()), "{}", namespace);
+        assert_eq!(guest.read(fd, 8), Err(__WASI_EBADF), "{}", namespace);
+    }
+}
+
+fn test_fd_renumber_preserves_rights() {
+    for namespace in NAMESPACES {
+        let mut guest = new_guest(namespace);
+        let fd = guest.open(100);
+        let rights = guest.rights(fd);
+        assert_ne!(rights.0, 0);
+
+        assert_eq!(
+            guest.call_errno("renumber", &[Value::I32(fd), Value::I32(20)]),
+            __WASI_ESUCCESS
+        );
+        assert_eq!(guest.rights(20), rights, "{}", namespace);
+    }
+}
+
+fn test_fd_renumber_replaces_destination() {
+    for namespace in NAMESPACES {
+        let mut guest = new_guest(namespace);
+        let a = guest.open(100);
+        let b = guest.open(110);
+
+        assert_eq!(
+            guest.call_errno("renumber", &[Value::I32(a), Value::I32(b)]),
+            __WASI_ESUCCESS
+        );
+        assert_eq!(guest.read(b, 8), Ok(b"aaaaaaaa".to_vec()), "{}", namespace);
+        assert_eq!(guest.read(a, 8), Err(__WASI_EBADF), "{}", namespace);
+    }
+}
+
+fn test_fd_renumber_same_fd() {
+    for namespace in NAMESPACES {
+        let mut guest = new_guest(namespace);
+        let fd = guest.open(100);
+        assert_eq!(guest.read(fd, 3), Ok(b"aaa".to_vec()));
+
+        assert_eq!(
+            guest.call_errno("renumber", &[Value::I32(fd), Value::I32(fd)]),
+            __WASI_ESUCCESS
+        );
+        assert_eq!(guest.read(fd, 8), Ok(b"aaaaa".to_vec()), "{}", namespace);
+    }
+}
+
+fn test_fd_renumber_closes_destination() {
+    for namespace in NAMESPACES {
+        let mut guest = new_guest(namespace);
+        let tracker = Arc::new(());
+        let to = guest.open_host_file("tracked", Box::new(TrackedFile(tracker.clone())));
+        let from = guest.open(100);
+        assert_eq!(Arc::strong_count(&tracker), 2);
+
+        assert_eq!(
+            guest.call_errno("renumber", &[Value::I32(from), Value::I32(to)]),
+            __WASI_ESUCCESS
+        );
+        assert_eq!(Arc::strong_count(&tracker), 1, "{}", namespace);
+        assert_eq!(guest.read(to, 8), Ok(b"aaaaaaaa".to_vec()), "{}", namespace);
+        assert_eq!(guest.read(from, 8), Err(__WASI_EBADF), "{}", namespace);
+
+        // `from` is no longer open
+        assert_eq!(
+            guest.call_errno("renumber", &[Value::I32(from), Value::I32(to)]),
+            __WASI_EBADF,
+            "{}",
+            namespace
+        );
+        assert_eq!(guest.read(to, 8), Ok(Vec::new()), "{}", namespace);
+    }
+}
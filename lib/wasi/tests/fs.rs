@@ -0,0 +1,3999 @@
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use wasmer::{Instance, Memory, Module, Store, Value};
+use wasmer_vfs::{mem_fs, FileSystem, VirtualFile};
+use wasmer_wasi::types::{
+    __WASI_EACCES, __WASI_EBADF, __WASI_EEXIST, __WASI_EILSEQ, __WASI_EINVAL, __WASI_EIO,
+    __WASI_EISDIR, __WASI_ELOOP, __WASI_EMFILE, __WASI_ENAMETOOLONG, __WASI_ENOENT, __WASI_ENOSPC,
+    __WASI_ENOTDIR, __WASI_ENOTEMPTY, __WASI_EPERM, __WASI_ERANGE, __WASI_EROFS, __WASI_ESUCCESS,
+    __WASI_EXDEV, __WASI_FDFLAG_APPEND, __WASI_FDFLAG_DSYNC, __WASI_FDFLAG_NONBLOCK,
+    __WASI_FDFLAG_RSYNC, __WASI_FDFLAG_SYNC, __WASI_FILETYPE_DIRECTORY,
+    __WASI_FILETYPE_REGULAR_FILE, __WASI_FILETYPE_SYMBOLIC_LINK, __WASI_LOOKUP_SYMLINK_FOLLOW,
+    __WASI_O_CREAT, __WASI_O_DIRECTORY, __WASI_O_EXCL, __WASI_O_TRUNC,
+    __WASI_RIGHT_FD_FDSTAT_SET_FLAGS, __WASI_RIGHT_FD_READ,
+};
+use wasmer_wasi::{
+    FdOptions, FsError, FsSnapshot, MountedFileSystem, QuotaFile, ValidatingFile, WasiEnv,
+    WasiFunctionEnv, WasiState, WasiStateBuilder, WasiStateCreationError, ALL_RIGHTS,
+    DEFAULT_IO_BLOCK_SIZE, DEFAULT_MAX_PATH_LEN, VIRTUAL_ROOT_FD,
+};
+
+mod common;
+use common::{run_wat, run_wat_with, Guest};
+
+mod sys {
+    #[test]
+    fn test_readonly_root_with_scratch() {
+        super::test_readonly_root_with_scratch()
+    }
+
+    #[test]
+    fn test_path_open_invalid_utf8() {
+        super::test_path_open_invalid_utf8()
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_path_open_non_ascii() {
+        super::test_path_open_non_ascii()
+    }
+
+    #[test]
+    fn test_materialize_to() {
+        super::test_materialize_to()
+    }
+
+    #[test]
+    fn test_read_removed_file() {
+        super::test_read_removed_file()
+    }
+
+    #[test]
+    fn test_removed_file_errno() {
+        super::test_removed_file_errno()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fd_filestat_set_times() {
+        super::test_fd_filestat_set_times()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_filestat_set_times() {
+        super::test_path_filestat_set_times()
+    }
+
+    #[test]
+    fn test_vectored_host_io() {
+        super::test_vectored_host_io()
+    }
+
+    #[test]
+    fn test_open_file_relative() {
+        super::test_open_file_relative()
+    }
+
+    #[test]
+    fn test_map_dir() {
+        super::test_map_dir()
+    }
+
+    #[test]
+    fn test_validating_file() {
+        super::test_validating_file()
+    }
+
+    #[test]
+    fn test_quota_file() {
+        super::test_quota_file()
+    }
+
+    #[test]
+    fn test_content_hash() {
+        super::test_content_hash()
+    }
+
+    #[test]
+    fn test_fd_allocate() {
+        super::test_fd_allocate()
+    }
+
+    #[test]
+    fn test_path_symlink() {
+        super::test_path_symlink()
+    }
+
+    #[test]
+    fn test_fork() {
+        super::test_fork()
+    }
+
+    #[test]
+    fn test_path_open_excl() {
+        super::test_path_open_excl()
+    }
+
+    #[test]
+    fn test_create_file() {
+        super::test_create_file()
+    }
+
+    #[test]
+    fn test_read_dir() {
+        super::test_read_dir()
+    }
+
+    #[test]
+    fn test_path_rename_across_preopens() {
+        super::test_path_rename_across_preopens()
+    }
+
+    #[test]
+    fn test_devfs() {
+        super::test_devfs()
+    }
+
+    #[test]
+    fn test_filestat_current_len() {
+        super::test_filestat_current_len()
+    }
+
+    #[test]
+    fn test_current_dir() {
+        super::test_current_dir()
+    }
+
+    #[test]
+    fn test_pread_pwrite_past_eof() {
+        super::test_pread_pwrite_past_eof()
+    }
+
+    #[test]
+    fn test_fdstat_set_flags() {
+        super::test_fdstat_set_flags()
+    }
+
+    #[test]
+    fn test_fd_seek_end() {
+        super::test_fd_seek_end()
+    }
+
+    #[test]
+    fn test_read_dir_fd() {
+        super::test_read_dir_fd()
+    }
+
+    #[test]
+    fn test_shared_readonly_fs() {
+        super::test_shared_readonly_fs()
+    }
+
+    #[test]
+    fn test_rename_overwrite() {
+        super::test_rename_overwrite()
+    }
+
+    #[test]
+    fn test_path_mapper() {
+        super::test_path_mapper()
+    }
+    #[test]
+    fn test_default_dir_mode() {
+        super::test_default_dir_mode()
+    }
+
+    #[test]
+    fn test_take_file() {
+        super::test_take_file()
+    }
+
+    #[test]
+    fn test_filestat() {
+        super::test_filestat()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_umask() {
+        super::test_file_umask()
+    }
+
+    #[test]
+    fn test_nested_preopens() {
+        super::test_nested_preopens()
+    }
+
+    #[test]
+    fn test_path_open_kind_mismatch() {
+        super::test_path_open_kind_mismatch()
+    }
+
+    #[test]
+    fn test_max_open_fds() {
+        super::test_max_open_fds()
+    }
+
+    #[test]
+    fn test_fd_reflection() {
+        super::test_fd_reflection()
+    }
+
+    #[test]
+    fn test_preferred_io_block_size() {
+        super::test_preferred_io_block_size()
+    }
+
+    #[test]
+    fn test_zero_length_iovecs() {
+        super::test_zero_length_iovecs()
+    }
+
+    #[test]
+    fn test_prestat_dir_name_round_trip() {
+        super::test_prestat_dir_name_round_trip()
+    }
+
+    #[test]
+    fn test_mount_tar() {
+        super::test_mount_tar()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_no_follow_symlinks() {
+        super::test_no_follow_symlinks()
+    }
+
+    #[test]
+    fn test_path_remove_directory() {
+        super::test_path_remove_directory()
+    }
+
+    #[test]
+    fn test_path_remove_directory_host() {
+        super::test_path_remove_directory_host()
+    }
+
+    #[test]
+    fn test_max_path_len() {
+        super::test_max_path_len()
+    }
+
+    #[test]
+    fn test_symlink_loops() {
+        super::test_symlink_loops()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_loops_host() {
+        super::test_symlink_loops_host()
+    }
+}
+
+#[cfg(feature = "js")]
+mod js {
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    fn test_readonly_root_with_scratch() {
+        super::test_readonly_root_with_scratch()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_path_open_invalid_utf8() {
+        super::test_path_open_invalid_utf8()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_open_file_relative() {
+        super::test_open_file_relative()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_validating_file() {
+        super::test_validating_file()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_quota_file() {
+        super::test_quota_file()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_content_hash() {
+        super::test_content_hash()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fd_allocate() {
+        super::test_fd_allocate()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_path_symlink() {
+        super::test_path_symlink()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fork() {
+        super::test_fork()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_create_file() {
+        super::test_create_file()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_read_dir() {
+        super::test_read_dir()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_path_rename_across_preopens() {
+        super::test_path_rename_across_preopens()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_devfs() {
+        super::test_devfs()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_current_dir() {
+        super::test_current_dir()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_pread_pwrite_past_eof() {
+        super::test_pread_pwrite_past_eof()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fdstat_set_flags() {
+        super::test_fdstat_set_flags()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fd_seek_end() {
+        super::test_fd_seek_end()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_read_dir_fd() {
+        super::test_read_dir_fd()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_shared_readonly_fs() {
+        super::test_shared_readonly_fs()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_rename_overwrite() {
+        super::test_rename_overwrite()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_path_mapper() {
+        super::test_path_mapper()
+    }
+    #[wasm_bindgen_test]
+    fn test_default_dir_mode() {
+        super::test_default_dir_mode()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_take_file() {
+        super::test_take_file()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_removed_file_errno() {
+        super::test_removed_file_errno()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_filestat() {
+        super::test_filestat()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_path_open_kind_mismatch() {
+        super::test_path_open_kind_mismatch()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_max_open_fds() {
+        super::test_max_open_fds()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fd_reflection() {
+        super::test_fd_reflection()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_preferred_io_block_size() {
+        super::test_preferred_io_block_size()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_zero_length_iovecs() {
+        super::test_zero_length_iovecs()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_prestat_dir_name_round_trip() {
+        super::test_prestat_dir_name_round_trip()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_mount_tar() {
+        super::test_mount_tar()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_path_remove_directory() {
+        super::test_path_remove_directory()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_max_path_len() {
+        super::test_max_path_len()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_symlink_loops() {
+        super::test_symlink_loops()
+    }
+}
+
+fn test_readonly_root_with_scratch() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "hello")
+        (data (i32.const 32) "hi")
+
+        ;; Creates `hello` in the preopened directory `$dir` and writes to it
+        (func $create (param $dir i32) (result i32)
+            (local $errno i32)
+            (local.set $errno
+                (call $path_open
+                    (local.get $dir) (i32.const 0) (i32.const 16) (i32.const 5)
+                    (i32.const 1) ;; __WASI_O_CREAT
+                    (i64.const -1) (i64.const -1) (i32.const 0)
+                    (i32.const 0) ;; new fd
+                )
+            )
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (i32.store (i32.const 4) (i32.const 32))
+            (i32.store (i32.const 8) (i32.const 2))
+            (call $fd_write (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+
+        ;; fd 4 is the root, fd 5 the scratch directory
+        (func (export "create_in_root") (result i32) (call $create (i32.const 4)))
+        (func (export "create_in_scratch") (result i32) (call $create (i32.const 5)))
+    )
+    "#;
+    let root = tempfile::tempdir().unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .readonly_root_with_scratch(root.path(), "/tmp")
+            .unwrap();
+    });
+
+    assert_eq!(guest.call_errno("create_in_scratch", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("create_in_root", &[]), __WASI_EPERM);
+    assert!(!root.path().join("hello").exists());
+}
+
+/// A module exporting `open_valid` and `open_invalid`, which
+/// open a file named `café.txt` in the preopened directory, respectively
+/// with a valid and an invalid UTF-8 encoding of the name, and return the
+/// errno of `path_open`
+const PATH_OPEN_NON_ASCII_WAT: &[u8] = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "caf\c3\a9.txt")
+        (data (i32.const 32) "caf\e9.txt")
+
+        (func (export "open_valid") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 9)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0) (i32.const 0))
+        )
+        (func (export "open_invalid") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 32) (i32.const 8)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0) (i32.const 0))
+        )
+    )
+    "#;
+
+fn test_path_open_invalid_utf8() {
+    let mut guest = run_wat(PATH_OPEN_NON_ASCII_WAT, |builder| {
+        builder.preopen_dir(".").unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open_invalid", &[]), __WASI_EILSEQ);
+}
+
+#[cfg(windows)]
+fn test_path_open_non_ascii() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("caf\u{e9}.txt"), b"").unwrap();
+
+    let mut guest = run_wat(PATH_OPEN_NON_ASCII_WAT, |builder| {
+        builder.preopen_dir(dir.path()).unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open_valid", &[]), __WASI_ESUCCESS);
+}
+
+fn test_materialize_to() {
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.create_dir(Path::new("/data/sub")).unwrap();
+    let write_file = |path: &str, content: &[u8]| {
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap()
+            .write_all(content)
+            .unwrap()
+    };
+    write_file("/data/a.txt", b"hello");
+    write_file("/data/sub/b.txt", b"world");
+
+    let state = WasiState::new("command-name")
+        .set_fs(Box::new(fs.clone()))
+        .map_dir("data", "/data")
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let host_dir = std::env::temp_dir().join("wasmer-wasi-materialize");
+    let _ = std::fs::remove_dir_all(&host_dir);
+    let inodes = state.inodes.read().unwrap();
+
+    state.fs.materialize_to(&inodes, &host_dir, false).unwrap();
+    assert_eq!(
+        std::fs::read(host_dir.join("data/a.txt")).unwrap(),
+        b"hello"
+    );
+    assert_eq!(
+        std::fs::read(host_dir.join("data/sub/b.txt")).unwrap(),
+        b"world"
+    );
+
+    // Only the modified file is written again, host-only files are kept
+    write_file("/data/sub/b.txt", b"changed");
+    std::fs::write(host_dir.join("data/host-only.txt"), b"kept").unwrap();
+
+    state.fs.materialize_to(&inodes, &host_dir, true).unwrap();
+    assert_eq!(
+        std::fs::read(host_dir.join("data/sub/b.txt")).unwrap(),
+        b"changed"
+    );
+    assert_eq!(
+        std::fs::read(host_dir.join("data/host-only.txt")).unwrap(),
+        b"kept"
+    );
+}
+
+fn test_read_removed_file() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "removed.txt")
+
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 11)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        ;; Reads the file into the buffer at offset 64, the number of bytes
+        ;; read is stored at offset 12
+        (func (export "read") (result i32)
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 32))
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+    )
+    "#;
+    let dir = std::env::temp_dir().join("wasmer-wasi-removed-file");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("removed.txt"), b"still here").unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.preopen_dir(&dir).unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+
+    std::fs::remove_file(dir.join("removed.txt")).unwrap();
+
+    let errno = guest.call_errno("read", &[]);
+    if cfg!(unix) {
+        // The fd keeps the removed file alive until it is closed
+        assert_eq!(errno, __WASI_ESUCCESS);
+        let nread = guest.read_u32(12) as usize;
+        assert_eq!(guest.read_memory(64, nread), b"still here");
+    } else {
+        assert!(errno == __WASI_ESUCCESS || errno == __WASI_EBADF);
+    }
+}
+
+/// A file removed from the host by another process, on a host that reports
+/// it as missing
+#[derive(Debug)]
+struct RemovedFile;
+
+impl Read for RemovedFile {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::NotFound.into())
+    }
+}
+
+impl Write for RemovedFile {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::NotFound.into())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::io::Seek for RemovedFile {
+    fn seek(&mut self, _pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl VirtualFile for RemovedFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+fn test_removed_file_errno() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_pread" (func $fd_pread (param i32 i32 i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_pwrite" (func $fd_pwrite (param i32 i32 i32 i64 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 4) "\40\00\00\00\08\00\00\00")
+        (data (i32.const 16) "removed.txt")
+
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 11)
+                (i32.const 1) ;; __WASI_O_CREAT
+                (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        (func (export "read") (result i32)
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+        (func (export "pread") (result i32)
+            (call $fd_pread (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i64.const 0) (i32.const 12))
+        )
+        (func (export "write") (result i32)
+            (call $fd_write (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+        (func (export "pwrite") (result i32)
+            (call $fd_pwrite (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i64.const 0) (i32.const 12))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(mem_fs::FileSystem::default()))
+            .preopen_dir("/")
+            .unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    {
+        let state = &guest.env().state;
+        let inodes = state.inodes.read().unwrap();
+        state
+            .fs
+            .swap_file(&inodes, guest.read_u32(0), Box::new(RemovedFile))
+            .unwrap();
+    }
+
+    // The fd is still open, the file behind it is missing
+    for name in ["read", "pread", "write", "pwrite"] {
+        assert_eq!(guest.call_errno(name, &[]), __WASI_EBADF, "{}", name);
+    }
+}
+
+#[cfg(unix)]
+fn test_fd_filestat_set_times() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_filestat_set_times" (func $fd_filestat_set_times (param i32 i64 i64 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "times.txt")
+
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 9)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        (func (export "set_times") (param $atim i64) (param $mtim i64) (param $flags i32) (result i32)
+            (call $fd_filestat_set_times (i32.load (i32.const 0)) (local.get $atim) (local.get $mtim) (local.get $flags))
+        )
+    )
+    "#;
+    let dir = std::env::temp_dir().join("wasmer-wasi-set-times");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("times.txt"), b"").unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.preopen_dir(&dir).unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+
+    const ATIM: i32 = 1;
+    const ATIM_NOW: i32 = 2;
+    const MTIM: i32 = 4;
+    const MTIM_NOW: i32 = 8;
+    let mut set_times = |atim: i64, mtim: i64, flags: i32| {
+        guest.call_errno(
+            "set_times",
+            &[Value::I64(atim), Value::I64(mtim), Value::I32(flags)],
+        )
+    };
+    let metadata = || std::fs::metadata(dir.join("times.txt")).unwrap();
+
+    // Explicit and `NOW` bits can't be combined for the same timestamp
+    assert_eq!(set_times(0, 0, ATIM | ATIM_NOW), __WASI_EINVAL);
+    assert_eq!(set_times(0, 0, MTIM | MTIM_NOW), __WASI_EINVAL);
+
+    let atim = 1_000_000_000_000_000_000i64;
+    let mtim = 1_100_000_000_000_000_000i64;
+    assert_eq!(set_times(atim, mtim, ATIM | MTIM), __WASI_ESUCCESS);
+    assert_eq!(
+        metadata().accessed().unwrap(),
+        UNIX_EPOCH + Duration::from_nanos(atim as u64)
+    );
+    assert_eq!(
+        metadata().modified().unwrap(),
+        UNIX_EPOCH + Duration::from_nanos(mtim as u64)
+    );
+
+    // Only the modification time is set to the current time
+    let before = SystemTime::now() - Duration::from_secs(1);
+    assert_eq!(set_times(0, 0, MTIM_NOW), __WASI_ESUCCESS);
+    assert!(metadata().modified().unwrap() >= before);
+    assert_eq!(
+        metadata().accessed().unwrap(),
+        UNIX_EPOCH + Duration::from_nanos(atim as u64)
+    );
+}
+
+#[cfg(unix)]
+fn test_path_filestat_set_times() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_filestat_set_times" (func $path_filestat_set_times (param i32 i32 i32 i32 i64 i64 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "times.txt")
+        (data (i32.const 32) "link.txt")
+
+        (func (export "set_file_times") (param $atim i64) (param $mtim i64) (param $flags i32) (result i32)
+            (call $path_filestat_set_times
+                (i32.const 4) (i32.const 1) (i32.const 16) (i32.const 9)
+                (local.get $atim) (local.get $mtim) (local.get $flags))
+        )
+
+        (func (export "set_link_times") (param $atim i64) (param $mtim i64) (param $flags i32) (result i32)
+            (call $path_filestat_set_times
+                (i32.const 4) (i32.const 0) (i32.const 32) (i32.const 8)
+                (local.get $atim) (local.get $mtim) (local.get $flags))
+        )
+    )
+    "#;
+    let dir = std::env::temp_dir().join("wasmer-wasi-path-set-times");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("times.txt"), b"").unwrap();
+    std::os::unix::fs::symlink("times.txt", dir.join("link.txt")).unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.preopen_dir(&dir).unwrap();
+    });
+
+    const ATIM: i32 = 1;
+    const ATIM_NOW: i32 = 2;
+    const MTIM: i32 = 4;
+    const MTIM_NOW: i32 = 8;
+    let mut set_times = |name: &str, atim: i64, mtim: i64, flags: i32| {
+        guest.call_errno(
+            name,
+            &[Value::I64(atim), Value::I64(mtim), Value::I32(flags)],
+        )
+    };
+    let metadata = || std::fs::metadata(dir.join("times.txt")).unwrap();
+    let link_metadata = || std::fs::symlink_metadata(dir.join("link.txt")).unwrap();
+
+    assert_eq!(
+        set_times("set_file_times", 0, 0, ATIM | ATIM_NOW),
+        __WASI_EINVAL
+    );
+
+    let atim = 1_000_000_000_000_000_000i64;
+    let mtim = 1_100_000_000_000_000_000i64;
+    assert_eq!(
+        set_times("set_file_times", atim, mtim, ATIM | MTIM),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(
+        metadata().accessed().unwrap(),
+        UNIX_EPOCH + Duration::from_nanos(atim as u64)
+    );
+    assert_eq!(
+        metadata().modified().unwrap(),
+        UNIX_EPOCH + Duration::from_nanos(mtim as u64)
+    );
+
+    // Only the modification time is set to the current time
+    let before = SystemTime::now() - Duration::from_secs(1);
+    assert_eq!(set_times("set_file_times", 0, 0, MTIM_NOW), __WASI_ESUCCESS);
+    assert!(metadata().modified().unwrap() >= before);
+    assert_eq!(
+        metadata().accessed().unwrap(),
+        UNIX_EPOCH + Duration::from_nanos(atim as u64)
+    );
+
+    // Without `__WASI_LOOKUP_SYMLINK_FOLLOW` the link itself is updated
+    let target_modified = metadata().modified().unwrap();
+    assert_eq!(set_times("set_link_times", 0, mtim, MTIM), __WASI_ESUCCESS);
+    assert_eq!(
+        link_metadata().modified().unwrap(),
+        UNIX_EPOCH + Duration::from_nanos(mtim as u64)
+    );
+    assert_eq!(metadata().modified().unwrap(), target_modified);
+}
+
+fn test_vectored_host_io() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "vectored.txt")
+        (data (i32.const 32) "abcdefghij")
+
+        (func $open (param $oflags i32) (result i32)
+            (drop (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 12)
+                (local.get $oflags) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            ))
+            (i32.load (i32.const 0))
+        )
+
+        ;; Writes `abcdefghij` with one iovec per byte, returns the number of
+        ;; bytes written
+        (func (export "write") (result i32)
+            (local $i i32)
+            (loop $iovs
+                (i32.store (i32.add (i32.const 256) (i32.mul (local.get $i) (i32.const 8)))
+                    (i32.add (i32.const 32) (local.get $i)))
+                (i32.store (i32.add (i32.const 260) (i32.mul (local.get $i) (i32.const 8)))
+                    (i32.const 1))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br_if $iovs (i32.lt_u (local.get $i) (i32.const 10)))
+            )
+            (drop (call $fd_write
+                (call $open (i32.const 9)) ;; __WASI_O_CREAT | __WASI_O_TRUNC
+                (i32.const 256) (i32.const 10) (i32.const 8)))
+            (i32.load (i32.const 8))
+        )
+
+        ;; Reads the file back into three 4-byte iovecs at offsets 512, 520
+        ;; and 528, returns the number of bytes read
+        (func (export "read") (result i32)
+            (i32.store (i32.const 400) (i32.const 512))
+            (i32.store (i32.const 404) (i32.const 4))
+            (i32.store (i32.const 408) (i32.const 520))
+            (i32.store (i32.const 412) (i32.const 4))
+            (i32.store (i32.const 416) (i32.const 528))
+            (i32.store (i32.const 420) (i32.const 4))
+            (drop (call $fd_read (call $open (i32.const 0)) (i32.const 400) (i32.const 3) (i32.const 8)))
+            (i32.load (i32.const 8))
+        )
+    )
+    "#;
+    let dir = std::env::temp_dir().join("wasmer-wasi-vectored-io");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.preopen_dir(&dir).unwrap();
+    });
+
+    assert_eq!(*guest.call("write", &[]), [Value::I32(10)]);
+    assert_eq!(
+        std::fs::read(dir.join("vectored.txt")).unwrap(),
+        b"abcdefghij"
+    );
+
+    assert_eq!(*guest.call("read", &[]), [Value::I32(10)]);
+    assert_eq!(
+        guest.read_memory(512, 20),
+        b"abcd\0\0\0\0efgh\0\0\0\0ij\0\0"
+    );
+}
+
+fn test_open_file_relative() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "sub/hello.txt")
+
+        ;; Reads `sub/hello.txt` from the preopened directory `b` into the
+        ;; buffer at offset 64, returns the number of bytes read
+        (func (export "read") (result i32)
+            (drop (call $path_open
+                (i32.const 5) (i32.const 0) (i32.const 16) (i32.const 13)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            ))
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 32))
+            (drop (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12)))
+            (i32.load (i32.const 12))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/a")).unwrap();
+    fs.create_dir(Path::new("/b")).unwrap();
+    fs.create_dir(Path::new("/b/sub")).unwrap();
+
+    // The file provided by the host lives outside of the guest filesystem
+    let host_fs = mem_fs::FileSystem::default();
+    let mut host_file = host_fs
+        .new_open_options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(Path::new("/hello.txt"))
+        .unwrap();
+    host_file.write_all(b"from host").unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs))
+            .map_dir("a", "/a")
+            .unwrap()
+            .map_dir("b", "/b")
+            .unwrap();
+    });
+
+    {
+        let state = Arc::get_mut(&mut guest.env_mut().state).unwrap();
+        let mut inodes = state.inodes.write().unwrap();
+        let fs = &mut state.fs;
+        let options = FdOptions {
+            rights: ALL_RIGHTS,
+            rights_inheriting: ALL_RIGHTS,
+            ..FdOptions::default()
+        };
+        // the working directory `/` is not under a preopened directory
+        assert_eq!(
+            fs.open_file_relative(
+                &mut inodes,
+                host_fs
+                    .new_open_options()
+                    .read(true)
+                    .open(Path::new("/hello.txt"))
+                    .unwrap(),
+                "hello.txt",
+                options
+            ),
+            Err(FsError::InvalidFd)
+        );
+        assert_eq!(
+            fs.set_cwd(&inodes, "/elsewhere"),
+            Err(FsError::EntityNotFound)
+        );
+
+        fs.set_cwd(&inodes, "/b").unwrap();
+        assert_eq!(fs.cwd(), "/b");
+        fs.open_file_relative(&mut inodes, host_file, "sub/hello.txt", options)
+            .unwrap();
+    }
+
+    assert_eq!(*guest.call("read", &[]), [Value::I32(9)]);
+    assert_eq!(guest.read_memory(64, 9), b"from host");
+}
+
+fn test_map_dir() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_prestat_dir_name" (func $fd_prestat_dir_name (param i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "file.txt")
+
+        ;; Writes the name of the preopened fd 4 at offset 128
+        (func (export "prestat_dir_name") (result i32)
+            (call $fd_prestat_dir_name (i32.const 4) (i32.const 128) (i32.const 5))
+        )
+
+        ;; Reads `/data/file.txt` into the buffer at offset 64, returns the
+        ;; number of bytes read
+        (func (export "read") (result i32)
+            (drop (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            ))
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 32))
+            (drop (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12)))
+            (i32.load (i32.const 12))
+        )
+    )
+    "#;
+    let dir = std::env::temp_dir().join("wasmer-wasi-map-dir");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("file.txt"), b"mapped").unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.map_dir("/data", &dir).unwrap();
+    });
+
+    assert_eq!(guest.call_errno("prestat_dir_name", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_memory(128, 4), b"data");
+
+    assert_eq!(*guest.call("read", &[]), [Value::I32(6)]);
+    assert_eq!(guest.read_memory(64, 6), b"mapped");
+
+    // Aliases are normalized paths from the root, `data` and `/data` collide
+    assert!(matches!(
+        WasiState::new("command-name").map_dir("../data", &dir),
+        Err(WasiStateCreationError::MappedDirAliasFormattingError(_))
+    ));
+    assert!(matches!(
+        WasiState::new("command-name")
+            .map_dir("data", &dir)
+            .unwrap()
+            .map_dir("/data/", &dir)
+            .unwrap()
+            .build(),
+        Err(WasiStateCreationError::PreopenedDirectoryError(_))
+    ));
+}
+
+fn test_validating_file() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_sync" (func $fd_sync (param i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; A payload followed by the sum of its bytes
+        (data (i32.const 64) "abc\26")
+
+        ;; Writes `$len` bytes at `$ptr` to stdout
+        (func (export "write") (param $ptr i32) (param $len i32) (result i32)
+            (i32.store (i32.const 0) (local.get $ptr))
+            (i32.store (i32.const 4) (local.get $len))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+
+        (func (export "sync") (result i32)
+            (call $fd_sync (i32.const 1))
+        )
+
+        (func (export "close") (result i32)
+            (call $fd_close (i32.const 1))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    let open_output = || {
+        fs.new_open_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(Path::new("/output"))
+            .unwrap()
+    };
+    let validations = Arc::new(AtomicUsize::new(0));
+    let counter = validations.clone();
+    let output = ValidatingFile::new(
+        open_output(),
+        Box::new(move |data: &[u8]| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            match data.split_last() {
+                Some((sum, payload))
+                    if payload.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == *sum =>
+                {
+                    Ok(())
+                }
+                _ => Err(FsError::InvalidData),
+            }
+        }),
+    )
+    .unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdout(Box::new(output));
+    });
+
+    let read_output = || {
+        let mut content = Vec::new();
+        open_output().read_to_end(&mut content).unwrap();
+        content
+    };
+    let write = |guest: &mut Guest, offset: i32| {
+        guest.call_errno("write", &[Value::I32(offset), Value::I32(1)])
+    };
+
+    // The payload alone is missing its checksum, so it never reaches the
+    // wrapped file. Writes are only validated once the content is committed
+    for offset in 64..67 {
+        assert_eq!(write(&mut guest, offset), __WASI_ESUCCESS);
+    }
+    assert_eq!(validations.load(Ordering::SeqCst), 0);
+    assert_eq!(guest.call_errno("sync", &[]), __WASI_EIO);
+    assert_eq!(validations.load(Ordering::SeqCst), 1);
+    assert!(read_output().is_empty());
+
+    // Appending the checksum makes the content valid
+    assert_eq!(write(&mut guest, 67), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("sync", &[]), __WASI_ESUCCESS);
+    assert_eq!(read_output(), b"abc\x26");
+
+    // Further writes invalidate the checksum, closing the fd reports it and
+    // the committed content is kept
+    assert_eq!(write(&mut guest, 64), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("close", &[]), __WASI_EIO);
+    assert_eq!(validations.load(Ordering::SeqCst), 3);
+    assert_eq!(read_output(), b"abc\x26");
+
+    // What is left to read is the buffered content past the cursor, so that
+    // a non-blocking read at the end of the file reports the end of the file
+    let mut file = ValidatingFile::new(open_output(), Box::new(|_| Ok(()))).unwrap();
+    assert_eq!(file.bytes_available_read(), Ok(Some(4)));
+    file.write_all(b"xyz").unwrap();
+    assert_eq!(file.bytes_available_read(), Ok(Some(1)));
+    file.write_all(b"!!").unwrap();
+    assert_eq!(file.bytes_available_read(), Ok(Some(0)));
+}
+
+fn test_quota_file() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 33)
+        (export "memory" (memory 0))
+
+        ;; Writes `$len` bytes at offset 65536 to stdout
+        (func (export "write") (param $len i32) (result i32)
+            (i32.store (i32.const 0) (i32.const 65536))
+            (i32.store (i32.const 4) (local.get $len))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    const MIB: u64 = 1024 * 1024;
+    let fs = mem_fs::FileSystem::default();
+    let open_output = || {
+        fs.new_open_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(Path::new("/output"))
+            .unwrap()
+    };
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdout(Box::new(QuotaFile::new(open_output(), MIB)));
+    });
+
+    // Only the first MiB fits in the quota
+    assert_eq!(
+        guest.call_errno("write", &[Value::I32(2 * MIB as i32)]),
+        __WASI_ENOSPC
+    );
+    let mut content = Vec::new();
+    open_output().read_to_end(&mut content).unwrap();
+    assert_eq!(content.len() as u64, MIB);
+
+    assert_eq!(guest.call_errno("write", &[Value::I32(1)]), __WASI_ENOSPC);
+
+    // Growing the file counts against the quota too
+    let grown = fs
+        .new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/grown"))
+        .unwrap();
+    let mut file = QuotaFile::new(grown, 4);
+    file.write_all(b"ab").unwrap();
+    assert_eq!(file.set_len(6), Err(FsError::WriteZero));
+    assert_eq!(file.current_len(), Ok(2));
+    file.set_len(4).unwrap();
+    assert_eq!(file.remaining(), 0);
+
+    // Duplicates share the quota
+    let mut file = QuotaFile::new(open_output(), 4);
+    let mut duplicate = file.try_clone().unwrap();
+    duplicate.write_all(b"abc").unwrap();
+    assert_eq!(file.remaining(), 1);
+    assert_eq!(
+        file.write_all(b"de").unwrap_err().kind(),
+        std::io::ErrorKind::WriteZero
+    );
+}
+
+fn test_content_hash() {
+    let fs = mem_fs::FileSystem::default();
+    let mut output = fs
+        .new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/output"))
+        .unwrap();
+    output.write_all(b"hello world").unwrap();
+
+    let state = WasiState::new("command-name")
+        .stdout(output)
+        .build()
+        .unwrap();
+    let inodes = state.inodes.read().unwrap();
+
+    let digest = state.fs.content_hash(&inodes, 1).unwrap().unwrap();
+    let digest = digest
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    assert_eq!(
+        digest,
+        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+    );
+    assert_eq!(
+        state.fs.content_hash(&inodes, VIRTUAL_ROOT_FD),
+        Err(__WASI_EISDIR)
+    );
+}
+
+fn test_fd_allocate() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_allocate" (func $fd_allocate (param i32 i64 i64) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_filestat_get" (func $fd_filestat_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "data.txt")
+
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        (func (export "allocate") (param $offset i64) (param $len i64) (result i32)
+            (call $fd_allocate (i32.load (i32.const 0)) (local.get $offset) (local.get $len))
+        )
+
+        ;; Returns the size of the file
+        (func (export "size") (result i64)
+            (drop (call $fd_filestat_get (i32.load (i32.const 0)) (i32.const 64)))
+            (i64.load (i32.const 96))
+        )
+
+        ;; Reads the file into the buffer at offset 256, the number of bytes
+        ;; read is stored at offset 12
+        (func (export "read") (result i32)
+            (i32.store (i32.const 4) (i32.const 256))
+            (i32.store (i32.const 8) (i32.const 64))
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/data.txt"))
+        .unwrap()
+        .write_all(b"abc")
+        .unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.set_fs(Box::new(fs)).preopen_dir("/").unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    assert_eq!(
+        guest.call_errno("allocate", &[Value::I64(5), Value::I64(10)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(*guest.call("size", &[]), [Value::I64(15)]);
+
+    // Allocating a range that is already in the file doesn't shrink it
+    assert_eq!(
+        guest.call_errno("allocate", &[Value::I64(0), Value::I64(2)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(*guest.call("size", &[]), [Value::I64(15)]);
+
+    assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+    let nread = guest.read_u32(12) as usize;
+    assert_eq!(
+        guest.read_memory(256, nread),
+        b"abc\0\0\0\0\0\0\0\0\0\0\0\0"
+    );
+}
+
+fn test_path_symlink() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_symlink" (func $path_symlink (param i32 i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_readlink" (func $path_readlink (param i32 i32 i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "target.txt")
+        (data (i32.const 32) "link")
+
+        (func (export "symlink") (result i32)
+            (call $path_symlink (i32.const 16) (i32.const 10) (i32.const 4) (i32.const 32) (i32.const 4))
+        )
+
+        ;; Reads the link into the buffer at offset 64, the number of bytes
+        ;; used is stored at offset 8
+        (func (export "readlink") (param $buf_len i32) (result i32)
+            (call $path_readlink (i32.const 4) (i32.const 32) (i32.const 4) (i32.const 64) (local.get $buf_len) (i32.const 8))
+        )
+
+        ;; Opens the link, the new fd is stored at offset 0
+        (func (export "open") (param $dirflags i32) (result i32)
+            (call $path_open
+                (i32.const 4) (local.get $dirflags) (i32.const 32) (i32.const 4)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        ;; Reads the opened file into the buffer at offset 128, the number of
+        ;; bytes read is stored at offset 12
+        (func (export "read") (result i32)
+            (i32.store (i32.const 96) (i32.const 128))
+            (i32.store (i32.const 100) (i32.const 64))
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 96) (i32.const 1) (i32.const 12))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/target.txt"))
+        .unwrap()
+        .write_all(b"content")
+        .unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.set_fs(Box::new(fs)).preopen_dir("/").unwrap();
+    });
+
+    assert_eq!(guest.call_errno("symlink", &[]), __WASI_ESUCCESS);
+
+    assert_eq!(
+        guest.call_errno("readlink", &[Value::I32(32)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(guest.read_u32(8), 10);
+    assert_eq!(guest.read_memory(64, 10), b"target.txt");
+
+    // A buffer that is too small gets the truncated target
+    guest.write_memory(64, &[0; 10]);
+    assert_eq!(
+        guest.call_errno("readlink", &[Value::I32(6)]),
+        __WASI_ERANGE
+    );
+    assert_eq!(guest.read_u32(8), 6);
+    assert_eq!(guest.read_memory(64, 10), b"target\0\0\0\0");
+
+    // The link is followed when opening it, unless asked not to
+    assert_eq!(guest.call_errno("open", &[Value::I32(0)]), __WASI_ELOOP);
+    assert_eq!(
+        guest.call_errno("open", &[Value::I32(__WASI_LOOKUP_SYMLINK_FOLLOW as i32)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+    let nread = guest.read_u32(12) as usize;
+    assert_eq!(guest.read_memory(128, nread), b"content");
+}
+
+fn test_fork() {
+    let mut store = Store::default();
+    let module = Module::new(&mut store, br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "data.txt")
+        (data (i32.const 32) "new.txt")
+
+        ;; Opens the file named by the bytes at `$path`, creating it if
+        ;; needed, the new fd is stored at offset 0
+        (func (export "open") (param $path i32) (param $len i32) (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (local.get $path) (local.get $len)
+                (i32.const 1) ;; O_CREAT
+                (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        ;; Reads up to 3 bytes into the buffer at offset 256, the number of
+        ;; bytes read is stored at offset 12
+        (func (export "read") (param $fd i32) (result i32)
+            (i32.store (i32.const 4) (i32.const 256))
+            (i32.store (i32.const 8) (i32.const 3))
+            (call $fd_read (local.get $fd) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+
+        (func (export "close") (param $fd i32) (result i32)
+            (call $fd_close (local.get $fd))
+        )
+    )
+    "#).unwrap();
+
+    let fs = mem_fs::FileSystem::default();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/data.txt"))
+        .unwrap()
+        .write_all(b"abcdef")
+        .unwrap();
+
+    let parent_env = WasiState::new("command-name")
+        .set_fs(Box::new(fs.clone()))
+        .preopen_dir("/")
+        .unwrap()
+        .finalize(&mut store)
+        .unwrap();
+    let instantiate = |store: &mut Store, wasi_env: &WasiFunctionEnv| {
+        let import_object = wasi_env.import_object(store, &module).unwrap();
+        let instance = Instance::new(store, &module, &import_object).unwrap();
+        let memory = instance.exports.get_memory("memory").unwrap().clone();
+        wasi_env.data_mut(store).set_memory(memory.clone());
+        (instance, memory)
+    };
+    let call = |store: &mut Store, guest: &(Instance, Memory), name: &str, params: &[Value]| {
+        let function = guest.0.exports.get_function(name).unwrap();
+        function.call(store, params).unwrap()[0].clone()
+    };
+    let open = |store: &mut Store, guest: &(Instance, Memory), path: i32, len: i32| {
+        assert_eq!(
+            call(store, guest, "open", &[Value::I32(path), Value::I32(len)]),
+            Value::I32(__WASI_ESUCCESS as i32)
+        );
+        let mut fd = [0; 4];
+        guest.1.read(store, 0, &mut fd).unwrap();
+        i32::from_le_bytes(fd)
+    };
+    let read = |store: &mut Store, guest: &(Instance, Memory), fd: i32| {
+        assert_eq!(
+            call(store, guest, "read", &[Value::I32(fd)]),
+            Value::I32(__WASI_ESUCCESS as i32)
+        );
+        let mut nread = [0; 4];
+        guest.1.read(store, 12, &mut nread).unwrap();
+        let mut data = vec![0; u32::from_le_bytes(nread) as usize];
+        guest.1.read(store, 256, &mut data).unwrap();
+        data
+    };
+
+    let parent = instantiate(&mut store, &parent_env);
+    let fd = open(&mut store, &parent, 16, 8);
+    assert_eq!(read(&mut store, &parent, fd), b"abc");
+
+    let child_state = parent_env.data_mut(&mut store).state.fork().unwrap();
+    let child_env = WasiFunctionEnv::new(&mut store, WasiEnv::new(child_state));
+    let child = instantiate(&mut store, &child_env);
+
+    // The child inherits the fd and its offset, closing it in the child
+    // leaves it open in the parent
+    assert_eq!(read(&mut store, &child, fd), b"def");
+    assert_eq!(
+        call(&mut store, &child, "close", &[Value::I32(fd)]),
+        Value::I32(__WASI_ESUCCESS as i32)
+    );
+    assert_eq!(read(&mut store, &parent, fd), b"def");
+
+    // The fds opened after the fork are not shared, and the in-memory file
+    // system is copied, the files the child creates are not visible to the
+    // parent
+    let new_fd = open(&mut store, &child, 32, 7);
+    assert_eq!(
+        call(&mut store, &parent, "read", &[Value::I32(new_fd)]),
+        Value::I32(__WASI_EBADF as i32)
+    );
+    let child_fs = &child_env.data_mut(&mut store).state.fs.fs_backing;
+    assert!(child_fs.metadata(Path::new("/new.txt")).is_ok());
+    assert!(fs.metadata(Path::new("/new.txt")).is_err());
+
+    // A child can't be given another file system than its parent
+    let state = WasiState::new("command-name")
+        .set_fs(Box::new(UnclonableFs(fs)))
+        .build()
+        .unwrap();
+    assert!(matches!(
+        state.fork(),
+        Err(WasiStateCreationError::WasiFsCreationError(_))
+    ));
+}
+
+/// A file system that can't be shared, it doesn't implement `try_clone`
+#[derive(Debug)]
+struct UnclonableFs(mem_fs::FileSystem);
+
+impl FileSystem for UnclonableFs {
+    fn read_dir(&self, path: &Path) -> Result<wasmer_vfs::ReadDir, FsError> {
+        self.0.read_dir(path)
+    }
+    fn create_dir(&self, path: &Path) -> Result<(), FsError> {
+        self.0.create_dir(path)
+    }
+    fn remove_dir(&self, path: &Path) -> Result<(), FsError> {
+        self.0.remove_dir(path)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), FsError> {
+        self.0.rename(from, to)
+    }
+    fn metadata(&self, path: &Path) -> Result<wasmer_vfs::Metadata, FsError> {
+        self.0.metadata(path)
+    }
+    fn remove_file(&self, path: &Path) -> Result<(), FsError> {
+        self.0.remove_file(path)
+    }
+    fn new_open_options(&self) -> wasmer_vfs::OpenOptions {
+        self.0.new_open_options()
+    }
+}
+
+fn test_path_open_excl() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "excl.txt")
+
+        (func (export "create") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                (i32.const 5) ;; __WASI_O_CREAT | __WASI_O_EXCL
+                (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+    )
+    "#;
+    let dir = std::env::temp_dir().join("wasmer-wasi-path-open-excl");
+    std::fs::create_dir_all(&dir).unwrap();
+    let _ = std::fs::remove_file(dir.join("excl.txt"));
+    let fs = mem_fs::FileSystem::default();
+
+    // Two instances sharing the same host directory, then the same
+    // in-memory file system: only the first one creates the file
+    let builders: [&dyn Fn() -> WasiStateBuilder; 2] = [
+        &|| {
+            let mut builder = WasiState::new("command-name");
+            builder.preopen_dir(&dir).unwrap();
+            builder
+        },
+        &|| {
+            let mut builder = WasiState::new("command-name");
+            builder
+                .set_fs(Box::new(fs.clone()))
+                .preopen_dir("/")
+                .unwrap();
+            builder
+        },
+    ];
+    for builder in builders {
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let mut guest = run_wat_with(wat, &mut builder());
+            results.push(guest.call_errno("create", &[]));
+            // Within the same instance
+            results.push(guest.call_errno("create", &[]));
+        }
+        assert_eq!(
+            results,
+            [__WASI_ESUCCESS, __WASI_EEXIST, __WASI_EEXIST, __WASI_EEXIST]
+        );
+    }
+}
+
+fn test_create_file() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "in/nested/data.txt")
+
+        ;; Reads `in/nested/data.txt` into the buffer at offset 64, returns
+        ;; the number of bytes read
+        (func (export "read") (result i32)
+            (drop (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 18)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            ))
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 32))
+            (drop (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12)))
+            (i32.load (i32.const 12))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .preopen_dir("/")
+            .unwrap();
+    });
+
+    {
+        let state = Arc::get_mut(&mut guest.env_mut().state).unwrap();
+        let mut inodes = state.inodes.write().unwrap();
+        let wasi_fs = &mut state.fs;
+        // fd 4 is the preopened directory `/`
+        wasi_fs
+            .create_file(&mut inodes, 4, "in/nested/data.txt", b"seeded".to_vec())
+            .unwrap();
+        assert_eq!(
+            wasi_fs.create_file(&mut inodes, 4, "in/nested/data.txt", Vec::new()),
+            Err(FsError::AlreadyExists)
+        );
+        assert_eq!(
+            wasi_fs.create_file(&mut inodes, 4, "in/nested/data.txt/more", Vec::new()),
+            Err(FsError::BaseNotDirectory)
+        );
+    }
+    // The file only exists in the guest's view of the file system
+    assert!(fs.read_dir(Path::new("/in")).is_err());
+
+    assert_eq!(*guest.call("read", &[]), [Value::I32(6)]);
+    assert_eq!(guest.read_memory(64, 6), b"seeded");
+}
+
+fn test_read_dir() {
+    let mut store = Store::default();
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.create_dir(Path::new("/data/sub")).unwrap();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/data/file.txt"))
+        .unwrap();
+
+    let wasi_env = WasiState::new("command-name")
+        .set_fs(Box::new(fs))
+        .preopen_dir("/")
+        .unwrap()
+        .finalize(&mut store)
+        .unwrap();
+    let state = Arc::get_mut(&mut wasi_env.data_mut(&mut store).state).unwrap();
+    let mut inodes = state.inodes.write().unwrap();
+    let wasi_fs = &mut state.fs;
+    // fd 4 is the preopened directory `/`
+    wasi_fs
+        .create_file(&mut inodes, 4, "data/memory.txt", Vec::new())
+        .unwrap();
+    wasi_fs
+        .create_file(&mut inodes, 4, "in-memory/nested.txt", Vec::new())
+        .unwrap();
+
+    // The entries of the backing and the in-memory ones are listed
+    let entries = wasi_fs.read_dir(&mut inodes, 4, "data").unwrap();
+    let names = entries
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.filetype))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            ("file.txt", __WASI_FILETYPE_REGULAR_FILE),
+            ("memory.txt", __WASI_FILETYPE_REGULAR_FILE),
+            ("sub", __WASI_FILETYPE_DIRECTORY),
+        ]
+    );
+    for entry in entries.iter() {
+        let path = format!("data/{}", entry.name);
+        let stat = wasi_fs.filestat(&mut inodes, 4, &path, false).unwrap();
+        assert_eq!(entry.inode, stat.st_ino);
+    }
+
+    let entries = wasi_fs.read_dir(&mut inodes, 4, "in-memory").unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "nested.txt");
+    assert!(wasi_fs
+        .read_dir(&mut inodes, 4, "data/sub")
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(
+        wasi_fs.read_dir(&mut inodes, 4, "data/file.txt"),
+        Err(FsError::BaseNotDirectory)
+    );
+    assert_eq!(
+        wasi_fs.read_dir(&mut inodes, 4, "data/file.txt/sub"),
+        Err(FsError::BaseNotDirectory)
+    );
+    assert_eq!(
+        wasi_fs.read_dir(&mut inodes, 4, "missing"),
+        Err(FsError::EntityNotFound)
+    );
+}
+
+fn test_path_rename_across_preopens() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_rename" (func $path_rename (param i32 i32 i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "file.txt")
+        (data (i32.const 32) "moved.txt")
+
+        ;; Moves `file.txt` from the preopened fd 4 to `moved.txt` in the
+        ;; preopened fd 5
+        (func (export "rename") (result i32)
+            (call $path_rename
+                (i32.const 4) (i32.const 16) (i32.const 8)
+                (i32.const 5) (i32.const 32) (i32.const 9)
+            )
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/a")).unwrap();
+    fs.create_dir(Path::new("/b")).unwrap();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/a/file.txt"))
+        .unwrap()
+        .write_all(b"moving")
+        .unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .map_dir("a", "/a")
+            .unwrap()
+            .map_dir("b", "/b")
+            .unwrap();
+    });
+
+    assert_eq!(guest.call_errno("rename", &[]), __WASI_ESUCCESS);
+    assert!(fs.metadata(Path::new("/a/file.txt")).is_err());
+    let mut contents = String::new();
+    fs.new_open_options()
+        .read(true)
+        .open(Path::new("/b/moved.txt"))
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    assert_eq!(contents, "moving");
+
+    // The scratch directory is a separate file system, entries can not be
+    // moved into it from the root
+    let root = std::env::temp_dir().join("wasmer-wasi-rename-exdev");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("file.txt"), b"staying").unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.readonly_root_with_scratch(&root, "/tmp").unwrap();
+    });
+
+    assert_eq!(guest.call_errno("rename", &[]), __WASI_EXDEV);
+    assert!(root.join("file.txt").exists());
+}
+
+fn test_devfs() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "null")
+        (data (i32.const 24) "zero")
+        (data (i32.const 32) "tty")
+        (data (i32.const 48) "hello")
+
+        ;; Opens the device named at `$name` in the preopened fd 4 (`/dev`)
+        (func $open (param $name i32) (param $len i32) (result i32)
+            (local $errno i32)
+            (local.set $errno
+                (call $path_open
+                    (i32.const 4) (i32.const 0) (local.get $name) (local.get $len)
+                    (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                    (i32.const 0) ;; new fd
+                )
+            )
+            (if (local.get $errno) (then (return (i32.sub (i32.const 0) (local.get $errno)))))
+            (i32.load (i32.const 0))
+        )
+
+        ;; Writes `hello` to `/dev/null` and then reads from it, returns the
+        ;; number of bytes written and read
+        (func (export "null") (result i32 i32)
+            (local $fd i32)
+            (local.set $fd (call $open (i32.const 16) (i32.const 4)))
+            (i32.store (i32.const 4) (i32.const 48))
+            (i32.store (i32.const 8) (i32.const 5))
+            (drop (call $fd_write (local.get $fd) (i32.const 4) (i32.const 1) (i32.const 12)))
+            (i32.load (i32.const 12))
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 16))
+            (drop (call $fd_read (local.get $fd) (i32.const 4) (i32.const 1) (i32.const 12)))
+            (i32.load (i32.const 12))
+        )
+
+        ;; Reads 16 bytes from `/dev/zero` into the buffer at offset 64,
+        ;; returns the number of bytes read
+        (func (export "zero") (result i32)
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 16))
+            (drop (call $fd_read (call $open (i32.const 24) (i32.const 4)) (i32.const 4) (i32.const 1) (i32.const 12)))
+            (i32.load (i32.const 12))
+        )
+
+        ;; Opens `/dev/tty`, returns the new fd or the negated errno
+        (func (export "tty") (result i32)
+            (call $open (i32.const 32) (i32.const 3))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |builder| {
+        builder.with_devfs();
+    });
+
+    assert_eq!(*guest.call("null", &[]), [Value::I32(5), Value::I32(0)]);
+
+    guest.write_memory(64, &[0xff; 16]);
+    assert_eq!(*guest.call("zero", &[]), [Value::I32(16)]);
+    assert_eq!(guest.read_memory(64, 16), [0u8; 16]);
+
+    // The devices of the host are not exposed
+    assert_eq!(
+        *guest.call("tty", &[]),
+        [Value::I32(-(__WASI_ENOENT as i32))]
+    );
+}
+
+fn test_filestat_current_len() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_filestat_get" (func $fd_filestat_get (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "growing.txt")
+
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 11)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        ;; Writes the filestat to offset 64, `st_size` lands at offset 96
+        (func (export "stat") (result i32)
+            (call $fd_filestat_get (i32.load (i32.const 0)) (i32.const 64))
+        )
+    )
+    "#;
+    let dir = std::env::temp_dir().join("wasmer-wasi-current-len");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("growing.txt"), b"four").unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.preopen_dir(&dir).unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("stat", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u64(96), 4);
+
+    // Grow the file behind the guest's back
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join("growing.txt"))
+        .unwrap()
+        .write_all(b" and more")
+        .unwrap();
+
+    assert_eq!(guest.call_errno("stat", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u64(96), 13);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn test_current_dir() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "file.txt")
+
+        ;; Opens the relative path `file.txt` from the preopened fd 4 and
+        ;; reads it into the buffer at offset 64, the number of bytes read
+        ;; is stored at offset 12
+        (func (export "read") (result i32)
+            (local $errno i32)
+            (local.set $errno
+                (call $path_open
+                    (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                    (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                    (i32.const 0) ;; new fd
+                )
+            )
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 32))
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.create_dir(Path::new("/data/sub")).unwrap();
+    for (path, contents) in [("/data/file.txt", "top"), ("/data/sub/file.txt", "nested")] {
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open(Path::new(path))
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs))
+            .map_dir("data", "/data")
+            .unwrap()
+            .current_dir("/data/sub");
+    });
+
+    assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+    let nread = guest.read_u32(12) as usize;
+    assert_eq!(guest.read_memory(64, nread), b"nested");
+}
+
+fn test_fd_seek_end() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_seek" (func $fd_seek (param i32 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "file.txt")
+        (data (i32.const 32) "hello world")
+        (data (i32.const 48) "!")
+
+        ;; Opens `file.txt` with the fd flags `$flags`, the fd is stored at
+        ;; offset 0
+        (func (export "open") (param $flags i32) (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                (i32.const 1) ;; O_CREAT
+                (i64.const -1) (i64.const -1) (local.get $flags)
+                (i32.const 0) ;; new fd
+            )
+        )
+        ;; Writes the `$len` bytes at `$ptr` at the cursor
+        (func (export "write") (param $ptr i32) (param $len i32) (result i32)
+            (i32.store (i32.const 4) (local.get $ptr))
+            (i32.store (i32.const 8) (local.get $len))
+            (call $fd_write (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+        ;; Seeks relative to the end of the file, the new offset is stored at
+        ;; offset 64
+        (func (export "seek_end") (param $offset i64) (result i32)
+            (call $fd_seek (i32.load (i32.const 0)) (local.get $offset) (i32.const 2) (i32.const 64))
+        )
+        ;; Reads up to 16 bytes at the cursor to offset 128, the number of
+        ;; bytes read is stored at offset 12
+        (func (export "read") (result i32)
+            (i32.store (i32.const 4) (i32.const 128))
+            (i32.store (i32.const 8) (i32.const 16))
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .preopen_dir("/")
+            .unwrap();
+    });
+
+    let seek_end = |guest: &mut Guest, offset: i64| {
+        let errno = guest.call_errno("seek_end", &[Value::I64(offset)]);
+        (errno, guest.read_u64(64))
+    };
+    let read = |guest: &mut Guest| {
+        assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+        let len = guest.read_u32(12) as usize;
+        guest.read_memory(128, len)
+    };
+
+    assert_eq!(guest.call_errno("open", &[Value::I32(0)]), __WASI_ESUCCESS);
+    let hello = [Value::I32(32), Value::I32(11)];
+    assert_eq!(guest.call_errno("write", &hello), __WASI_ESUCCESS);
+    assert_eq!(seek_end(&mut guest, -4), (__WASI_ESUCCESS, 7));
+    assert_eq!(read(&mut guest), b"orld");
+
+    // Seeking past the end is fine, the next write leaves a gap of zeros
+    assert_eq!(seek_end(&mut guest, 2), (__WASI_ESUCCESS, 13));
+    assert_eq!(read(&mut guest), b"");
+    let bang = [Value::I32(48), Value::I32(1)];
+    assert_eq!(guest.call_errno("write", &bang), __WASI_ESUCCESS);
+    let mut contents = Vec::new();
+    fs.new_open_options()
+        .read(true)
+        .open(Path::new("/file.txt"))
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    assert_eq!(contents, b"hello world\0\0!");
+
+    // But seeking before the start is not
+    assert_eq!(seek_end(&mut guest, -15).0, __WASI_EINVAL);
+
+    // The end is the length of the file, also for a handle in append mode
+    // that didn't write anything yet
+    assert_eq!(
+        guest.call_errno("open", &[Value::I32(__WASI_FDFLAG_APPEND as i32)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(seek_end(&mut guest, 0), (__WASI_ESUCCESS, 14));
+}
+
+fn test_read_dir_fd() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_pread" (func $fd_pread (param i32 i32 i32 i64 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "dir")
+
+        ;; Opens `dir` as a directory, the fd is stored at offset 0
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 3)
+                (i32.const 2) ;; O_DIRECTORY
+                (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+        ;; Reads up to `$len` bytes of `$fd` to offset 64
+        (func (export "read") (param $fd i32) (param $len i32) (result i32)
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (local.get $len))
+            (call $fd_read (local.get $fd) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+        (func (export "pread") (param $fd i32) (result i32)
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 16))
+            (call $fd_pread (local.get $fd) (i32.const 4) (i32.const 1) (i64.const 0) (i32.const 12))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/dir")).unwrap();
+    let mut guest = run_wat(wat, |builder| {
+        builder.set_fs(Box::new(fs)).preopen_dir("/").unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    let dir_fd = guest.read_u32(0) as i32;
+
+    // Both the preopened directory, which has no read right, and the opened
+    // one fail the same way, even when reading nothing
+    for fd in [4, dir_fd] {
+        let read = guest.call_errno("read", &[Value::I32(fd), Value::I32(16)]);
+        assert_eq!(read, __WASI_EISDIR);
+        let read_nothing = guest.call_errno("read", &[Value::I32(fd), Value::I32(0)]);
+        assert_eq!(read_nothing, __WASI_EISDIR);
+        assert_eq!(guest.call_errno("pread", &[Value::I32(fd)]), __WASI_EISDIR);
+    }
+}
+
+fn test_fdstat_set_flags() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_fdstat_get" (func $fd_fdstat_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_fdstat_set_flags" (func $fd_fdstat_set_flags (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_fdstat_set_rights" (func $fd_fdstat_set_rights (param i32 i64 i64) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_tell" (func $fd_tell (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "file.txt")
+        (data (i32.const 32) "end")
+
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+        (func (export "set_rights") (param $rights i64) (result i32)
+            (call $fd_fdstat_set_rights (i32.load (i32.const 0)) (local.get $rights) (i64.const 0))
+        )
+        (func (export "set_flags") (param $flags i32) (result i32)
+            (call $fd_fdstat_set_flags (i32.load (i32.const 0)) (local.get $flags))
+        )
+        ;; The fdstat is stored at offset 64
+        (func (export "fdstat") (result i32)
+            (call $fd_fdstat_get (i32.load (i32.const 0)) (i32.const 64))
+        )
+        ;; Writes `end` at the cursor
+        (func (export "write") (result i32)
+            (i32.store (i32.const 4) (i32.const 32))
+            (i32.store (i32.const 8) (i32.const 3))
+            (call $fd_write (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+        ;; Stores the offset of the fd at offset 24
+        (func (export "tell") (result i32)
+            (call $fd_tell (i32.load (i32.const 0)) (i32.const 24))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/data/file.txt"))
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .map_dir("data", "/data")
+            .unwrap();
+    });
+
+    let set_flags =
+        |guest: &mut Guest, flags: u16| guest.call_errno("set_flags", &[Value::I32(flags as i32)]);
+    let flags = |guest: &mut Guest| {
+        assert_eq!(guest.call_errno("fdstat", &[]), __WASI_ESUCCESS);
+        let flags = guest.read_memory(66, 2);
+        u16::from_le_bytes([flags[0], flags[1]])
+    };
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    assert_eq!(flags(&mut guest), 0);
+    for flag in [
+        __WASI_FDFLAG_APPEND,
+        __WASI_FDFLAG_DSYNC,
+        __WASI_FDFLAG_NONBLOCK,
+        __WASI_FDFLAG_RSYNC,
+        __WASI_FDFLAG_SYNC,
+        0,
+    ] {
+        assert_eq!(set_flags(&mut guest, flag), __WASI_ESUCCESS);
+        assert_eq!(flags(&mut guest), flag);
+    }
+
+    // Unknown flags are rejected and leave the flags as they were
+    let append_sync = __WASI_FDFLAG_APPEND | __WASI_FDFLAG_SYNC;
+    assert_eq!(set_flags(&mut guest, append_sync), __WASI_ESUCCESS);
+    assert_eq!(set_flags(&mut guest, 1 << 5), __WASI_EINVAL);
+    assert_eq!(flags(&mut guest), append_sync);
+
+    // In append mode writes go to the end, whatever the cursor
+    assert_eq!(guest.call_errno("write", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("tell", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u64(24), 8);
+    let mut contents = Vec::new();
+    fs.new_open_options()
+        .read(true)
+        .open(Path::new("/data/file.txt"))
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    assert_eq!(contents, b"helloend");
+
+    // A read-only fd can't append
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    let rights = __WASI_RIGHT_FD_READ | __WASI_RIGHT_FD_FDSTAT_SET_FLAGS;
+    let params = [Value::I64(rights as i64)];
+    assert_eq!(guest.call_errno("set_rights", &params), __WASI_ESUCCESS);
+    assert_eq!(set_flags(&mut guest, __WASI_FDFLAG_APPEND), __WASI_EINVAL);
+    assert_eq!(set_flags(&mut guest, __WASI_FDFLAG_RSYNC), __WASI_ESUCCESS);
+    assert_eq!(flags(&mut guest), __WASI_FDFLAG_RSYNC);
+}
+
+fn test_pread_pwrite_past_eof() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_pread" (func $fd_pread (param i32 i32 i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_pwrite" (func $fd_pwrite (param i32 i32 i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_tell" (func $fd_tell (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "file.txt")
+        (data (i32.const 32) "end")
+
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        ;; Reads at `offset` into the buffer at offset 64, the number of
+        ;; bytes read is stored at offset 12
+        (func (export "pread") (param $offset i64) (result i32)
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 32))
+            (call $fd_pread (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (local.get $offset) (i32.const 12))
+        )
+
+        ;; Writes `end` at `offset`
+        (func (export "pwrite") (param $offset i64) (result i32)
+            (i32.store (i32.const 4) (i32.const 32))
+            (i32.store (i32.const 8) (i32.const 3))
+            (call $fd_pwrite (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (local.get $offset) (i32.const 12))
+        )
+
+        ;; Stores the offset of the fd at offset 24
+        (func (export "tell") (result i32)
+            (call $fd_tell (i32.load (i32.const 0)) (i32.const 24))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/data/file.txt"))
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .map_dir("data", "/data")
+            .unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+
+    // Reading past the end is a zero read, not an error
+    assert_eq!(
+        guest.call_errno("pread", &[Value::I64(100)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(guest.read_u32(12), 0);
+
+    // Writing past the end leaves a gap of zeros
+    assert_eq!(
+        guest.call_errno("pwrite", &[Value::I64(8)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(guest.read_u32(12), 3);
+
+    assert_eq!(guest.call_errno("pread", &[Value::I64(0)]), __WASI_ESUCCESS);
+    let nread = guest.read_u32(12) as usize;
+    assert_eq!(guest.read_memory(64, nread), b"hello\0\0\0end");
+
+    let mut contents = Vec::new();
+    fs.new_open_options()
+        .read(true)
+        .open(Path::new("/data/file.txt"))
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    assert_eq!(contents, b"hello\0\0\0end");
+
+    // None of this moved the offset of the fd
+    assert_eq!(guest.call_errno("tell", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u64(24), 0);
+}
+
+fn test_shared_readonly_fs() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_unlink_file" (func $path_unlink_file (param i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "a.txt")
+        (data (i32.const 24) "b.txt")
+        (data (i32.const 32) "new")
+
+        ;; Truncates `a.txt` in the preopened fd 4 and writes `new` to it
+        (func (export "write") (result i32)
+            (local $errno i32)
+            (local.set $errno
+                (call $path_open
+                    (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 5)
+                    (i32.const 8) ;; __WASI_O_TRUNC
+                    (i64.const -1) (i64.const -1) (i32.const 0)
+                    (i32.const 0) ;; new fd
+                )
+            )
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (i32.store (i32.const 4) (i32.const 32))
+            (i32.store (i32.const 8) (i32.const 3))
+            (call $fd_write (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+
+        (func (export "unlink") (result i32)
+            (call $path_unlink_file (i32.const 4) (i32.const 24) (i32.const 5))
+        )
+    )
+    "#;
+    let source = mem_fs::FileSystem::default();
+    source.create_dir(Path::new("/dir")).unwrap();
+    for (path, contents) in [
+        ("/a.txt", "old a"),
+        ("/b.txt", "old b"),
+        ("/dir/c.txt", "c"),
+    ] {
+        source
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open(Path::new(path))
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+    let snapshot = Arc::new(FsSnapshot::from_fs(&source, "/").unwrap());
+
+    let instantiate = || {
+        run_wat(wat, |builder| {
+            builder
+                .shared_readonly_fs(snapshot.clone())
+                .map_dir("data", "/")
+                .unwrap();
+        })
+    };
+    let mut writer = instantiate();
+    let reader = instantiate();
+
+    assert_eq!(writer.call_errno("write", &[]), __WASI_ESUCCESS);
+    assert_eq!(writer.call_errno("unlink", &[]), __WASI_ESUCCESS);
+
+    let read = |fs: &dyn FileSystem, path: &str| {
+        let mut contents = String::new();
+        fs.new_open_options()
+            .read(true)
+            .open(Path::new(path))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    };
+
+    // The writes and removals of an instance are private to it
+    let writer_fs = writer.env().state.fs.fs_backing.as_ref();
+    // Only the touched directory is copied up, its children stay visible
+    writer_fs
+        .set_times(Path::new("/dir"), None, Some(1), true)
+        .unwrap();
+    assert_eq!(writer_fs.metadata(Path::new("/dir")).unwrap().modified, 1);
+    assert_eq!(read(writer_fs, "/dir/c.txt"), "c");
+    writer_fs
+        .rename(Path::new("/dir"), Path::new("/moved"))
+        .unwrap();
+    assert_eq!(read(writer_fs, "/a.txt"), "new");
+    assert!(matches!(
+        writer_fs.metadata(Path::new("/b.txt")),
+        Err(FsError::EntityNotFound)
+    ));
+    assert!(matches!(
+        writer_fs.metadata(Path::new("/dir")),
+        Err(FsError::EntityNotFound)
+    ));
+    assert_eq!(read(writer_fs, "/moved/c.txt"), "c");
+
+    let reader_fs = reader.env().state.fs.fs_backing.as_ref();
+    assert_eq!(read(reader_fs, "/a.txt"), "old a");
+    assert_eq!(read(reader_fs, "/b.txt"), "old b");
+    assert_eq!(read(reader_fs, "/dir/c.txt"), "c");
+    let mut names = reader_fs
+        .read_dir(Path::new("/"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["a.txt", "b.txt", "dir"]);
+
+    // The snapshot replaces the file system backing, it can't be combined with `set_fs`
+    let result = WasiState::new("command-name")
+        .set_fs(Box::new(mem_fs::FileSystem::default()))
+        .shared_readonly_fs(snapshot.clone())
+        .build();
+    assert!(matches!(
+        result,
+        Err(WasiStateCreationError::WasiFsCreationError(_))
+    ));
+}
+
+fn test_rename_overwrite() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_rename" (func $path_rename (param i32 i32 i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "target.txt")
+        (data (i32.const 32) "other.txt")
+
+        ;; Moves `target.txt` over the existing `other.txt`
+        (func (export "rename") (result i32)
+            (call $path_rename
+                (i32.const 4) (i32.const 16) (i32.const 10)
+                (i32.const 4) (i32.const 32) (i32.const 9)
+            )
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/a")).unwrap();
+    fs.create_dir(Path::new("/a/dir")).unwrap();
+    fs.create_dir(Path::new("/a/full")).unwrap();
+    for (path, contents) in [
+        ("/a/source.txt", "new"),
+        ("/a/target.txt", "old"),
+        ("/a/other.txt", "other"),
+        ("/a/full/entry.txt", ""),
+    ] {
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open(Path::new(path))
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+    let read = |path: &str| {
+        let mut contents = String::new();
+        fs.new_open_options()
+            .read(true)
+            .open(Path::new(path))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    };
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .map_dir("a", "/a")
+            .unwrap();
+    });
+
+    {
+        let state = &guest.env().state;
+        let mut inodes = state.inodes.write().unwrap();
+        let wasi_fs = &state.fs;
+        // fd 4 is the preopened directory `/a`
+        assert_eq!(
+            wasi_fs.rename(&mut inodes, 4, "source.txt", 4, "target.txt", false),
+            Err(__WASI_EEXIST)
+        );
+        assert_eq!(read("/a/target.txt"), "old");
+        assert_eq!(
+            wasi_fs.rename(&mut inodes, 4, "source.txt", 4, "dir", true),
+            Err(__WASI_EISDIR)
+        );
+        assert_eq!(
+            wasi_fs.rename(&mut inodes, 4, "dir", 4, "target.txt", true),
+            Err(__WASI_ENOTDIR)
+        );
+        assert_eq!(
+            wasi_fs.rename(&mut inodes, 4, "dir", 4, "full", true),
+            Err(__WASI_ENOTEMPTY)
+        );
+        wasi_fs
+            .rename(&mut inodes, 4, "source.txt", 4, "target.txt", true)
+            .unwrap();
+        wasi_fs
+            .rename(&mut inodes, 4, "full", 4, "dir", true)
+            .unwrap();
+    }
+    assert!(fs.metadata(Path::new("/a/source.txt")).is_err());
+    assert_eq!(read("/a/target.txt"), "new");
+    assert_eq!(read("/a/dir/entry.txt"), "");
+
+    // `path_rename` replaces the destination
+    assert_eq!(guest.call_errno("rename", &[]), __WASI_ESUCCESS);
+    assert!(fs.metadata(Path::new("/a/target.txt")).is_err());
+    assert_eq!(read("/a/other.txt"), "new");
+
+    // The target is only replaced once the rename succeeds, here the
+    // backing refuses to move the source to another file system
+    let other_fs = mem_fs::FileSystem::default();
+    other_fs
+        .new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/source.txt"))
+        .unwrap()
+        .write_all(b"elsewhere")
+        .unwrap();
+    let mut mounted = MountedFileSystem::new(Box::new(fs.clone()));
+    mounted.mount("/b", Box::new(other_fs));
+
+    let state = WasiState::new("command-name")
+        .set_fs(Box::new(mounted))
+        .map_dir("a", "/a")
+        .unwrap()
+        .map_dir("b", "/b")
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut inodes = state.inodes.write().unwrap();
+    // fds 4 and 5 are the preopened directories `/a` and `/b`
+    assert_eq!(
+        state
+            .fs
+            .rename(&mut inodes, 5, "source.txt", 4, "other.txt", true),
+        Err(__WASI_EXDEV)
+    );
+    assert_eq!(read("/a/other.txt"), "new");
+}
+
+fn test_path_mapper() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_unlink_file" (func $path_unlink_file (param i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 8) "\40\00\00\00\20\00\00\00")
+
+        ;; Opens the path written at offset 128 into the fd at offset 0
+        (func (export "open") (param $len i32) (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 128) (local.get $len)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        ;; Unlinks the path written at offset 128
+        (func (export "unlink") (param $len i32) (result i32)
+            (call $path_unlink_file (i32.const 4) (i32.const 128) (local.get $len))
+        )
+
+        ;; Reads up to 32 bytes to offset 64, the amount read is at offset 4
+        (func (export "read") (result i32)
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 8) (i32.const 1) (i32.const 4))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/a")).unwrap();
+    fs.create_dir(Path::new("/a/fixtures")).unwrap();
+    for (path, contents) in [
+        ("/a/fixtures/hosts", "127.0.0.1 fixture"),
+        ("/a/fixtures/motd", "welcome"),
+        ("/a/secret.txt", "secret"),
+        ("/outside.txt", "outside"),
+    ] {
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open(Path::new(path))
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .map_dir("a", "/a")
+            .unwrap()
+            .path_mapper(Box::new(|path| match path {
+                "etc/hosts" => Some("fixtures/hosts".to_string()),
+                "etc/motd" => Some("fixtures/motd".to_string()),
+                "escape.txt" => Some("../outside.txt".to_string()),
+                "secret.txt" => None,
+                path => Some(path.to_string()),
+            }));
+    });
+
+    let call = |guest: &mut Guest, name: &str, path: &str| {
+        guest.write_memory(128, path.as_bytes());
+        guest.call_errno(name, &[Value::I32(path.len() as i32)])
+    };
+    let open = |guest: &mut Guest, path: &str| call(guest, "open", path);
+
+    // The rewritten path is the one that gets opened
+    assert_eq!(open(&mut guest, "etc/hosts"), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+    let nread = guest.read_u32(4) as usize;
+    assert_eq!(guest.read_memory(64, nread), b"127.0.0.1 fixture");
+
+    // Rejected paths are denied even though they exist
+    assert_eq!(open(&mut guest, "secret.txt"), __WASI_EACCES);
+
+    // Rewritten paths still can't leave the preopened directory
+    assert_ne!(open(&mut guest, "escape.txt"), __WASI_ESUCCESS);
+
+    // Unlinking goes through the mapper too
+    assert_eq!(call(&mut guest, "unlink", "secret.txt"), __WASI_EACCES);
+    assert!(fs.metadata(Path::new("/a/secret.txt")).is_ok());
+    assert_eq!(call(&mut guest, "unlink", "etc/motd"), __WASI_ESUCCESS);
+    assert!(fs.metadata(Path::new("/a/fixtures/motd")).is_err());
+}
+
+fn test_default_dir_mode() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_create_directory" (func $path_create_directory (param i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_filestat_get" (func $path_filestat_get (param i32 i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "sub")
+
+        (func (export "create") (result i32)
+            (call $path_create_directory (i32.const 4) (i32.const 16) (i32.const 3))
+        )
+
+        ;; The filetype of the filestat written at offset 64 is at offset 80
+        (func (export "filestat") (result i32)
+            (call $path_filestat_get (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 3) (i32.const 64))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/a")).unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .map_dir("a", "/a")
+            .unwrap()
+            .default_dir_mode(0o700);
+    });
+
+    assert_eq!(guest.call_errno("create", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("filestat", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_memory(80, 1)[0], __WASI_FILETYPE_DIRECTORY);
+
+    assert_eq!(fs.metadata(Path::new("/a/sub")).unwrap().mode(), 0o700);
+}
+
+fn test_take_file() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "out.txt")
+        (data (i32.const 32) "backed.txt")
+        (data (i32.const 48) "output")
+
+        ;; Opens `out.txt`, writes to it and returns the fd
+        (func (export "produce") (result i32)
+            (drop (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 7)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            ))
+            (i32.store (i32.const 4) (i32.const 48))
+            (i32.store (i32.const 8) (i32.const 6))
+            (drop (call $fd_write (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12)))
+            (i32.load (i32.const 0))
+        )
+        ;; Creates `backed.txt` in the file system backing
+        (func (export "create") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 32) (i32.const 10)
+                (i32.const 1) ;; O_CREAT
+                (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/seeded.txt"))
+        .unwrap()
+        .write_all(b"seeded")
+        .unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .preopen_dir("/")
+            .unwrap();
+    });
+
+    {
+        let state = Arc::get_mut(&mut guest.env_mut().state).unwrap();
+        let mut inodes = state.inodes.write().unwrap();
+        // fd 4 is the preopened directory `/`
+        state
+            .fs
+            .create_file(&mut inodes, 4, "out.txt", Vec::new())
+            .unwrap();
+    }
+
+    let fd = match guest.call("produce", &[])[0] {
+        Value::I32(fd) => fd as u32,
+        ref other => panic!("unexpected result {:?}", other),
+    };
+    assert_eq!(guest.call_errno("create", &[]), __WASI_ESUCCESS);
+
+    let state = Arc::get_mut(&mut guest.env_mut().state).unwrap();
+    let mut inodes = state.inodes.write().unwrap();
+    let wasi_fs = &mut state.fs;
+    assert_eq!(
+        wasi_fs.take_file(&mut inodes, 4, "out.txt").unwrap(),
+        b"output"
+    );
+    // The file is gone, along with the guest's fd
+    assert_eq!(
+        wasi_fs.take_file(&mut inodes, 4, "out.txt"),
+        Err(FsError::EntityNotFound)
+    );
+    assert!(wasi_fs.get_fd(fd).is_err());
+
+    // Files of the backing are read and removed from it, whether the guest
+    // has them open or not
+    assert_eq!(
+        wasi_fs.take_file(&mut inodes, 4, "backed.txt").unwrap(),
+        b""
+    );
+    assert!(fs.metadata(Path::new("/backed.txt")).is_err());
+    assert_eq!(
+        wasi_fs.take_file(&mut inodes, 4, "seeded.txt").unwrap(),
+        b"seeded"
+    );
+    assert!(fs.metadata(Path::new("/seeded.txt")).is_err());
+    assert_eq!(
+        wasi_fs.take_file(&mut inodes, 4, "seeded.txt"),
+        Err(FsError::EntityNotFound)
+    );
+    assert_eq!(
+        wasi_fs.take_file(&mut inodes, 4, "missing.txt"),
+        Err(FsError::EntityNotFound)
+    );
+    drop(inodes);
+
+    // Files on the host are left alone
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("host.txt"), b"host").unwrap();
+    let mut guest = run_wat(wat, |builder| {
+        builder.preopen_dir(dir.path()).unwrap();
+    });
+    let state = Arc::get_mut(&mut guest.env_mut().state).unwrap();
+    let mut inodes = state.inodes.write().unwrap();
+    assert_eq!(
+        state.fs.take_file(&mut inodes, 4, "host.txt"),
+        Err(FsError::PermissionDenied)
+    );
+    assert_eq!(std::fs::read(dir.path().join("host.txt")).unwrap(), b"host");
+}
+
+fn test_nested_preopens() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "data/sub/file.txt")
+
+        ;; Reads `data/sub/file.txt` from the virtual root into the buffer at
+        ;; offset 64, returns the number of bytes read
+        (func (export "read") (result i32)
+            (drop (call $path_open
+                (i32.const 3) (i32.const 0) (i32.const 16) (i32.const 17)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            ))
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 32))
+            (drop (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12)))
+            (i32.load (i32.const 12))
+        )
+    )
+    "#;
+    let outer = std::env::temp_dir().join("wasmer-wasi-nested-preopens-outer");
+    let inner = std::env::temp_dir().join("wasmer-wasi-nested-preopens-inner");
+    std::fs::create_dir_all(outer.join("sub")).unwrap();
+    std::fs::create_dir_all(&inner).unwrap();
+    std::fs::write(outer.join("sub").join("file.txt"), b"outer").unwrap();
+    std::fs::write(inner.join("file.txt"), b"inner").unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .map_dir("/data", &outer)
+            .unwrap()
+            .map_dir("/data/sub", &inner)
+            .unwrap();
+    });
+
+    // `/data/sub` is a longer match than `/data`
+    assert_eq!(*guest.call("read", &[]), [Value::I32(5)]);
+    assert_eq!(guest.read_memory(64, 5), b"inner");
+}
+
+fn test_filestat() {
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/data")).unwrap();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/data/file.txt"))
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+
+    let state = WasiState::new("command-name")
+        .set_fs(Box::new(fs))
+        .map_dir("data", "/data")
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut inodes = state.inodes.write().unwrap();
+
+    // fd 4 is the preopened directory `/data`
+    let stat = state
+        .fs
+        .filestat(&mut inodes, 4, "file.txt", false)
+        .unwrap();
+    assert_eq!(stat.st_filetype, __WASI_FILETYPE_REGULAR_FILE);
+    assert_eq!(stat.st_size, 5);
+    assert_eq!(stat.st_nlink, 1);
+
+    let stat = state.fs.filestat(&mut inodes, 4, ".", false).unwrap();
+    assert_eq!(stat.st_filetype, __WASI_FILETYPE_DIRECTORY);
+
+    assert_eq!(
+        state.fs.filestat(&mut inodes, 4, "missing.txt", false),
+        Err(FsError::EntityNotFound)
+    );
+}
+
+#[cfg(unix)]
+fn test_file_umask() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "new.txt")
+
+        (func (export "create") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 7)
+                (i32.const 1) ;; O_CREAT
+                (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+    )
+    "#;
+    let dir = std::env::temp_dir().join("wasmer-wasi-file-umask");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mem_fs = mem_fs::FileSystem::default();
+    mem_fs.create_dir(Path::new("/data")).unwrap();
+
+    for host in [true, false] {
+        let mut builder = WasiState::new("command-name");
+        if host {
+            builder.preopen_dir(&dir).unwrap();
+        } else {
+            builder
+                .set_fs(Box::new(mem_fs.clone()))
+                .map_dir("data", "/data")
+                .unwrap();
+        }
+        let mut guest = run_wat_with(wat, builder.file_umask(0o077));
+
+        assert_eq!(guest.call_errno("create", &[]), __WASI_ESUCCESS);
+    }
+
+    let mode = std::fs::metadata(dir.join("new.txt"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(
+        mem_fs.metadata(Path::new("/data/new.txt")).unwrap().mode(),
+        0o600
+    );
+}
+
+fn test_path_open_kind_mismatch() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "dir")
+        (data (i32.const 32) "file.txt")
+
+        ;; Opens the path at `$path` with `$oflags`, the new fd is stored at
+        ;; offset 0
+        (func (export "open") (param $path i32) (param $len i32) (param $oflags i32) (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (local.get $path) (local.get $len)
+                (local.get $oflags) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        (func (export "read") (result i32)
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 32))
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/dir")).unwrap();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/file.txt"))
+        .unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.set_fs(Box::new(fs)).preopen_dir("/").unwrap();
+    });
+
+    let open = |guest: &mut Guest, path: &str, oflags: u16| {
+        let offset = if path == "dir" { 16 } else { 32 };
+        let params = [
+            Value::I32(offset),
+            Value::I32(path.len() as i32),
+            Value::I32(oflags as i32),
+        ];
+        guest.call_errno("open", &params)
+    };
+
+    // A directory can be opened without `__WASI_O_DIRECTORY`, but not read
+    assert_eq!(open(&mut guest, "dir", 0), __WASI_ESUCCESS);
+    assert_eq!(open(&mut guest, "dir", __WASI_O_DIRECTORY), __WASI_ESUCCESS);
+    // ... or opened with file semantics
+    assert_eq!(open(&mut guest, "dir", __WASI_O_TRUNC), __WASI_EISDIR);
+    assert_eq!(open(&mut guest, "dir", __WASI_O_CREAT), __WASI_EISDIR);
+    assert_eq!(
+        open(&mut guest, "dir", __WASI_O_CREAT | __WASI_O_EXCL),
+        __WASI_EEXIST
+    );
+
+    assert_eq!(
+        open(&mut guest, "file.txt", __WASI_O_DIRECTORY),
+        __WASI_ENOTDIR
+    );
+    assert_eq!(open(&mut guest, "file.txt", 0), __WASI_ESUCCESS);
+
+    assert_eq!(open(&mut guest, "dir", 0), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("read", &[]), __WASI_EISDIR);
+}
+
+fn test_max_open_fds() {
+    let wat = br#"
+    (module
+        (import "wasix_32v1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasix_32v1" "fd_close" (func $fd_close (param i32) (result i32)))
+        (import "wasix_32v1" "fd_dup" (func $fd_dup (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "file.txt")
+
+        ;; The new fd is stored at offset 0
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                (i32.const 1) ;; __WASI_O_CREAT
+                (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+        (func (export "dup") (param $fd i32) (result i32)
+            (call $fd_dup (local.get $fd) (i32.const 0))
+        )
+        (func (export "close") (param $fd i32) (result i32)
+            (call $fd_close (local.get $fd))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(mem_fs::FileSystem::default()))
+            .preopen_dir("/")
+            .unwrap()
+            .max_open_fds(16);
+    });
+
+    // Stdio and the preopened directory don't count
+    let mut fds = Vec::new();
+    for _ in 0..16 {
+        assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+        fds.push(guest.read_u32(0) as i32);
+    }
+    assert_eq!(guest.call_errno("open", &[]), __WASI_EMFILE);
+    assert_eq!(
+        guest.call_errno("dup", &[Value::I32(fds[0])]),
+        __WASI_EMFILE
+    );
+
+    let fd = fds.pop().unwrap();
+    assert_eq!(
+        guest.call_errno("close", &[Value::I32(fd)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("open", &[]), __WASI_EMFILE);
+    let fd = guest.read_u32(0) as i32;
+    assert_eq!(
+        guest.call_errno("close", &[Value::I32(fd)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(
+        guest.call_errno("dup", &[Value::I32(fds[0])]),
+        __WASI_ESUCCESS
+    );
+}
+
+fn test_fd_reflection() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_readdir" (func $fd_readdir (param i32 i32 i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_readlink" (func $path_readlink (param i32 i32 i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_renumber" (func $fd_renumber (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; The number of bytes used is stored at offset 0, the link name is
+        ;; read from offset 16 and the entries are written at offset 64
+        (func (export "readdir") (param $fd i32) (result i32)
+            (call $fd_readdir (local.get $fd) (i32.const 64) (i32.const 512) (i64.const 0) (i32.const 0))
+        )
+        (func (export "readlink") (param $fd i32) (param $len i32) (result i32)
+            (call $path_readlink (local.get $fd) (i32.const 16) (local.get $len) (i32.const 64) (i32.const 32) (i32.const 0))
+        )
+        (func (export "close") (param $fd i32) (result i32)
+            (call $fd_close (local.get $fd))
+        )
+        (func (export "renumber") (param $from i32) (param $to i32) (result i32)
+            (call $fd_renumber (local.get $from) (local.get $to))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/a")).unwrap();
+    fs.create_dir(Path::new("/b")).unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs))
+            .with_fd_reflection()
+            .map_dir("a", "/a")
+            .unwrap()
+            .map_dir("b", "/b")
+            .unwrap();
+    });
+
+    // The reflection directory is preopened first
+    let reflection_fd = Value::I32(4);
+    let list = |guest: &mut Guest| {
+        assert_eq!(
+            guest.call_errno("readdir", &[reflection_fd.clone()]),
+            __WASI_ESUCCESS
+        );
+        let buf = guest.read_memory(64, guest.read_u32(0) as usize);
+        let mut names = Vec::new();
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            let namlen = u32::from_le_bytes([rest[16], rest[17], rest[18], rest[19]]) as usize;
+            assert_eq!(rest[20], __WASI_FILETYPE_SYMBOLIC_LINK);
+            names.push(String::from_utf8(rest[24..24 + namlen].to_vec()).unwrap());
+            rest = &rest[24 + namlen..];
+        }
+        names
+    };
+    let readlink = |guest: &mut Guest, name: &str| {
+        guest.write_memory(16, name.as_bytes());
+        let params = [reflection_fd.clone(), Value::I32(name.len() as i32)];
+        match guest.call_errno("readlink", &params) {
+            __WASI_ESUCCESS => {
+                let target = guest.read_memory(64, guest.read_u32(0) as usize);
+                Ok(String::from_utf8(target).unwrap())
+            }
+            errno => Err(errno),
+        }
+    };
+
+    assert_eq!(list(&mut guest), ["4", "5", "6"]);
+    assert_eq!(readlink(&mut guest, "4").unwrap(), "proc/self/fd");
+    assert_eq!(readlink(&mut guest, "5").unwrap(), "a");
+    assert_eq!(readlink(&mut guest, "6").unwrap(), "b");
+    assert_eq!(readlink(&mut guest, "7"), Err(__WASI_ENOENT));
+
+    // The listing follows the fds of the guest
+    assert_eq!(guest.call_errno("close", &[Value::I32(6)]), __WASI_ESUCCESS);
+    assert_eq!(
+        guest.call_errno("renumber", &[Value::I32(5), Value::I32(10)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(list(&mut guest), ["4", "10"]);
+    assert_eq!(readlink(&mut guest, "10").unwrap(), "a");
+    assert_eq!(readlink(&mut guest, "6"), Err(__WASI_ENOENT));
+}
+
+fn test_preferred_io_block_size() {
+    let fs = mem_fs::FileSystem::default();
+    let open = |path: &str| {
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open(Path::new(path))
+            .unwrap()
+    };
+
+    let state = WasiState::new("command-name")
+        .stdout(open("/stdout"))
+        .build()
+        .unwrap();
+    let inodes = state.inodes.read().unwrap();
+    assert_eq!(
+        state.fs.preferred_io_block_size(&inodes, 1),
+        Ok(DEFAULT_IO_BLOCK_SIZE)
+    );
+    assert_eq!(
+        state.fs.preferred_io_block_size(&inodes, VIRTUAL_ROOT_FD),
+        Ok(DEFAULT_IO_BLOCK_SIZE)
+    );
+    assert_eq!(
+        state.fs.preferred_io_block_size(&inodes, 100),
+        Err(__WASI_EBADF)
+    );
+    drop(inodes);
+
+    let state = WasiState::new("command-name")
+        .stdout(open("/stdout"))
+        .io_block_size(512)
+        .build()
+        .unwrap();
+    let inodes = state.inodes.read().unwrap();
+    assert_eq!(state.fs.preferred_io_block_size(&inodes, 1), Ok(512));
+    drop(inodes);
+
+    // Host files report their own block size
+    #[cfg(all(unix, feature = "host-fs"))]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = std::env::temp_dir().join("wasmer-wasi-io-block-size");
+        let host_file = wasmer_vfs::host_fs::FileSystem::default()
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open(&path)
+            .unwrap();
+        let state = WasiState::new("command-name")
+            .stdout(host_file)
+            .io_block_size(512)
+            .build()
+            .unwrap();
+        let inodes = state.inodes.read().unwrap();
+        assert_eq!(
+            state.fs.preferred_io_block_size(&inodes, 1),
+            Ok(std::fs::metadata(&path).unwrap().blksize() as u32)
+        );
+    }
+}
+
+fn test_zero_length_iovecs() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "file.txt")
+
+        ;; The new fd is stored at offset 0, the number of bytes written or
+        ;; read at offset 8 and the iovecs are read from offset 64
+        (func (export "open") (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 8)
+                (i32.const 1) ;; __WASI_O_CREAT
+                (i64.const -1) (i64.const -1) (i32.const 0) (i32.const 0))
+        )
+        (func (export "write") (param $fd i32) (param $iovs_len i32) (result i32)
+            (call $fd_write (local.get $fd) (i32.const 64) (local.get $iovs_len) (i32.const 8))
+        )
+        (func (export "read") (param $fd i32) (param $iovs_len i32) (result i32)
+            (call $fd_read (local.get $fd) (i32.const 64) (local.get $iovs_len) (i32.const 8))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .preopen_dir("/")
+            .unwrap();
+    });
+
+    // Stores the iovecs and calls `name` with them
+    let io = |guest: &mut Guest, name: &str, fd: u32, iovs: &[(u32, u32)]| {
+        for (i, (buf, buf_len)) in iovs.iter().enumerate() {
+            let offset = 64 + i as u64 * 8;
+            guest.write_memory(offset, &buf.to_le_bytes());
+            guest.write_memory(offset + 4, &buf_len.to_le_bytes());
+        }
+        let params = [Value::I32(fd as i32), Value::I32(iovs.len() as i32)];
+        match guest.call_errno(name, &params) {
+            __WASI_ESUCCESS => Ok(guest.read_u32(8)),
+            errno => Err(errno),
+        }
+    };
+    let open = |guest: &mut Guest| {
+        assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+        guest.read_u32(0)
+    };
+
+    let writer = open(&mut guest);
+    guest.write_memory(200, b"abc");
+    guest.write_memory(300, b"de");
+    assert_eq!(
+        io(
+            &mut guest,
+            "write",
+            writer,
+            &[(500, 0), (200, 3), (600, 0), (300, 2), (0, 0)]
+        ),
+        Ok(5)
+    );
+    let mut content = String::new();
+    fs.new_open_options()
+        .read(true)
+        .open(Path::new("/file.txt"))
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "abcde");
+
+    // Nothing is read into empty buffers
+    let reader = open(&mut guest);
+    assert_eq!(
+        io(&mut guest, "read", reader, &[(1000, 0), (1010, 0)]),
+        Ok(0)
+    );
+    assert_eq!(io(&mut guest, "read", reader, &[]), Ok(0));
+
+    // Only the non-empty buffers are filled, from the start of the file
+    assert_eq!(
+        io(
+            &mut guest,
+            "read",
+            reader,
+            &[(1000, 0), (1000, 2), (1010, 0), (1020, 10)]
+        ),
+        Ok(5)
+    );
+    assert_eq!(
+        guest.read_memory(1000, 25),
+        b"ab\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0cde\0\0"
+    );
+}
+
+fn test_prestat_dir_name_round_trip() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_prestat_get" (func $fd_prestat_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_prestat_dir_name" (func $fd_prestat_dir_name (param i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Writes the prestat of the preopened fd 4 at offset 8
+        (func (export "prestat_get") (result i32)
+            (call $fd_prestat_get (i32.const 4) (i32.const 8))
+        )
+        ;; Writes the name of the preopened fd 4 at offset 256
+        (func (export "prestat_dir_name") (param $len i32) (result i32)
+            (call $fd_prestat_dir_name (i32.const 4) (i32.const 256) (local.get $len))
+        )
+    )
+    "#;
+    let alias = "a-rather-long-preopen-alias-".repeat(8) + "end";
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(mem_fs::FileSystem::default()))
+            .map_dir(&alias, "/")
+            .unwrap();
+    });
+
+    // `pr_name_len` follows the tag of the prestat
+    assert_eq!(guest.call_errno("prestat_get", &[]), __WASI_ESUCCESS);
+    let name_len = guest.read_u32(12) as usize;
+    assert_eq!(name_len, alias.len());
+
+    // A buffer one byte too small is rejected and left untouched
+    guest.write_memory(256, &vec![0xff; name_len + 1]);
+    assert_eq!(
+        guest.call_errno("prestat_dir_name", &[Value::I32(name_len as i32 - 1)]),
+        __WASI_ENAMETOOLONG
+    );
+    let name = guest.read_memory(256, name_len + 1);
+    assert!(name.iter().all(|b| *b == 0xff));
+
+    // Exactly `pr_name_len` bytes are written, without a NUL after them
+    assert_eq!(
+        guest.call_errno("prestat_dir_name", &[Value::I32(name_len as i32)]),
+        __WASI_ESUCCESS
+    );
+    let name = guest.read_memory(256, name_len + 1);
+    assert_eq!(&name[..name_len], alias.as_bytes());
+    assert_eq!(name[name_len], 0xff);
+}
+
+/// Builds a ustar entry: its header followed by `content`
+fn tar_entry(name: &str, entry_type: u8, link: &str, content: &[u8]) -> Vec<u8> {
+    let mut header = [0; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", content.len()).as_bytes());
+    header[156] = entry_type;
+    header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+    header[257..265].copy_from_slice(b"ustar\000");
+    // the checksum is computed with its own field set to spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+    let mut entry = header.to_vec();
+    entry.extend_from_slice(content);
+    entry.resize((entry.len() + 511) / 512 * 512, 0);
+    entry
+}
+
+fn test_mount_tar() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_unlink_file" (func $path_unlink_file (param i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_symlink" (func $path_symlink (param i32 i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_rename" (func $path_rename (param i32 i32 i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Opens the path of `len` bytes at offset 16 in the archive, the new
+        ;; fd is stored at offset 0
+        (func (export "open") (param $len i32) (param $oflags i32) (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 1) (i32.const 16) (local.get $len)
+                (local.get $oflags) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        ;; Reads the opened file into the buffer at offset 128, the number of
+        ;; bytes read is stored at offset 12
+        (func (export "read") (result i32)
+            (i32.store (i32.const 4) (i32.const 128))
+            (i32.store (i32.const 8) (i32.const 64))
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12))
+        )
+
+        ;; Unlinks the path of `len` bytes at offset 16 in the archive
+        (func (export "unlink") (param $len i32) (result i32)
+            (call $path_unlink_file (i32.const 4) (i32.const 16) (local.get $len))
+        )
+
+        ;; Creates a symlink to `readme.txt` at the path of `len` bytes at
+        ;; offset 16 in the archive
+        (func (export "symlink") (param $len i32) (result i32)
+            (call $path_symlink (i32.const 200) (i32.const 10) (i32.const 4) (i32.const 16) (local.get $len))
+        )
+
+        ;; Renames the path of `len` bytes at offset 16 in the archive to
+        ;; `readme.txt`
+        (func (export "rename") (param $len i32) (result i32)
+            (call $path_rename (i32.const 4) (i32.const 16) (local.get $len) (i32.const 4) (i32.const 200) (i32.const 10))
+        )
+
+        (data (i32.const 200) "readme.txt")
+    )
+    "#;
+    let archive = [
+        tar_entry("docs/", b'5', "", b""),
+        tar_entry("docs/readme.txt", b'0', "", b"read me"),
+        tar_entry("nested/dirs/file.txt", b'0', "", b"nested"),
+        tar_entry("latest", b'2', "docs/readme.txt", b""),
+        vec![0; 1024],
+    ]
+    .concat();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(mem_fs::FileSystem::default()))
+            .mount_tar("/assets", archive);
+    });
+
+    let open = |guest: &mut Guest, path: &str, oflags: u16| {
+        guest.write_memory(16, path.as_bytes());
+        let params = [Value::I32(path.len() as i32), Value::I32(oflags as i32)];
+        guest.call_errno("open", &params)
+    };
+    let read = |guest: &mut Guest| {
+        assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+        let nread = guest.read_u32(12) as usize;
+        guest.read_memory(128, nread)
+    };
+
+    assert_eq!(open(&mut guest, "docs/readme.txt", 0), __WASI_ESUCCESS);
+    assert_eq!(read(&mut guest), b"read me");
+    // parent directories missing from the archive are created
+    assert_eq!(open(&mut guest, "nested/dirs/file.txt", 0), __WASI_ESUCCESS);
+    assert_eq!(read(&mut guest), b"nested");
+    // symlinks are followed like the ones created by the guest
+    assert_eq!(open(&mut guest, "latest", 0), __WASI_ESUCCESS);
+    assert_eq!(read(&mut guest), b"read me");
+
+    assert_eq!(open(&mut guest, "new.txt", __WASI_O_CREAT), __WASI_EROFS);
+
+    // the virtual symlinks are as read-only as the rest of the archive
+    let call_with_path = |guest: &mut Guest, name: &str, path: &str| {
+        guest.write_memory(16, path.as_bytes());
+        guest.call_errno(name, &[Value::I32(path.len() as i32)])
+    };
+    assert_eq!(call_with_path(&mut guest, "unlink", "latest"), __WASI_EROFS);
+    assert_eq!(call_with_path(&mut guest, "rename", "latest"), __WASI_EROFS);
+    assert_eq!(
+        call_with_path(&mut guest, "symlink", "docs/new"),
+        __WASI_EROFS
+    );
+    assert_eq!(
+        call_with_path(&mut guest, "unlink", "docs/readme.txt"),
+        __WASI_EROFS
+    );
+    assert_eq!(open(&mut guest, "latest", 0), __WASI_ESUCCESS);
+    assert_eq!(read(&mut guest), b"read me");
+
+    // the archive is checked when the state is built
+    let result = WasiState::new("command-name")
+        .set_fs(Box::new(mem_fs::FileSystem::default()))
+        .mount_tar("/assets", b"not a tar archive".to_vec())
+        .build();
+    assert_eq!(
+        result.err(),
+        Some(WasiStateCreationError::FileSystemError(
+            FsError::InvalidData
+        ))
+    );
+}
+
+#[cfg(unix)]
+fn test_no_follow_symlinks() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_readlink" (func $path_readlink (param i32 i32 i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Opens the path of `len` bytes at offset 16, the new fd is stored
+        ;; at offset 0
+        (func (export "open") (param $dirflags i32) (param $len i32) (result i32)
+            (call $path_open
+                (i32.const 4) (local.get $dirflags) (i32.const 16) (local.get $len)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        ;; Reads the link at offset 16 into the buffer at offset 64, the
+        ;; number of bytes used is stored at offset 8
+        (func (export "readlink") (param $len i32) (result i32)
+            (call $path_readlink (i32.const 4) (i32.const 16) (local.get $len) (i32.const 64) (i32.const 32) (i32.const 8))
+        )
+    )
+    "#;
+    let root = std::env::temp_dir().join("wasmer-wasi-no-follow-symlinks");
+    let sandbox = root.join("sandbox");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&sandbox).unwrap();
+    std::fs::write(root.join("secret.txt"), b"secret").unwrap();
+    std::fs::write(sandbox.join("file.txt"), b"file").unwrap();
+    std::os::unix::fs::symlink("../secret.txt", sandbox.join("escape")).unwrap();
+    std::os::unix::fs::symlink("..", sandbox.join("up")).unwrap();
+
+    let mut guest = run_wat(wat, |builder| {
+        builder.preopen_dir(&sandbox).unwrap().no_follow_symlinks();
+    });
+
+    let open = |guest: &mut Guest, path: &str, dirflags: u32| {
+        guest.write_memory(16, path.as_bytes());
+        let params = [Value::I32(dirflags as i32), Value::I32(path.len() as i32)];
+        guest.call_errno("open", &params)
+    };
+
+    assert_eq!(open(&mut guest, "file.txt", 0), __WASI_ESUCCESS);
+    for dirflags in [0, __WASI_LOOKUP_SYMLINK_FOLLOW] {
+        assert_eq!(open(&mut guest, "escape", dirflags), __WASI_ELOOP);
+        assert_eq!(open(&mut guest, "up/secret.txt", dirflags), __WASI_ELOOP);
+        assert_eq!(
+            open(&mut guest, "up/sandbox/file.txt", dirflags),
+            __WASI_ELOOP
+        );
+    }
+
+    // The link itself can still be read
+    guest.write_memory(16, b"escape");
+    assert_eq!(
+        guest.call_errno("readlink", &[Value::I32(6)]),
+        __WASI_ESUCCESS
+    );
+    let used = guest.read_u32(8) as usize;
+    assert_eq!(guest.read_memory(64, used), b"../secret.txt");
+}
+
+fn test_path_remove_directory() {
+    let fs = mem_fs::FileSystem::default();
+    fs.create_dir(Path::new("/empty")).unwrap();
+    fs.create_dir(Path::new("/full")).unwrap();
+    for path in ["/full/file.txt", "/file.txt"] {
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .open(Path::new(path))
+            .unwrap()
+            .write_all(b"file")
+            .unwrap();
+    }
+
+    let mut builder = WasiState::new("command-name");
+    builder.set_fs(Box::new(fs)).preopen_dir("/").unwrap();
+    check_path_remove_directory(&mut builder);
+}
+
+fn test_path_remove_directory_host() {
+    let root = std::env::temp_dir().join("wasmer-wasi-path-remove-directory");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("empty")).unwrap();
+    std::fs::create_dir_all(root.join("full")).unwrap();
+    std::fs::write(root.join("full").join("file.txt"), b"file").unwrap();
+    std::fs::write(root.join("file.txt"), b"file").unwrap();
+
+    let mut builder = WasiState::new("command-name");
+    builder.preopen_dir(&root).unwrap();
+    check_path_remove_directory(&mut builder);
+
+    assert!(!root.join("empty").exists());
+    assert!(root.join("file.txt").is_file());
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+/// Removes the directories of a preopen holding the empty directory
+/// `empty`, the directory `full` with the file `full/file.txt` and the file
+/// `file.txt`
+fn check_path_remove_directory(builder: &mut WasiStateBuilder) {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_remove_directory" (func $path_remove_directory (param i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_unlink_file" (func $path_unlink_file (param i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Each function takes the path of `len` bytes at offset 16
+
+        (func (export "open") (param $len i32) (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (local.get $len)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        (func (export "rmdir") (param $len i32) (result i32)
+            (call $path_remove_directory (i32.const 4) (i32.const 16) (local.get $len))
+        )
+
+        (func (export "unlink") (param $len i32) (result i32)
+            (call $path_unlink_file (i32.const 4) (i32.const 16) (local.get $len))
+        )
+    )
+    "#;
+    let mut guest = run_wat_with(wat, builder);
+
+    let call = |guest: &mut Guest, name: &str, path: &str| {
+        guest.write_memory(16, path.as_bytes());
+        guest.call_errno(name, &[Value::I32(path.len() as i32)])
+    };
+
+    assert_eq!(call(&mut guest, "rmdir", "empty"), __WASI_ESUCCESS);
+    assert_eq!(call(&mut guest, "open", "empty"), __WASI_ENOENT);
+
+    assert_eq!(call(&mut guest, "rmdir", "full"), __WASI_ENOTEMPTY);
+    // Once its file is gone, the directory can be removed
+    assert_eq!(call(&mut guest, "unlink", "full/file.txt"), __WASI_ESUCCESS);
+    assert_eq!(call(&mut guest, "rmdir", "full"), __WASI_ESUCCESS);
+    assert_eq!(call(&mut guest, "open", "full"), __WASI_ENOENT);
+
+    assert_eq!(call(&mut guest, "rmdir", "file.txt"), __WASI_ENOTDIR);
+    assert_eq!(call(&mut guest, "open", "file.txt"), __WASI_ESUCCESS);
+}
+
+fn test_max_path_len() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_create_directory" (func $path_create_directory (param i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_rename" (func $path_rename (param i32 i32 i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; The paths start at offset 16
+
+        (func (export "open") (param $len i32) (result i32)
+            (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (local.get $len)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+
+        (func (export "mkdir") (param $len i32) (result i32)
+            (call $path_create_directory (i32.const 4) (i32.const 16) (local.get $len))
+        )
+
+        (func (export "rename") (param $old_len i32) (param $new_len i32) (result i32)
+            (call $path_rename
+                (i32.const 4) (i32.const 16) (local.get $old_len)
+                (i32.const 4) (i32.const 16) (local.get $new_len)
+            )
+        )
+    )
+    "#;
+    for max_path_len in [None, Some(16)] {
+        let mut builder = WasiState::new("command-name");
+        builder
+            .set_fs(Box::new(mem_fs::FileSystem::default()))
+            .preopen_dir("/")
+            .unwrap();
+        if let Some(max_path_len) = max_path_len {
+            builder.max_path_len(max_path_len);
+        }
+        let max_path_len = max_path_len.unwrap_or(DEFAULT_MAX_PATH_LEN);
+
+        let mut guest = run_wat_with(wat, &mut builder);
+        guest.write_memory(16, "a".repeat(max_path_len + 1).as_bytes());
+
+        let call = |guest: &mut Guest, name: &str, lens: &[usize]| {
+            let params = lens
+                .iter()
+                .map(|len| Value::I32(*len as i32))
+                .collect::<Vec<_>>();
+            guest.call_errno(name, &params)
+        };
+
+        assert_eq!(call(&mut guest, "open", &[max_path_len]), __WASI_ENOENT);
+        assert_eq!(
+            call(&mut guest, "open", &[max_path_len + 1]),
+            __WASI_ENAMETOOLONG
+        );
+        assert_eq!(call(&mut guest, "mkdir", &[max_path_len]), __WASI_ESUCCESS);
+        assert_eq!(
+            call(&mut guest, "mkdir", &[max_path_len + 1]),
+            __WASI_ENAMETOOLONG
+        );
+        assert_eq!(
+            call(&mut guest, "rename", &[max_path_len, max_path_len + 1]),
+            __WASI_ENAMETOOLONG
+        );
+        // The length is checked before the path is read, a path running
+        // past the end of the memory is not a fault
+        assert_eq!(
+            call(&mut guest, "open", &[i32::MAX as usize]),
+            __WASI_ENAMETOOLONG
+        );
+    }
+}
+
+/// Symlinks going in circles, and a chain of 41 symlinks `l0` to `l40`
+/// leading to `file.txt`, as `(link, target)`
+fn looping_symlinks() -> Vec<(String, String)> {
+    let mut links = vec![
+        ("self".to_string(), "self".to_string()),
+        ("a".to_string(), "b".to_string()),
+        ("b".to_string(), "a".to_string()),
+    ];
+    links.extend((0..40).map(|i| (format!("l{}", i), format!("l{}", i + 1))));
+    links.push(("l40".to_string(), "file.txt".to_string()));
+    links
+}
+
+fn test_symlink_loops() {
+    let fs = mem_fs::FileSystem::default();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/file.txt"))
+        .unwrap();
+
+    let mut builder = WasiState::new("command-name");
+    builder.set_fs(Box::new(fs)).preopen_dir("/").unwrap();
+    check_symlink_loops(&mut builder, true);
+}
+
+#[cfg(unix)]
+fn test_symlink_loops_host() {
+    let root = std::env::temp_dir().join("wasmer-wasi-symlink-loops");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("file.txt"), b"file").unwrap();
+    for (link, target) in looping_symlinks() {
+        std::os::unix::fs::symlink(target, root.join(link)).unwrap();
+    }
+
+    let mut builder = WasiState::new("command-name");
+    builder.preopen_dir(&root).unwrap();
+    check_symlink_loops(&mut builder, false);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+/// Resolves the [`looping_symlinks`] of a preopen holding `file.txt`, the
+/// guest creates the symlinks first if `create_links` is set
+fn check_symlink_loops(builder: &mut WasiStateBuilder, create_links: bool) {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_symlink" (func $path_symlink (param i32 i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Creates a symlink at the path at offset 16 pointing to the path at
+        ;; offset 64
+        (func (export "symlink") (param $link_len i32) (param $target_len i32) (result i32)
+            (call $path_symlink (i32.const 64) (local.get $target_len) (i32.const 4) (i32.const 16) (local.get $link_len))
+        )
+
+        ;; Opens the path at offset 16
+        (func (export "open") (param $len i32) (param $dirflags i32) (result i32)
+            (call $path_open
+                (i32.const 4) (local.get $dirflags) (i32.const 16) (local.get $len)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            )
+        )
+    )
+    "#;
+    let mut guest = run_wat_with(wat, builder);
+
+    let open = |guest: &mut Guest, path: &str, dirflags: u32| {
+        guest.write_memory(16, path.as_bytes());
+        let params = [Value::I32(path.len() as i32), Value::I32(dirflags as i32)];
+        guest.call_errno("open", &params)
+    };
+
+    if create_links {
+        for (link, target) in looping_symlinks() {
+            guest.write_memory(16, link.as_bytes());
+            guest.write_memory(64, target.as_bytes());
+            let params = [
+                Value::I32(link.len() as i32),
+                Value::I32(target.len() as i32),
+            ];
+            assert_eq!(guest.call_errno("symlink", &params), __WASI_ESUCCESS);
+        }
+    }
+
+    let follow = __WASI_LOOKUP_SYMLINK_FOLLOW;
+    assert_eq!(open(&mut guest, "self", follow), __WASI_ELOOP);
+    assert_eq!(open(&mut guest, "a", follow), __WASI_ELOOP);
+    for dirflags in [0, follow] {
+        assert_eq!(open(&mut guest, "self/file.txt", dirflags), __WASI_ELOOP);
+        assert_eq!(open(&mut guest, "b/file.txt", dirflags), __WASI_ELOOP);
+    }
+    // Up to 40 symlinks are followed
+    assert_eq!(open(&mut guest, "l1", follow), __WASI_ESUCCESS);
+    assert_eq!(open(&mut guest, "l0", follow), __WASI_ELOOP);
+}
@@ -1,7 +1,17 @@
-use std::io::{Read, Write};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
-use wasmer::{Instance, Module, Store};
-use wasmer_wasi::{Pipe, WasiState};
+use wasmer::{Instance, Module, Store, Value};
+use wasmer_vfs::{mem_fs, FileSystem};
+use wasmer_wasi::types::{__WASI_ESPIPE, __WASI_ESUCCESS};
+use wasmer_wasi::{
+    BytesAvailable, CountingFile, FsError, GenericFile, Pipe, StaticInput, Tee, ValidatingFile,
+    VirtualFile, WasiState,
+};
+
+mod common;
+use common::run_wat;
 
 mod sys {
     #[test]
@@ -18,6 +28,56 @@ mod sys {
     fn test_env() {
         super::test_env()
     }
+
+    #[test]
+    fn test_generic_file() {
+        super::test_generic_file()
+    }
+
+    #[test]
+    fn test_output_callbacks() {
+        super::test_output_callbacks()
+    }
+
+    #[test]
+    fn test_tee() {
+        super::test_tee()
+    }
+
+    #[test]
+    fn test_datasync() {
+        super::test_datasync()
+    }
+
+    #[test]
+    fn test_short_writes() {
+        super::test_short_writes()
+    }
+
+    #[test]
+    fn test_flush_all() {
+        super::test_flush_all()
+    }
+
+    #[test]
+    fn test_static_input() {
+        super::test_static_input()
+    }
+
+    #[test]
+    fn test_static_input_nonblocking() {
+        super::test_static_input_nonblocking()
+    }
+
+    #[test]
+    fn test_counting_file() {
+        super::test_counting_file()
+    }
+
+    #[test]
+    fn test_pipe_pread_pwrite() {
+        super::test_pipe_pread_pwrite()
+    }
 }
 
 #[cfg(feature = "js")]
@@ -38,6 +98,56 @@ mod js {
     fn test_env() {
         super::test_env()
     }
+
+    #[wasm_bindgen_test]
+    fn test_generic_file() {
+        super::test_generic_file()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_output_callbacks() {
+        super::test_output_callbacks()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_tee() {
+        super::test_tee()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_datasync() {
+        super::test_datasync()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_short_writes() {
+        super::test_short_writes()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_flush_all() {
+        super::test_flush_all()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_static_input() {
+        super::test_static_input()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_static_input_nonblocking() {
+        super::test_static_input_nonblocking()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_counting_file() {
+        super::test_counting_file()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_pipe_pread_pwrite() {
+        super::test_pipe_pread_pwrite()
+    }
 }
 
 fn test_stdout() {
@@ -174,3 +284,578 @@ fn test_stdin() {
     stdin.read_to_end(&mut buf).unwrap();
     assert_eq!(buf.len(), 0);
 }
+
+/// A stream that knows how many bytes are left to read
+struct Remaining(Cursor<Vec<u8>>);
+
+impl Read for Remaining {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Remaining {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Seek for Remaining {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl BytesAvailable for Remaining {
+    fn bytes_available_read(&self) -> Option<usize> {
+        Some(self.0.get_ref().len() - self.0.position() as usize)
+    }
+}
+
+fn test_generic_file() {
+    let mut file = GenericFile::new(Cursor::new(b"Hello".to_vec()));
+    file.seek(SeekFrom::Start(2)).unwrap();
+    assert_eq!(file.size(), 5);
+    assert_eq!(file.stream_position().unwrap(), 2);
+    assert_eq!(file.last_modified(), 0);
+    assert_eq!(file.bytes_available().unwrap(), 0);
+    assert!(file.get_fd().is_none());
+
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Reads stdin into the buffer at offset 64, returns the number of
+        ;; bytes read
+        (func (export "read") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 32))
+            (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (i32.load (i32.const 8))
+        )
+    )
+    "#;
+    let stdin =
+        GenericFile::with_bytes_available(Remaining(Cursor::new(b"Hello, stdin!\n".to_vec())));
+    assert_eq!(stdin.bytes_available().unwrap(), 14);
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdin(Box::new(stdin));
+    });
+
+    assert_eq!(*guest.call("read", &[]), [Value::I32(14)]);
+    assert_eq!(guest.read_memory(64, 14), b"Hello, stdin!\n");
+}
+
+fn test_output_callbacks() {
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "hello")
+        (data (i32.const 32) "oops")
+
+        (func (export "write") (param $fd i32) (param $ptr i32) (param $len i32) (result i32)
+            (i32.store (i32.const 0) (local.get $ptr))
+            (i32.store (i32.const 4) (local.get $len))
+            (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    let mut guest = run_wat(wat, |builder| {
+        let stdout = stdout.clone();
+        let stderr = stderr.clone();
+        builder
+            .stdout_callback(Box::new(move |bytes| {
+                stdout.lock().unwrap().push(bytes.to_vec())
+            }))
+            .stderr_callback(Box::new(move |bytes| {
+                stderr.lock().unwrap().push(bytes.to_vec())
+            }));
+    });
+
+    let result = guest.call("write", &[Value::I32(1), Value::I32(16), Value::I32(5)]);
+    assert_eq!(*result, [Value::I32(0)]);
+    // The callback is called during the write, not at the end of the run
+    assert_eq!(*stdout.lock().unwrap(), [b"hello".to_vec()]);
+
+    guest.call("write", &[Value::I32(2), Value::I32(32), Value::I32(4)]);
+    guest.call("write", &[Value::I32(1), Value::I32(16), Value::I32(2)]);
+    assert_eq!(*stdout.lock().unwrap(), [b"hello".to_vec(), b"he".to_vec()]);
+    assert_eq!(*stderr.lock().unwrap(), [b"oops".to_vec()]);
+}
+
+fn test_tee() {
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "hello")
+
+        (func (export "write") (result i32)
+            (i32.store (i32.const 0) (i32.const 16))
+            (i32.store (i32.const 4) (i32.const 5))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    let mut terminal = Pipe::default();
+    let mut capture = Pipe::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdout(Box::new(Tee::new(
+            Box::new(terminal.clone()),
+            Box::new(capture.clone()),
+        )));
+    });
+
+    assert_eq!(*guest.call("write", &[]), [Value::I32(0)]);
+    for pipe in [&mut terminal, &mut capture] {
+        let mut output = String::new();
+        pipe.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "hello");
+    }
+
+    // A failing file doesn't keep the other one from being written to
+    let full = GenericFile::new(Cursor::new(vec![0; 2].into_boxed_slice()));
+    let mut tee = Tee::new(Box::new(full), Box::new(capture.clone()));
+    assert_eq!(
+        tee.write(b"abc").unwrap_err().kind(),
+        io::ErrorKind::WriteZero
+    );
+    let mut output = String::new();
+    capture.read_to_string(&mut output).unwrap();
+    assert_eq!(output, "abc");
+    assert!(tee.read(&mut [0; 1]).is_err());
+}
+
+/// A file that records which kind of sync was asked of it
+#[derive(Debug, Default)]
+struct SyncRecorder(Arc<Mutex<Vec<&'static str>>>);
+
+impl Read for SyncRecorder {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for SyncRecorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SyncRecorder {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl VirtualFile for SyncRecorder {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn sync_to_disk(&self) -> Result<(), FsError> {
+        self.0.lock().unwrap().push("sync");
+        Ok(())
+    }
+    fn sync_data_to_disk(&self) -> Result<(), FsError> {
+        self.0.lock().unwrap().push("datasync");
+        Ok(())
+    }
+}
+
+fn test_datasync() {
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_datasync" (func $fd_datasync (param i32) (result i32)))
+        (import "wasi_unstable" "fd_sync" (func $fd_sync (param i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (func (export "datasync") (result i32)
+            (call $fd_datasync (i32.const 1))
+        )
+        (func (export "sync") (result i32)
+            (call $fd_sync (i32.const 1))
+        )
+    )
+    "#;
+    let syncs = Arc::new(Mutex::new(Vec::new()));
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdout(Box::new(SyncRecorder(syncs.clone())));
+    });
+
+    assert_eq!(*guest.call("datasync", &[]), [Value::I32(0)]);
+    assert_eq!(*syncs.lock().unwrap(), ["datasync"]);
+
+    assert_eq!(*guest.call("sync", &[]), [Value::I32(0)]);
+    assert_eq!(*syncs.lock().unwrap(), ["datasync", "sync"]);
+}
+
+/// A file that writes a single byte at a time and is interrupted every
+/// other write
+#[derive(Debug, Default)]
+struct TrickleFile {
+    written: Arc<Mutex<Vec<u8>>>,
+    interrupt: bool,
+}
+
+impl Read for TrickleFile {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for TrickleFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        match buf.first() {
+            Some(byte) => {
+                self.written.lock().unwrap().push(*byte);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for TrickleFile {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl VirtualFile for TrickleFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Ok(())
+    }
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+fn test_short_writes() {
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 64) "hello")
+        (data (i32.const 80) " world")
+
+        ;; Writes `hello world` to stdout with two iovecs, the number of
+        ;; bytes written is stored at offset 0
+        (func (export "write") (result i32)
+            (i32.store (i32.const 16) (i32.const 64))
+            (i32.store (i32.const 20) (i32.const 5))
+            (i32.store (i32.const 24) (i32.const 80))
+            (i32.store (i32.const 28) (i32.const 6))
+            (call $fd_write (i32.const 1) (i32.const 16) (i32.const 2) (i32.const 0))
+        )
+    )
+    "#;
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdout(Box::new(TrickleFile {
+            written: written.clone(),
+            interrupt: false,
+        }));
+    });
+
+    assert_eq!(*guest.call("write", &[]), [Value::I32(0)]);
+    assert_eq!(guest.read_u32(0), 11);
+    assert_eq!(*written.lock().unwrap(), b"hello world");
+}
+
+fn test_flush_all() {
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 64) "hello")
+
+        (func (export "write") (result i32)
+            (i32.store (i32.const 16) (i32.const 64))
+            (i32.store (i32.const 20) (i32.const 5))
+            (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 0))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    let open_output = || {
+        fs.new_open_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(Path::new("/output"))
+            .unwrap()
+    };
+    let read_output = || {
+        let mut content = Vec::new();
+        open_output().read_to_end(&mut content).unwrap();
+        content
+    };
+    // The validating file only writes its content out when committed, the
+    // flush done by `fd_write` leaves it buffered
+    let stdout = ValidatingFile::new(open_output(), Box::new(|_| Ok(()))).unwrap();
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdout(Box::new(stdout));
+    });
+
+    assert_eq!(*guest.call("write", &[]), [Value::I32(0)]);
+    assert_eq!(read_output(), b"");
+
+    let state = Arc::get_mut(&mut guest.env_mut().state).unwrap();
+    state.flush_all().unwrap();
+    assert_eq!(read_output(), b"hello");
+
+    // What is still buffered is written out when the state is dropped
+    assert_eq!(*guest.call("write", &[]), [Value::I32(0)]);
+    assert_eq!(read_output(), b"hello");
+    drop(guest);
+    assert_eq!(read_output(), b"hellohello");
+}
+
+fn test_static_input() {
+    let input: Arc<[u8]> = Arc::from(&b"Hello, stdin!\n"[..]);
+
+    let mut file = StaticInput::new(input.clone());
+    assert!(Arc::ptr_eq(file.data(), &input));
+    assert_eq!(file.size(), 14);
+    file.seek(SeekFrom::Start(7)).unwrap();
+    assert_eq!(file.bytes_available().unwrap(), 7);
+    assert_eq!(
+        file.write(b"x").unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    assert_eq!(file.set_len(0), Err(FsError::PermissionDenied));
+
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Reads stdin into the buffer at offset 64, returns the number of
+        ;; bytes read
+        (func (export "read") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 32))
+            (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (i32.load (i32.const 8))
+        )
+    )
+    "#;
+    // Every instance reads the whole input on its own
+    for _ in 0..2 {
+        let mut guest = run_wat(wat, |builder| {
+            builder.stdin(Box::new(StaticInput::new(input.clone())));
+        });
+
+        assert_eq!(*guest.call("read", &[]), [Value::I32(14)]);
+        assert_eq!(guest.read_memory(64, 14), b"Hello, stdin!\n");
+        assert_eq!(*guest.call("read", &[]), [Value::I32(0)]);
+    }
+}
+
+/// A non-blocking stdin that was read to the end is at EOF, reads return
+/// zero bytes rather than `EAGAIN`
+fn test_static_input_nonblocking() {
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Reads stdin into the buffer at offset 64, the number of bytes
+        ;; read is stored at offset 8
+        (func (export "read") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 32))
+            (i32.store (i32.const 8) (i32.const -1))
+            (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .stdin(Box::new(StaticInput::new(&b"hi"[..])))
+            .nonblocking(true);
+    });
+
+    assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u32(8), 2);
+
+    for _ in 0..2 {
+        assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+        assert_eq!(guest.read_u32(8), 0);
+    }
+}
+
+fn test_counting_file() {
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Copies up to 32 bytes from stdin to stdout
+        (func (export "copy") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 32))
+            (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (i32.store (i32.const 4) (i32.load (i32.const 8)))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    let stdin = CountingFile::new(Box::new(StaticInput::new(&b"Hello, stdin!\n"[..])));
+    let mut output = Pipe::default();
+    let stdout = CountingFile::new(Box::new(output.clone()));
+    let (stdin_counters, stdout_counters) = (stdin.counters(), stdout.counters());
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdin(Box::new(stdin)).stdout(Box::new(stdout));
+    });
+
+    for _ in 0..2 {
+        assert_eq!(*guest.call("copy", &[]), [Value::I32(0)]);
+    }
+
+    // The data goes through unchanged
+    let mut copied = String::new();
+    output.read_to_string(&mut copied).unwrap();
+    assert_eq!(copied, "Hello, stdin!\n");
+    assert_eq!(stdin_counters.bytes_read(), 14);
+    assert_eq!(stdin_counters.bytes_written(), 0);
+    assert_eq!(stdout_counters.bytes_read(), 0);
+    assert_eq!(stdout_counters.bytes_written(), 14);
+
+    // Duplicates count towards the same counters
+    let mut file = CountingFile::new(Box::new(Pipe::default()));
+    let mut duplicate = file.try_clone().unwrap();
+    duplicate.write_all(b"abc").unwrap();
+    file.write_all(b"de").unwrap();
+    assert_eq!(file.bytes_written(), 5);
+    let mut buf = [0; 8];
+    assert_eq!(file.read(&mut buf).unwrap(), 5);
+    assert_eq!(file.bytes_read(), 5);
+}
+
+fn test_pipe_pread_pwrite() {
+    let wat = br#"
+    (module
+        (import "wasi_unstable" "fd_pread" (func $fd_pread (param i32 i32 i32 i64 i32) (result i32)))
+        (import "wasi_unstable" "fd_pwrite" (func $fd_pwrite (param i32 i32 i32 i64 i32) (result i32)))
+        (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+        (data (i32.const 64) "data")
+
+        ;; Reads up to 4 bytes of stdin at offset 0
+        (func (export "pread") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 4))
+            (call $fd_pread (i32.const 0) (i32.const 0) (i32.const 1) (i64.const 0) (i32.const 8))
+        )
+
+        ;; Writes "data" to the given fd at offset 0
+        (func (export "pwrite") (param $fd i32) (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 4))
+            (call $fd_pwrite (local.get $fd) (i32.const 0) (i32.const 1) (i64.const 0) (i32.const 8))
+        )
+
+        ;; Writes "data" to stdout
+        (func (export "write") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 4))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    let mut stdin = Pipe::new();
+    stdin.write_all(b"abc").unwrap();
+    let mut stdout = Pipe::new();
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .stdin(Box::new(stdin.clone()))
+            .stdout(Box::new(stdout.clone()))
+            .stderr(Box::new(CountingFile::new(Box::new(Pipe::new()))));
+    });
+
+    // Pipes can't seek, nothing is read or written
+    assert_eq!(guest.call_errno("pwrite", &[Value::I32(1)]), __WASI_ESPIPE);
+    assert_eq!(guest.call_errno("pwrite", &[Value::I32(2)]), __WASI_ESPIPE);
+    assert_eq!(guest.call_errno("pread", &[]), __WASI_ESPIPE);
+    let mut buf = Vec::new();
+    stdin.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"abc");
+    buf.clear();
+    stdout.read_to_end(&mut buf).unwrap();
+    assert!(buf.is_empty());
+
+    // The pipe can still be written to in order
+    assert_eq!(guest.call_errno("write", &[]), __WASI_ESUCCESS);
+    stdout.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"data");
+}
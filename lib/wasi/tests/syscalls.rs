@@ -0,0 +1,1669 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use wasmer::{
+    Function, FunctionEnvMut, Instance, Memory32, MemoryError, Module, Pages, Store, Value, WasmPtr,
+};
+use wasmer_vfs::{mem_fs, FileSystem};
+use wasmer_wasi::types::{
+    __WASI_CLOCK_MONOTONIC, __WASI_CLOCK_PROCESS_CPUTIME_ID, __WASI_CLOCK_REALTIME,
+    __WASI_CLOCK_THREAD_CPUTIME_ID, __WASI_EAGAIN, __WASI_EBADF, __WASI_EINVAL, __WASI_EIO,
+    __WASI_ENOENT, __WASI_ENOSYS, __WASI_ENOTCAPABLE, __WASI_ENOTSUP, __WASI_EPERM,
+    __WASI_ESUCCESS, __WASI_EVENTTYPE_CLOCK, __WASI_EVENTTYPE_FD_WRITE,
+    __WASI_FILETYPE_CHARACTER_DEVICE, __WASI_FILETYPE_DIRECTORY, __WASI_FILETYPE_REGULAR_FILE,
+    __WASI_FILETYPE_SOCKET_DGRAM, __WASI_FILETYPE_SOCKET_STREAM, __WASI_RIGHT_FD_WRITE,
+    __WASI_SOCK_RECV_INPUT_PEEK, __WASI_SOCK_RECV_INPUT_WAITALL,
+    __WASI_SOCK_RECV_OUTPUT_DATA_TRUNCATED, __WASI_STDOUT_FILENO,
+};
+use wasmer_wasi::{
+    get_wasi_version, run_wasi_start, FdKind, FsError, ManualClock, Pipe, WasiEnv, WasiError,
+    WasiState, WasiStateBuilder, WasiStateCreationError, WasiVersion, SYSCALL_INSTRUCTIONS,
+};
+
+mod common;
+
+use common::{run_wat, run_wat_with, Guest};
+
+mod sys {
+    #[test]
+    fn test_syscall_error_stats() {
+        super::test_syscall_error_stats()
+    }
+
+    #[test]
+    fn test_on_exit() {
+        super::test_on_exit()
+    }
+
+    #[test]
+    fn test_stub_syscalls() {
+        super::test_stub_syscalls()
+    }
+
+    #[test]
+    fn test_set_args() {
+        super::test_set_args()
+    }
+
+    #[test]
+    fn test_program_name() {
+        super::test_program_name()
+    }
+
+    #[test]
+    fn test_non_utf8_args() {
+        super::test_non_utf8_args()
+    }
+
+    #[test]
+    fn test_grow_memory() {
+        super::test_grow_memory()
+    }
+
+    #[test]
+    fn test_max_read_size() {
+        super::test_max_read_size()
+    }
+
+    #[test]
+    fn test_fds() {
+        super::test_fds()
+    }
+
+    #[test]
+    fn test_poll_oneoff_no_subscriptions() {
+        super::test_poll_oneoff_no_subscriptions()
+    }
+
+    #[test]
+    fn test_poll_oneoff_clocks() {
+        super::test_poll_oneoff_clocks()
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        super::test_manual_clock()
+    }
+
+    #[test]
+    fn test_nonblocking() {
+        super::test_nonblocking()
+    }
+
+    #[test]
+    fn test_nonblocking_pipe() {
+        super::test_nonblocking_pipe()
+    }
+
+    #[test]
+    fn test_deterministic() {
+        super::test_deterministic()
+    }
+
+    #[test]
+    fn test_record_replay_randomness() {
+        super::test_record_replay_randomness()
+    }
+
+    #[test]
+    fn test_clock_res_get() {
+        super::test_clock_res_get()
+    }
+
+    #[test]
+    fn test_sock_recv_flags() {
+        super::test_sock_recv_flags()
+    }
+
+    #[test]
+    fn test_fdstat_set_rights() {
+        super::test_fdstat_set_rights()
+    }
+
+    #[test]
+    fn test_environ_get() {
+        super::test_environ_get()
+    }
+
+    #[test]
+    fn test_read_write_bytes() {
+        super::test_read_write_bytes()
+    }
+    #[test]
+    fn test_max_instructions() {
+        super::test_max_instructions()
+    }
+
+    #[test]
+    fn test_bare_wasi_namespace() {
+        super::test_bare_wasi_namespace()
+    }
+
+    #[test]
+    fn test_fdstat_filetype() {
+        super::test_fdstat_filetype()
+    }
+
+    #[test]
+    fn test_run_wasi_start() {
+        super::test_run_wasi_start()
+    }
+
+    #[test]
+    fn test_syscall_tracer() {
+        super::test_syscall_tracer()
+    }
+
+    #[test]
+    fn test_close_fd() {
+        super::test_close_fd()
+    }
+}
+
+#[cfg(feature = "js")]
+mod js {
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    fn test_syscall_error_stats() {
+        super::test_syscall_error_stats()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_on_exit() {
+        super::test_on_exit()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_stub_syscalls() {
+        super::test_stub_syscalls()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_set_args() {
+        super::test_set_args()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_program_name() {
+        super::test_program_name()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_non_utf8_args() {
+        super::test_non_utf8_args()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_grow_memory() {
+        super::test_grow_memory()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_max_read_size() {
+        super::test_max_read_size()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fds() {
+        super::test_fds()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_poll_oneoff_no_subscriptions() {
+        super::test_poll_oneoff_no_subscriptions()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_poll_oneoff_clocks() {
+        super::test_poll_oneoff_clocks()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_nonblocking() {
+        super::test_nonblocking()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_nonblocking_pipe() {
+        super::test_nonblocking_pipe()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_deterministic() {
+        super::test_deterministic()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_record_replay_randomness() {
+        super::test_record_replay_randomness()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_clock_res_get() {
+        super::test_clock_res_get()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fdstat_set_rights() {
+        super::test_fdstat_set_rights()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_environ_get() {
+        super::test_environ_get()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_read_write_bytes() {
+        super::test_read_write_bytes()
+    }
+    #[wasm_bindgen_test]
+    fn test_max_instructions() {
+        super::test_max_instructions()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_bare_wasi_namespace() {
+        super::test_bare_wasi_namespace()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fdstat_filetype() {
+        super::test_fdstat_filetype()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_run_wasi_start() {
+        super::test_run_wasi_start()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_syscall_tracer() {
+        super::test_syscall_tracer()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_close_fd() {
+        super::test_close_fd()
+    }
+}
+
+fn test_syscall_error_stats() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (func $main (export "_start")
+            ;; None of these file descriptors are open
+            (drop (call $fd_close (i32.const 99)))
+            (drop (call $fd_close (i32.const 100)))
+            (drop (call $fd_close (i32.const 101)))
+            (drop (call $fd_read (i32.const 99) (i32.const 0) (i32.const 0) (i32.const 16)))
+            (drop (call $fd_read (i32.const 99) (i32.const 0) (i32.const 0) (i32.const 16)))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |_| {});
+
+    guest.call("_start", &[]);
+
+    let stats = guest.env().state().syscall_error_stats();
+    assert_eq!(stats["fd_close"][&__WASI_EBADF], 3);
+    assert_eq!(stats["fd_read"][&__WASI_EBADF], 2);
+    assert_eq!(stats.len(), 2);
+}
+
+fn test_stub_syscalls() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "sock_send" (func $sock_send (param i32 i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (data (i32.const 0) "\08\00\00\00\05\00\00\00hello")
+        (export "memory" (memory 0))
+
+        (func (export "send") (result i32)
+            (call $sock_send (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 0) (i32.const 16))
+        )
+        (func (export "write") (result i32)
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16))
+        )
+    )
+    "#;
+    let mut stdout = Pipe::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .stdout(Box::new(stdout.clone()))
+            .stub_syscalls(&["sock_send"])
+            .stub_syscalls_with_errno(&["fd_write"], __WASI_EPERM);
+    });
+
+    assert_eq!(guest.call_errno("send", &[]), __WASI_ENOSYS);
+    // The stubbed syscall does nothing
+    assert_eq!(guest.call_errno("write", &[]), __WASI_EPERM);
+    let mut output = Vec::new();
+    stdout.read_to_end(&mut output).unwrap();
+    assert!(output.is_empty());
+
+    let stats = guest.env().state().syscall_error_stats();
+    assert_eq!(stats["sock_send"][&__WASI_ENOSYS], 1);
+    assert_eq!(stats["fd_write"][&__WASI_EPERM], 1);
+
+    for name in ["not_a_syscall", "proc_exit"] {
+        let result = WasiState::new("command-name")
+            .stub_syscalls(&[name])
+            .build();
+        assert!(matches!(
+            result,
+            Err(WasiStateCreationError::InvalidStubbedSyscall(syscall)) if syscall == name
+        ));
+    }
+}
+
+fn test_on_exit() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (func $main (export "_start")
+            (call $proc_exit (i32.const 42))
+        )
+    )
+    "#;
+    let exit_codes = Arc::new(Mutex::new(Vec::new()));
+    let mut guest = run_wat(wat, |builder| {
+        let exit_codes = exit_codes.clone();
+        builder.on_exit(Box::new(move |code| exit_codes.lock().unwrap().push(code)));
+    });
+
+    let err = guest.try_call("_start", &[]).unwrap_err();
+
+    // The trap is still raised after the callback ran
+    let err = match err.downcast::<WasiError>() {
+        Ok(err @ WasiError::Exit(42)) => err,
+        other => panic!("unexpected result: {:?}", other),
+    };
+    assert_eq!(err.exit_code(), Some(42));
+    assert_eq!(WasiError::UnknownWasiVersion.exit_code(), None);
+    assert_eq!(*exit_codes.lock().unwrap(), vec![42]);
+}
+
+fn test_set_args() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Writes the second argument to stdout
+        (func $main (export "_start")
+            (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+            (drop (call $args_get (i32.const 16) (i32.const 256)))
+            ;; iov.iov_base - argv[1]
+            (i32.store (i32.const 32) (i32.load (i32.const 20)))
+            ;; iov.iov_len - argv_buf_size - (argv[1] - argv_buf) - 1
+            (i32.store (i32.const 36)
+                (i32.sub
+                    (i32.sub (i32.load (i32.const 4)) (i32.sub (i32.load (i32.const 20)) (i32.const 256)))
+                    (i32.const 1)))
+            (drop (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 40)))
+        )
+    )
+    "#;
+    let mut stdout = Pipe::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder.arg("first").stdout(Box::new(stdout.clone()));
+    });
+
+    guest.call("_start", &[]);
+
+    {
+        let state = Arc::get_mut(&mut guest.env_mut().state).unwrap();
+        assert_eq!(
+            state.set_args(&[b"command-name".to_vec(), b"sec\0ond".to_vec()]),
+            Err(FsError::InvalidInput)
+        );
+        for env in [&b"NO_SEPARATOR"[..], b"KEY=value=1", b"=value"] {
+            assert_eq!(state.set_envs(&[env.to_vec()]), Err(FsError::InvalidInput));
+        }
+        state
+            .set_args(&[b"command-name".to_vec(), b"second".to_vec()])
+            .unwrap();
+        state.set_envs(&[b"KEY=value".to_vec()]).unwrap();
+    }
+    guest.call("_start", &[]);
+
+    let mut stdout_str = String::new();
+    stdout.read_to_string(&mut stdout_str).unwrap();
+    assert_eq!(stdout_str, "firstsecond");
+}
+
+fn test_program_name() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Writes the first argument to stdout
+        (func $main (export "_start")
+            (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+            (drop (call $args_get (i32.const 16) (i32.const 256)))
+            ;; iov.iov_base - argv[0]
+            (i32.store (i32.const 32) (i32.load (i32.const 16)))
+            ;; iov.iov_len - argv[1] - argv[0] - 1
+            (i32.store (i32.const 36)
+                (i32.sub
+                    (i32.sub (i32.load (i32.const 20)) (i32.load (i32.const 16)))
+                    (i32.const 1)))
+            (drop (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 40)))
+        )
+    )
+    "#;
+    let mut stdout = Pipe::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .arg("first")
+            .program_name("custom-name")
+            .arg("second")
+            .stdout(Box::new(stdout.clone()));
+    });
+    assert_eq!(
+        guest.env().state.args,
+        vec![
+            b"custom-name".to_vec(),
+            b"first".to_vec(),
+            b"second".to_vec()
+        ]
+    );
+
+    guest.call("_start", &[]);
+
+    let mut stdout_str = String::new();
+    stdout.read_to_string(&mut stdout_str).unwrap();
+    assert_eq!(stdout_str, "custom-name");
+}
+
+fn test_non_utf8_args() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Writes the 2 bytes of the second argument to stdout
+        (func $main (export "_start")
+            (drop (call $args_get (i32.const 16) (i32.const 256)))
+            ;; iov.iov_base - argv[1]
+            (i32.store (i32.const 32) (i32.load (i32.const 20)))
+            ;; iov.iov_len
+            (i32.store (i32.const 36) (i32.const 2))
+            (drop (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 40)))
+        )
+    )
+    "#;
+    let mut stdout = Pipe::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder.arg(b"\xff\xfe").stdout(Box::new(stdout.clone()));
+    });
+
+    guest.call("_start", &[]);
+
+    let mut output = Vec::new();
+    stdout.read_to_end(&mut output).unwrap();
+    assert_eq!(output, b"\xff\xfe");
+}
+
+fn test_grow_memory() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &mut store,
+        br#"
+    (module
+        (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+        (memory 1 4)
+        (export "memory" (memory 0))
+    )
+    "#,
+    )
+    .unwrap();
+
+    let wasi_env = WasiState::new("command-name").finalize(&mut store).unwrap();
+
+    // Nothing to grow before the memory is set
+    let env = wasi_env.data_mut(&mut store).clone();
+    assert!(matches!(
+        env.grow_memory(&mut store, Pages(1)),
+        Err(MemoryError::Generic(_))
+    ));
+
+    let import_object = wasi_env.import_object(&mut store, &module).unwrap();
+    let instance = Instance::new(&mut store, &module, &import_object).unwrap();
+    let memory = instance.exports.get_memory("memory").unwrap();
+    wasi_env.data_mut(&mut store).set_memory(memory.clone());
+
+    let env = wasi_env.data_mut(&mut store).clone();
+    assert_eq!(env.grow_memory(&mut store, Pages(2)).unwrap(), Pages(1));
+    assert_eq!(memory.size(&store), Pages(3));
+    // The maximum of the memory still applies
+    assert!(matches!(
+        env.grow_memory(&mut store, Pages(2)),
+        Err(MemoryError::CouldNotGrow { .. })
+    ));
+    assert_eq!(memory.size(&store), Pages(3));
+}
+
+fn test_max_read_size() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Reads up to 64 bytes from stdin and returns how many were read
+        (func (export "read_stdin") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 64))
+            (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+            (i32.load (i32.const 8))
+        )
+    )
+    "#;
+    let mut stdin = Pipe::default();
+    stdin.write_all(&[b'x'; 100]).unwrap();
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdin(Box::new(stdin.clone())).max_read_size(8);
+    });
+
+    assert_eq!(*guest.call("read_stdin", &[]), [Value::I32(8)]);
+
+    // The rest of the input is still available for the next reads
+    let mut rest = Vec::new();
+    stdin.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest.len(), 92);
+
+    // A read size of 0 would make every read look like the end of the file
+    let result = WasiState::new("command-name").max_read_size(0).build();
+    assert!(matches!(
+        result,
+        Err(WasiStateCreationError::ZeroMaxReadSize)
+    ));
+}
+
+fn test_fds() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "leaked.txt")
+
+        ;; Opens `leaked.txt`, reads 3 bytes from it and never closes it
+        (func $main (export "_start")
+            (drop (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 10)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            ))
+            (i32.store (i32.const 4) (i32.const 64))
+            (i32.store (i32.const 8) (i32.const 3))
+            (drop (call $fd_read (i32.load (i32.const 0)) (i32.const 4) (i32.const 1) (i32.const 12)))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/leaked.txt"))
+        .unwrap()
+        .write_all(b"leaked")
+        .unwrap();
+    let mut guest = run_wat(wat, |builder| {
+        builder.set_fs(Box::new(fs)).preopen_dir("/").unwrap();
+    });
+
+    guest.call("_start", &[]);
+
+    let fds = guest.env().state().fds();
+    let kinds = fds
+        .iter()
+        .map(|(fd, info)| (*fd, info.kind))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            (0, FdKind::Stdio),
+            (1, FdKind::Stdio),
+            (2, FdKind::Stdio),
+            (3, FdKind::Dir),
+            (4, FdKind::Dir),
+            (5, FdKind::File),
+        ]
+    );
+    let (_, leaked) = &fds[5];
+    assert_eq!(leaked.path, Some(PathBuf::from("/leaked.txt")));
+    assert_eq!(leaked.offset, 3);
+}
+
+fn test_poll_oneoff_no_subscriptions() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Polls nothing, the number of events is stored at offset 0
+        (func (export "poll") (result i32)
+            (i32.store (i32.const 0) (i32.const -1))
+            (call $poll_oneoff (i32.const 16) (i32.const 64) (i32.const 0) (i32.const 0))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |_| {});
+
+    assert_eq!(guest.call_errno("poll", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u32(0), 0);
+}
+
+fn test_poll_oneoff_clocks() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Polls the subscriptions at offset 0, the events are stored at
+        ;; offset 512 and their number at offset 1024
+        (func (export "poll") (param $nsubscriptions i32) (result i32)
+            (call $poll_oneoff (i32.const 0) (i32.const 512) (local.get $nsubscriptions) (i32.const 1024))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |_| {});
+
+    // Writes the subscription `index`, subscriptions are 48 bytes long
+    let subscribe =
+        |guest: &Guest, index: u64, userdata: u64, (event_type, data): (u8, [u64; 2])| {
+            let mut subscription = [0; 48];
+            subscription[0..8].copy_from_slice(&userdata.to_le_bytes());
+            subscription[8] = event_type;
+            subscription[16..24].copy_from_slice(&data[0].to_le_bytes());
+            subscription[24..32].copy_from_slice(&data[1].to_le_bytes());
+            guest.write_memory(index * 48, &subscription);
+        };
+    let clock = |timeout: Duration| {
+        let data = [__WASI_CLOCK_MONOTONIC as u64, timeout.as_nanos() as u64];
+        (__WASI_EVENTTYPE_CLOCK, data)
+    };
+    // Returns the userdata and type of every event, events are 32 bytes long
+    let poll = |guest: &mut Guest, nsubscriptions: i32| {
+        assert_eq!(
+            guest.call_errno("poll", &[Value::I32(nsubscriptions)]),
+            __WASI_ESUCCESS
+        );
+        (0..guest.read_u32(1024) as u64)
+            .map(|i| {
+                let event = guest.read_memory(512 + i * 32, 32);
+                (guest.read_u64(512 + i * 32), event[10])
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The shorter timer fires first, even when it is not the first subscription
+    subscribe(&guest, 0, 1, clock(Duration::from_secs(5)));
+    subscribe(&guest, 1, 2, clock(Duration::from_millis(10)));
+    assert_eq!(poll(&mut guest, 2), [(2, __WASI_EVENTTYPE_CLOCK)]);
+
+    // Every timer that expired fires
+    subscribe(&guest, 0, 1, clock(Duration::from_millis(10)));
+    subscribe(&guest, 1, 2, clock(Duration::from_millis(10)));
+    let mut events = poll(&mut guest, 2);
+    events.sort_unstable();
+    assert_eq!(
+        events,
+        [(1, __WASI_EVENTTYPE_CLOCK), (2, __WASI_EVENTTYPE_CLOCK)]
+    );
+
+    // Timers can be mixed with fds, stdout is always writable
+    let stdout = (__WASI_EVENTTYPE_FD_WRITE, [__WASI_STDOUT_FILENO as u64, 0]);
+    subscribe(&guest, 0, 1, clock(Duration::from_secs(5)));
+    subscribe(&guest, 1, 2, stdout);
+    assert_eq!(poll(&mut guest, 2), [(2, __WASI_EVENTTYPE_FD_WRITE)]);
+}
+
+fn test_manual_clock() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "clock_res_get" (func $clock_res_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "path_filestat_set_times" (func $path_filestat_set_times (param i32 i32 i32 i32 i64 i64 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 160) "times.txt")
+
+        ;; Stores the monotonic time at offset 128
+        (func (export "now") (result i32)
+            (call $clock_time_get (i32.const 1) (i64.const 0) (i32.const 128))
+        )
+        ;; Stores the resolution of the monotonic clock at offset 136
+        (func (export "res") (result i32)
+            (call $clock_res_get (i32.const 1) (i32.const 136))
+        )
+        ;; Sets the modification time of `times.txt` to the current time
+        (func (export "touch") (result i32)
+            (call $path_filestat_set_times
+                (i32.const 4) (i32.const 0) (i32.const 160) (i32.const 9)
+                (i64.const 0) (i64.const 0)
+                (i32.const 8) ;; MTIM_NOW
+            )
+        )
+        ;; Polls the subscription at offset 0, the event is stored at offset
+        ;; 64 and the number of events at offset 96
+        (func (export "poll") (result i32)
+            (call $poll_oneoff (i32.const 0) (i32.const 64) (i32.const 1) (i32.const 96))
+        )
+    )
+    "#;
+    let fs = mem_fs::FileSystem::default();
+    fs.new_open_options()
+        .write(true)
+        .create(true)
+        .open(Path::new("/times.txt"))
+        .unwrap();
+    let clock = ManualClock::new();
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(fs.clone()))
+            .preopen_dir("/")
+            .unwrap()
+            .clock(clock.clone());
+    });
+
+    // Writes a clock subscription with the userdata 1, `flags` are the
+    // subscription clock flags
+    let subscribe = |guest: &Guest, timeout: Duration, flags: u8| {
+        let mut subscription = [0; 48];
+        subscription[0..8].copy_from_slice(&1u64.to_le_bytes());
+        subscription[8] = __WASI_EVENTTYPE_CLOCK;
+        subscription[16..24].copy_from_slice(&(__WASI_CLOCK_MONOTONIC as u64).to_le_bytes());
+        subscription[24..32].copy_from_slice(&(timeout.as_nanos() as u64).to_le_bytes());
+        subscription[40] = flags;
+        guest.write_memory(0, &subscription);
+    };
+
+    assert_eq!(guest.call_errno("now", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u64(128), 0);
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(guest.call_errno("now", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u64(128), 5_000_000_000);
+
+    assert_eq!(guest.call_errno("res", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u64(136), 1);
+
+    // `*_NOW` timestamps are read from the clock
+    assert_eq!(guest.call_errno("touch", &[]), __WASI_ESUCCESS);
+    assert_eq!(
+        fs.metadata(Path::new("/times.txt")).unwrap().modified,
+        5_000_000_000
+    );
+
+    // Waiting for the timer moves the clock forward by a second, without
+    // waiting for a real second
+    subscribe(&guest, Duration::from_secs(1), 0);
+    let started = std::time::Instant::now();
+    assert_eq!(guest.call_errno("poll", &[]), __WASI_ESUCCESS);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(guest.read_u32(96), 1);
+    assert_eq!(guest.read_u64(64), 1);
+    assert_eq!(clock.time(), Duration::from_secs(6));
+
+    // An absolute deadline that the clock already reached fires right away
+    subscribe(&guest, clock.time(), 1);
+    assert_eq!(guest.call_errno("poll", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u32(96), 1);
+}
+
+fn test_nonblocking() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Reads up to 64 bytes from stdin into the buffer at offset 64, the
+        ;; number of bytes read is stored at offset 8
+        (func (export "read_stdin") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 64))
+            (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+
+        ;; Polls a monotonic clock with a zero timeout, the number of events
+        ;; is stored at offset 8
+        (func (export "poll_now") (result i32)
+            (i64.store (i32.const 128) (i64.const 42)) ;; userdata
+            (i32.store8 (i32.const 136) (i32.const 0)) ;; clock event
+            (i32.store (i32.const 144) (i32.const 1)) ;; monotonic clock
+            (i64.store (i32.const 152) (i64.const 0)) ;; timeout
+            (i64.store (i32.const 160) (i64.const 0)) ;; precision
+            (i32.store16 (i32.const 168) (i32.const 0)) ;; relative
+            (call $poll_oneoff (i32.const 128) (i32.const 192) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    let mut stdin = Pipe::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdin(Box::new(stdin.clone())).nonblocking(true);
+    });
+
+    assert_eq!(guest.call_errno("read_stdin", &[]), __WASI_EAGAIN);
+
+    // Retrying once data is available succeeds
+    stdin.write_all(b"hello").unwrap();
+    assert_eq!(guest.call_errno("read_stdin", &[]), __WASI_ESUCCESS);
+    let nread = guest.read_u32(8) as usize;
+    assert_eq!(guest.read_memory(64, nread), b"hello");
+
+    assert_eq!(guest.call_errno("poll_now", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u32(8), 1);
+    assert_eq!(guest.read_u64(192), 42);
+}
+
+fn test_nonblocking_pipe() {
+    let wat = br#"
+    (module
+        (import "wasix_32v1" "fd_pipe" (func $fd_pipe (param i32 i32) (result i32)))
+        (import "wasix_32v1" "fd_fdstat_set_flags" (func $fd_fdstat_set_flags (param i32 i32) (result i32)))
+        (import "wasix_32v1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+        (import "wasix_32v1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+        (import "wasix_32v1" "fd_close" (func $fd_close (param i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 128) "hello")
+
+        ;; Creates a pipe, the fds of its ends are stored at offsets 0 and 4,
+        ;; and makes the first one non-blocking
+        (func (export "open") (result i32)
+            (drop (call $fd_pipe (i32.const 0) (i32.const 4)))
+            (call $fd_fdstat_set_flags (i32.load (i32.const 0)) (i32.const 4)) ;; NONBLOCK
+        )
+
+        ;; Reads up to 64 bytes from the pipe into the buffer at offset 64,
+        ;; the number of bytes read is stored at offset 16
+        (func (export "read") (result i32)
+            (i32.store (i32.const 8) (i32.const 64))
+            (i32.store (i32.const 12) (i32.const 64))
+            (call $fd_read (i32.load (i32.const 0)) (i32.const 8) (i32.const 1) (i32.const 16))
+        )
+
+        (func (export "write") (result i32)
+            (i32.store (i32.const 8) (i32.const 128))
+            (i32.store (i32.const 12) (i32.const 5))
+            (call $fd_write (i32.load (i32.const 4)) (i32.const 8) (i32.const 1) (i32.const 16))
+        )
+
+        (func (export "close_writer") (result i32)
+            (call $fd_close (i32.load (i32.const 4)))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |_| {});
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    // The pipe is empty, reading from it would block
+    assert_eq!(guest.call_errno("read", &[]), __WASI_EAGAIN);
+
+    assert_eq!(guest.call_errno("write", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+    let nread = guest.read_u32(16) as usize;
+    assert_eq!(guest.read_memory(64, nread), b"hello");
+
+    assert_eq!(guest.call_errno("read", &[]), __WASI_EAGAIN);
+
+    // Once the other end is closed the pipe is at EOF
+    assert_eq!(guest.call_errno("close_writer", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+    assert_eq!(guest.read_u32(16), 0);
+}
+
+/// Runs a module that writes 16 random bytes and two readings of the
+/// monotonic clock to stdout, returning the output
+fn run_deterministic(seed: u64) -> Vec<u8> {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (func $main (export "_start")
+            (drop (call $random_get (i32.const 64) (i32.const 16)))
+            (drop (call $clock_time_get (i32.const 1) (i64.const 1) (i32.const 80)))
+            (drop (call $clock_time_get (i32.const 1) (i64.const 1) (i32.const 88)))
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 32))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+        )
+    )
+    "#;
+    let mut stdout = Pipe::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdout(Box::new(stdout.clone())).deterministic(seed);
+    });
+
+    guest.call("_start", &[]);
+
+    let mut output = Vec::new();
+    stdout.read_to_end(&mut output).unwrap();
+    output
+}
+
+fn test_deterministic() {
+    let output = run_deterministic(42);
+    assert_eq!(output.len(), 32);
+    assert_eq!(output, run_deterministic(42));
+    assert_ne!(output[..16], run_deterministic(43)[..16]);
+
+    // The clock starts at 0 and advances by `1 + seed % 1_000_000` per call
+    let mut first = [0; 8];
+    first.copy_from_slice(&output[16..24]);
+    let mut second = [0; 8];
+    second.copy_from_slice(&output[24..32]);
+    assert_eq!(u64::from_le_bytes(first), 0);
+    assert_eq!(u64::from_le_bytes(second), 43);
+}
+
+/// Runs a module that calls `random_get` once for each length of `lens`,
+/// returning the errno and the bytes of every call
+fn run_random_gets(builder: &mut WasiStateBuilder, lens: &[i32]) -> Vec<(u16, Vec<u8>)> {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Writes `$len` random bytes at offset 0
+        (func (export "random") (param $len i32) (result i32)
+            (call $random_get (i32.const 0) (local.get $len))
+        )
+    )
+    "#;
+    let mut guest = run_wat_with(wat, builder);
+
+    lens.iter()
+        .map(|len| {
+            guest.write_memory(0, &[0; 64]);
+            let errno = guest.call_errno("random", &[Value::I32(*len)]);
+            (errno, guest.read_memory(0, *len as usize))
+        })
+        .collect()
+}
+
+fn test_record_replay_randomness() {
+    let recorder = Arc::new(Mutex::new(Vec::new()));
+    let recorded = run_random_gets(
+        WasiState::new("command-name").record_randomness(recorder.clone()),
+        &[16, 0, 8],
+    );
+    assert!(recorded.iter().all(|(errno, _)| *errno == __WASI_ESUCCESS));
+    let recording = recorder.lock().unwrap().clone();
+    assert_eq!(recording, [&recorded[0].1[..], &recorded[2].1[..]].concat());
+
+    // The replay gets the same bytes, also when they are asked for in
+    // different chunks
+    let replayed = run_random_gets(
+        WasiState::new("command-name").replay_randomness(recording.clone()),
+        &[16, 0, 8],
+    );
+    assert_eq!(replayed, recorded);
+    let replayed = run_random_gets(
+        WasiState::new("command-name").replay_randomness(recording.clone()),
+        &[4, 20],
+    );
+    assert_eq!(replayed[0].1, recording[..4]);
+    assert_eq!(replayed[1].1, recording[4..]);
+
+    // Asking for more than was recorded fails without consuming the rest
+    let replayed = run_random_gets(
+        WasiState::new("command-name").replay_randomness(recording.clone()),
+        &[20, 8, 4],
+    );
+    assert_eq!(replayed[0], (__WASI_ESUCCESS, recording[..20].to_vec()));
+    assert_eq!(replayed[1].0, __WASI_EIO);
+    assert_eq!(replayed[2], (__WASI_ESUCCESS, recording[20..].to_vec()));
+}
+
+fn test_clock_res_get() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "clock_res_get" (func $clock_res_get (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; The resolution is stored at offset 0
+        (func (export "res") (param $clock_id i32) (result i32)
+            (call $clock_res_get (local.get $clock_id) (i32.const 0))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |_| {});
+
+    let mut res = |clock_id: i32| {
+        let errno = guest.call_errno("res", &[Value::I32(clock_id)]);
+        (errno, guest.read_u64(0))
+    };
+
+    let plausible = |resolution: u64| resolution > 0 && resolution <= 1_000_000_000;
+    for clock_id in [__WASI_CLOCK_REALTIME, __WASI_CLOCK_MONOTONIC] {
+        let (errno, resolution) = res(clock_id as i32);
+        assert_eq!(errno, __WASI_ESUCCESS);
+        assert!(plausible(resolution), "{}", resolution);
+    }
+    // The CPU-time clocks are not available on every host
+    for clock_id in [
+        __WASI_CLOCK_PROCESS_CPUTIME_ID,
+        __WASI_CLOCK_THREAD_CPUTIME_ID,
+    ] {
+        match res(clock_id as i32) {
+            (__WASI_ESUCCESS, resolution) => assert!(plausible(resolution), "{}", resolution),
+            (errno, _) => assert_eq!(errno, __WASI_ENOTSUP),
+        }
+    }
+    assert_eq!(res(4).0, __WASI_EINVAL);
+}
+
+fn test_sock_recv_flags() {
+    let wat = br#"
+    (module
+        (import "wasix_32v1" "sock_open" (func $sock_open (param i32 i32 i32 i32) (result i32)))
+        (import "wasix_32v1" "sock_bind" (func $sock_bind (param i32 i32) (result i32)))
+        (import "wasix_32v1" "sock_addr_local" (func $sock_addr_local (param i32 i32) (result i32)))
+        (import "wasix_32v1" "sock_recv" (func $sock_recv (param i32 i32 i32 i32 i32 i32) (result i32)))
+        (import "wasix_32v1" "sock_send" (func $sock_send (param i32 i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; 127.0.0.1, any port
+        (data (i32.const 16) "\01\00\00\00\7f\00\00\01")
+
+        ;; Opens a UDP socket, its fd is stored at offset 0 and its address
+        ;; at offset 64
+        (func (export "open") (result i32)
+            (drop (call $sock_open (i32.const 1) (i32.const 0) (i32.const 0) (i32.const 0)))
+            (drop (call $sock_bind (i32.load (i32.const 0)) (i32.const 16)))
+            (call $sock_addr_local (i32.load (i32.const 0)) (i32.const 64))
+        )
+
+        ;; Receives up to `len` bytes into the buffer at offset 128, the
+        ;; number of bytes received is stored at offset 108 and the output
+        ;; flags at offset 112
+        (func (export "recv") (param $flags i32) (param $len i32) (result i32)
+            (i32.store (i32.const 100) (i32.const 128))
+            (i32.store (i32.const 104) (local.get $len))
+            (call $sock_recv
+                (i32.load (i32.const 0)) (i32.const 100) (i32.const 1)
+                (local.get $flags) (i32.const 108) (i32.const 112))
+        )
+
+        (func (export "send") (param $flags i32) (result i32)
+            (i32.store (i32.const 100) (i32.const 128))
+            (i32.store (i32.const 104) (i32.const 1))
+            (call $sock_send
+                (i32.load (i32.const 0)) (i32.const 100) (i32.const 1)
+                (local.get $flags) (i32.const 108))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |_| {});
+
+    let received = |guest: &Guest| {
+        let len = guest.read_u32(108) as usize;
+        let flags = guest.read_memory(112, 2);
+        (
+            guest.read_memory(128, len),
+            u16::from_le_bytes([flags[0], flags[1]]),
+        )
+    };
+    let recv = |guest: &mut Guest, flags: u16, len: i32| {
+        guest.call_errno("recv", &[Value::I32(flags as i32), Value::I32(len)])
+    };
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+    let port = guest.read_memory(66, 2);
+    let guest_addr = SocketAddr::from(([127, 0, 0, 1], u16::from_be_bytes([port[0], port[1]])));
+    let host = UdpSocket::bind("127.0.0.1:0").unwrap();
+    host.send_to(b"hello world", guest_addr).unwrap();
+
+    // Peeking leaves the datagram in the socket
+    assert_eq!(
+        recv(&mut guest, __WASI_SOCK_RECV_INPUT_PEEK, 5),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(
+        received(&guest),
+        (b"hello".to_vec(), __WASI_SOCK_RECV_OUTPUT_DATA_TRUNCATED)
+    );
+    assert_eq!(recv(&mut guest, 0, 64), __WASI_ESUCCESS);
+    assert_eq!(received(&guest), (b"hello world".to_vec(), 0));
+
+    // The rest of a datagram that doesn't fit is dropped
+    host.send_to(b"0123456789", guest_addr).unwrap();
+    host.send_to(b"next", guest_addr).unwrap();
+    assert_eq!(recv(&mut guest, 0, 4), __WASI_ESUCCESS);
+    assert_eq!(
+        received(&guest),
+        (b"0123".to_vec(), __WASI_SOCK_RECV_OUTPUT_DATA_TRUNCATED)
+    );
+    assert_eq!(recv(&mut guest, 0, 64), __WASI_ESUCCESS);
+    assert_eq!(received(&guest), (b"next".to_vec(), 0));
+
+    assert_eq!(
+        recv(&mut guest, __WASI_SOCK_RECV_INPUT_WAITALL, 64),
+        __WASI_ENOTSUP
+    );
+    assert_eq!(guest.call_errno("send", &[Value::I32(1)]), __WASI_ENOTSUP);
+}
+
+fn test_fdstat_set_rights() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_fdstat_get" (func $fd_fdstat_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_fdstat_set_rights" (func $fd_fdstat_set_rights (param i32 i64 i64) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 32) "hi")
+
+        ;; The fdstat is stored at offset 64
+        (func (export "fdstat") (result i32)
+            (call $fd_fdstat_get (i32.const 1) (i32.const 64))
+        )
+        (func (export "set_rights") (param $rights i64) (result i32)
+            (call $fd_fdstat_set_rights (i32.const 1) (local.get $rights) (i64.const 0))
+        )
+        (func (export "write") (result i32)
+            (i32.store (i32.const 0) (i32.const 32))
+            (i32.store (i32.const 4) (i32.const 2))
+            (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    let mut stdout = Pipe::default();
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdout(Box::new(stdout.clone()));
+    });
+
+    let rights = |guest: &mut Guest| {
+        assert_eq!(guest.call_errno("fdstat", &[]), __WASI_ESUCCESS);
+        guest.read_u64(72)
+    };
+    let original_rights = rights(&mut guest);
+    assert_ne!(original_rights & __WASI_RIGHT_FD_WRITE, 0);
+
+    let narrowed_rights = original_rights & !__WASI_RIGHT_FD_WRITE;
+    assert_eq!(
+        guest.call_errno("set_rights", &[Value::I64(narrowed_rights as i64)]),
+        __WASI_ESUCCESS
+    );
+    assert_eq!(rights(&mut guest), narrowed_rights);
+
+    assert_eq!(guest.call_errno("write", &[]), __WASI_EPERM);
+    let mut output = String::new();
+    stdout.read_to_string(&mut output).unwrap();
+    assert_eq!(output, "");
+
+    // Rights that were dropped can not be regained
+    assert_eq!(
+        guest.call_errno("set_rights", &[Value::I64(original_rights as i64)]),
+        __WASI_ENOTCAPABLE
+    );
+    assert_eq!(rights(&mut guest), narrowed_rights);
+}
+
+fn test_environ_get() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "environ_sizes_get" (func $environ_sizes_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "environ_get" (func $environ_get (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Writes the count and buffer size to 0 and 4, the pointers to 16
+        ;; and the strings to 256
+        (func (export "environ") (result i32)
+            (local $errno i32)
+            (local.set $errno (call $environ_sizes_get (i32.const 0) (i32.const 4)))
+            (if (local.get $errno) (then (return (local.get $errno))))
+            (call $environ_get (i32.const 16) (i32.const 256))
+        )
+    )
+    "#;
+    let vars: [(&str, &str); 3] = [("ZETA", "last"), ("ALPHA", "a=b"), ("MIDDLE", "")];
+    let mut guest = run_wat(wat, |builder| {
+        for (key, value) in vars {
+            builder.env(key, value);
+        }
+    });
+
+    assert_eq!(guest.call_errno("environ", &[]), __WASI_ESUCCESS);
+
+    assert_eq!(guest.read_u32(0) as usize, vars.len());
+    let buf_size = guest.read_u32(4) as u64;
+    let expected: Vec<String> = vars.iter().map(|(k, v)| format!("{}={}\0", k, v)).collect();
+    assert_eq!(
+        buf_size,
+        expected.iter().map(|e| e.len() as u64).sum::<u64>()
+    );
+
+    // Entries are laid out back to back, in insertion order
+    let mut offset = 256;
+    for (i, entry) in expected.iter().enumerate() {
+        assert_eq!(guest.read_u32(16 + 4 * i as u64) as u64, offset);
+        assert_eq!(guest.read_memory(offset, entry.len()), entry.as_bytes());
+        offset += entry.len() as u64;
+    }
+    assert_eq!(offset, 256 + buf_size);
+}
+
+fn test_read_write_bytes() {
+    let mut store = Store::default();
+    let module = Module::new(
+        &mut store,
+        br#"
+    (module
+        (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+        (import "env" "shout" (func $shout (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "hello")
+
+        (func (export "shout") (param i32 i32) (result i32)
+            (call $shout (local.get 0) (local.get 1))
+        )
+    )
+    "#,
+    )
+    .unwrap();
+
+    // A custom import sharing the `WasiEnv`, upper-cases a guest string
+    fn shout(ctx: FunctionEnvMut<'_, WasiEnv>, ptr: WasmPtr<u8, Memory32>, len: u32) -> i32 {
+        let env = ctx.data();
+        let mut bytes = match env.read_bytes(&ctx, ptr, len) {
+            Ok(bytes) => bytes,
+            Err(_) => return -1,
+        };
+        bytes.make_ascii_uppercase();
+        match env.write_bytes(&ctx, ptr, &bytes) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        }
+    }
+
+    let wasi_env = WasiState::new("command-name").finalize(&mut store).unwrap();
+    let mut import_object = wasi_env.import_object(&mut store, &module).unwrap();
+    let shout = Function::new_native(&mut store, &wasi_env.env, shout);
+    import_object.define("env", "shout", shout);
+    let instance = Instance::new(&mut store, &module, &import_object).unwrap();
+    let memory = instance.exports.get_memory("memory").unwrap();
+    wasi_env.data_mut(&mut store).set_memory(memory.clone());
+
+    let shout = instance.exports.get_function("shout").unwrap();
+    let result = shout
+        .call(&mut store, &[Value::I32(16), Value::I32(5)])
+        .unwrap();
+    assert_eq!(*result, [Value::I32(0)]);
+    let result = shout
+        .call(&mut store, &[Value::I32(65534), Value::I32(5)])
+        .unwrap();
+    assert_eq!(*result, [Value::I32(-1)]);
+
+    let env = wasi_env.env.as_ref(&store);
+    let ptr = WasmPtr::<u8, Memory32>::new(16);
+    assert_eq!(env.read_bytes(&store, ptr, 5).unwrap(), b"HELLO");
+    // the end of the range overflows the address space
+    assert_eq!(
+        env.read_bytes(&store, WasmPtr::<u8, Memory32>::new(u32::MAX), 2),
+        Err(FsError::InvalidInput)
+    );
+    assert_eq!(
+        env.write_bytes(&store, WasmPtr::<u8, Memory32>::new(65535), b"no"),
+        Err(FsError::InvalidInput)
+    );
+}
+
+fn test_max_instructions() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "sched_yield" (func $sched_yield (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (func (export "yield") (result i32)
+            (call $sched_yield)
+        )
+
+        ;; Yields forever
+        (func (export "spin")
+            (loop $spin
+                (drop (call $sched_yield))
+                (br $spin)
+            )
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |builder| {
+        builder.max_instructions(100);
+    });
+
+    // Every syscall is charged
+    let state = guest.env().state.clone();
+    assert_eq!(guest.call_errno("yield", &[]), __WASI_ESUCCESS);
+    assert_eq!(
+        state.remaining_instructions(),
+        Some(100 - SYSCALL_INSTRUCTIONS)
+    );
+
+    // The embedder can charge the instructions run between syscalls, the
+    // budget is only checked when the guest makes a syscall
+    state.consume_instructions(100);
+    assert_eq!(state.remaining_instructions(), Some(0));
+    let err = guest.try_call("yield", &[]).unwrap_err();
+    assert!(matches!(
+        err.downcast::<WasiError>(),
+        Ok(WasiError::OutOfFuel)
+    ));
+
+    // A guest that keeps making syscalls runs out of its budget
+    let mut guest = run_wat(wat, |builder| {
+        builder.max_instructions(1000);
+    });
+    let err = guest.try_call("spin", &[]).unwrap_err();
+    assert!(matches!(
+        err.downcast::<WasiError>(),
+        Ok(WasiError::OutOfFuel)
+    ));
+    assert_eq!(guest.env().state.remaining_instructions(), Some(0));
+
+    // Without a budget nothing is counted
+    let state = WasiState::new("command-name").build().unwrap();
+    state.consume_instructions(60);
+    assert_eq!(state.remaining_instructions(), None);
+}
+
+fn test_bare_wasi_namespace() {
+    let wat = br#"
+    (module
+        (import "wasi" "sched_yield" (func $sched_yield (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (func (export "yield") (result i32)
+            (call $sched_yield)
+        )
+    )
+    "#;
+    let module = Module::new(&Store::default(), wat).unwrap();
+    assert!(matches!(
+        get_wasi_version(&module, false),
+        Some(WasiVersion::Latest)
+    ));
+
+    let mut guest = run_wat(wat, |_| {});
+    assert_eq!(guest.call_errno("yield", &[]), __WASI_ESUCCESS);
+}
+
+fn test_fdstat_filetype() {
+    let wat = br#"
+    (module
+        (import "wasix_32v1" "fd_pipe" (func $fd_pipe (param i32 i32) (result i32)))
+        (import "wasix_32v1" "sock_open" (func $sock_open (param i32 i32 i32 i32) (result i32)))
+        (import "wasix_32v1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasix_32v1" "fd_fdstat_get" (func $fd_fdstat_get (param i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 32) "file.txt")
+
+        ;; Opens a pipe (fds at offsets 0 and 4), a TCP socket (offset 8),
+        ;; a UDP socket (offset 12) and a file (offset 16)
+        (func (export "open") (result i32)
+            (i32.or
+                (i32.or
+                    (call $fd_pipe (i32.const 0) (i32.const 4))
+                    (call $sock_open (i32.const 1) (i32.const 1) (i32.const 0) (i32.const 8)))
+                (i32.or
+                    (call $sock_open (i32.const 1) (i32.const 0) (i32.const 0) (i32.const 12))
+                    (call $path_open
+                        (i32.const 4) (i32.const 0) (i32.const 32) (i32.const 8)
+                        (i32.const 1) ;; __WASI_O_CREAT
+                        (i64.const -1) (i64.const -1) (i32.const 0)
+                        (i32.const 16))))
+        )
+
+        ;; The fdstat is stored at offset 64
+        (func (export "fdstat") (param $fd i32) (result i32)
+            (call $fd_fdstat_get (local.get $fd) (i32.const 64))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |builder| {
+        builder
+            .set_fs(Box::new(mem_fs::FileSystem::default()))
+            .preopen_dir("/")
+            .unwrap();
+    });
+
+    assert_eq!(guest.call_errno("open", &[]), __WASI_ESUCCESS);
+
+    let filetype = |guest: &mut Guest, fd: u32| {
+        assert_eq!(
+            guest.call_errno("fdstat", &[Value::I32(fd as i32)]),
+            __WASI_ESUCCESS
+        );
+        guest.read_memory(64, 1)[0]
+    };
+
+    assert_eq!(
+        filetype(&mut guest, __WASI_STDOUT_FILENO),
+        __WASI_FILETYPE_CHARACTER_DEVICE
+    );
+    // The preopened directory
+    assert_eq!(filetype(&mut guest, 4), __WASI_FILETYPE_DIRECTORY);
+    for (offset, expected) in [
+        (0, __WASI_FILETYPE_CHARACTER_DEVICE),
+        (4, __WASI_FILETYPE_CHARACTER_DEVICE),
+        (8, __WASI_FILETYPE_SOCKET_STREAM),
+        (12, __WASI_FILETYPE_SOCKET_DGRAM),
+        (16, __WASI_FILETYPE_REGULAR_FILE),
+    ] {
+        let fd = guest.read_u32(offset);
+        assert_eq!(
+            filetype(&mut guest, fd),
+            expected,
+            "fd at offset {}",
+            offset
+        );
+    }
+}
+
+fn test_run_wasi_start() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+        (import "wasi_snapshot_preview1" "sched_yield" (func $sched_yield (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+        (global $mode (export "mode") (mut i32) (i32.const 0))
+
+        ;; Returns, exits with 7 or traps depending on `mode`
+        (func (export "_start")
+            (drop (call $sched_yield))
+            (if (i32.eq (global.get $mode) (i32.const 1))
+                (then (call $proc_exit (i32.const 7))))
+            (if (i32.eq (global.get $mode) (i32.const 2))
+                (then unreachable))
+        )
+    )
+    "#;
+    let mut guest = run_wat(wat, |_| {});
+
+    let mode = guest.instance.exports.get_global("mode").unwrap().clone();
+    assert_eq!(
+        run_wasi_start(&mut guest.store, &guest.instance).unwrap(),
+        0
+    );
+    mode.set(&mut guest.store, Value::I32(1)).unwrap();
+    assert_eq!(
+        run_wasi_start(&mut guest.store, &guest.instance).unwrap(),
+        7
+    );
+    mode.set(&mut guest.store, Value::I32(2)).unwrap();
+    assert!(matches!(
+        run_wasi_start(&mut guest.store, &guest.instance),
+        Err(WasiError::Trap(_))
+    ));
+
+    // Reactors have no `_start`
+    let mut store = Store::default();
+    let module = Module::new(&store, "(module)").unwrap();
+    let instance = Instance::new(&mut store, &module, &Default::default()).unwrap();
+    assert!(matches!(
+        run_wasi_start(&mut store, &instance),
+        Err(WasiError::NoStart(_))
+    ));
+}
+
+fn test_syscall_tracer() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "path_open" (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        (data (i32.const 16) "missing.txt")
+
+        (func $main (export "_start")
+            (drop (call $path_open
+                (i32.const 4) (i32.const 0) (i32.const 16) (i32.const 11)
+                (i32.const 0) (i64.const -1) (i64.const -1) (i32.const 0)
+                (i32.const 0) ;; new fd
+            ))
+            (drop (call $fd_close (i32.const 99)))
+        )
+    )
+    "#;
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut guest = run_wat(wat, |builder| {
+        let events = events.clone();
+        builder
+            .set_fs(Box::new(mem_fs::FileSystem::default()))
+            .preopen_dir("/")
+            .unwrap()
+            .syscall_tracer(Box::new(move |event| events.lock().unwrap().push(event)));
+    });
+
+    guest.call("_start", &[]);
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+
+    let path_open = &events[0];
+    assert_eq!(path_open.name, "path_open");
+    assert_eq!(path_open.arg("dirfd"), Some(4));
+    assert_eq!(path_open.arg("path"), Some(16));
+    assert_eq!(path_open.arg("path_len"), Some(11));
+    assert_eq!(path_open.arg("fs_rights_base"), Some(u64::MAX));
+    assert_eq!(path_open.arg("missing"), None);
+    assert_eq!(path_open.path("path"), Some("missing.txt"));
+    assert_eq!(path_open.errno, Some(__WASI_ENOENT));
+
+    let fd_close = &events[1];
+    assert_eq!(fd_close.name, "fd_close");
+    assert_eq!(fd_close.args, vec![("fd", 99)]);
+    assert!(fd_close.paths.is_empty());
+    assert_eq!(fd_close.errno, Some(__WASI_EBADF));
+}
+
+fn test_close_fd() {
+    let wat = br#"
+    (module
+        (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+
+        (memory 1)
+        (export "memory" (memory 0))
+
+        ;; Reads stdin into the buffer at offset 64
+        (func (export "read") (result i32)
+            (i32.store (i32.const 0) (i32.const 64))
+            (i32.store (i32.const 4) (i32.const 32))
+            (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8))
+        )
+    )
+    "#;
+    let mut stdin = Pipe::default();
+    stdin.write_all(b"abc").unwrap();
+    let mut guest = run_wat(wat, |builder| {
+        builder.stdin(Box::new(stdin));
+    });
+
+    assert_eq!(guest.call_errno("read", &[]), __WASI_ESUCCESS);
+
+    let state = guest.env().state.clone();
+    assert_eq!(state.close_fd(0), Ok(()));
+    assert_eq!(guest.call_errno("read", &[]), __WASI_EBADF);
+    assert!(!state.fds().iter().any(|(fd, _)| *fd == 0));
+
+    assert_eq!(state.close_fd(0), Err(FsError::InvalidFd));
+    assert_eq!(state.close_fd(99), Err(FsError::InvalidFd));
+}
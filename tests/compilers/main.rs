@@ -14,6 +14,7 @@ mod middlewares;
 // mod multi_value_imports;
 mod native_functions;
 mod serialize;
+mod shared_memory;
 mod traps;
 mod wasi;
 mod wast;
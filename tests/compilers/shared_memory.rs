@@ -0,0 +1,50 @@
+//! Testing the shared memories of the threads proposal.
+use anyhow::Result;
+use wasmer::*;
+
+fn threads_config(mut config: crate::Config) -> crate::Config {
+    let mut features = Features::default();
+    features.threads(true);
+    config.set_features(features);
+    config
+}
+
+#[compiler_test(shared_memory)]
+fn shared_memory_links(config: crate::Config) -> Result<()> {
+    let mut store = threads_config(config).store();
+    let module = Module::new(
+        &store,
+        r#"
+    (module
+        (import "env" "memory" (memory 1 4 shared))
+        (export "memory" (memory 0)))
+    "#,
+    )?;
+
+    let memory = Memory::new(&mut store, MemoryType::new(1, Some(4), true))?;
+    let imports = imports! {
+        "env" => {
+            "memory" => memory.clone(),
+        },
+    };
+    let instance = Instance::new(&mut store, &module, &imports)?;
+
+    let exported = instance.exports.get_memory("memory")?;
+    assert!(exported.ty(&store).shared);
+
+    // Growing doesn't move a shared memory
+    let base = memory.data_ptr(&store);
+    exported.grow(&mut store, 3)?;
+    assert_eq!(memory.data_ptr(&store), base);
+    assert!(memory.grow(&mut store, 1).is_err());
+
+    // A shared memory can't be imported as a non-shared one
+    let module = Module::new(&store, r#"(module (import "env" "memory" (memory 1 4)))"#)?;
+    assert!(Instance::new(&mut store, &module, &imports).is_err());
+
+    // Modules can't define their own shared memories until atomics are
+    // supported
+    assert!(Module::new(&store, r#"(module (memory 1 4 shared))"#).is_err());
+
+    Ok(())
+}